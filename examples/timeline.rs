@@ -101,5 +101,6 @@ fn main() {
 
     timeline::handle(&mut Session::open(opts.output), timeline::Request {
         root: opts.root,
+        ..Default::default()
     }).expect("failed to execute the action");
 }
@@ -20,12 +20,22 @@ const PROTOS: &'static [&'static str] = &[
     "grr/grr/proto/grr_response_proto/user.proto",
 ];
 
+const RRG_PROTOS: &'static [&'static str] = &[
+    "rrg/memory.proto",
+    "rrg/startup.proto",
+    "rrg/stat.proto",
+    "rrg/timeline.proto",
+];
+
 const INCLUDES: &'static [&'static str] = &[
     "grr/grr/proto",
+    ".",
 ];
 
 fn main() {
-    prost_build::compile_protos(&PROTOS, &INCLUDES)
+    let protos = PROTOS.iter().chain(RRG_PROTOS).copied().collect::<Vec<_>>();
+
+    prost_build::compile_protos(&protos, &INCLUDES)
         .expect("failed to compile proto files");
 
     // There is a problem with one enum generated by PROST!: it's values use
@@ -44,4 +54,22 @@ fn main() {
 
     std::fs::write(&target, grr)
         .expect("failed to write updated output file");
+
+    // Generated types are not capable of reflection, so we also store the
+    // descriptors of all the compiled files. These drive the conversion of
+    // messages to and from JSON (see the `json` module).
+    let mut protoc = std::process::Command::new(prost_build::protoc());
+    protoc
+        .arg("--include_imports")
+        .arg(format!("--descriptor_set_out={}",
+                     outdir.join("descriptors.bin").display()));
+
+    for include in INCLUDES {
+        protoc.arg("-I").arg(include);
+    }
+    protoc.arg("-I").arg(prost_build::protoc_include());
+
+    let status = protoc.args(protos).status()
+        .expect("failed to execute protoc");
+    assert!(status.success(), "failed to generate descriptors: {}", status);
 }
@@ -0,0 +1,121 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+//! Extensions of GRR messages with RRG-specific fields.
+//!
+//! Some information collected by RRG has no counterpart in the GRR protocol
+//! (e.g. Linux-specific file metadata). Such information is put into an RRG
+//! message that is encoded right after the GRR message it extends. Since
+//! concatenating encoded messages is the same as merging them, the result is
+//! still a valid GRR message: servers unaware of the extension simply skip the
+//! fields they do not know.
+//!
+//! Fields of extension messages are numbered from [`FIELD_MIN`] on, so that
+//! they do not collide with fields of the extended GRR messages.
+//!
+//! [`FIELD_MIN`]: constant.FIELD_MIN.html
+
+use prost::bytes::{Buf, BufMut};
+use prost::encoding::{DecodeContext, WireType};
+use prost::{DecodeError, Message};
+
+/// A minimum number of a field of an extension message.
+pub const FIELD_MIN: u32 = 100;
+
+/// A GRR message extended with fields of an RRG message.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Extended<M, E> {
+    /// The original GRR message.
+    pub message: M,
+    /// The RRG-specific extension of the message.
+    pub extension: E,
+}
+
+impl<M, E> Message for Extended<M, E>
+where
+    M: Message,
+    E: Message,
+{
+    fn encode_raw<B>(&self, buf: &mut B)
+    where
+        B: BufMut,
+    {
+        self.message.encode_raw(buf);
+        self.extension.encode_raw(buf);
+    }
+
+    fn merge_field<B>(
+        &mut self,
+        tag: u32,
+        wire_type: WireType,
+        buf: &mut B,
+        ctx: DecodeContext,
+    ) -> Result<(), DecodeError>
+    where
+        B: Buf,
+    {
+        if tag >= FIELD_MIN {
+            self.extension.merge_field(tag, wire_type, buf, ctx)
+        } else {
+            self.message.merge_field(tag, wire_type, buf, ctx)
+        }
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.message.encoded_len() + self.extension.encoded_len()
+    }
+
+    fn clear(&mut self) {
+        self.message.clear();
+        self.extension.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_decode_original() {
+        let entry = Extended {
+            message: crate::TimelineEntry {
+                path: Some(b"/foo".to_vec()),
+                ino: Some(42),
+                ..Default::default()
+            },
+            extension: crate::rrg::TimelineEntryExtension {
+                linux_attributes: Some(0x10),
+                mnt_id: Some(1337),
+            },
+        };
+
+        let data = encode(&entry);
+        let decoded = crate::TimelineEntry::decode(&data[..]).unwrap();
+        assert_eq!(decoded, entry.message);
+    }
+
+    #[test]
+    fn test_decode_extended() {
+        let entry = Extended {
+            message: crate::StatEntry {
+                st_size: Some(1024),
+                ..Default::default()
+            },
+            extension: crate::rrg::StatEntryExtension {
+                mnt_id: Some(1337),
+            },
+        };
+
+        let data = encode(&entry);
+        assert_eq!(Extended::decode(&data[..]).unwrap(), entry);
+    }
+
+    fn encode<M: Message>(message: &M) -> Vec<u8> {
+        let mut data = vec!();
+        message.encode(&mut data).unwrap();
+        data
+    }
+}
@@ -0,0 +1,818 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+//! Conversion of encoded proto messages to and from JSON.
+//!
+//! Types generated by PROST! are not capable of reflection, so the conversion
+//! is driven by descriptors of all the proto files compiled into this crate
+//! (generated by `protoc` at build time).
+//!
+//! Message types are identified by their fully qualified names (e.g.
+//! `grr.StatEntry`), but names without the package are accepted as well as
+//! long as they are not ambiguous. RRG messages named the same as GRR ones are
+//! their extensions, so they take precedence over them. Fields are keyed by
+//! their proto names (JSON names are also accepted on input), enums are
+//! represented by the names of their values and bytes are encoded with base64.
+//! Unknown fields of encoded messages are skipped.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use lazy_static::lazy_static;
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::{DescriptorProto, EnumDescriptorProto, FieldDescriptorProto};
+use serde_json::Value;
+
+/// Serialized descriptors of all the proto files compiled into this crate.
+const DESCRIPTORS: &[u8] = include_bytes!(concat!(env!("OUT_DIR"),
+                                                  "/descriptors.bin"));
+
+lazy_static! {
+    static ref REGISTRY: Registry = Registry::new();
+}
+
+/// An error type for failures that can occur when converting messages.
+#[derive(Debug)]
+pub enum Error {
+    /// There is no message type with the given name.
+    UnknownType(String),
+    /// The encoded message is malformed.
+    Malformed(String),
+    /// The JSON value does not match the message type.
+    Mismatch(String),
+}
+
+/// Converts the given encoded message of the specified type to JSON.
+pub fn to_json(name: &str, data: &[u8]) -> Result<Value, Error> {
+    let registry = &*REGISTRY;
+    registry.decode(registry.message(name)?, data)
+}
+
+/// Encodes the given JSON value as a message of the specified type.
+pub fn from_json(name: &str, value: &Value) -> Result<Vec<u8>, Error> {
+    let registry = &*REGISTRY;
+
+    let mut data = vec!();
+    registry.encode(registry.message(name)?, value, &mut data)?;
+
+    Ok(data)
+}
+
+/// Looks up a descriptor of the message type with the specified name.
+pub(crate) fn message_type(name: &str)
+    -> Result<&'static DescriptorProto, Error>
+{
+    REGISTRY.message(name)
+}
+
+/// A wire type of varint-encoded values.
+const WIRE_VARINT: u64 = 0;
+/// A wire type of 64-bit fixed-width values.
+const WIRE_FIXED64: u64 = 1;
+/// A wire type of length-delimited values.
+const WIRE_LEN: u64 = 2;
+/// A wire type of 32-bit fixed-width values.
+const WIRE_FIXED32: u64 = 5;
+
+/// A single value read from an encoded message.
+#[derive(Clone, Copy)]
+enum Wire<'a> {
+    /// A varint-encoded value (integers, booleans and enums).
+    Varint(u64),
+    /// A 64-bit value (doubles and fixed-width integers).
+    Fixed64(u64),
+    /// A length-delimited value (strings, bytes, messages or packed values).
+    Len(&'a [u8]),
+    /// A 32-bit value (floats and fixed-width integers).
+    Fixed32(u32),
+}
+
+/// Descriptors of all known message and enum types, by their full names.
+struct Registry {
+    messages: HashMap<String, DescriptorProto>,
+    enums: HashMap<String, EnumDescriptorProto>,
+}
+
+impl Registry {
+
+    /// Builds the registry out of the descriptors embedded in the crate.
+    fn new() -> Registry {
+        let set: prost_types::FileDescriptorSet = {
+            prost::Message::decode(DESCRIPTORS)
+                .expect("malformed embedded descriptors")
+        };
+
+        let mut registry = Registry {
+            messages: HashMap::new(),
+            enums: HashMap::new(),
+        };
+
+        for file in set.file {
+            let package = file.package.unwrap_or_default();
+            registry.add_enums(&package, file.enum_type);
+            registry.add_messages(&package, file.message_type);
+        }
+
+        registry
+    }
+
+    /// Registers the given message types (and types nested in them).
+    fn add_messages(&mut self, scope: &str, messages: Vec<DescriptorProto>) {
+        for mut message in messages {
+            let name = qualify(scope, message.name());
+
+            let enums = std::mem::take(&mut message.enum_type);
+            self.add_enums(&name, enums);
+
+            let nested = std::mem::take(&mut message.nested_type);
+            self.add_messages(&name, nested);
+
+            self.messages.insert(name, message);
+        }
+    }
+
+    /// Registers the given enum types.
+    fn add_enums(&mut self, scope: &str, enums: Vec<EnumDescriptorProto>) {
+        for enum_type in enums {
+            self.enums.insert(qualify(scope, enum_type.name()), enum_type);
+        }
+    }
+
+    /// Looks up a message type with the given (possibly unqualified) name.
+    fn message(&self, name: &str) -> Result<&DescriptorProto, Error> {
+        let name = name.trim_start_matches('.');
+        if let Some(message) = self.messages.get(name) {
+            return Ok(message);
+        }
+
+        if let Some(message) = self.messages.get(&qualify("rrg", name)) {
+            return Ok(message);
+        }
+
+        let suffix = format!(".{}", name);
+        let mut candidates = self.messages.iter()
+            .filter(|(candidate, _)| candidate.ends_with(&suffix));
+
+        match (candidates.next(), candidates.next()) {
+            (Some((_, message)), None) => Ok(message),
+            _ => Err(Error::UnknownType(String::from(name))),
+        }
+    }
+
+    /// Looks up an enum type with the given fully qualified name.
+    fn enum_type(&self, name: &str) -> Result<&EnumDescriptorProto, Error> {
+        let name = name.trim_start_matches('.');
+        self.enums.get(name)
+            .ok_or_else(|| Error::UnknownType(String::from(name)))
+    }
+
+    /// Converts the given encoded message to a JSON object.
+    fn decode(&self, message: &DescriptorProto, mut buf: &[u8])
+        -> Result<Value, Error>
+    {
+        let mut object = serde_json::Map::new();
+
+        while !buf.is_empty() {
+            let key = read_varint(&mut buf)?;
+
+            let wire = match key & 0x07 {
+                WIRE_VARINT => Wire::Varint(read_varint(&mut buf)?),
+                WIRE_FIXED64 => {
+                    let bytes = read_bytes(&mut buf, 8)?;
+                    Wire::Fixed64(u64::from_le_bytes(array(bytes)))
+                }
+                WIRE_LEN => {
+                    let len = read_varint(&mut buf)?;
+                    Wire::Len(read_bytes(&mut buf, len)?)
+                }
+                WIRE_FIXED32 => {
+                    let bytes = read_bytes(&mut buf, 4)?;
+                    Wire::Fixed32(u32::from_le_bytes(array(bytes)))
+                }
+                wire_type => {
+                    let error = format!("unsupported wire type {}", wire_type);
+                    return Err(Error::Malformed(error));
+                }
+            };
+
+            let number = key >> 3;
+            let field = message.field.iter()
+                .find(|field| field.number() as u64 == number);
+
+            let field = match field {
+                Some(field) => field,
+                None => continue,
+            };
+
+            let name = String::from(field.name());
+            if field.label() != Label::Repeated {
+                object.insert(name, self.decode_value(field, wire)?);
+                continue;
+            }
+
+            let values = self.decode_values(field, wire)?;
+            match object.entry(name).or_insert_with(|| Value::Array(vec!())) {
+                Value::Array(array) => array.extend(values),
+                _ => unreachable!(),
+            }
+        }
+
+        Ok(Value::Object(object))
+    }
+
+    /// Converts a single occurrence of a repeated field to JSON values.
+    ///
+    /// Repeated scalar fields can be packed, in which case one occurrence
+    /// carries many values.
+    fn decode_values(&self, field: &FieldDescriptorProto, wire: Wire)
+        -> Result<Vec<Value>, Error>
+    {
+        let mut buf = match wire {
+            Wire::Len(buf) if wire_type(field.r#type()) != WIRE_LEN => buf,
+            wire => return Ok(vec!(self.decode_value(field, wire)?)),
+        };
+
+        let mut values = vec!();
+        while !buf.is_empty() {
+            let wire = match wire_type(field.r#type()) {
+                WIRE_FIXED64 => {
+                    let bytes = read_bytes(&mut buf, 8)?;
+                    Wire::Fixed64(u64::from_le_bytes(array(bytes)))
+                }
+                WIRE_FIXED32 => {
+                    let bytes = read_bytes(&mut buf, 4)?;
+                    Wire::Fixed32(u32::from_le_bytes(array(bytes)))
+                }
+                _ => Wire::Varint(read_varint(&mut buf)?),
+            };
+
+            values.push(self.decode_value(field, wire)?);
+        }
+
+        Ok(values)
+    }
+
+    /// Converts a single value of the given field to JSON.
+    fn decode_value(&self, field: &FieldDescriptorProto, wire: Wire)
+        -> Result<Value, Error>
+    {
+        let value = match (field.r#type(), wire) {
+            (Type::Double, Wire::Fixed64(bits)) => float(f64::from_bits(bits)),
+            (Type::Float, Wire::Fixed32(bits)) => {
+                float(f64::from(f32::from_bits(bits)))
+            }
+            (Type::Int64, Wire::Varint(value)) => Value::from(value as i64),
+            (Type::Uint64, Wire::Varint(value)) => Value::from(value),
+            // Negative 32-bit integers are sign-extended to 64 bits, so the
+            // truncation gives back the original value.
+            (Type::Int32, Wire::Varint(value)) => Value::from(value as i32),
+            (Type::Uint32, Wire::Varint(value)) => Value::from(value as u32),
+            (Type::Sint32, Wire::Varint(value)) => {
+                Value::from(unzigzag(value) as i32)
+            }
+            (Type::Sint64, Wire::Varint(value)) => Value::from(unzigzag(value)),
+            (Type::Bool, Wire::Varint(value)) => Value::from(value != 0),
+            (Type::Enum, Wire::Varint(value)) => {
+                let enum_type = self.enum_type(field.type_name())?;
+                let number = value as i32;
+
+                match enum_type.value.iter().find(|v| v.number() == number) {
+                    Some(value) => Value::from(value.name()),
+                    None => Value::from(number),
+                }
+            }
+            (Type::Fixed64, Wire::Fixed64(value)) => Value::from(value),
+            (Type::Sfixed64, Wire::Fixed64(value)) => {
+                Value::from(value as i64)
+            }
+            (Type::Fixed32, Wire::Fixed32(value)) => Value::from(value),
+            (Type::Sfixed32, Wire::Fixed32(value)) => {
+                Value::from(value as i32)
+            }
+            // Some GRR messages use strings for data that is not necessarily
+            // valid Unicode (e.g. paths), so we prefer a lossy conversion to
+            // not being able to show the message at all.
+            (Type::String, Wire::Len(data)) => {
+                Value::from(String::from_utf8_lossy(data).into_owned())
+            }
+            (Type::Bytes, Wire::Len(data)) => Value::from(encode_base64(data)),
+            (Type::Message, Wire::Len(data)) => {
+                self.decode(self.message(field.type_name())?, data)?
+            }
+            _ => {
+                let message = format!("invalid value of '{}'", field.name());
+                return Err(Error::Malformed(message));
+            }
+        };
+
+        Ok(value)
+    }
+
+    /// Encodes the given JSON object as a message of the specified type.
+    fn encode(&self, message: &DescriptorProto, value: &Value,
+              buf: &mut Vec<u8>) -> Result<(), Error>
+    {
+        let object = match value.as_object() {
+            Some(object) => object,
+            None => {
+                let message = format!("'{}' is not an object", message.name());
+                return Err(Error::Mismatch(message));
+            }
+        };
+
+        for (key, value) in object {
+            let field = message.field.iter().find(|field| {
+                field.name() == *key || field.json_name() == *key
+            });
+
+            let field = match field {
+                Some(field) => field,
+                None => {
+                    return Err(Error::Mismatch(format!(
+                        "unknown field '{}' of '{}'", key, message.name()
+                    )));
+                }
+            };
+
+            match (field.label(), value) {
+                (_, Value::Null) => (),
+                (Label::Repeated, Value::Array(values)) => {
+                    for value in values {
+                        self.encode_value(field, value, buf)?;
+                    }
+                }
+                (Label::Repeated, _) => {
+                    let message = format!("'{}' is not an array", key);
+                    return Err(Error::Mismatch(message));
+                }
+                (_, value) => self.encode_value(field, value, buf)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Encodes a single value of the given field.
+    fn encode_value(&self, field: &FieldDescriptorProto, value: &Value,
+                    buf: &mut Vec<u8>) -> Result<(), Error>
+    {
+        let mismatch = || {
+            Error::Mismatch(format!("invalid value of '{}'", field.name()))
+        };
+
+        let int = || -> Result<i64, Error> {
+            let result = match *value {
+                Value::Number(ref number) => number.as_i64(),
+                Value::String(ref string) => string.parse().ok(),
+                _ => None,
+            };
+            result.ok_or_else(mismatch)
+        };
+
+        let uint = || -> Result<u64, Error> {
+            let result = match *value {
+                Value::Number(ref number) => number.as_u64(),
+                Value::String(ref string) => string.parse().ok(),
+                _ => None,
+            };
+            result.ok_or_else(mismatch)
+        };
+
+        let int32 = || i32::try_from(int()?).map_err(|_| mismatch());
+        let uint32 = || u32::try_from(uint()?).map_err(|_| mismatch());
+
+        let double = || -> Result<f64, Error> {
+            let result = match *value {
+                Value::Number(ref number) => number.as_f64(),
+                Value::String(ref string) => string.parse().ok(),
+                _ => None,
+            };
+            result.ok_or_else(mismatch)
+        };
+
+        let key = (field.number() as u64) << 3 | wire_type(field.r#type());
+        write_varint(key, buf);
+
+        match field.r#type() {
+            Type::Double => buf.extend(&double()?.to_bits().to_le_bytes()),
+            Type::Float => {
+                buf.extend(&(double()? as f32).to_bits().to_le_bytes())
+            }
+            Type::Int64 => write_varint(int()? as u64, buf),
+            Type::Uint64 => write_varint(uint()?, buf),
+            Type::Int32 => write_varint(int32()? as i64 as u64, buf),
+            Type::Uint32 => write_varint(u64::from(uint32()?), buf),
+            Type::Sint32 => write_varint(zigzag(i64::from(int32()?)), buf),
+            Type::Sint64 => write_varint(zigzag(int()?), buf),
+            Type::Bool => {
+                let value = value.as_bool().ok_or_else(mismatch)?;
+                write_varint(value as u64, buf);
+            }
+            Type::Enum => {
+                let enum_type = self.enum_type(field.type_name())?;
+
+                let number = match value.as_str() {
+                    Some(name) => enum_type.value.iter()
+                        .find(|value| value.name() == name)
+                        .map(|value| value.number())
+                        .ok_or_else(mismatch)?,
+                    None => int32()?,
+                };
+
+                write_varint(number as i64 as u64, buf);
+            }
+            Type::Fixed64 => buf.extend(&uint()?.to_le_bytes()),
+            Type::Sfixed64 => buf.extend(&int()?.to_le_bytes()),
+            Type::Fixed32 => buf.extend(&uint32()?.to_le_bytes()),
+            Type::Sfixed32 => buf.extend(&int32()?.to_le_bytes()),
+            Type::String => {
+                let string = value.as_str().ok_or_else(mismatch)?;
+                write_len(string.as_bytes(), buf);
+            }
+            Type::Bytes => {
+                let data = value.as_str()
+                    .and_then(decode_base64)
+                    .ok_or_else(mismatch)?;
+                write_len(&data, buf);
+            }
+            Type::Message => {
+                let message = self.message(field.type_name())?;
+
+                let mut data = vec!();
+                self.encode(message, value, &mut data)?;
+                write_len(&data, buf);
+            }
+            Type::Group => {
+                let message = format!("'{}' is a group", field.name());
+                return Err(Error::Mismatch(message));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the wire type used to encode values of the given type.
+fn wire_type(r#type: Type) -> u64 {
+    match r#type {
+        Type::Double | Type::Fixed64 | Type::Sfixed64 => WIRE_FIXED64,
+        Type::Float | Type::Fixed32 | Type::Sfixed32 => WIRE_FIXED32,
+        Type::String | Type::Bytes | Type::Message => WIRE_LEN,
+        // Groups are deprecated and not used by any of the compiled messages.
+        Type::Group => 3,
+        _ => WIRE_VARINT,
+    }
+}
+
+/// Builds a fully qualified name of a type defined in the given scope.
+fn qualify(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        String::from(name)
+    } else {
+        format!("{}.{}", scope, name)
+    }
+}
+
+/// Converts the given floating-point number to JSON.
+///
+/// JSON has no representation for infinities and NaNs, so (as in the canonical
+/// proto JSON mapping) these are represented as strings.
+fn float(value: f64) -> Value {
+    match serde_json::Number::from_f64(value) {
+        Some(number) => Value::Number(number),
+        None if value.is_nan() => Value::from("NaN"),
+        None if value > 0.0 => Value::from("Infinity"),
+        None => Value::from("-Infinity"),
+    }
+}
+
+/// Reads a single varint from the beginning of the buffer.
+fn read_varint(buf: &mut &[u8]) -> Result<u64, Error> {
+    let mut value = 0;
+
+    for shift in (0..64).step_by(7) {
+        let (byte, rest) = match buf.split_first() {
+            Some((byte, rest)) => (*byte, rest),
+            None => {
+                let message = String::from("truncated varint");
+                return Err(Error::Malformed(message));
+            }
+        };
+        *buf = rest;
+
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err(Error::Malformed(String::from("varint too long")))
+}
+
+/// Reads the given number of bytes from the beginning of the buffer.
+fn read_bytes<'a>(buf: &mut &'a [u8], len: u64) -> Result<&'a [u8], Error> {
+    if len > buf.len() as u64 {
+        return Err(Error::Malformed(String::from("truncated value")));
+    }
+
+    let (bytes, rest) = buf.split_at(len as usize);
+    *buf = rest;
+
+    Ok(bytes)
+}
+
+/// Converts a slice (of a length verified by the caller) into an array.
+fn array<A: Default + AsMut<[u8]>>(bytes: &[u8]) -> A {
+    let mut array = A::default();
+    array.as_mut().copy_from_slice(bytes);
+    array
+}
+
+/// Writes the given value as a varint.
+fn write_varint(mut value: u64, buf: &mut Vec<u8>) {
+    while value >= 0x80 {
+        buf.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Writes the given bytes as a length-delimited value.
+fn write_len(data: &[u8], buf: &mut Vec<u8>) {
+    write_varint(data.len() as u64, buf);
+    buf.extend(data);
+}
+
+/// Maps a signed integer to an unsigned one using the ZigZag encoding.
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+/// Maps a ZigZag-encoded integer back to the signed one.
+fn unzigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+/// An alphabet of the standard base64 encoding.
+const BASE64: &[u8; 64] = {
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/"
+};
+
+/// Encodes the given bytes using the standard (padded) base64 encoding.
+pub(crate) fn encode_base64(data: &[u8]) -> String {
+    let mut result = String::with_capacity(data.len() * 4 / 3 + 4);
+
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, byte)| {
+            bits | u32::from(*byte) << (16 - 8 * i)
+        });
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let index = (bits >> (18 - 6 * i)) & 0x3f;
+                result.push(char::from(BASE64[index as usize]));
+            } else {
+                result.push('=');
+            }
+        }
+    }
+
+    result
+}
+
+/// Decodes the given base64 string (padded or not).
+fn decode_base64(string: &str) -> Option<Vec<u8>> {
+    let string = string.trim_end_matches('=');
+
+    let mut result = Vec::with_capacity(string.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut count = 0;
+
+    for byte in string.bytes() {
+        let value = BASE64.iter().position(|char| *char == byte)?;
+
+        bits = (bits << 6 | value as u32) & 0xffff;
+        count += 6;
+
+        if count >= 8 {
+            count -= 8;
+            result.push((bits >> count) as u8);
+        }
+    }
+
+    Some(result)
+}
+
+impl std::fmt::Display for Error {
+
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use Error::*;
+
+        match *self {
+            UnknownType(ref name) => {
+                write!(fmt, "unknown message type '{}'", name)
+            }
+            Malformed(ref message) => {
+                write!(fmt, "malformed message: {}", message)
+            }
+            Mismatch(ref message) => {
+                write!(fmt, "invalid JSON value: {}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_to_json_scalars() {
+        let args = crate::rrg::TimelineArgs {
+            root: Some(b"/foo".to_vec()),
+            max_depth: Some(3),
+            cross_device: Some(true),
+            ..Default::default()
+        };
+
+        assert_eq!(to_json("rrg.TimelineArgs", &encode(&args)).unwrap(), json!({
+            "root": "L2Zvbw==",
+            "max_depth": 3,
+            "cross_device": true,
+        }));
+    }
+
+    #[test]
+    fn test_to_json_repeated() {
+        let args = crate::rrg::TimelineArgs {
+            excludes: vec!(String::from("/proc"), String::from("/sys")),
+            ..Default::default()
+        };
+
+        assert_eq!(to_json("rrg.TimelineArgs", &encode(&args)).unwrap(), json!({
+            "excludes": ["/proc", "/sys"],
+        }));
+    }
+
+    #[test]
+    fn test_to_json_nested() {
+        let checkpoint = crate::rrg::TimelineCheckpoint {
+            dirs: vec!(crate::rrg::TimelineCheckpointDir {
+                depth: Some(1),
+                consumed: Some(42),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let data = encode(&checkpoint);
+        assert_eq!(to_json("rrg.TimelineCheckpoint", &data).unwrap(), json!({
+            "dirs": [{ "depth": 1, "consumed": 42 }],
+        }));
+    }
+
+    #[test]
+    fn test_to_json_enum() {
+        let spec = crate::PathSpec {
+            pathtype: Some(crate::path_spec::PathType::Registry as i32),
+            ..Default::default()
+        };
+
+        assert_eq!(to_json("PathSpec", &encode(&spec)).unwrap(), json!({
+            "pathtype": "REGISTRY",
+        }));
+    }
+
+    #[test]
+    fn test_to_json_unqualified_name() {
+        let args = crate::rrg::TimelineArgs {
+            threads: Some(4),
+            ..Default::default()
+        };
+
+        assert_eq!(to_json("TimelineArgs", &encode(&args)).unwrap(), json!({
+            "threads": 4,
+        }));
+    }
+
+    #[test]
+    fn test_to_json_unqualified_name_extended() {
+        let info = crate::rrg::StartupInfo {
+            boot_time: Some(42),
+            crash_count: Some(1),
+            ..Default::default()
+        };
+
+        // There is also `StartupInfo` of GRR, but the RRG one extends it.
+        assert_eq!(to_json("StartupInfo", &encode(&info)).unwrap(), json!({
+            "boot_time": 42,
+            "crash_count": 1,
+        }));
+    }
+
+    #[test]
+    fn test_to_json_unknown_type() {
+        assert!(to_json("rrg.Foo", &[]).is_err());
+    }
+
+    #[test]
+    fn test_to_json_truncated() {
+        let args = crate::rrg::TimelineArgs {
+            root: Some(b"/foo/bar/baz".to_vec()),
+            ..Default::default()
+        };
+
+        let data = encode(&args);
+        assert!(to_json("rrg.TimelineArgs", &data[..4]).is_err());
+    }
+
+    #[test]
+    fn test_from_json() {
+        let value = json!({
+            "root": "L2Zvbw",
+            "excludes": ["/proc"],
+            "max_size": "1099511627776",
+            "cross_device": false,
+        });
+
+        let data = from_json("rrg.TimelineArgs", &value).unwrap();
+        let args: crate::rrg::TimelineArgs = {
+            prost::Message::decode(&data[..]).unwrap()
+        };
+
+        assert_eq!(args.root, Some(b"/foo".to_vec()));
+        assert_eq!(args.excludes, vec!(String::from("/proc")));
+        assert_eq!(args.max_size, Some(1 << 40));
+        assert_eq!(args.cross_device, Some(false));
+    }
+
+    #[test]
+    fn test_from_json_enum() {
+        let value = json!({ "pathtype": "REGISTRY" });
+
+        let data = from_json("PathSpec", &value).unwrap();
+        let spec: crate::PathSpec = prost::Message::decode(&data[..]).unwrap();
+
+        assert_eq!(spec.pathtype(), crate::path_spec::PathType::Registry);
+    }
+
+    #[test]
+    fn test_from_json_unknown_field() {
+        let value = json!({ "foo": 42 });
+        assert!(from_json("rrg.TimelineArgs", &value).is_err());
+    }
+
+    #[test]
+    fn test_from_json_invalid_value() {
+        let value = json!({ "max_depth": -1 });
+        assert!(from_json("rrg.TimelineArgs", &value).is_err());
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let value = json!({
+            "args_sha256": encode_base64(&[0xff; 32]),
+            "root_visited": true,
+            "dirs": [
+                { "path": "L2Zvbw==", "depth": 0, "consumed": 1 },
+                { "path": "L2Zvby9iYXI=", "depth": 1, "consumed": 2 },
+            ],
+        });
+
+        let data = from_json("rrg.TimelineCheckpoint", &value).unwrap();
+        assert_eq!(to_json("rrg.TimelineCheckpoint", &data).unwrap(), value);
+    }
+
+    #[test]
+    fn test_base64_round_trip() {
+        for len in 0..16 {
+            let data = (0..len).map(|i| (i * 37) as u8).collect::<Vec<_>>();
+            assert_eq!(decode_base64(&encode_base64(&data)).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_zigzag() {
+        for value in &[0, 1, -1, 42, -42, i64::MAX, i64::MIN] {
+            assert_eq!(unzigzag(zigzag(*value)), *value);
+        }
+    }
+
+    fn encode<M: prost::Message>(message: &M) -> Vec<u8> {
+        let mut data = vec!();
+        message.encode(&mut data).unwrap();
+        data
+    }
+}
@@ -4,7 +4,10 @@
 // in the LICENSE file or at https://opensource.org/licenses/MIT.
 
 pub mod convert;
+pub mod extension;
+pub mod json;
 pub mod path;
+pub mod text;
 
 use std::path::PathBuf;
 
@@ -14,6 +17,11 @@ use rrg_macro::ack;
 
 include!(concat!(env!("OUT_DIR"), "/grr.rs"));
 
+/// Messages specific to RRG (that have no counterpart in the GRR protocol).
+pub mod rrg {
+    include!(concat!(env!("OUT_DIR"), "/rrg.rs"));
+}
+
 impl From<bool> for DataBlob {
 
     fn from(value: bool) -> DataBlob {
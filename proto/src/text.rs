@@ -0,0 +1,583 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+//! Conversion of messages in the Protocol Buffers text format.
+//!
+//! The text is parsed (guided by descriptors of the message type) into a JSON
+//! value that is then encoded by the `json` module, so message types are named
+//! the same way as there.
+//!
+//! Fields are keyed by their proto names, message values can be delimited by
+//! either braces or angle brackets and values of repeated fields can be given
+//! one by one or as a list. Strings and bytes use C-style escapes and adjacent
+//! string literals are concatenated. Extensions and expanded `Any` messages are
+//! not supported.
+
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::{DescriptorProto, FieldDescriptorProto};
+use serde_json::Value;
+
+use crate::json;
+
+/// An error type for failures that can occur when parsing messages.
+#[derive(Debug)]
+pub enum Error {
+    /// The text is not a well-formed text-format message.
+    Syntax(String),
+    /// The message does not match the message type.
+    Mismatch(String),
+    /// The parsed message could not be encoded.
+    Encode(json::Error),
+}
+
+/// Encodes the given text-format message as a message of the specified type.
+pub fn from_text(name: &str, text: &str) -> Result<Vec<u8>, Error> {
+    let message = json::message_type(name).map_err(Error::Encode)?;
+
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        pos: 0,
+    };
+    let value = parser.message(message, None)?;
+
+    json::from_json(name, &value).map_err(Error::Encode)
+}
+
+/// A single lexical token of the text format.
+#[derive(Debug, PartialEq)]
+enum Token {
+    /// A field name, an enum value name or a special literal (e.g. `true`).
+    Ident(String),
+    /// An unsigned numeric literal (integer or floating-point).
+    Number(String),
+    /// A string literal (with escape sequences already resolved).
+    Str(Vec<u8>),
+    /// A punctuation character (e.g. `:`, `{` or `-`).
+    Punct(char),
+}
+
+/// A parser of a tokenized text-format message.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+
+    /// Parses fields of a message until the given delimiter (or the end).
+    fn message(&mut self, message: &DescriptorProto, end: Option<char>)
+        -> Result<Value, Error>
+    {
+        let mut object = serde_json::Map::new();
+
+        loop {
+            match (self.tokens.get(self.pos), end) {
+                (Some(&Token::Punct(punct)), Some(end)) if punct == end => {
+                    self.pos += 1;
+                    break;
+                }
+                (None, Some(end)) => {
+                    return Err(Error::Syntax(format!("missing '{}'", end)));
+                }
+                (None, None) => break,
+                _ => (),
+            }
+
+            let name = match self.next() {
+                Some(Token::Ident(name)) => name.clone(),
+                token => {
+                    let message = format!("expected a field name, got {:?}",
+                                          token);
+                    return Err(Error::Syntax(message));
+                }
+            };
+
+            let field = message.field.iter().find(|field| field.name() == name);
+            let field = match field {
+                Some(field) => field,
+                None => {
+                    return Err(Error::Mismatch(format!(
+                        "unknown field '{}' of '{}'", name, message.name()
+                    )));
+                }
+            };
+
+            // The colon is optional only before message values.
+            if !self.eat(':') && field.r#type() != Type::Message {
+                let message = format!("missing ':' after '{}'", name);
+                return Err(Error::Syntax(message));
+            }
+
+            if field.label() == Label::Repeated {
+                let values = object.entry(name)
+                    .or_insert_with(|| Value::Array(vec!()));
+                let values = match values.as_array_mut() {
+                    Some(values) => values,
+                    None => unreachable!(),
+                };
+
+                if self.eat('[') {
+                    if !self.eat(']') {
+                        loop {
+                            values.push(self.value(field)?);
+                            if self.eat(']') {
+                                break;
+                            }
+                            self.expect(',')?;
+                        }
+                    }
+                } else {
+                    values.push(self.value(field)?);
+                }
+            } else {
+                let value = self.value(field)?;
+                if object.insert(name.clone(), value).is_some() {
+                    let message = format!("duplicate field '{}'", name);
+                    return Err(Error::Mismatch(message));
+                }
+            }
+
+            if !self.eat(';') {
+                self.eat(',');
+            }
+        }
+
+        Ok(Value::Object(object))
+    }
+
+    /// Parses a single value of the given field.
+    fn value(&mut self, field: &FieldDescriptorProto) -> Result<Value, Error> {
+        let mismatch = || {
+            Error::Mismatch(format!("invalid value of '{}'", field.name()))
+        };
+
+        let value = match field.r#type() {
+            Type::Message => {
+                let end = if self.eat('{') {
+                    '}'
+                } else if self.eat('<') {
+                    '>'
+                } else {
+                    return Err(mismatch());
+                };
+
+                let message = json::message_type(field.type_name())
+                    .map_err(Error::Encode)?;
+                self.message(message, Some(end))?
+            }
+            Type::String => {
+                let data = self.string().ok_or_else(mismatch)?;
+                let string = String::from_utf8(data).map_err(|_| mismatch())?;
+                Value::from(string)
+            }
+            Type::Bytes => {
+                let data = self.string().ok_or_else(mismatch)?;
+                Value::from(json::encode_base64(&data))
+            }
+            Type::Bool => match self.next() {
+                Some(Token::Ident(ident)) => match ident.as_str() {
+                    "true" | "True" | "t" => Value::from(true),
+                    "false" | "False" | "f" => Value::from(false),
+                    _ => return Err(mismatch()),
+                },
+                Some(Token::Number(number)) => match number.as_str() {
+                    "1" => Value::from(true),
+                    "0" => Value::from(false),
+                    _ => return Err(mismatch()),
+                },
+                _ => return Err(mismatch()),
+            },
+            Type::Enum => match self.tokens.get(self.pos) {
+                Some(Token::Ident(ident)) => {
+                    let value = Value::from(ident.clone());
+                    self.pos += 1;
+                    value
+                }
+                _ => self.integer().ok_or_else(mismatch)?,
+            },
+            Type::Double | Type::Float => {
+                self.float().ok_or_else(mismatch)?
+            }
+            Type::Group => {
+                let message = format!("'{}' is a group", field.name());
+                return Err(Error::Mismatch(message));
+            }
+            _ => self.integer().ok_or_else(mismatch)?,
+        };
+
+        Ok(value)
+    }
+
+    /// Parses a (possibly concatenated) string literal.
+    fn string(&mut self) -> Option<Vec<u8>> {
+        let mut result = None;
+
+        while let Some(Token::Str(data)) = self.tokens.get(self.pos) {
+            result.get_or_insert_with(Vec::new).extend(data);
+            self.pos += 1;
+        }
+
+        result
+    }
+
+    /// Parses a (possibly negative) integer literal.
+    fn integer(&mut self) -> Option<Value> {
+        let negative = self.eat('-');
+
+        let number = match self.next() {
+            Some(Token::Number(number)) => number,
+            _ => return None,
+        };
+
+        let hex = number.starts_with("0x") || number.starts_with("0X");
+
+        let magnitude = if hex {
+            u64::from_str_radix(&number[2..], 16).ok()?
+        } else if number.len() > 1 && number.starts_with('0') {
+            u64::from_str_radix(&number[1..], 8).ok()?
+        } else {
+            number.parse::<u64>().ok()?
+        };
+
+        if negative {
+            let value = -i128::from(magnitude);
+            if value < i128::from(i64::MIN) {
+                return None;
+            }
+            Some(Value::from(value as i64))
+        } else {
+            Some(Value::from(magnitude))
+        }
+    }
+
+    /// Parses a (possibly negative) floating-point literal.
+    fn float(&mut self) -> Option<Value> {
+        let negative = self.eat('-');
+
+        let value = match self.next() {
+            Some(Token::Number(number)) => {
+                number.trim_end_matches(&['f', 'F'][..])
+                    .parse::<f64>().ok()?
+            }
+            Some(Token::Ident(ident)) => match ident.to_lowercase().as_str() {
+                "inf" | "infinity" => f64::INFINITY,
+                "nan" => f64::NAN,
+                _ => return None,
+            },
+            _ => return None,
+        };
+
+        let value = if negative { -value } else { value };
+
+        // Infinities and NaNs have no JSON representation, so (as in the
+        // `json` module) they are passed as strings.
+        match serde_json::Number::from_f64(value) {
+            Some(number) => Some(Value::Number(number)),
+            None => Some(Value::from(value.to_string())),
+        }
+    }
+
+    /// Returns the next token and advances the parser.
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.pos);
+        if token.is_some() {
+            self.pos += 1;
+        }
+
+        token
+    }
+
+    /// Skips the next token if it is the given punctuation character.
+    fn eat(&mut self, punct: char) -> bool {
+        if self.tokens.get(self.pos) == Some(&Token::Punct(punct)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Skips the next token, failing if it is not the given character.
+    fn expect(&mut self, punct: char) -> Result<(), Error> {
+        if self.eat(punct) {
+            Ok(())
+        } else {
+            Err(Error::Syntax(format!("expected '{}'", punct)))
+        }
+    }
+}
+
+/// Splits the given text into tokens (skipping whitespace and comments).
+fn tokenize(text: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = vec!();
+    let mut chars = text.chars().peekable();
+
+    while let Some(&char) = chars.peek() {
+        match char {
+            _ if char.is_whitespace() => {
+                chars.next();
+            }
+            '#' => {
+                for char in &mut chars {
+                    if char == '\n' {
+                        break;
+                    }
+                }
+            }
+            '"' | '\'' => {
+                chars.next();
+                tokens.push(Token::Str(string(&mut chars, char)?));
+            }
+            '0'..='9' | '.' => {
+                let mut number = String::new();
+                while let Some(&char) = chars.peek() {
+                    let exponent = (char == '-' || char == '+')
+                        && !number.starts_with("0x")
+                        && number.ends_with(&['e', 'E'][..]);
+
+                    if char.is_ascii_alphanumeric() || char == '.' || exponent {
+                        number.push(char);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Number(number));
+            }
+            _ if char.is_ascii_alphabetic() || char == '_' => {
+                let mut ident = String::new();
+                while let Some(&char) = chars.peek() {
+                    if char.is_ascii_alphanumeric() || char == '_' {
+                        ident.push(char);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Ident(ident));
+            }
+            ':' | '{' | '}' | '<' | '>' | '[' | ']' | ',' | ';' | '-' => {
+                chars.next();
+                tokens.push(Token::Punct(char));
+            }
+            _ => {
+                let message = format!("unexpected character '{}'", char);
+                return Err(Error::Syntax(message));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Reads the rest of a string literal terminated by the given quote.
+fn string<I>(chars: &mut std::iter::Peekable<I>, quote: char)
+    -> Result<Vec<u8>, Error>
+where
+    I: Iterator<Item = char>,
+{
+    let mut result = vec!();
+
+    loop {
+        match chars.next() {
+            Some('\n') | None => {
+                let message = String::from("unterminated string literal");
+                return Err(Error::Syntax(message));
+            }
+            Some(char) if char == quote => return Ok(result),
+            Some('\\') => (),
+            Some(char) => {
+                let mut buf = [0; 4];
+                result.extend(char.encode_utf8(&mut buf).as_bytes());
+                continue;
+            }
+        }
+
+        let escape = chars.next().unwrap_or('\n');
+        let byte = match escape {
+            'n' => b'\n',
+            't' => b'\t',
+            'r' => b'\r',
+            'a' => 0x07,
+            'b' => 0x08,
+            'f' => 0x0c,
+            'v' => 0x0b,
+            '\\' | '\'' | '"' | '?' => escape as u8,
+            '0'..='7' => {
+                let mut value = escape.to_digit(8).unwrap_or(0);
+                for _ in 0..2 {
+                    match chars.peek().and_then(|char| char.to_digit(8)) {
+                        Some(digit) => value = value * 8 + digit,
+                        None => break,
+                    }
+                    chars.next();
+                }
+
+                if value > 0xff {
+                    let message = format!("invalid octal escape '{:o}'", value);
+                    return Err(Error::Syntax(message));
+                }
+                value as u8
+            }
+            'x' | 'X' => {
+                let mut value = 0;
+                let mut len = 0;
+                while len < 2 {
+                    match chars.peek().and_then(|char| char.to_digit(16)) {
+                        Some(digit) => value = value * 16 + digit,
+                        None => break,
+                    }
+                    chars.next();
+                    len += 1;
+                }
+
+                if len == 0 {
+                    let message = String::from("invalid hex escape");
+                    return Err(Error::Syntax(message));
+                }
+                value as u8
+            }
+            _ => {
+                let message = format!("invalid escape '\\{}'", escape);
+                return Err(Error::Syntax(message));
+            }
+        };
+
+        result.push(byte);
+    }
+}
+
+impl std::fmt::Display for Error {
+
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use Error::*;
+
+        match *self {
+            Syntax(ref message) => {
+                write!(fmt, "malformed text format: {}", message)
+            }
+            Mismatch(ref message) => {
+                write!(fmt, "invalid text format value: {}", message)
+            }
+            Encode(ref error) => {
+                write!(fmt, "failed to encode the message: {}", error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            Error::Encode(ref error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_from_text_scalars() {
+        let text = r#"
+            root: "/foo"  # A comment.
+            max_depth: 3
+            cross_device: true
+            max_size: 0x10000000000
+        "#;
+
+        let args: crate::rrg::TimelineArgs = decode("rrg.TimelineArgs", text);
+        assert_eq!(args.root, Some(b"/foo".to_vec()));
+        assert_eq!(args.max_depth, Some(3));
+        assert_eq!(args.cross_device, Some(true));
+        assert_eq!(args.max_size, Some(1 << 40));
+    }
+
+    #[test]
+    fn test_from_text_repeated() {
+        let text = r#"excludes: "/proc" excludes: ["/sys", '/dev']"#;
+
+        let args: crate::rrg::TimelineArgs = decode("rrg.TimelineArgs", text);
+        assert_eq!(args.excludes, vec!(
+            String::from("/proc"),
+            String::from("/sys"),
+            String::from("/dev"),
+        ));
+    }
+
+    #[test]
+    fn test_from_text_nested() {
+        let text = r#"
+            root_visited: true
+            dirs { path: "/foo" depth: 0 consumed: 1 }
+            dirs: < path: "/foo/bar", depth: 1, consumed: 2 >
+        "#;
+
+        let checkpoint: crate::rrg::TimelineCheckpoint = {
+            decode("rrg.TimelineCheckpoint", text)
+        };
+        assert_eq!(checkpoint.root_visited, Some(true));
+        assert_eq!(checkpoint.dirs.len(), 2);
+        assert_eq!(checkpoint.dirs[0].path, Some(b"/foo".to_vec()));
+        assert_eq!(checkpoint.dirs[1].path, Some(b"/foo/bar".to_vec()));
+        assert_eq!(checkpoint.dirs[1].depth, Some(1));
+        assert_eq!(checkpoint.dirs[1].consumed, Some(2));
+    }
+
+    #[test]
+    fn test_from_text_escapes() {
+        let text = r#"root: "\x00\377\n\"" 'foo'"#;
+
+        let args: crate::rrg::TimelineArgs = decode("rrg.TimelineArgs", text);
+        assert_eq!(args.root, Some(b"\x00\xff\n\"foo".to_vec()));
+    }
+
+    #[test]
+    fn test_from_text_enum() {
+        let spec: crate::PathSpec = decode("PathSpec", "pathtype: REGISTRY");
+        assert_eq!(spec.pathtype(), crate::path_spec::PathType::Registry);
+    }
+
+    #[test]
+    fn test_from_text_empty() {
+        let args: crate::rrg::TimelineArgs = decode("rrg.TimelineArgs", "");
+        assert_eq!(args, Default::default());
+    }
+
+    #[test]
+    fn test_from_text_unknown_field() {
+        assert!(from_text("rrg.TimelineArgs", "foo: 42").is_err());
+    }
+
+    #[test]
+    fn test_from_text_duplicate_field() {
+        let text = "max_depth: 1 max_depth: 2";
+        assert!(from_text("rrg.TimelineArgs", text).is_err());
+    }
+
+    #[test]
+    fn test_from_text_invalid_value() {
+        assert!(from_text("rrg.TimelineArgs", "max_depth: -1").is_err());
+        assert!(from_text("rrg.TimelineArgs", "cross_device: 42").is_err());
+        assert!(from_text("rrg.TimelineArgs", "root: 42").is_err());
+    }
+
+    #[test]
+    fn test_from_text_malformed() {
+        assert!(from_text("rrg.TimelineArgs", "max_depth 1").is_err());
+        assert!(from_text("rrg.TimelineArgs", "root: \"foo").is_err());
+        let text = "dirs { depth: 1";
+        assert!(from_text("rrg.TimelineCheckpoint", text).is_err());
+    }
+
+    fn decode<M: prost::Message + Default>(name: &str, text: &str) -> M {
+        let data = from_text(name, text).unwrap();
+        M::decode(&data[..]).unwrap()
+    }
+}
@@ -0,0 +1,521 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+//! Evaluation of conditions of the file finder action.
+
+use std::fs::File;
+use std::io::{Read as _, Seek as _, SeekFrom};
+use std::time::SystemTime;
+
+use log::{debug, warn};
+
+use crate::fs::Entry;
+use super::contents::{self, Literal, Match, Pattern};
+use super::request::{
+    Condition, ContentsLiteralMatchConditionOptions,
+    ContentsRegexMatchConditionOptions,
+};
+
+/// Checks whether the given entry satisfies all the specified conditions.
+///
+/// Conditions on file metadata are cheap to evaluate, so they are checked
+/// first and file contents are read only if all of them are met. Reasons why
+/// the entry has been rejected are logged on the debug level.
+///
+/// If some of the conditions is not met, `None` is returned. Otherwise, the
+/// result contains all the fragments of the file matched by the contents
+/// conditions (which is empty if there are no such conditions).
+pub fn check(entry: &Entry, conditions: &[Condition]) -> Option<Vec<Match>> {
+    for condition in conditions.iter().filter(|cond| !is_contents(cond)) {
+        if let Err(reason) = check_metadata(entry, condition) {
+            debug!("rejected '{}': {}", entry.path.display(), reason);
+            return None;
+        }
+    }
+
+    let mut matches = vec!();
+
+    for condition in conditions.iter().filter(|cond| is_contents(cond)) {
+        let hits = match condition {
+            Condition::ContentsRegexMatch(options) => {
+                regex_matches(entry, options)
+            }
+            Condition::ContentsLiteralMatch(options) => {
+                literal_matches(entry, options)
+            }
+            _ => vec!(),
+        };
+
+        if hits.is_empty() {
+            debug!("rejected '{}': no contents match", entry.path.display());
+            return None;
+        }
+
+        matches.extend(hits);
+    }
+
+    Some(matches)
+}
+
+/// Checks whether the given condition concerns contents of the file.
+fn is_contents(condition: &Condition) -> bool {
+    matches! {
+        condition,
+        Condition::ContentsRegexMatch(_) | Condition::ContentsLiteralMatch(_)
+    }
+}
+
+/// Checks whether the entry satisfies the given metadata condition.
+///
+/// In case the condition is not met, the error contains a human-readable
+/// explanation why. Contents conditions are always considered to be met.
+fn check_metadata(entry: &Entry, condition: &Condition) -> Result<(), String> {
+    use Condition::*;
+
+    match *condition {
+        MinModificationTime(min) => {
+            check_min_time("modification", modification_time(entry), min)
+        }
+        MaxModificationTime(max) => {
+            check_max_time("modification", modification_time(entry), max)
+        }
+        MinAccessTime(min) => {
+            check_min_time("access", access_time(entry), min)
+        }
+        MaxAccessTime(max) => {
+            check_max_time("access", access_time(entry), max)
+        }
+        MinInodeChangeTime(min) => {
+            check_min_time("inode change", inode_change_time(entry), min)
+        }
+        MaxInodeChangeTime(max) => {
+            check_max_time("inode change", inode_change_time(entry), max)
+        }
+        MinSize(min) => match entry.metadata.len() {
+            size if size >= min => Ok(()),
+            size => Err(format!("size {} is smaller than {}", size, min)),
+        },
+        MaxSize(max) => match entry.metadata.len() {
+            size if size <= max => Ok(()),
+            size => Err(format!("size {} is bigger than {}", size, max)),
+        },
+        ExtFlagsLinuxBitsSet(bits) => match flags_linux(entry) {
+            Some(flags) if flags & bits == bits => Ok(()),
+            Some(flags) => {
+                Err(format!("flags {:#x} do not have {:#x} set", flags, bits))
+            }
+            None => Err(String::from("flags are not available")),
+        },
+        ExtFlagsLinuxBitsUnset(bits) => match flags_linux(entry) {
+            Some(flags) if flags & bits == 0 => Ok(()),
+            Some(flags) => {
+                Err(format!("flags {:#x} have some of {:#x} set", flags, bits))
+            }
+            None => Err(String::from("flags are not available")),
+        },
+        // TODO: Add support for collecting file flags on macOS. Until then,
+        // we assume that no flags are set (this is what GRR does on other
+        // platforms as well).
+        ExtFlagsOsxBitsSet(bits) if bits != 0 => {
+            Err(format!("macOS flags {:#x} are not set", bits))
+        }
+        ExtFlagsOsxBitsSet(_) | ExtFlagsOsxBitsUnset(_) => Ok(()),
+        ContentsRegexMatch(_) | ContentsLiteralMatch(_) => Ok(()),
+    }
+}
+
+/// Checks whether the time is not earlier than the specified minimum.
+fn check_min_time(
+    kind: &str,
+    time: Option<SystemTime>,
+    min: SystemTime,
+) -> Result<(), String> {
+    match time {
+        Some(time) if time >= min => Ok(()),
+        Some(time) => Err(format! {
+            "{} time {} is earlier than {}", kind, utc(time), utc(min)
+        }),
+        None => Err(format!("{} time is not available", kind)),
+    }
+}
+
+/// Checks whether the time is not later than the specified maximum.
+fn check_max_time(
+    kind: &str,
+    time: Option<SystemTime>,
+    max: SystemTime,
+) -> Result<(), String> {
+    match time {
+        Some(time) if time <= max => Ok(()),
+        Some(time) => Err(format! {
+            "{} time {} is later than {}", kind, utc(time), utc(max)
+        }),
+        None => Err(format!("{} time is not available", kind)),
+    }
+}
+
+/// Converts the given time to a (displayable) UTC date.
+fn utc(time: SystemTime) -> chrono::DateTime<chrono::Utc> {
+    time.into()
+}
+
+/// Returns the modification time of the given entry (if available).
+fn modification_time(entry: &Entry) -> Option<SystemTime> {
+    entry.metadata.modified().ok()
+}
+
+/// Returns the access time of the given entry (if available).
+fn access_time(entry: &Entry) -> Option<SystemTime> {
+    entry.metadata.accessed().ok()
+}
+
+/// Returns the inode change time of the given entry (if available).
+#[cfg(target_family = "unix")]
+fn inode_change_time(entry: &Entry) -> Option<SystemTime> {
+    use std::convert::TryFrom as _;
+    use std::os::unix::fs::MetadataExt as _;
+
+    let secs = u64::try_from(entry.metadata.ctime()).ok()?;
+    let nanos = u32::try_from(entry.metadata.ctime_nsec()).ok()?;
+
+    let duration = std::time::Duration::new(secs, nanos);
+    std::time::UNIX_EPOCH.checked_add(duration)
+}
+
+/// Returns the inode change time of the given entry (if available).
+#[cfg(not(target_family = "unix"))]
+fn inode_change_time(_: &Entry) -> Option<SystemTime> {
+    None
+}
+
+/// Returns Linux-specific extended flags of the given entry (if available).
+#[cfg(target_os = "linux")]
+fn flags_linux(entry: &Entry) -> Option<u32> {
+    match crate::fs::linux::flags(&entry.path) {
+        Ok(flags) => Some(flags),
+        Err(error) => {
+            let path = entry.path.display();
+            warn!("failed to collect flags for '{}': {}", path, error);
+            None
+        }
+    }
+}
+
+/// Returns Linux-specific extended flags of the given entry (if available).
+#[cfg(not(target_os = "linux"))]
+fn flags_linux(_: &Entry) -> Option<u32> {
+    None
+}
+
+/// Finds all fragments of the entry contents that match the given regex.
+fn regex_matches(
+    entry: &Entry,
+    options: &ContentsRegexMatchConditionOptions,
+) -> Vec<Match> {
+    let opts = contents::Opts {
+        mode: options.mode,
+        bytes_before: options.bytes_before as usize,
+        bytes_after: options.bytes_after as usize,
+    };
+
+    scan(entry, options.start_offset, options.length, &options.regex, opts)
+}
+
+/// Finds all fragments of the entry contents that match the given literal.
+fn literal_matches(
+    entry: &Entry,
+    options: &ContentsLiteralMatchConditionOptions,
+) -> Vec<Match> {
+    if options.literal.is_empty() {
+        warn!("empty literal in the contents literal match condition");
+        return vec!();
+    }
+
+    let opts = contents::Opts {
+        mode: options.mode,
+        bytes_before: options.bytes_before as usize,
+        bytes_after: options.bytes_after as usize,
+    };
+
+    let literal = xor(&options.literal, options.xor_in_key);
+    let pattern = Literal(&literal);
+
+    let mut matches = scan(entry, options.start_offset, options.length,
+                           &pattern, opts);
+
+    for hit in &mut matches {
+        hit.data = xor(&hit.data, options.xor_out_key);
+    }
+
+    matches
+}
+
+/// Searches a fragment of the entry contents for occurrences of the pattern.
+///
+/// Errors are logged and an empty list of matches is returned in such cases.
+/// Only contents of regular files are searched: reading from e.g. a FIFO could
+/// block forever and reading from a device like `/dev/zero` might never end.
+fn scan<P>(
+    entry: &Entry,
+    offset: u64,
+    length: u64,
+    pattern: &P,
+    opts: contents::Opts,
+) -> Vec<Match>
+where
+    P: Pattern + ?Sized,
+{
+    if !entry.metadata.is_file() {
+        debug!("not searching '{}': not a regular file", entry.path.display());
+        return vec!();
+    }
+
+    let result = File::open(&entry.path).and_then(|mut file| {
+        file.seek(SeekFrom::Start(offset))?;
+        contents::scan(file.take(length), pattern, opts)
+    });
+
+    match result {
+        Ok(mut matches) => {
+            for hit in &mut matches {
+                hit.offset += offset;
+            }
+            matches
+        }
+        Err(error) => {
+            let path = entry.path.display();
+            warn!("failed to search contents of '{}': {}", path, error);
+            vec!()
+        }
+    }
+}
+
+/// XORs every byte of the given data with the lowest byte of the key.
+fn xor(data: &[u8], key: u32) -> Vec<u8> {
+    data.iter().map(|byte| byte ^ key as u8).collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use super::super::request::MatchMode;
+
+    #[test]
+    fn test_check_no_conditions() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::write(tempdir.path().join("foo"), b"foobar").unwrap();
+
+        let matches = check(&entry(tempdir.path().join("foo")), &[]);
+        assert_eq!(matches.unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_check_size() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::write(tempdir.path().join("foo"), b"foobar").unwrap();
+
+        let entry = entry(tempdir.path().join("foo"));
+        assert!(check(&entry, &[Condition::MinSize(6)]).is_some());
+        assert!(check(&entry, &[Condition::MinSize(7)]).is_none());
+        assert!(check(&entry, &[Condition::MaxSize(6)]).is_some());
+        assert!(check(&entry, &[Condition::MaxSize(5)]).is_none());
+    }
+
+    #[test]
+    fn test_check_modification_time() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::write(tempdir.path().join("foo"), b"foobar").unwrap();
+
+        let entry = entry(tempdir.path().join("foo"));
+        let past = std::time::UNIX_EPOCH;
+        let future = SystemTime::now() + std::time::Duration::from_secs(3600);
+
+        let conditions = [
+            Condition::MinModificationTime(past),
+            Condition::MaxModificationTime(future),
+        ];
+        assert!(check(&entry, &conditions).is_some());
+
+        let conditions = [Condition::MinModificationTime(future)];
+        assert!(check(&entry, &conditions).is_none());
+    }
+
+    #[test]
+    fn test_check_access_time() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::write(tempdir.path().join("foo"), b"foobar").unwrap();
+
+        let entry = entry(tempdir.path().join("foo"));
+        let future = SystemTime::now() + std::time::Duration::from_secs(3600);
+
+        let conditions = [Condition::MaxAccessTime(future)];
+        assert!(check(&entry, &conditions).is_some());
+
+        let conditions = [Condition::MinAccessTime(future)];
+        assert!(check(&entry, &conditions).is_none());
+    }
+
+    #[test]
+    fn test_check_osx_flags() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::write(tempdir.path().join("foo"), b"foobar").unwrap();
+
+        let entry = entry(tempdir.path().join("foo"));
+        assert!(check(&entry, &[Condition::ExtFlagsOsxBitsSet(0)]).is_some());
+        assert!(check(&entry, &[Condition::ExtFlagsOsxBitsSet(1)]).is_none());
+        assert!(check(&entry, &[Condition::ExtFlagsOsxBitsUnset(1)]).is_some());
+    }
+
+    #[test]
+    fn test_check_metadata_reason() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::write(tempdir.path().join("foo"), b"foobar").unwrap();
+
+        let entry = entry(tempdir.path().join("foo"));
+
+        let reason = check_metadata(&entry, &Condition::MinSize(7));
+        assert_eq!(reason.unwrap_err(), "size 6 is smaller than 7");
+
+        let reason = check_metadata(&entry, &Condition::MaxSize(5));
+        assert_eq!(reason.unwrap_err(), "size 6 is bigger than 5");
+    }
+
+    #[test]
+    fn test_check_regex_match() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::write(tempdir.path().join("foo"), b"foo bar baz").unwrap();
+
+        let condition = Condition::ContentsRegexMatch(
+            ContentsRegexMatchConditionOptions {
+                regex: regex::bytes::Regex::new("ba.").unwrap(),
+                mode: MatchMode::AllHits,
+                bytes_before: 1,
+                bytes_after: 0,
+                start_offset: 0,
+                length: 1024,
+            }
+        );
+
+        let entry = entry(tempdir.path().join("foo"));
+        let matches = check(&entry, &[condition]).unwrap();
+
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].offset, 3);
+        assert_eq!(matches[0].data, b" bar");
+        assert_eq!(matches[1].offset, 7);
+        assert_eq!(matches[1].data, b" baz");
+    }
+
+    #[test]
+    fn test_check_regex_no_match() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::write(tempdir.path().join("foo"), b"foo bar baz").unwrap();
+
+        let condition = Condition::ContentsRegexMatch(
+            ContentsRegexMatchConditionOptions {
+                regex: regex::bytes::Regex::new("quux").unwrap(),
+                mode: MatchMode::AllHits,
+                bytes_before: 0,
+                bytes_after: 0,
+                start_offset: 0,
+                length: 1024,
+            }
+        );
+
+        let entry = entry(tempdir.path().join("foo"));
+        assert!(check(&entry, &[condition]).is_none());
+    }
+
+    #[test]
+    fn test_check_literal_match_first_hit() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::write(tempdir.path().join("foo"), b"foo bar foo").unwrap();
+
+        let condition = Condition::ContentsLiteralMatch(
+            ContentsLiteralMatchConditionOptions {
+                literal: b"foo".to_vec(),
+                mode: MatchMode::FirstHit,
+                start_offset: 0,
+                length: 1024,
+                bytes_before: 0,
+                bytes_after: 2,
+                xor_in_key: 0,
+                xor_out_key: 0,
+            }
+        );
+
+        let entry = entry(tempdir.path().join("foo"));
+        let matches = check(&entry, &[condition]).unwrap();
+
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].offset, 0);
+        assert_eq!(matches[0].data, b"foo b");
+    }
+
+    #[test]
+    fn test_check_literal_match_start_offset() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::write(tempdir.path().join("foo"), b"foo bar foo").unwrap();
+
+        let condition = Condition::ContentsLiteralMatch(
+            ContentsLiteralMatchConditionOptions {
+                literal: b"foo".to_vec(),
+                mode: MatchMode::AllHits,
+                start_offset: 1,
+                length: 1024,
+                bytes_before: 1,
+                bytes_after: 0,
+                xor_in_key: 0,
+                xor_out_key: 0,
+            }
+        );
+
+        let entry = entry(tempdir.path().join("foo"));
+        let matches = check(&entry, &[condition]).unwrap();
+
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].offset, 7);
+        assert_eq!(matches[0].data, b" foo");
+    }
+
+    // FIFOs are supported only on Unix-like systems.
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_check_literal_match_fifo() {
+        use std::os::unix::ffi::OsStrExt as _;
+
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("fifo");
+
+        let path_c = std::ffi::CString::new(path.as_os_str().as_bytes())
+            .unwrap();
+        assert_eq!(unsafe { libc::mkfifo(path_c.as_ptr(), 0o600) }, 0);
+
+        let condition = Condition::ContentsLiteralMatch(
+            ContentsLiteralMatchConditionOptions {
+                literal: b"foo".to_vec(),
+                mode: MatchMode::FirstHit,
+                start_offset: 0,
+                length: 1024,
+                bytes_before: 0,
+                bytes_after: 0,
+                xor_in_key: 0,
+                xor_out_key: 0,
+            }
+        );
+
+        // Nobody writes to the FIFO, so opening it would block forever.
+        let entry = entry(path);
+        assert!(check(&entry, &[condition]).is_none());
+    }
+
+    /// Constructs a filesystem entry for the given path.
+    fn entry(path: std::path::PathBuf) -> Entry {
+        let metadata = std::fs::symlink_metadata(&path).unwrap();
+
+        Entry::new(path, metadata)
+    }
+}
@@ -0,0 +1,325 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+//! Streaming search of file contents for the file finder action.
+//!
+//! Files are read in bounded buffers, so that arbitrarily big files can be
+//! searched in constant memory. Consecutive buffers overlap, so matches that
+//! cross buffer boundaries are not missed.
+
+use std::io::Read;
+
+use regex::bytes::Regex;
+
+use super::request::MatchMode;
+
+/// A number of bytes read from the file at once.
+const BUFFER_SIZE: usize = 1024 * 1024;
+
+/// A maximum length of regex matches that are guaranteed to be found.
+///
+/// Regular expressions can match fragments of arbitrary length, but scanning
+/// the file in bounded buffers requires some limit. Matches longer than this
+/// that happen to cross buffer boundaries might be truncated or missed.
+const MAX_REGEX_MATCH_LEN: usize = 64 * 1024;
+
+/// A fragment of the file contents matched by one of the contents conditions.
+#[derive(Debug)]
+pub struct Match {
+    /// An offset of the fragment within the file.
+    pub offset: u64,
+    /// Matched data (including the requested context).
+    pub data: Vec<u8>,
+}
+
+/// A pattern that the file contents can be searched for.
+pub trait Pattern {
+
+    /// Finds the leftmost occurrence of the pattern starting at `pos`.
+    ///
+    /// The result is a pair of the start (inclusive) and the end (exclusive)
+    /// positions of the match within `data`.
+    fn find_from(&self, data: &[u8], pos: usize) -> Option<(usize, usize)>;
+
+    /// Returns the maximum length of the matches of the pattern.
+    fn max_len(&self) -> usize;
+}
+
+impl Pattern for Regex {
+
+    fn find_from(&self, data: &[u8], pos: usize) -> Option<(usize, usize)> {
+        // We use `find_at` rather than slicing the data so that anchors and
+        // word boundaries look at the bytes preceding `pos`.
+        Regex::find_at(self, data, pos).map(|hit| (hit.start(), hit.end()))
+    }
+
+    fn max_len(&self) -> usize {
+        MAX_REGEX_MATCH_LEN
+    }
+}
+
+/// A pattern that matches a sequence of bytes exactly.
+pub struct Literal<'a>(pub &'a [u8]);
+
+impl<'a> Pattern for Literal<'a> {
+
+    fn find_from(&self, data: &[u8], pos: usize) -> Option<(usize, usize)> {
+        if self.0.is_empty() {
+            return None;
+        }
+
+        let start = data[pos..].windows(self.0.len())
+            .position(|window| window == self.0)?;
+
+        Some((pos + start, pos + start + self.0.len()))
+    }
+
+    fn max_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+/// Options of the contents search.
+#[derive(Clone, Copy, Debug)]
+pub struct Opts {
+    /// Whether to report all matches or only the first one.
+    pub mode: MatchMode,
+    /// Number of bytes preceding the match to include in the results.
+    pub bytes_before: usize,
+    /// Number of bytes following the match to include in the results.
+    pub bytes_after: usize,
+}
+
+/// Searches contents of the given reader for occurrences of the pattern.
+///
+/// Offsets of returned matches are relative to the initial position of the
+/// reader.
+///
+/// # Errors
+///
+/// This function will return an error if reading from the reader fails.
+pub fn scan<R, P>(reader: R, pattern: &P, opts: Opts)
+    -> std::io::Result<Vec<Match>>
+where
+    R: Read,
+    P: Pattern + ?Sized,
+{
+    scan_buffered(reader, pattern, opts, BUFFER_SIZE)
+}
+
+/// Searches contents of the reader using buffers of the specified size.
+fn scan_buffered<R, P>(
+    mut reader: R,
+    pattern: &P,
+    opts: Opts,
+    buffer_size: usize,
+) -> std::io::Result<Vec<Match>>
+where
+    R: Read,
+    P: Pattern + ?Sized,
+{
+    let mut matches = vec!();
+
+    let mut buf = vec!();
+    // An offset of the first byte of the buffer within the scanned data.
+    let mut buf_offset = 0;
+    // A position within the buffer from which the search should continue.
+    let mut pos = 0;
+
+    loop {
+        let len = reader.by_ref()
+            .take(buffer_size as u64)
+            .read_to_end(&mut buf)?;
+        let eof = len < buffer_size;
+
+        // Matches starting after the limit might not be complete yet, so we
+        // defer them until we read more data (unless there is no more data).
+        let limit = if eof {
+            buf.len()
+        } else {
+            buf.len().saturating_sub(pattern.max_len())
+        };
+
+        let mut resume = limit;
+        while pos <= buf.len() {
+            let (start, end) = match pattern.find_from(&buf, pos) {
+                Some(bounds) => bounds,
+                None => break,
+            };
+
+            if !eof && (start > limit || end + opts.bytes_after > buf.len()) {
+                resume = std::cmp::min(start, limit);
+                break;
+            }
+
+            let context_start = start.saturating_sub(opts.bytes_before);
+            let context_end = std::cmp::min(end + opts.bytes_after, buf.len());
+
+            matches.push(Match {
+                offset: buf_offset + context_start as u64,
+                data: buf[context_start..context_end].to_vec(),
+            });
+
+            if opts.mode == MatchMode::FirstHit {
+                return Ok(matches);
+            }
+
+            // Empty matches would make us stuck at the same position forever,
+            // so in such cases we have to move forward explicitly.
+            pos = if start < end { end } else { end + 1 };
+        }
+
+        if eof {
+            return Ok(matches);
+        }
+
+        pos = std::cmp::max(pos, resume);
+
+        // We keep the context of the next match and at least one byte before
+        // it, so that regex anchors and word boundaries work as expected.
+        let keep = pos.saturating_sub(std::cmp::max(opts.bytes_before, 1));
+        buf.drain(..keep);
+        buf_offset += keep as u64;
+        pos -= keep;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const ALL_HITS: Opts = Opts {
+        mode: MatchMode::AllHits,
+        bytes_before: 0,
+        bytes_after: 0,
+    };
+
+    #[test]
+    fn test_scan_literal_all_hits() {
+        let data = b"foo bar foo baz foo";
+
+        let matches = scan(&data[..], &Literal(b"foo"), ALL_HITS).unwrap();
+
+        assert_eq!(matches.len(), 3);
+        assert_eq!(matches[0].offset, 0);
+        assert_eq!(matches[1].offset, 8);
+        assert_eq!(matches[2].offset, 16);
+        assert!(matches.iter().all(|hit| hit.data == b"foo"));
+    }
+
+    #[test]
+    fn test_scan_literal_first_hit() {
+        let data = b"foo bar foo baz foo";
+        let opts = Opts {
+            mode: MatchMode::FirstHit,
+            ..ALL_HITS
+        };
+
+        let matches = scan(&data[..], &Literal(b"foo"), opts).unwrap();
+
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].offset, 0);
+    }
+
+    #[test]
+    fn test_scan_literal_no_hits() {
+        let data = b"foo bar baz";
+
+        let matches = scan(&data[..], &Literal(b"quux"), ALL_HITS).unwrap();
+        assert!(matches.is_empty());
+    }
+
+    #[test]
+    fn test_scan_literal_buffer_boundary() {
+        let data = b"xxxxxxxfooxxxxxxx";
+
+        for buffer_size in 1..data.len() {
+            let matches = scan_buffered(&data[..], &Literal(b"foo"), ALL_HITS,
+                                        buffer_size).unwrap();
+
+            assert_eq!(matches.len(), 1, "buffer size: {}", buffer_size);
+            assert_eq!(matches[0].offset, 7);
+            assert_eq!(matches[0].data, b"foo");
+        }
+    }
+
+    #[test]
+    fn test_scan_regex_buffer_boundary() {
+        let data = b"lorem 192.168.0.1 ipsum 10.0.0.1 dolor";
+        let regex = Regex::new(r"\d+\.\d+\.\d+\.\d+").unwrap();
+
+        for buffer_size in 1..data.len() {
+            let matches = scan_buffered(&data[..], &regex, ALL_HITS,
+                                        buffer_size).unwrap();
+
+            assert_eq!(matches.len(), 2, "buffer size: {}", buffer_size);
+            assert_eq!(matches[0].offset, 6);
+            assert_eq!(matches[0].data, b"192.168.0.1");
+            assert_eq!(matches[1].offset, 24);
+            assert_eq!(matches[1].data, b"10.0.0.1");
+        }
+    }
+
+    #[test]
+    fn test_scan_context_buffer_boundary() {
+        let data = b"abcdefFOOghijkl";
+        let opts = Opts {
+            mode: MatchMode::AllHits,
+            bytes_before: 3,
+            bytes_after: 4,
+        };
+
+        for buffer_size in 1..data.len() {
+            let matches = scan_buffered(&data[..], &Literal(b"FOO"), opts,
+                                        buffer_size).unwrap();
+
+            assert_eq!(matches.len(), 1, "buffer size: {}", buffer_size);
+            assert_eq!(matches[0].offset, 3);
+            assert_eq!(matches[0].data, b"defFOOghij");
+        }
+    }
+
+    #[test]
+    fn test_scan_context_clamped() {
+        let data = b"FOObarFOO";
+        let opts = Opts {
+            mode: MatchMode::AllHits,
+            bytes_before: 5,
+            bytes_after: 5,
+        };
+
+        let matches = scan(&data[..], &Literal(b"FOO"), opts).unwrap();
+
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].offset, 0);
+        assert_eq!(matches[0].data, b"FOObarFOO");
+        assert_eq!(matches[1].offset, 1);
+        assert_eq!(matches[1].data, b"OObarFOO");
+    }
+
+    #[test]
+    fn test_scan_regex_word_boundary() {
+        let data = b"foobar bar";
+        let regex = Regex::new(r"\bbar").unwrap();
+
+        for buffer_size in 1..data.len() {
+            let matches = scan_buffered(&data[..], &regex, ALL_HITS,
+                                        buffer_size).unwrap();
+
+            assert_eq!(matches.len(), 1, "buffer size: {}", buffer_size);
+            assert_eq!(matches[0].offset, 7);
+        }
+    }
+
+    #[test]
+    fn test_scan_regex_empty_matches() {
+        let data = b"abc";
+        let regex = Regex::new(r"x*").unwrap();
+
+        let matches = scan(&data[..], &regex, ALL_HITS).unwrap();
+        assert_eq!(matches.len(), 4);
+    }
+}
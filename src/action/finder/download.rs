@@ -0,0 +1,352 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+//! Chunked file download for the file finder action.
+//!
+//! Downloaded files are split into chunks of fixed size that are sent to the
+//! transfer store as separate blobs. The server can then reassemble the file
+//! using a list of chunk digests (a _blob image_). Blobs with the same content
+//! are sent only once (as long as they are among the recently sent ones).
+//!
+//! Apart from the file finder, the same mechanism is used by other actions that
+//! need to send big blobs of data (e.g. dumps of process memory).
+
+use std::collections::{HashSet, VecDeque};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use log::warn;
+
+use crate::action::timeline::{Chunk, ChunkId};
+use crate::session::{self, Session};
+
+/// A list of chunks that a downloaded file consists of.
+#[derive(Debug)]
+pub struct Image {
+    /// A number of bytes per chunk that the file was divided into.
+    pub chunk_size: u64,
+    /// Descriptors of all the chunks of the file (in order).
+    pub chunks: Vec<ChunkRef>,
+}
+
+/// A reference to a single chunk of a downloaded file.
+#[derive(Debug)]
+pub struct ChunkRef {
+    /// An offset of the chunk within the file.
+    pub offset: u64,
+    /// A length of the chunk (the last one can be shorter than others).
+    pub length: u64,
+    /// An identifier of the blob with chunk data.
+    pub id: ChunkId,
+}
+
+/// An error type for failures that can occur when transferring data.
+#[derive(Debug)]
+pub enum Error {
+    /// A failure occurred when reading the data to transfer.
+    Read(std::io::Error),
+    /// A failure occurred when sending a chunk to the transfer store.
+    Send(session::Error),
+}
+
+/// A maximum number of identifiers of sent blobs remembered for deduplication.
+///
+/// Remembering all the blobs would make memory usage proportional to the total
+/// size of downloaded data. With this limit, the identifiers take at most a few
+/// megabytes and duplicated chunks are still caught if they are close enough.
+const MAX_SENT_CHUNKS: usize = 64 * 1024;
+
+/// A maximum size of a chunk that data can be divided into (in bytes).
+///
+/// Every chunk is read into memory and sent as a single message, so requests
+/// asking for bigger chunks should be rejected.
+pub const MAX_CHUNK_SIZE: u64 = 16 * 1024 * 1024;
+
+/// A state of downloads performed within a single action invocation.
+pub struct Transfer {
+    /// Identifiers of blobs that have recently been sent to the transfer store.
+    sent: HashSet<ChunkId>,
+    /// Identifiers of recently sent blobs, in the order they were sent in.
+    sent_order: VecDeque<ChunkId>,
+    /// A maximum number of identifiers of sent blobs to remember.
+    capacity: usize,
+}
+
+impl Default for Transfer {
+
+    fn default() -> Transfer {
+        Transfer::with_capacity(MAX_SENT_CHUNKS)
+    }
+}
+
+impl Transfer {
+
+    /// Creates a transfer that remembers at most `capacity` sent blobs.
+    fn with_capacity(capacity: usize) -> Transfer {
+        Transfer {
+            sent: HashSet::new(),
+            sent_order: VecDeque::new(),
+            capacity: capacity,
+        }
+    }
+
+    /// Sends at most `max_size` first bytes of a file to the transfer store.
+    ///
+    /// Failures to read the file are logged and result in `None` being
+    /// returned. Note that some chunks of the file might have been sent before
+    /// the failure occurred.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if sending some of the chunks to the
+    /// transfer store fails.
+    pub fn file<S>(
+        &mut self,
+        session: &mut S,
+        path: &Path,
+        max_size: u64,
+        chunk_size: u64,
+    ) -> session::Result<Option<Image>>
+    where
+        S: Session,
+    {
+        if chunk_size == 0 {
+            warn!("cannot download '{}' in empty chunks", path.display());
+            return Ok(None);
+        }
+
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(error) => {
+                warn!("failed to open '{}': {}", path.display(), error);
+                return Ok(None);
+            }
+        };
+
+        match self.reader(session, file.take(max_size), chunk_size) {
+            Ok(image) => Ok(Some(image)),
+            Err(Error::Read(error)) => {
+                warn!("failed to read '{}': {}", path.display(), error);
+                Ok(None)
+            }
+            Err(Error::Send(error)) => Err(error),
+        }
+    }
+
+    /// Sends all the data yielded by the reader to the transfer store.
+    ///
+    /// Offsets of the chunks in the returned image are relative to the initial
+    /// position of the reader.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if reading from the reader fails or
+    /// if sending some of the chunks to the transfer store fails.
+    pub fn reader<S, R>(
+        &mut self,
+        session: &mut S,
+        mut reader: R,
+        chunk_size: u64,
+    ) -> Result<Image, Error>
+    where
+        S: Session,
+        R: Read,
+    {
+        let mut image = Image {
+            chunk_size: chunk_size,
+            chunks: vec!(),
+        };
+
+        let mut offset = 0;
+        loop {
+            let mut data = vec!();
+            reader.by_ref()
+                .take(chunk_size)
+                .read_to_end(&mut data)
+                .map_err(Error::Read)?;
+
+            if data.is_empty() {
+                break;
+            }
+
+            let length = data.len() as u64;
+
+            let chunk = Chunk::from_bytes(data);
+            let id = chunk.id();
+
+            if !self.sent.contains(&id) {
+                session.send(session::Sink::TRANSFER_STORE, chunk)
+                    .map_err(Error::Send)?;
+
+                self.remember(id.clone());
+            }
+
+            image.chunks.push(ChunkRef {
+                offset: offset,
+                length: length,
+                id: id,
+            });
+
+            offset += length;
+        }
+
+        Ok(image)
+    }
+
+    /// Marks the blob with the given identifier as sent.
+    ///
+    /// If the capacity is exceeded, the least recently sent blob is forgotten.
+    fn remember(&mut self, id: ChunkId) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.sent_order.len() == self.capacity {
+            if let Some(oldest) = self.sent_order.pop_front() {
+                self.sent.remove(&oldest);
+            }
+        }
+
+        self.sent.insert(id.clone());
+        self.sent_order.push_back(id);
+    }
+}
+
+impl From<Image> for rrg_proto::BlobImageDescriptor {
+
+    fn from(image: Image) -> rrg_proto::BlobImageDescriptor {
+        let chunks = image.chunks.into_iter().map(|chunk| {
+            rrg_proto::BlobImageChunkDescriptor {
+                offset: Some(chunk.offset),
+                length: Some(chunk.length),
+                digest: Some(chunk.id.to_sha256_bytes()),
+            }
+        }).collect();
+
+        rrg_proto::BlobImageDescriptor {
+            chunks: chunks,
+            chunk_size: Some(image.chunk_size),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_file_chunks() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::write(tempdir.path().join("foo"), b"foobarbaz").unwrap();
+
+        let mut session = session::test::Fake::new();
+        let mut transfer = Transfer::default();
+
+        let path = tempdir.path().join("foo");
+        let image = transfer.file(&mut session, &path, 1024, 4).unwrap();
+        let image = image.unwrap();
+
+        assert_eq!(image.chunk_size, 4);
+        assert_eq!(image.chunks.len(), 3);
+        assert_eq!(image.chunks[0].offset, 0);
+        assert_eq!(image.chunks[0].length, 4);
+        assert_eq!(image.chunks[1].offset, 4);
+        assert_eq!(image.chunks[1].length, 4);
+        assert_eq!(image.chunks[2].offset, 8);
+        assert_eq!(image.chunks[2].length, 1);
+
+        assert_eq!(session.response_count(session::Sink::TRANSFER_STORE), 3);
+
+        let blobs = session.responses::<Chunk>(session::Sink::TRANSFER_STORE)
+            .map(|chunk| chunk.data.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(blobs[0], b"foob");
+        assert_eq!(blobs[1], b"arba");
+        assert_eq!(blobs[2], b"z");
+    }
+
+    #[test]
+    fn test_file_truncated() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::write(tempdir.path().join("foo"), b"foobarbaz").unwrap();
+
+        let mut session = session::test::Fake::new();
+        let mut transfer = Transfer::default();
+
+        let path = tempdir.path().join("foo");
+        let image = transfer.file(&mut session, &path, 6, 4).unwrap().unwrap();
+
+        assert_eq!(image.chunks.len(), 2);
+        assert_eq!(image.chunks[1].length, 2);
+    }
+
+    #[test]
+    fn test_file_deduplication() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::write(tempdir.path().join("foo"), b"abcabcabc").unwrap();
+        std::fs::write(tempdir.path().join("bar"), b"abcxyz").unwrap();
+
+        let mut session = session::test::Fake::new();
+        let mut transfer = Transfer::default();
+
+        let path = tempdir.path().join("foo");
+        let image = transfer.file(&mut session, &path, 1024, 3).unwrap();
+        assert_eq!(image.unwrap().chunks.len(), 3);
+        assert_eq!(session.response_count(session::Sink::TRANSFER_STORE), 1);
+
+        let path = tempdir.path().join("bar");
+        let image = transfer.file(&mut session, &path, 1024, 3).unwrap();
+        assert_eq!(image.unwrap().chunks.len(), 2);
+        assert_eq!(session.response_count(session::Sink::TRANSFER_STORE), 2);
+    }
+
+    #[test]
+    fn test_file_deduplication_bounded() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::write(tempdir.path().join("foo"), b"abcabcxyzabc").unwrap();
+
+        let mut session = session::test::Fake::new();
+        let mut transfer = Transfer::with_capacity(1);
+
+        let path = tempdir.path().join("foo");
+        let image = transfer.file(&mut session, &path, 1024, 3).unwrap();
+        assert_eq!(image.unwrap().chunks.len(), 4);
+
+        // The `abc` chunk is forgotten once `xyz` is sent, so it is sent again.
+        assert_eq!(session.response_count(session::Sink::TRANSFER_STORE), 3);
+        assert_eq!(transfer.sent.len(), 1);
+        assert_eq!(transfer.sent_order.len(), 1);
+    }
+
+    #[test]
+    fn test_file_empty() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::write(tempdir.path().join("foo"), b"").unwrap();
+
+        let mut session = session::test::Fake::new();
+        let mut transfer = Transfer::default();
+
+        let path = tempdir.path().join("foo");
+        let image = transfer.file(&mut session, &path, 1024, 3).unwrap();
+
+        assert!(image.unwrap().chunks.is_empty());
+        assert_eq!(session.response_count(session::Sink::TRANSFER_STORE), 0);
+    }
+
+    #[test]
+    fn test_file_non_existent() {
+        let tempdir = tempfile::tempdir().unwrap();
+
+        let mut session = session::test::Fake::new();
+        let mut transfer = Transfer::default();
+
+        let path = tempdir.path().join("foo");
+        let image = transfer.file(&mut session, &path, 1024, 3).unwrap();
+
+        assert!(image.is_none());
+    }
+}
@@ -4,7 +4,550 @@
 // in the LICENSE file or at https://opensource.org/licenses/MIT.
 
 //! Handler for `client side file finder` action.
+//!
+//! The file finder action searches the filesystem for files matching given
+//! path queries (supporting globs, recursive components and alternatives),
+//! filters them through the specified conditions (e.g. modification time or
+//! contents) and performs an action (stat, hash or download) on every file that
+//! satisfies all of them.
 
 pub mod request;
 pub mod groups;
 pub mod glob;
+mod condition;
+pub mod contents;
+pub mod download;
+mod path;
+
+use std::fs::Metadata;
+use std::path::PathBuf;
+
+use rrg_macro::ack;
+
+use crate::fs::Entry;
+use crate::session::{self, RegexParseError, Session};
+use self::contents::Match;
+use self::request::{
+    Action, DownloadActionOptions, HashActionOptions, Request,
+    StatActionOptions,
+};
+
+/// A response type for the file finder action.
+#[derive(Debug)]
+pub struct Response {
+    /// A path to the file that the result corresponds to.
+    path: PathBuf,
+    /// Metadata about the file.
+    metadata: Metadata,
+    /// Extended attributes of the file.
+    #[cfg(target_family = "unix")]
+    ext_attrs: Vec<crate::fs::unix::ExtAttr>,
+    /// Additional Linux-specific file flags.
+    #[cfg(target_os = "linux")]
+    flags_linux: Option<u32>,
+    /// Fragments of the file matched by the contents conditions.
+    matches: Vec<Match>,
+    /// Digests of the file contents (if the hash action was requested).
+    hash: Option<crate::action::hash::Hash>,
+    /// Chunks of the file sent to the server (if it was downloaded).
+    image: Option<download::Image>,
+}
+
+/// An error type for failures that can occur during the file finder action.
+#[derive(Debug)]
+enum Error {
+    /// A failure occurred when parsing a glob in one of the path queries.
+    Glob(RegexParseError),
+}
+
+impl std::error::Error for Error {
+
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use Error::*;
+
+        match *self {
+            Glob(ref error) => Some(error),
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use Error::*;
+
+        match *self {
+            Glob(ref error) => {
+                write!(fmt, "invalid path query: {}", error)
+            }
+        }
+    }
+}
+
+impl From<Error> for session::Error {
+
+    fn from(error: Error) -> session::Error {
+        session::Error::action(error)
+    }
+}
+
+/// Handles requests for the file finder action.
+pub fn handle<S>(session: &mut S, request: Request) -> session::Result<()>
+where
+    S: Session,
+{
+    let mut transfer = download::Transfer::default();
+
+    for query in &request.path_queries {
+        let opts = path::Opts {
+            follow_links: request.follow_links,
+            xdev_mode: request.xdev_mode,
+        };
+
+        let entries = path::resolve(query, opts).map_err(Error::Glob)?;
+
+        for entry in entries {
+            session.heartbeat();
+            session.check_cancelled()?;
+
+            if !request.process_non_regular_files && !entry.metadata.is_file() {
+                continue;
+            }
+
+            let matches = match condition::check(&entry, &request.conditions) {
+                Some(matches) => matches,
+                None => continue,
+            };
+
+            let response = match request.action {
+                Action::Stat(ref options) => stat(entry, options, matches),
+                Action::Hash(ref options) => hash(entry, options, matches),
+                Action::Download(ref options) => {
+                    download(session, &mut transfer, entry, options, matches)?
+                }
+            };
+
+            session.reply(response)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Performs the stat action on the given entry.
+fn stat(entry: Entry, options: &StatActionOptions, matches: Vec<Match>)
+    -> Response
+{
+    let metadata = if options.resolve_links {
+        ack! {
+            std::fs::metadata(&entry.path),
+            warn: "failed to resolve '{}'", entry.path.display()
+        }.unwrap_or(entry.metadata)
+    } else {
+        entry.metadata
+    };
+
+    #[cfg(target_family = "unix")]
+    let ext_attrs = if options.collect_ext_attrs {
+        ext_attrs(&entry.path)
+    } else {
+        vec!()
+    };
+
+    #[cfg(target_os = "linux")]
+    let flags_linux = if !metadata.file_type().is_symlink() {
+        ack! {
+            crate::fs::linux::flags(&entry.path),
+            warn: "failed to collect flags for '{}'", entry.path.display()
+        }
+    } else {
+        None
+    };
+
+    Response {
+        path: entry.path,
+        metadata: metadata,
+        #[cfg(target_family = "unix")]
+        ext_attrs: ext_attrs,
+        #[cfg(target_os = "linux")]
+        flags_linux: flags_linux,
+        matches: matches,
+        hash: None,
+        image: None,
+    }
+}
+
+/// Performs the hash action on the given entry.
+///
+/// Files bigger than the limit specified in the options are either skipped
+/// (in which case the response contains only the stat information) or only
+/// the part of the file below the limit is hashed.
+fn hash(entry: Entry, options: &HashActionOptions, matches: Vec<Match>)
+    -> Response
+{
+    use self::request::HashActionOversizedFilePolicy as Policy;
+
+    let max_size = if entry.metadata.len() <= options.max_size {
+        Some(entry.metadata.len())
+    } else {
+        match options.oversized_file_policy {
+            Policy::Skip => None,
+            Policy::HashTruncated => Some(options.max_size),
+        }
+    };
+
+    // Only regular files can be hashed (non-regular files can be present in
+    // the results if the request specifies so).
+    let hash = match max_size {
+        Some(max_size) if entry.metadata.is_file() => ack! {
+            crate::action::hash::file(&entry.path, max_size),
+            warn: "failed to hash '{}'", entry.path.display()
+        },
+        _ => None,
+    };
+
+    let options = StatActionOptions {
+        resolve_links: false,
+        collect_ext_attrs: options.collect_ext_attrs,
+    };
+
+    Response {
+        hash: hash,
+        ..stat(entry, &options, matches)
+    }
+}
+
+/// Performs the download action on the given entry.
+///
+/// Files bigger than the limit specified in the options are either skipped
+/// (in which case the response contains only the stat information) or only
+/// the part of the file below the limit is sent.
+fn download<S>(
+    session: &mut S,
+    transfer: &mut download::Transfer,
+    entry: Entry,
+    options: &DownloadActionOptions,
+    matches: Vec<Match>,
+) -> session::Result<Response>
+where
+    S: Session,
+{
+    use self::request::DownloadActionOversizedFilePolicy as Policy;
+
+    let max_size = if entry.metadata.len() <= options.max_size {
+        Some(entry.metadata.len())
+    } else {
+        match options.oversized_file_policy {
+            Policy::Skip => None,
+            Policy::DownloadTruncated => Some(options.max_size),
+        }
+    };
+
+    // Like with hashing, only regular files can be downloaded.
+    let image = match max_size {
+        Some(max_size) if entry.metadata.is_file() => {
+            let chunk_size = options.chunk_size;
+            transfer.file(session, &entry.path, max_size, chunk_size)?
+        }
+        _ => None,
+    };
+
+    let options = StatActionOptions {
+        resolve_links: false,
+        collect_ext_attrs: options.collect_ext_attrs,
+    };
+
+    Ok(Response {
+        image: image,
+        ..stat(entry, &options, matches)
+    })
+}
+
+/// Collects extended attributes of the specified file.
+#[cfg(target_family = "unix")]
+fn ext_attrs<P>(path: &P) -> Vec<crate::fs::unix::ExtAttr>
+where
+    P: AsRef<std::path::Path>,
+{
+    let ext_attrs = ack! {
+        crate::fs::unix::ext_attrs(path),
+        warn: "failed to collect attributes for '{}'", path.as_ref().display()
+    };
+
+    ext_attrs.map(Iterator::collect).unwrap_or_default()
+}
+
+impl super::Response for Response {
+
+    const RDF_NAME: Option<&'static str> = Some("FileFinderResult");
+
+    type Proto = rrg_proto::FileFinderResult;
+
+    fn into_proto(self) -> rrg_proto::FileFinderResult {
+        use rrg_proto::convert::IntoLossy as _;
+
+        let path = self.path;
+
+        let matches = self.matches.into_iter().map(|hit| {
+            rrg_proto::BufferReference {
+                offset: Some(hit.offset),
+                length: Some(hit.data.len() as u64),
+                data: Some(hit.data),
+                pathspec: Some(path.clone().into()),
+                ..Default::default()
+            }
+        }).collect();
+
+        let stat_entry = rrg_proto::StatEntry {
+            pathspec: Some(path.into()),
+            #[cfg(target_family = "unix")]
+            ext_attrs: self.ext_attrs.into_iter().map(Into::into).collect(),
+            #[cfg(target_os = "linux")]
+            st_flags_linux: self.flags_linux,
+            ..self.metadata.into_lossy()
+        };
+
+        rrg_proto::FileFinderResult {
+            stat_entry: Some(stat_entry),
+            matches: matches,
+            hash_entry: self.hash.map(Into::into),
+            transferred_file: self.image.map(Into::into),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs::File;
+
+    use super::*;
+    use super::request::Condition;
+    use super::request::HashActionOversizedFilePolicy as HashPolicy;
+    use super::request::DownloadActionOversizedFilePolicy as DownloadPolicy;
+
+    #[test]
+    fn test_no_queries() {
+        let request = stat_request(vec!(), vec!());
+
+        let mut session = session::test::Fake::new();
+        assert!(handle(&mut session, request).is_ok());
+
+        assert_eq!(session.reply_count(), 0);
+    }
+
+    #[test]
+    fn test_glob_query() {
+        let tempdir = tempfile::tempdir().unwrap();
+        File::create(tempdir.path().join("foo")).unwrap();
+        File::create(tempdir.path().join("bar")).unwrap();
+        File::create(tempdir.path().join("baz")).unwrap();
+
+        let query = tempdir.path().join("ba*");
+        let request = stat_request(vec!(query), vec!());
+
+        let mut session = session::test::Fake::new();
+        assert!(handle(&mut session, request).is_ok());
+
+        let mut paths = session.replies::<Response>()
+            .map(|reply| reply.path.clone())
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        assert_eq!(paths.len(), 2);
+        assert_eq!(paths[0], tempdir.path().join("bar"));
+        assert_eq!(paths[1], tempdir.path().join("baz"));
+    }
+
+    #[test]
+    fn test_non_regular_files() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tempdir.path().join("foo")).unwrap();
+        File::create(tempdir.path().join("bar")).unwrap();
+
+        let query = tempdir.path().join("*");
+
+        let mut session = session::test::Fake::new();
+        let request = stat_request(vec!(query.clone()), vec!());
+        assert!(handle(&mut session, request).is_ok());
+        assert_eq!(session.reply_count(), 1);
+
+        let mut session = session::test::Fake::new();
+        let mut request = stat_request(vec!(query.clone()), vec!());
+        request.process_non_regular_files = true;
+        assert!(handle(&mut session, request).is_ok());
+        assert_eq!(session.reply_count(), 2);
+    }
+
+    #[test]
+    fn test_conditions() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::write(tempdir.path().join("foo"), b"123").unwrap();
+        std::fs::write(tempdir.path().join("bar"), b"123456").unwrap();
+
+        let query = tempdir.path().join("*");
+        let request = stat_request(vec!(query), vec!(Condition::MinSize(4)));
+
+        let mut session = session::test::Fake::new();
+        assert!(handle(&mut session, request).is_ok());
+
+        assert_eq!(session.reply_count(), 1);
+
+        let reply = session.reply::<Response>(0);
+        assert_eq!(reply.path, tempdir.path().join("bar"));
+        assert_eq!(reply.metadata.len(), 6);
+    }
+
+    #[test]
+    fn test_hash_action() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::write(tempdir.path().join("foo"), b"foobar").unwrap();
+
+        let query = tempdir.path().join("foo");
+        let request = hash_request(query, 1024, HashPolicy::Skip);
+
+        let mut session = session::test::Fake::new();
+        assert!(handle(&mut session, request).is_ok());
+
+        assert_eq!(session.reply_count(), 1);
+
+        let hash = session.reply::<Response>(0).hash.as_ref().unwrap();
+        assert_eq!(hash.num_bytes, 6);
+    }
+
+    #[test]
+    fn test_hash_action_oversized_skip() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::write(tempdir.path().join("foo"), b"foobar").unwrap();
+
+        let query = tempdir.path().join("foo");
+        let request = hash_request(query, 3, HashPolicy::Skip);
+
+        let mut session = session::test::Fake::new();
+        assert!(handle(&mut session, request).is_ok());
+
+        assert_eq!(session.reply_count(), 1);
+
+        let reply = session.reply::<Response>(0);
+        assert_eq!(reply.metadata.len(), 6);
+        assert!(reply.hash.is_none());
+    }
+
+    #[test]
+    fn test_hash_action_oversized_truncated() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::write(tempdir.path().join("foo"), b"foobar").unwrap();
+        std::fs::write(tempdir.path().join("bar"), b"foo").unwrap();
+
+        let query = tempdir.path().join("foo");
+        let request = hash_request(query, 3, HashPolicy::HashTruncated);
+
+        let mut session = session::test::Fake::new();
+        assert!(handle(&mut session, request).is_ok());
+
+        let hash = session.reply::<Response>(0).hash.clone().unwrap();
+        let expected = crate::action::hash::file(tempdir.path().join("bar"), 3);
+        assert_eq!(hash, expected.unwrap());
+    }
+
+    #[test]
+    fn test_download_action() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::write(tempdir.path().join("foo"), b"foobar").unwrap();
+
+        let query = tempdir.path().join("foo");
+        let request = download_request(query, 1024, DownloadPolicy::Skip);
+
+        let mut session = session::test::Fake::new();
+        assert!(handle(&mut session, request).is_ok());
+
+        assert_eq!(session.reply_count(), 1);
+        assert_eq!(session.response_count(session::Sink::TRANSFER_STORE), 2);
+
+        let image = session.reply::<Response>(0).image.as_ref().unwrap();
+        assert_eq!(image.chunks.len(), 2);
+    }
+
+    #[test]
+    fn test_download_action_oversized_skip() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::write(tempdir.path().join("foo"), b"foobar").unwrap();
+
+        let query = tempdir.path().join("foo");
+        let request = download_request(query, 3, DownloadPolicy::Skip);
+
+        let mut session = session::test::Fake::new();
+        assert!(handle(&mut session, request).is_ok());
+
+        assert_eq!(session.reply_count(), 1);
+        assert_eq!(session.response_count(session::Sink::TRANSFER_STORE), 0);
+        assert!(session.reply::<Response>(0).image.is_none());
+    }
+
+    #[test]
+    fn test_download_action_oversized_truncated() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::write(tempdir.path().join("foo"), b"foobar").unwrap();
+
+        let query = tempdir.path().join("foo");
+        let policy = DownloadPolicy::DownloadTruncated;
+        let request = download_request(query, 5, policy);
+
+        let mut session = session::test::Fake::new();
+        assert!(handle(&mut session, request).is_ok());
+
+        let image = session.reply::<Response>(0).image.as_ref().unwrap();
+        assert_eq!(image.chunks.len(), 2);
+        assert_eq!(image.chunks[1].length, 2);
+    }
+
+    /// Constructs a stat request for the given queries and conditions.
+    fn stat_request(queries: Vec<PathBuf>, conditions: Vec<Condition>)
+        -> Request
+    {
+        Request {
+            path_queries: queries.into_iter()
+                .map(|query| query.to_string_lossy().into_owned())
+                .collect(),
+            action: Action::Stat(StatActionOptions {
+                resolve_links: false,
+                collect_ext_attrs: false,
+            }),
+            conditions: conditions,
+            process_non_regular_files: false,
+            follow_links: false,
+            xdev_mode: rrg_proto::file_finder_args::XDev::Local,
+        }
+    }
+
+    /// Constructs a hash request for the given query and size limits.
+    fn hash_request(query: PathBuf, max_size: u64, policy: HashPolicy)
+        -> Request
+    {
+        Request {
+            action: Action::Hash(HashActionOptions {
+                max_size: max_size,
+                oversized_file_policy: policy,
+                collect_ext_attrs: false,
+            }),
+            ..stat_request(vec!(query), vec!())
+        }
+    }
+
+    /// Constructs a download request for the given query and size limits.
+    fn download_request(query: PathBuf, max_size: u64, policy: DownloadPolicy)
+        -> Request
+    {
+        Request {
+            action: Action::Download(DownloadActionOptions {
+                max_size: max_size,
+                oversized_file_policy: policy,
+                use_external_stores: false,
+                collect_ext_attrs: false,
+                chunk_size: 3,
+            }),
+            ..stat_request(vec!(query), vec!())
+        }
+    }
+}
@@ -0,0 +1,769 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+//! Utilities for resolving path queries of the file finder action.
+//!
+//! A path query is an absolute path where each component can be a literal
+//! name, a glob expression (e.g. `*.txt` or `id_[dr]sa`) or a recursive
+//! component (`**` or `**N`) that matches all descendants of a directory up to
+//! the depth of `N` (3 if not specified). Before the query is resolved, all its
+//! alternatives (e.g. `{foo,bar}`) are expanded.
+//!
+//! Queries are resolved lazily, so even queries matching huge amounts of files
+//! are processed in memory proportional to the size of the directories being
+//! traversed rather than the number of results.
+
+use std::path::{Component as PathComponent, Path, PathBuf};
+
+use lazy_static::lazy_static;
+use log::warn;
+use regex::Regex;
+
+use crate::fs::Entry;
+use crate::session::RegexParseError;
+use super::glob::glob_to_regex;
+use super::groups::expand_groups;
+use super::request::XDevMode;
+
+/// A depth of recursive components that do not specify it explicitly.
+const DEFAULT_RECURSIVE_DEPTH: u32 = 3;
+
+/// Options that affect how path queries are resolved.
+#[derive(Clone, Copy, Debug)]
+pub struct Opts {
+    /// Whether to descend into symlinked directories in recursive components.
+    pub follow_links: bool,
+    /// Whether to descend into other devices in recursive components.
+    pub xdev_mode: XDevMode,
+}
+
+/// A single component of a path query.
+#[derive(Debug)]
+enum Component {
+    /// A component that has to match the name of the file exactly.
+    Literal(PathBuf),
+    /// A component that matches file names against a glob expression.
+    Glob(Regex),
+    /// A component that matches all descendants up to the specified depth.
+    Recursive(u32),
+}
+
+/// A path query split into its root and a list of components.
+#[derive(Debug)]
+struct Query {
+    /// A root of the query (e.g. `/` on Linux or `C:\` on Windows).
+    root: PathBuf,
+    /// Components of the query that follow the root.
+    components: Vec<Component>,
+}
+
+/// Resolves the given path query into an iterator over matching entries.
+///
+/// The query can contain alternatives in form of `{a,b}`, glob expressions
+/// in particular path components (e.g. `/home/*/.bash_history`) and recursive
+/// components (e.g. `/home/**/.ssh`).
+///
+/// Errors that occur while traversing the filesystem (e.g. because of missing
+/// permissions) are logged and the offending paths are skipped.
+///
+/// # Errors
+///
+/// This function will return an error if some of the glob expressions in the
+/// query is not valid. All alternatives are parsed before the iterator is
+/// returned, so no such error can surface in the middle of the traversal.
+pub fn resolve(query: &str, opts: Opts) -> Result<Resolve, RegexParseError> {
+    let mut queries = vec!();
+
+    for path in expand_groups(query) {
+        match parse(&path)? {
+            Some(query) => queries.push(query),
+            None => warn!("path query '{}' is not absolute, skipping", path),
+        }
+    }
+
+    // Queries are popped from the back, so we reverse them in order to resolve
+    // alternatives in the order in which they were specified.
+    queries.reverse();
+
+    Ok(Resolve {
+        queries: queries,
+        components: vec!(),
+        pending: vec!(),
+        devs: Devs::new(opts.xdev_mode),
+        opts: opts,
+    })
+}
+
+/// Returns literal roots of all the alternatives of the given path query.
+///
+/// Every path matched by the query is a descendant of one of its roots, so the
+/// roots can be used to verify that the query stays within certain directories.
+/// Alternatives that are not valid queries or that refer to parent directories
+/// (and thus could escape their roots) are returned unchanged.
+pub fn roots(query: &str) -> Vec<PathBuf> {
+    expand_groups(query).into_iter().map(|path| {
+        let is_parent = |component| component == PathComponent::ParentDir;
+        if Path::new(&path).components().any(is_parent) {
+            return PathBuf::from(path);
+        }
+
+        match parse(&path) {
+            Ok(Some(query)) => query.root,
+            _ => PathBuf::from(path),
+        }
+    }).collect()
+}
+
+/// Iterator over filesystem entries matching a path query.
+///
+/// The iterator can be constructed with the [`resolve`] function.
+///
+/// [`resolve`]: fn.resolve.html
+pub struct Resolve {
+    /// Queries (for particular alternatives) that are yet to be resolved.
+    queries: Vec<Query>,
+    /// Components of the query that is currently being resolved.
+    components: Vec<Component>,
+    /// A stack of pending tasks for the query being currently resolved.
+    pending: Vec<Task>,
+    /// Device information needed to decide whether to cross devices.
+    devs: Devs,
+    /// Options that the query is resolved with.
+    opts: Opts,
+}
+
+/// A single unit of work of the query resolution.
+enum Task {
+    /// Match the entry against the component at the given index.
+    Match {
+        entry: Entry,
+        index: usize,
+    },
+    /// List the directory for the recursive component at the given index.
+    Recurse {
+        entry: Entry,
+        index: usize,
+        depth: u32,
+    },
+}
+
+impl Resolve {
+
+    /// Starts resolving the next query, returning `None` if there is none.
+    fn start(&mut self) -> Option<()> {
+        let query = self.queries.pop()?;
+
+        if let Some(root) = entry(query.root) {
+            self.pending.push(Task::Match {
+                entry: root,
+                index: 0,
+            });
+        }
+        self.components = query.components;
+
+        Some(())
+    }
+
+    /// Executes the given task, returning an entry if it matches the query.
+    fn execute(&mut self, task: Task) -> Option<Entry> {
+        let (entry, index) = match task {
+            Task::Match { entry, index } => (entry, index),
+            Task::Recurse { entry, index, depth } => {
+                self.recurse(&entry, index, depth);
+                return None;
+            }
+        };
+
+        // The entry has been matched against all the components, so it is one
+        // of the results.
+        if index == self.components.len() {
+            return Some(entry);
+        }
+
+        match &self.components[index] {
+            Component::Literal(name) => {
+                if let Some(child) = self::entry(entry.path.join(name)) {
+                    self.pending.push(Task::Match {
+                        entry: child,
+                        index: index + 1,
+                    });
+                }
+            }
+            Component::Glob(regex) => {
+                if !is_dir(&entry) {
+                    return None;
+                }
+
+                let children = list_dir(&entry)
+                    .filter(|child| matches(child, regex))
+                    .map(|child| Task::Match {
+                        entry: child,
+                        index: index + 1,
+                    });
+
+                self.pending.extend(children);
+            }
+            Component::Recursive(depth) => {
+                let depth = *depth;
+                if is_dir(&entry) {
+                    self.recurse(&entry, index, depth);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Schedules tasks for children of a directory of a recursive component.
+    fn recurse(&mut self, entry: &Entry, index: usize, depth: u32) {
+        for child in list_dir(entry) {
+            if depth > 1 {
+                if let Some(metadata) = self.descendable(entry, &child) {
+                    self.pending.push(Task::Recurse {
+                        entry: Entry {
+                            path: child.path.clone(),
+                            metadata: metadata,
+                            // Metadata of the entry follows symlinks, so the
+                            // extended one (that does not) would not match.
+                            #[cfg(target_os = "linux")] statx: None,
+                        },
+                        index: index,
+                        depth: depth - 1,
+                    });
+                }
+            }
+
+            self.pending.push(Task::Match {
+                entry: child,
+                index: index + 1,
+            });
+        }
+    }
+
+    /// Checks whether a recursive component should descend into `child`.
+    ///
+    /// If so, metadata of the child directory (with symlinks resolved) is
+    /// returned.
+    fn descendable(&self, parent: &Entry, child: &Entry)
+        -> Option<std::fs::Metadata>
+    {
+        let metadata = if child.metadata.file_type().is_symlink() {
+            if !self.opts.follow_links {
+                return None;
+            }
+
+            std::fs::metadata(&child.path).ok()?
+        } else {
+            child.metadata.clone()
+        };
+
+        if !metadata.is_dir() {
+            return None;
+        }
+
+        if !self.devs.crossable(&parent.metadata, &metadata) {
+            return None;
+        }
+
+        Some(metadata)
+    }
+}
+
+impl Iterator for Resolve {
+
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Entry> {
+        loop {
+            let task = match self.pending.pop() {
+                Some(task) => task,
+                None => {
+                    self.start()?;
+                    continue;
+                }
+            };
+
+            if let Some(entry) = self.execute(task) {
+                return Some(entry);
+            }
+        }
+    }
+}
+
+/// Device information needed to decide whether to cross devices.
+struct Devs {
+    /// A mode that the decision is based on.
+    mode: XDevMode,
+    /// Identifiers of devices with remote filesystems mounted on them.
+    #[cfg(target_os = "linux")]
+    remote: std::collections::HashSet<u64>,
+}
+
+impl Devs {
+
+    /// Collects device information required by the specified mode.
+    fn new(mode: XDevMode) -> Devs {
+        #[cfg(target_os = "linux")]
+        let remote = if mode == XDevMode::Local {
+            remote_devs()
+        } else {
+            std::collections::HashSet::new()
+        };
+
+        Devs {
+            mode: mode,
+            #[cfg(target_os = "linux")]
+            remote: remote,
+        }
+    }
+
+    /// Checks whether the traversal can move from `parent` to `child`.
+    #[cfg(target_family = "unix")]
+    fn crossable(&self, parent: &std::fs::Metadata, child: &std::fs::Metadata)
+        -> bool
+    {
+        use std::os::unix::fs::MetadataExt as _;
+
+        if parent.dev() == child.dev() {
+            return true;
+        }
+
+        match self.mode {
+            XDevMode::Always => true,
+            XDevMode::Never => false,
+            #[cfg(target_os = "linux")]
+            XDevMode::Local => !self.remote.contains(&child.dev()),
+            // On other systems we cannot tell whether the device is local or
+            // not, so we stay on the safe side and do not cross it.
+            #[cfg(not(target_os = "linux"))]
+            XDevMode::Local => false,
+        }
+    }
+
+    /// Checks whether the traversal can move from `parent` to `child`.
+    #[cfg(not(target_family = "unix"))]
+    fn crossable(&self, _: &std::fs::Metadata, _: &std::fs::Metadata) -> bool {
+        true
+    }
+}
+
+/// Returns identifiers of all devices with remote filesystems mounted on them.
+#[cfg(target_os = "linux")]
+fn remote_devs() -> std::collections::HashSet<u64> {
+    let mounts = match crate::fs::linux::mounts() {
+        Ok(mounts) => mounts,
+        Err(error) => {
+            warn!("failed to collect mount information: {}", error);
+            return std::collections::HashSet::new();
+        }
+    };
+
+    // Device identifiers are obtained only for remote filesystems, as this
+    // requires a call to the mounted filesystem itself.
+    mounts.into_iter()
+        .filter(|mount| mount.is_remote())
+        .filter_map(|mount| match mount.dev() {
+            Ok(dev) => Some(dev),
+            Err(error) => {
+                warn! {
+                    "failed to obtain device of '{}': {}",
+                    mount.path.display(), error
+                };
+                None
+            }
+        })
+        .collect()
+}
+
+/// Splits the given path into its root and a list of query components.
+///
+/// Literal components preceding the first non-literal one are merged into the
+/// root and consecutive literal components are merged together, so that they
+/// can be checked with a single call.
+///
+/// If the path is not absolute, `None` is returned.
+fn parse(path: &str) -> Result<Option<Query>, RegexParseError> {
+    let path = Path::new(path);
+    if !path.is_absolute() {
+        return Ok(None);
+    }
+
+    let mut root = PathBuf::new();
+    let mut components: Vec<Component> = vec!();
+
+    for component in path.components() {
+        let component = match component {
+            PathComponent::Prefix(_) | PathComponent::RootDir => {
+                root.push(component);
+                continue;
+            }
+            PathComponent::Normal(name) => {
+                parse_component(&name.to_string_lossy())?
+            }
+            PathComponent::CurDir | PathComponent::ParentDir => {
+                Component::Literal(PathBuf::from(component.as_os_str()))
+            }
+        };
+
+        match (components.last_mut(), component) {
+            (None, Component::Literal(name)) => root.push(name),
+            (Some(Component::Literal(prev)), Component::Literal(name)) => {
+                prev.push(name);
+            }
+            (_, component) => components.push(component),
+        }
+    }
+
+    Ok(Some(Query {
+        root: root,
+        components: components,
+    }))
+}
+
+/// Parses a single path component of the query.
+fn parse_component(name: &str) -> Result<Component, RegexParseError> {
+    lazy_static! {
+        static ref RECURSIVE_REGEX: Regex = {
+            Regex::new(r"^\*\*(?P<depth>\d+)?$").unwrap()
+        };
+    }
+
+    if let Some(captures) = RECURSIVE_REGEX.captures(name) {
+        let depth = match captures.name("depth") {
+            Some(depth) => match depth.as_str().parse() {
+                Ok(depth) => depth,
+                // Only absurdly long numbers can fail to parse, so we can just
+                // as well treat them as unbounded.
+                Err(_) => u32::MAX,
+            },
+            None => DEFAULT_RECURSIVE_DEPTH,
+        };
+
+        return Ok(Component::Recursive(depth));
+    }
+
+    if name.contains(|c| c == '*' || c == '?' || c == '[') {
+        Ok(Component::Glob(glob_to_regex(name)?))
+    } else {
+        Ok(Component::Literal(PathBuf::from(name)))
+    }
+}
+
+/// Collects metadata of the specified path and wraps it into an entry.
+fn entry(path: PathBuf) -> Option<Entry> {
+    match std::fs::symlink_metadata(&path) {
+        Ok(metadata) => Some(Entry::new(path, metadata)),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => None,
+        Err(error) => {
+            warn!("failed to stat '{}': {}", path.display(), error);
+            None
+        }
+    }
+}
+
+/// Returns an iterator over children of the given entry.
+///
+/// Errors are logged and result in an empty iterator.
+fn list_dir(entry: &Entry) -> impl Iterator<Item = Entry> {
+    let iter = match crate::fs::list_dir(&entry.path) {
+        Ok(iter) => Some(iter),
+        Err(error) => {
+            let path = entry.path.display();
+            warn!("failed to list '{}': {}", path, error);
+            None
+        }
+    };
+
+    iter.into_iter().flatten()
+}
+
+/// Checks whether the given entry is a directory (or a symlink to one).
+fn is_dir(entry: &Entry) -> bool {
+    if !entry.metadata.file_type().is_symlink() {
+        return entry.metadata.is_dir();
+    }
+
+    match std::fs::metadata(&entry.path) {
+        Ok(metadata) => metadata.is_dir(),
+        Err(_) => false,
+    }
+}
+
+/// Checks whether the name of the given entry matches the glob regex.
+fn matches(entry: &Entry, regex: &Regex) -> bool {
+    match entry.path.file_name() {
+        Some(name) => regex.is_match(&name.to_string_lossy()),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs::File;
+
+    use super::*;
+
+    const OPTS: Opts = Opts {
+        follow_links: false,
+        xdev_mode: XDevMode::Never,
+    };
+
+    #[test]
+    fn test_resolve_literal() {
+        let tempdir = tempfile::tempdir().unwrap();
+        File::create(tempdir.path().join("foo")).unwrap();
+
+        let results = resolve_paths(&tempdir.path().join("foo"), OPTS);
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0], tempdir.path().join("foo"));
+    }
+
+    #[test]
+    fn test_resolve_literal_non_existent() {
+        let tempdir = tempfile::tempdir().unwrap();
+
+        let results = resolve_paths(&tempdir.path().join("foo"), OPTS);
+
+        assert!(results.is_empty());
+    }
+
+    #[test]
+    fn test_resolve_glob() {
+        let tempdir = tempfile::tempdir().unwrap();
+        File::create(tempdir.path().join("abc")).unwrap();
+        File::create(tempdir.path().join("abd")).unwrap();
+        File::create(tempdir.path().join("xyz")).unwrap();
+
+        let results = resolve_paths(&tempdir.path().join("ab*"), OPTS);
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0], tempdir.path().join("abc"));
+        assert_eq!(results[1], tempdir.path().join("abd"));
+    }
+
+    #[test]
+    fn test_resolve_glob_in_the_middle() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tempdir.path().join("foo")).unwrap();
+        std::fs::create_dir(tempdir.path().join("bar")).unwrap();
+        File::create(tempdir.path().join("foo").join("quux")).unwrap();
+        File::create(tempdir.path().join("bar").join("quux")).unwrap();
+        File::create(tempdir.path().join("bar").join("norf")).unwrap();
+
+        let query = tempdir.path().join("*").join("quux");
+        let results = resolve_paths(&query, OPTS);
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0], tempdir.path().join("bar").join("quux"));
+        assert_eq!(results[1], tempdir.path().join("foo").join("quux"));
+    }
+
+    #[test]
+    fn test_resolve_groups() {
+        let tempdir = tempfile::tempdir().unwrap();
+        File::create(tempdir.path().join("foo")).unwrap();
+        File::create(tempdir.path().join("bar")).unwrap();
+        File::create(tempdir.path().join("baz")).unwrap();
+
+        let results = resolve_paths(&tempdir.path().join("{foo,baz}"), OPTS);
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0], tempdir.path().join("baz"));
+        assert_eq!(results[1], tempdir.path().join("foo"));
+    }
+
+    #[test]
+    fn test_resolve_relative() {
+        assert_eq!(resolve("foo/bar", OPTS).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_resolve_recursive_default_depth() {
+        let tempdir = tempfile::tempdir().unwrap();
+
+        let dir = tempdir.path().join("a").join("b").join("c").join("d");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let results = resolve_paths(&tempdir.path().join("**"), OPTS);
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0], tempdir.path().join("a"));
+        assert_eq!(results[1], tempdir.path().join("a").join("b"));
+        assert_eq!(results[2], tempdir.path().join("a").join("b").join("c"));
+    }
+
+    #[test]
+    fn test_resolve_recursive_explicit_depth() {
+        let tempdir = tempfile::tempdir().unwrap();
+
+        let dir = tempdir.path().join("a").join("b").join("c").join("d");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let results = resolve_paths(&tempdir.path().join("**1"), OPTS);
+        assert_eq!(results, vec!(tempdir.path().join("a")));
+
+        let results = resolve_paths(&tempdir.path().join("**4"), OPTS);
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[3], dir);
+    }
+
+    #[test]
+    fn test_resolve_recursive_with_suffix() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let dir = tempdir.path().join("a");
+
+        std::fs::create_dir_all(dir.join("b")).unwrap();
+        File::create(dir.join("id_rsa")).unwrap();
+        File::create(dir.join("b").join("id_dsa")).unwrap();
+        File::create(dir.join("b").join("foo")).unwrap();
+
+        let query = tempdir.path().join("**").join("id_*");
+        let results = resolve_paths(&query, OPTS);
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0], dir.join("b").join("id_dsa"));
+        assert_eq!(results[1], dir.join("id_rsa"));
+    }
+
+    // Symlinking is supported only on Unix-like systems.
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_resolve_recursive_follow_links() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let target = tempdir.path().join("target");
+        let symlink = tempdir.path().join("root").join("symlink");
+
+        std::fs::create_dir(&target).unwrap();
+        std::fs::create_dir(tempdir.path().join("root")).unwrap();
+        File::create(target.join("foo")).unwrap();
+        std::os::unix::fs::symlink(&target, &symlink).unwrap();
+
+        let query = tempdir.path().join("root").join("**");
+
+        let results = resolve_paths(&query, OPTS);
+        assert_eq!(results, vec!(symlink.clone()));
+
+        let opts = Opts {
+            follow_links: true,
+            ..OPTS
+        };
+
+        let results = resolve_paths(&query, opts);
+        assert_eq!(results, vec!(symlink.clone(), symlink.join("foo")));
+    }
+
+    // Symlinking is supported only on Unix-like systems.
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_resolve_recursive_symlink_loop() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let dir = tempdir.path().join("dir");
+
+        std::fs::create_dir(&dir).unwrap();
+        std::os::unix::fs::symlink(&dir, dir.join("symlink")).unwrap();
+
+        let opts = Opts {
+            follow_links: true,
+            ..OPTS
+        };
+
+        // The depth limit should prevent the traversal from looping forever.
+        let results = resolve_paths(&tempdir.path().join("**5"), opts);
+        assert_eq!(results.len(), 5);
+    }
+
+    #[test]
+    fn test_resolve_yields_full_matches() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let dir = tempdir.path().join("a").join("b");
+
+        std::fs::create_dir_all(&dir).unwrap();
+        File::create(tempdir.path().join("a").join("foo.txt")).unwrap();
+        File::create(dir.join("bar.txt")).unwrap();
+        File::create(dir.join("baz.log")).unwrap();
+
+        let query = tempdir.path().join("a").join("b").join("bar.txt");
+        let results = resolve_paths(&query, OPTS);
+        assert_eq!(results, vec!(dir.join("bar.txt")));
+
+        let query = tempdir.path().join("a").join("*").join("*.txt");
+        let results = resolve_paths(&query, OPTS);
+        assert_eq!(results, vec!(dir.join("bar.txt")));
+
+        let query = tempdir.path().join("**2").join("*.txt");
+        let results = resolve_paths(&query, OPTS);
+        assert_eq!(results, vec! {
+            tempdir.path().join("a").join("b").join("bar.txt"),
+            tempdir.path().join("a").join("foo.txt"),
+        });
+    }
+
+    #[test]
+    fn test_resolve_lazy() {
+        let tempdir = tempfile::tempdir().unwrap();
+        File::create(tempdir.path().join("foo")).unwrap();
+
+        let query = tempdir.path().join("*");
+        let results = resolve(&query.to_string_lossy(), OPTS).unwrap();
+
+        // Nothing is listed until the iterator is polled, so the file created
+        // after the call should be reported as well.
+        File::create(tempdir.path().join("bar")).unwrap();
+        assert_eq!(results.count(), 2);
+    }
+
+    #[test]
+    fn test_parse_literal_prefix() {
+        let query = parse("/foo/bar/*/baz/quux").unwrap().unwrap();
+
+        assert_eq!(query.root, PathBuf::from("/foo/bar"));
+        assert_eq!(query.components.len(), 2);
+        assert!(matches!(query.components[0], Component::Glob(_)));
+        match query.components[1] {
+            Component::Literal(ref name) => {
+                assert_eq!(name, &PathBuf::from("baz").join("quux"));
+            }
+            ref component => panic!("unexpected component: {:?}", component),
+        }
+    }
+
+    #[test]
+    fn test_parse_recursive() {
+        let query = parse("/**/foo/**7").unwrap().unwrap();
+
+        assert_eq!(query.components.len(), 3);
+        assert!(matches!(query.components[0], Component::Recursive(3)));
+        assert!(matches!(query.components[2], Component::Recursive(7)));
+    }
+
+    #[test]
+    fn test_roots() {
+        let roots = super::roots("/foo/{bar,baz}/*/quux");
+
+        assert_eq!(roots, vec! {
+            PathBuf::from("/foo/bar"),
+            PathBuf::from("/foo/baz"),
+        });
+    }
+
+    #[test]
+    fn test_roots_parent_dir() {
+        let roots = super::roots("/foo/*/../../bar");
+
+        assert_eq!(roots, vec!(PathBuf::from("/foo/*/../../bar")));
+    }
+
+    /// Resolves the given query and returns sorted paths of the results.
+    fn resolve_paths(query: &Path, opts: Opts) -> Vec<PathBuf> {
+        let mut results = resolve(&query.to_string_lossy(), opts).unwrap()
+            .map(|entry| entry.path)
+            .collect::<Vec<_>>();
+        results.sort();
+
+        results
+    }
+}
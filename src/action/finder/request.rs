@@ -10,7 +10,7 @@
 use crate::session::{
     parse_enum, time_from_micros, ParseError, ProtoEnum, RegexParseError,
 };
-use regex::Regex;
+use regex::bytes::Regex;
 use rrg_proto::{
     FileFinderAccessTimeCondition, FileFinderAction, FileFinderArgs,
     FileFinderCondition, FileFinderContentsLiteralMatchCondition,
@@ -20,10 +20,11 @@ use rrg_proto::{
     FileFinderSizeCondition, FileFinderStatActionOptions,
 };
 use std::convert::TryFrom;
+use std::path::PathBuf;
 
-type HashActionOversizedFilePolicy =
+pub type HashActionOversizedFilePolicy =
     rrg_proto::file_finder_hash_action_options::OversizedFilePolicy;
-type DownloadActionOversizedFilePolicy =
+pub type DownloadActionOversizedFilePolicy =
     rrg_proto::file_finder_download_action_options::OversizedFilePolicy;
 type RegexMatchMode =
     rrg_proto::file_finder_contents_regex_match_condition::Mode;
@@ -31,9 +32,16 @@ type LiteralMatchMode =
     rrg_proto::file_finder_contents_literal_match_condition::Mode;
 type ActionType = rrg_proto::file_finder_action::Action;
 type ConditionType = rrg_proto::file_finder_condition::Type;
-type XDevMode = rrg_proto::file_finder_args::XDev;
+pub type XDevMode = rrg_proto::file_finder_args::XDev;
 type PathType = rrg_proto::path_spec::PathType;
 
+/// A maximum number of bytes of context that can be requested around a match.
+///
+/// The context is kept in memory while the contents are scanned, so without a
+/// limit a single request could make the agent allocate arbitrary amounts of
+/// memory.
+const MAX_CONTEXT_SIZE: u32 = 1024 * 1024;
+
 #[derive(Debug)]
 pub struct Request {
     /// A list of paths to glob that supports `**` path recursion and
@@ -114,7 +122,7 @@ pub enum Condition {
     ContentsLiteralMatch(ContentsLiteralMatchConditionOptions),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum MatchMode {
     AllHits,
     FirstHit,
@@ -196,7 +204,7 @@ impl TryFrom<FileFinderDownloadActionOptions> for Action {
             max_size: proto.max_size(),
             collect_ext_attrs: proto.collect_ext_attrs(),
             use_external_stores: proto.use_external_stores(),
-            chunk_size: proto.chunk_size(),
+            chunk_size: parse_chunk_size(proto.chunk_size())?,
         }))
     }
 }
@@ -389,6 +397,32 @@ fn parse_regex(bytes: Vec<u8>) -> Result<Regex, ParseError> {
     }
 }
 
+/// Verifies that the requested size of match context is within the limit.
+fn parse_context_size(size: u32) -> Result<u32, ParseError> {
+    if size > MAX_CONTEXT_SIZE {
+        return Err(ParseError::malformed(format!(
+            "match context of {} bytes exceeds the limit of {} bytes",
+            size, MAX_CONTEXT_SIZE,
+        )));
+    }
+
+    Ok(size)
+}
+
+/// Verifies that the requested size of download chunks is within the limit.
+fn parse_chunk_size(size: u64) -> Result<u64, ParseError> {
+    use super::download::MAX_CHUNK_SIZE;
+
+    if size > MAX_CHUNK_SIZE {
+        return Err(ParseError::malformed(format!(
+            "chunk size of {} bytes exceeds the limit of {} bytes",
+            size, MAX_CHUNK_SIZE,
+        )));
+    }
+
+    Ok(size)
+}
+
 fn get_contents_regex_match_condition(
     proto: Option<FileFinderContentsRegexMatchCondition>,
 ) -> Result<Vec<Condition>, ParseError> {
@@ -397,8 +431,8 @@ fn get_contents_regex_match_condition(
         None => return Ok(vec![]),
     };
 
-    let bytes_before = options.bytes_before();
-    let bytes_after = options.bytes_after();
+    let bytes_before = parse_context_size(options.bytes_before())?;
+    let bytes_after = parse_context_size(options.bytes_after())?;
     let start_offset = options.start_offset();
     let length = options.length();
     let mode = MatchMode::from(parse_enum::<RegexMatchMode>(options.mode)?);
@@ -428,8 +462,8 @@ fn get_contents_literal_match_condition(
         None => return Ok(vec![]),
     };
 
-    let bytes_before = options.bytes_before();
-    let bytes_after = options.bytes_after();
+    let bytes_before = parse_context_size(options.bytes_before())?;
+    let bytes_after = parse_context_size(options.bytes_after())?;
     let start_offset = options.start_offset();
     let length = options.length();
     let xor_in_key = options.xor_in_key();
@@ -518,6 +552,13 @@ impl super::super::Request for Request {
             xdev_mode,
         })
     }
+
+    fn paths(&self) -> Vec<PathBuf> {
+        self.path_queries
+            .iter()
+            .flat_map(|query| super::path::roots(query))
+            .collect()
+    }
 }
 
 #[cfg(test)]
@@ -749,6 +790,26 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_download_action_chunk_size_too_large() {
+        use super::super::download::MAX_CHUNK_SIZE;
+
+        let err = Request::from_proto(FileFinderArgs {
+            action: Some(FileFinderAction {
+                action_type: Some(ActionType::Download as i32),
+                download: Some(FileFinderDownloadActionOptions {
+                    chunk_size: Some(MAX_CHUNK_SIZE + 1),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .unwrap_err();
+
+        assert!(matches!(err, ParseError::Malformed(_)));
+    }
+
     #[test]
     fn test_error_on_parsing_unknown_enum_value() {
         let err = Request::from_proto(FileFinderArgs {
@@ -1320,6 +1381,31 @@ mod tests {
         assert!(matches!(err, ParseError::Malformed(_)));
     }
 
+    #[test]
+    fn test_contents_regex_match_condition_context_too_large() {
+        let err = Request::from_proto(FileFinderArgs {
+            action: Some(FileFinderAction {
+                action_type: Some(ActionType::Stat as i32),
+                ..Default::default()
+            }),
+            conditions: vec![FileFinderCondition {
+                condition_type: Some(ConditionType::ContentsRegexMatch as i32),
+                contents_regex_match: Some(
+                    FileFinderContentsRegexMatchCondition {
+                        regex: Some(vec![97, 98, 99]),
+                        bytes_after: Some(MAX_CONTEXT_SIZE + 1),
+                        ..Default::default()
+                    },
+                ),
+                ..Default::default()
+            }],
+            ..Default::default()
+        })
+        .unwrap_err();
+
+        assert!(matches!(err, ParseError::Malformed(_)));
+    }
+
     #[test]
     fn test_default_contents_literal_match_condition() {
         let request = Request::from_proto(FileFinderArgs {
@@ -1389,4 +1475,31 @@ mod tests {
             v @ _ => panic!("Unexpected condition type: {:?}", v),
         }
     }
+
+    #[test]
+    fn test_contents_literal_match_condition_context_too_large() {
+        let err = Request::from_proto(FileFinderArgs {
+            action: Some(FileFinderAction {
+                action_type: Some(ActionType::Stat as i32),
+                ..Default::default()
+            }),
+            conditions: vec![FileFinderCondition {
+                condition_type: Some(
+                    ConditionType::ContentsLiteralMatch as i32,
+                ),
+                contents_literal_match: Some(
+                    FileFinderContentsLiteralMatchCondition {
+                        literal: Some(vec![97, 98, 99]),
+                        bytes_before: Some(MAX_CONTEXT_SIZE + 1),
+                        ..Default::default()
+                    },
+                ),
+                ..Default::default()
+            }],
+            ..Default::default()
+        })
+        .unwrap_err();
+
+        assert!(matches!(err, ParseError::Malformed(_)));
+    }
 }
@@ -0,0 +1,304 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+//! A handler and associated types for the file hash action.
+//!
+//! The file hash action computes MD5, SHA-1 and SHA-256 digests of a file. The
+//! file is read only once and all the digests are computed simultaneously.
+//!
+//! The hashing logic defined here is also used by other actions that need to
+//! compute file digests (like the file finder).
+
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest as _, Sha256};
+
+use crate::session::{self, Session};
+
+/// A size of the buffer used for reading the hashed data.
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// A request type for the file hash action.
+#[derive(Debug)]
+pub struct Request {
+    /// A path to the file to hash.
+    path: PathBuf,
+    /// A maximum number of bytes of the file to hash.
+    max_size: u64,
+}
+
+/// A response type for the file hash action.
+#[derive(Debug)]
+pub struct Response {
+    /// A path to the file that the result corresponds to.
+    path: PathBuf,
+    /// Digests of the file contents.
+    hash: Hash,
+}
+
+/// Digests of some data (e.g. file contents).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hash {
+    /// An MD5 digest of the data.
+    pub md5: [u8; 16],
+    /// A SHA-1 digest of the data.
+    pub sha1: [u8; 20],
+    /// A SHA-256 digest of the data.
+    pub sha256: [u8; 32],
+    /// A number of bytes that have been hashed.
+    pub num_bytes: u64,
+}
+
+/// An error type for failures that can occur during the file hash action.
+#[derive(Debug)]
+enum Error {
+    /// A failure occurred during the attempt to read the file.
+    Read(std::io::Error),
+}
+
+impl std::error::Error for Error {
+
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use Error::*;
+
+        match *self {
+            Read(ref error) => Some(error),
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use Error::*;
+
+        match *self {
+            Read(ref error) => {
+                write!(fmt, "unable to read the file: {}", error)
+            }
+        }
+    }
+}
+
+impl From<Error> for session::Error {
+
+    fn from(error: Error) -> session::Error {
+        session::Error::action(error)
+    }
+}
+
+/// Handles requests for the file hash action.
+pub fn handle<S>(session: &mut S, request: Request) -> session::Result<()>
+where
+    S: Session,
+{
+    let hash = file(&request.path, request.max_size).map_err(Error::Read)?;
+
+    session.reply(Response {
+        path: request.path,
+        hash: hash,
+    })?;
+
+    Ok(())
+}
+
+/// Computes digests of (at most `max_size` first bytes of) the given file.
+///
+/// # Errors
+///
+/// This function will return an error if the file cannot be opened or if any
+/// of the reads fails.
+pub fn file<P>(path: P, max_size: u64) -> std::io::Result<Hash>
+where
+    P: AsRef<Path>,
+{
+    let file = File::open(path)?;
+    reader(file.take(max_size))
+}
+
+/// Computes digests of all the data yielded by the given reader.
+///
+/// # Errors
+///
+/// This function will return an error if any of the reads fails.
+pub fn reader<R>(mut reader: R) -> std::io::Result<Hash>
+where
+    R: Read,
+{
+    let mut md5 = Md5::new();
+    let mut sha1 = Sha1::new();
+    let mut sha256 = Sha256::new();
+    let mut num_bytes = 0;
+
+    let mut buf = vec![0; BUFFER_SIZE];
+    loop {
+        let len = match reader.read(&mut buf[..]) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(ref error) if error.kind() == ErrorKind::Interrupted => {
+                continue;
+            }
+            Err(error) => return Err(error),
+        };
+
+        md5.input(&buf[..len]);
+        sha1.input(&buf[..len]);
+        sha256.input(&buf[..len]);
+        num_bytes += len as u64;
+    }
+
+    Ok(Hash {
+        md5: md5.result().into(),
+        sha1: sha1.result().into(),
+        sha256: sha256.result().into(),
+        num_bytes: num_bytes,
+    })
+}
+
+impl super::Request for Request {
+
+    type Proto = rrg_proto::FingerprintRequest;
+
+    fn from_proto(proto: Self::Proto) -> Result<Self, session::ParseError> {
+        use std::convert::TryInto as _;
+
+        let max_size = proto.max_filesize();
+
+        let path = proto.pathspec
+            .ok_or(session::MissingFieldError::new("path spec"))?
+            .try_into().map_err(session::ParseError::malformed)?;
+
+        Ok(Request {
+            path: path,
+            max_size: max_size,
+        })
+    }
+
+    fn paths(&self) -> Vec<PathBuf> {
+        vec!(self.path.clone())
+    }
+}
+
+impl super::Response for Response {
+
+    const RDF_NAME: Option<&'static str> = Some("FingerprintResponse");
+
+    type Proto = rrg_proto::FingerprintResponse;
+
+    fn into_proto(self) -> Self::Proto {
+        rrg_proto::FingerprintResponse {
+            pathspec: Some(self.path.into()),
+            hash: Some(self.hash.into()),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<Hash> for rrg_proto::Hash {
+
+    fn from(hash: Hash) -> rrg_proto::Hash {
+        rrg_proto::Hash {
+            md5: Some(hash.md5.to_vec()),
+            sha1: Some(hash.sha1.to_vec()),
+            sha256: Some(hash.sha256.to_vec()),
+            num_bytes: Some(hash.num_bytes),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_reader_empty() {
+        let hash = reader(&b""[..]).unwrap();
+
+        assert_eq!(hex(&hash.md5), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(hex(&hash.sha1), "da39a3ee5e6b4b0d3255\
+                                     bfef95601890afd80709");
+        assert_eq!(hex(&hash.sha256), "e3b0c44298fc1c149afbf4c8996fb924\
+                                       27ae41e4649b934ca495991b7852b855");
+        assert_eq!(hash.num_bytes, 0);
+    }
+
+    #[test]
+    fn test_reader_multiple_buffers() {
+        let data = vec![0xf0; 3 * BUFFER_SIZE + 42];
+
+        let hash = reader(&data[..]).unwrap();
+
+        let sha256: [u8; 32] = Sha256::digest(&data).into();
+        assert_eq!(hash.sha256, sha256);
+        assert_eq!(hash.num_bytes, data.len() as u64);
+    }
+
+    #[test]
+    fn test_handle_regular_file() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::write(tempdir.path().join("foo"), b"foo").unwrap();
+
+        let request = Request {
+            path: tempdir.path().join("foo"),
+            max_size: 1024,
+        };
+
+        let mut session = session::test::Fake::new();
+        assert!(handle(&mut session, request).is_ok());
+
+        assert_eq!(session.reply_count(), 1);
+
+        let reply = session.reply::<Response>(0);
+        assert_eq!(reply.path, tempdir.path().join("foo"));
+        assert_eq!(hex(&reply.hash.md5), "acbd18db4cc2f85cedef654fccc4a4d8");
+        assert_eq!(hex(&reply.hash.sha1), "0beec7b5ea3f0fdbc95d\
+                                           0dd47f3c5bc275da8a33");
+        assert_eq!(hex(&reply.hash.sha256), "2c26b46b68ffc68ff99b453c1d304134\
+                                             13422d706483bfa0f98a5e886266e7ae");
+        assert_eq!(reply.hash.num_bytes, 3);
+    }
+
+    #[test]
+    fn test_handle_truncated_file() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::write(tempdir.path().join("foo"), b"foobar").unwrap();
+
+        let request = Request {
+            path: tempdir.path().join("foo"),
+            max_size: 3,
+        };
+
+        let mut session = session::test::Fake::new();
+        assert!(handle(&mut session, request).is_ok());
+
+        let reply = session.reply::<Response>(0);
+        assert_eq!(hex(&reply.hash.md5), "acbd18db4cc2f85cedef654fccc4a4d8");
+        assert_eq!(reply.hash.num_bytes, 3);
+    }
+
+    #[test]
+    fn test_handle_non_existent_file() {
+        let tempdir = tempfile::tempdir().unwrap();
+
+        let request = Request {
+            path: tempdir.path().join("foo"),
+            max_size: 1024,
+        };
+
+        let mut session = session::test::Fake::new();
+        assert!(handle(&mut session, request).is_err());
+    }
+
+    /// Formats the given bytes as a lowercase hexadecimal string.
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
@@ -104,6 +104,10 @@ impl super::Request for Request {
             path: path,
         })
     }
+
+    fn paths(&self) -> Vec<PathBuf> {
+        vec!(self.path.clone())
+    }
 }
 
 impl super::Response for Response {
@@ -0,0 +1,719 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+//! A handler and associated types for the process memory scan action.
+//!
+//! The memory scan action reads virtual memory of the specified processes and
+//! searches it for the given literals and regular expressions. Regions can be
+//! narrowed down by their permissions, the file they map or their size, which
+//! makes it possible to e.g. look for injected code in anonymous executable
+//! regions. Matching regions can be optionally sent to the transfer store.
+//!
+//! Memory layout is read from `/proc/[pid]/maps` and the memory itself is read
+//! through `/proc/[pid]/mem`, so the agent has to be allowed to trace scanned
+//! processes.
+
+use std::fs::File;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+use log::{debug, warn};
+use regex::bytes::Regex;
+
+use crate::action::finder::contents::{self, Literal, Match};
+use crate::action::finder::download::{self, Image, Transfer};
+use crate::action::finder::request::MatchMode;
+use crate::session::{self, Session};
+
+/// A request type for the process memory scan action.
+#[derive(Debug)]
+pub struct Request {
+    /// Identifiers of processes to scan.
+    pids: Vec<u32>,
+    /// A filter that regions have to pass to be scanned.
+    filter: Filter,
+    /// Regular expressions to search the memory for.
+    regexes: Vec<Regex>,
+    /// Literals to search the memory for.
+    literals: Vec<Vec<u8>>,
+    /// Options of the search (match mode and context size).
+    opts: contents::Opts,
+    /// Whether to send matching regions to the transfer store.
+    dump: bool,
+    /// A number of bytes per chunk that dumped regions are divided into.
+    chunk_size: u64,
+}
+
+/// A response type for the process memory scan action.
+#[derive(Debug)]
+pub struct Response {
+    /// An identifier of the scanned process.
+    pid: u32,
+    /// The region in which the hits were found.
+    region: Region,
+    /// Hits found in the region (with offsets being absolute addresses).
+    hits: Vec<Match>,
+    /// A blob image of the region contents (if the region was dumped).
+    image: Option<Image>,
+}
+
+/// A region of process virtual memory.
+#[derive(Debug, PartialEq, Eq)]
+struct Region {
+    /// An address of the first byte of the region.
+    start: u64,
+    /// An address of the first byte after the region.
+    end: u64,
+    /// Permissions of the region as reported by the kernel (e.g. `r-xp`).
+    perms: String,
+    /// A path to the mapped file or a pseudo-path like `[stack]` (if any).
+    path: Option<PathBuf>,
+}
+
+/// Criteria that regions have to meet to be scanned.
+#[derive(Debug)]
+struct Filter {
+    /// Permissions (e.g. `rx`) that the region must have.
+    perms: String,
+    /// A regex that the path of the region must match.
+    path: Option<regex::Regex>,
+    /// A minimum size of the region (in bytes).
+    min_size: u64,
+    /// A maximum size of the region (in bytes).
+    max_size: u64,
+}
+
+/// Handles requests for the process memory scan action.
+pub fn handle<S>(session: &mut S, request: Request) -> session::Result<()>
+where
+    S: Session,
+{
+    let mut transfer = Transfer::default();
+
+    for &pid in &request.pids {
+        scan_process(session, &mut transfer, pid, &request)?;
+    }
+
+    Ok(())
+}
+
+/// Scans memory of a single process and replies with matching regions.
+///
+/// Failures specific to the process (e.g. insufficient permissions) are only
+/// logged, so that they do not affect scanning of other processes.
+fn scan_process<S>(
+    session: &mut S,
+    transfer: &mut Transfer,
+    pid: u32,
+    request: &Request,
+) -> session::Result<()>
+where
+    S: Session,
+{
+    let regions = match regions(pid) {
+        Ok(regions) => regions,
+        Err(error) => {
+            warn_process(pid, "failed to read memory map", error);
+            return Ok(());
+        }
+    };
+
+    let mem = match File::open(format!("/proc/{}/mem", pid)) {
+        Ok(mem) => mem,
+        Err(error) => {
+            warn_process(pid, "failed to open memory", error);
+            return Ok(());
+        }
+    };
+
+    for region in regions {
+        session.heartbeat();
+        session.check_cancelled()?;
+
+        if !request.filter.matches(&region) {
+            continue;
+        }
+
+        let hits = match scan_region(&mem, &region, request) {
+            Ok(hits) => hits,
+            Err(error) => {
+                // Some regions (e.g. guard pages or `[vvar]`) cannot be read
+                // even if the kernel reports them as readable, so this is not
+                // worth a warning.
+                debug!("failed to read region {:x}-{:x} of process {}: {}",
+                       region.start, region.end, pid, error);
+                continue;
+            }
+        };
+
+        if hits.is_empty() {
+            continue;
+        }
+
+        let image = if request.dump {
+            dump_region(session, transfer, &mem, &region, request.chunk_size)?
+        } else {
+            None
+        };
+
+        session.reply(Response {
+            pid: pid,
+            region: region,
+            hits: hits,
+            image: image,
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Searches the region for all the patterns specified in the request.
+fn scan_region(
+    mem: &File,
+    region: &Region,
+    request: &Request,
+) -> std::io::Result<Vec<Match>> {
+    let mut hits = vec!();
+
+    for regex in &request.regexes {
+        hits.extend(scan_pattern(mem, region, regex, request.opts)?);
+    }
+    for literal in &request.literals {
+        let literal = Literal(literal);
+        hits.extend(scan_pattern(mem, region, &literal, request.opts)?);
+    }
+
+    hits.sort_by_key(|hit| hit.offset);
+    Ok(hits)
+}
+
+/// Searches the region for a single pattern.
+///
+/// Offsets of the returned matches are absolute addresses in the process
+/// address space.
+fn scan_pattern<P>(
+    mut mem: &File,
+    region: &Region,
+    pattern: &P,
+    opts: contents::Opts,
+) -> std::io::Result<Vec<Match>>
+where
+    P: contents::Pattern + ?Sized,
+{
+    mem.seek(SeekFrom::Start(region.start))?;
+
+    let mut hits = contents::scan(mem.take(region.size()), pattern, opts)?;
+    for hit in &mut hits {
+        hit.offset += region.start;
+    }
+
+    Ok(hits)
+}
+
+/// Sends contents of the region to the transfer store.
+///
+/// Failures to read the region are logged and result in `None` being returned.
+fn dump_region<S>(
+    session: &mut S,
+    transfer: &mut Transfer,
+    mut mem: &File,
+    region: &Region,
+    chunk_size: u64,
+) -> session::Result<Option<Image>>
+where
+    S: Session,
+{
+    let result = mem.seek(SeekFrom::Start(region.start))
+        .map_err(download::Error::Read)
+        .and_then(|_| {
+            transfer.reader(session, mem.take(region.size()), chunk_size)
+        });
+
+    match result {
+        Ok(image) => Ok(Some(image)),
+        Err(download::Error::Read(error)) => {
+            warn!("failed to dump region {:x}-{:x}: {}",
+                  region.start, region.end, error);
+            Ok(None)
+        }
+        Err(download::Error::Send(error)) => Err(error),
+    }
+}
+
+/// Logs a failure to access memory of the given process.
+fn warn_process(pid: u32, message: &str, error: std::io::Error) {
+    // Processes can terminate at any moment, so it is not a problem if some
+    // of them disappeared before we got to scanning them.
+    if error.kind() == ErrorKind::NotFound {
+        debug!("process {} no longer exists", pid);
+    } else {
+        warn!("{} of process {}: {}", message, pid, error);
+    }
+}
+
+/// Reads the list of memory regions of the given process.
+fn regions(pid: u32) -> std::io::Result<Vec<Region>> {
+    let maps = std::fs::read_to_string(format!("/proc/{}/maps", pid))?;
+
+    let regions = maps.lines().filter_map(|line| {
+        let region = parse_region(line);
+        if region.is_none() {
+            warn!("invalid memory map entry of process {}: {}", pid, line);
+        }
+        region
+    }).collect();
+
+    Ok(regions)
+}
+
+/// Parses a single line of the `/proc/[pid]/maps` file.
+///
+/// The line consists of five whitespace-separated fields (address range,
+/// permissions, offset, device and inode) followed by an optional path. Note
+/// that the path itself can contain whitespace.
+fn parse_region(line: &str) -> Option<Region> {
+    let mut rest = line;
+    let mut fields = [""; 5];
+    for field in fields.iter_mut() {
+        rest = rest.trim_start();
+        let len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        *field = &rest[..len];
+        rest = &rest[len..];
+    }
+
+    let mut range = fields[0].split('-');
+    let start = u64::from_str_radix(range.next()?, 16).ok()?;
+    let end = u64::from_str_radix(range.next()?, 16).ok()?;
+    if range.next().is_some() || end < start || fields[4].is_empty() {
+        return None;
+    }
+
+    let path = rest.trim();
+
+    Some(Region {
+        start: start,
+        end: end,
+        perms: String::from(fields[1]),
+        path: if path.is_empty() { None } else { Some(PathBuf::from(path)) },
+    })
+}
+
+impl Region {
+
+    /// Returns the size of the region in bytes.
+    fn size(&self) -> u64 {
+        self.end - self.start
+    }
+}
+
+impl Filter {
+
+    /// Checks whether the given region meets all the criteria.
+    fn matches(&self, region: &Region) -> bool {
+        if !self.perms.chars().all(|perm| region.perms.contains(perm)) {
+            return false;
+        }
+
+        if let Some(ref regex) = self.path {
+            let path = match region.path {
+                Some(ref path) => path.to_string_lossy(),
+                None => "".into(),
+            };
+
+            if !regex.is_match(&path) {
+                return false;
+            }
+        }
+
+        self.min_size <= region.size() && region.size() <= self.max_size
+    }
+}
+
+/// Parses a regex from raw bytes of the proto message.
+fn parse_regex(bytes: Vec<u8>) -> Result<Regex, session::ParseError> {
+    let string = std::str::from_utf8(&bytes)
+        .map_err(session::ParseError::malformed)?;
+
+    match Regex::new(string) {
+        Ok(regex) => Ok(regex),
+        Err(error) => Err(session::RegexParseError {
+            raw_data: bytes,
+            error: error,
+        }.into()),
+    }
+}
+
+impl super::Request for Request {
+
+    type Proto = rrg_proto::rrg::ScanMemoryArgs;
+
+    fn from_proto(proto: Self::Proto) -> Result<Request, session::ParseError> {
+        let perms = String::from(proto.required_perms());
+        let invalid_perm = perms.chars().find(|perm| !"rwxsp".contains(*perm));
+        if let Some(perm) = invalid_perm {
+            return Err(session::ParseError::malformed(
+                session::UnsupportedValueError {
+                    name: "required perms",
+                    value: perm,
+                }
+            ));
+        }
+
+        let path = match proto.path_regex {
+            Some(ref path) => match regex::Regex::new(path) {
+                Ok(regex) => Some(regex),
+                Err(error) => return Err(session::RegexParseError {
+                    raw_data: path.bytes().collect(),
+                    error: error,
+                }.into()),
+            },
+            None => None,
+        };
+
+        // Every chunk is read into memory at once, so its size has to be within
+        // the limit.
+        let chunk_size = proto.chunk_size();
+        if chunk_size == 0 || chunk_size > download::MAX_CHUNK_SIZE {
+            return Err(session::ParseError::malformed(
+                session::UnsupportedValueError {
+                    name: "chunk size",
+                    value: chunk_size,
+                }
+            ));
+        }
+
+        let mode = if proto.first_hit_only() {
+            MatchMode::FirstHit
+        } else {
+            MatchMode::AllHits
+        };
+
+        let opts = contents::Opts {
+            mode: mode,
+            bytes_before: proto.bytes_before() as usize,
+            bytes_after: proto.bytes_after() as usize,
+        };
+
+        let filter = Filter {
+            perms: perms,
+            path: path,
+            min_size: proto.min_region_size(),
+            max_size: proto.max_region_size.unwrap_or(u64::MAX),
+        };
+
+        let dump = proto.dump_matching_regions();
+
+        let regexes = proto.regexes.into_iter()
+            .map(parse_regex)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Request {
+            pids: proto.pids,
+            filter: filter,
+            regexes: regexes,
+            literals: proto.literals,
+            opts: opts,
+            dump: dump,
+            chunk_size: chunk_size,
+        })
+    }
+}
+
+impl super::Response for Response {
+
+    const RDF_NAME: Option<&'static str> = None;
+
+    type Proto = rrg_proto::rrg::ScanMemoryResult;
+
+    fn into_proto(self) -> rrg_proto::rrg::ScanMemoryResult {
+        let hits = self.hits.into_iter().map(|hit| {
+            rrg_proto::rrg::MemoryHit {
+                address: Some(hit.offset),
+                data: Some(hit.data),
+            }
+        }).collect();
+
+        let start = self.region.start;
+        let chunks = self.image.map(|image| image.chunks).unwrap_or_default()
+            .into_iter()
+            .map(|chunk| rrg_proto::rrg::MemoryChunk {
+                address: Some(start + chunk.offset),
+                length: Some(chunk.length),
+                digest: Some(chunk.id.to_sha256_bytes()),
+            })
+            .collect();
+
+        rrg_proto::rrg::ScanMemoryResult {
+            pid: Some(self.pid),
+            region: Some(self.region.into()),
+            hits: hits,
+            chunks: chunks,
+        }
+    }
+}
+
+impl From<Region> for rrg_proto::rrg::MemoryRegion {
+
+    fn from(region: Region) -> rrg_proto::rrg::MemoryRegion {
+        rrg_proto::rrg::MemoryRegion {
+            start: Some(region.start),
+            end: Some(region.end),
+            perms: Some(region.perms),
+            path: region.path.map(|path| path.to_string_lossy().into_owned()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::process::{Child, Command};
+
+    use super::*;
+
+    #[test]
+    fn test_from_proto_chunk_size() {
+        use crate::action::Request as _;
+
+        let args = |chunk_size| rrg_proto::rrg::ScanMemoryArgs {
+            chunk_size: Some(chunk_size),
+            ..Default::default()
+        };
+
+        assert!(Request::from_proto(args(0)).is_err());
+        assert!(Request::from_proto(args(4096)).is_ok());
+        assert!(Request::from_proto(args(download::MAX_CHUNK_SIZE)).is_ok());
+
+        let chunk_size = download::MAX_CHUNK_SIZE + 1;
+        assert!(Request::from_proto(args(chunk_size)).is_err());
+    }
+
+    #[test]
+    fn test_parse_region_file() {
+        let line = "7f3c1a200000-7f3c1a222000 r-xp 00002000 08:01 1054 \
+                    /usr/lib/libfoo.so";
+
+        assert_eq!(parse_region(line), Some(Region {
+            start: 0x7f3c1a200000,
+            end: 0x7f3c1a222000,
+            perms: String::from("r-xp"),
+            path: Some(PathBuf::from("/usr/lib/libfoo.so")),
+        }));
+    }
+
+    #[test]
+    fn test_parse_region_path_with_spaces() {
+        let line = "1000-2000 rw-s 00000000 00:05 42    /tmp/foo bar (deleted)";
+
+        let region = parse_region(line).unwrap();
+        assert_eq!(region.path, Some(PathBuf::from("/tmp/foo bar (deleted)")));
+    }
+
+    #[test]
+    fn test_parse_region_anonymous() {
+        let line = "1000-3000 rw-p 00000000 00:00 0 ";
+
+        let region = parse_region(line).unwrap();
+        assert_eq!(region.size(), 0x2000);
+        assert_eq!(region.path, None);
+    }
+
+    #[test]
+    fn test_parse_region_invalid() {
+        assert_eq!(parse_region(""), None);
+        assert_eq!(parse_region("1000 rw-p 00000000 00:00 0"), None);
+        assert_eq!(parse_region("2000-1000 rw-p 00000000 00:00 0"), None);
+        assert_eq!(parse_region("1000-2000 rw-p 00000000"), None);
+    }
+
+    #[test]
+    fn test_filter_perms() {
+        let filter = Filter {
+            perms: String::from("rx"),
+            ..Filter::default()
+        };
+
+        assert!(filter.matches(&region("r-xp", None)));
+        assert!(!filter.matches(&region("rw-p", None)));
+    }
+
+    #[test]
+    fn test_filter_path() {
+        let filter = Filter {
+            path: Some(regex::Regex::new(r"^\[stack\]$").unwrap()),
+            ..Filter::default()
+        };
+
+        assert!(filter.matches(&region("rw-p", Some("[stack]"))));
+        assert!(!filter.matches(&region("rw-p", Some("/usr/lib/libc.so"))));
+        assert!(!filter.matches(&region("rw-p", None)));
+    }
+
+    #[test]
+    fn test_filter_size() {
+        let filter = Filter {
+            min_size: 0x1000,
+            max_size: 0x2000,
+            ..Filter::default()
+        };
+
+        let mut region = region("rw-p", None);
+        assert!(filter.matches(&region));
+
+        region.end = region.start + 0x800;
+        assert!(!filter.matches(&region));
+
+        region.end = region.start + 0x4000;
+        assert!(!filter.matches(&region));
+    }
+
+    #[test]
+    fn test_handle_child_stack() {
+        let marker = format!("rrg-memory-test-{}", std::process::id());
+        let child = Sleep::spawn(&marker);
+
+        let request = Request {
+            pids: vec!(child.pid()),
+            filter: Filter {
+                perms: String::from("rw"),
+                path: Some(regex::Regex::new(r"^\[stack\]$").unwrap()),
+                ..Filter::default()
+            },
+            regexes: vec!(),
+            literals: vec!(marker.clone().into_bytes()),
+            opts: contents::Opts {
+                mode: MatchMode::AllHits,
+                bytes_before: 0,
+                bytes_after: 0,
+            },
+            dump: true,
+            chunk_size: 4096,
+        };
+
+        let mut session = session::test::Fake::new();
+        assert!(handle(&mut session, request).is_ok());
+
+        assert_eq!(session.reply_count(), 1);
+
+        let reply = session.reply::<Response>(0);
+        assert_eq!(reply.pid, child.pid());
+        assert_eq!(reply.region.path, Some(PathBuf::from("[stack]")));
+
+        assert!(!reply.hits.is_empty());
+        for hit in &reply.hits {
+            assert!(reply.region.start <= hit.offset);
+            assert!(hit.offset < reply.region.end);
+            assert_eq!(hit.data, marker.as_bytes());
+        }
+
+        let image = reply.image.as_ref().unwrap();
+        let size = image.chunks.iter().map(|chunk| chunk.length).sum::<u64>();
+        assert_eq!(size, reply.region.size());
+
+        let sink = session::Sink::TRANSFER_STORE;
+        assert!(session.response_count(sink) > 0);
+    }
+
+    #[test]
+    fn test_handle_child_no_hits() {
+        let child = Sleep::spawn("rrg-memory-test");
+
+        let request = Request {
+            pids: vec!(child.pid()),
+            filter: Filter::default(),
+            regexes: vec!(Regex::new("rrg-memory-test-[0-9]{32}").unwrap()),
+            literals: vec!(),
+            opts: contents::Opts {
+                mode: MatchMode::FirstHit,
+                bytes_before: 0,
+                bytes_after: 0,
+            },
+            dump: true,
+            chunk_size: 4096,
+        };
+
+        let mut session = session::test::Fake::new();
+        assert!(handle(&mut session, request).is_ok());
+
+        assert_eq!(session.reply_count(), 0);
+        assert_eq!(session.response_count(session::Sink::TRANSFER_STORE), 0);
+    }
+
+    #[test]
+    fn test_handle_non_existent_process() {
+        let child = Sleep::spawn("rrg-memory-test");
+        let pid = child.pid();
+        drop(child);
+
+        let request = Request {
+            pids: vec!(pid),
+            filter: Filter::default(),
+            regexes: vec!(),
+            literals: vec!(b"foo".to_vec()),
+            opts: contents::Opts {
+                mode: MatchMode::AllHits,
+                bytes_before: 0,
+                bytes_after: 0,
+            },
+            dump: false,
+            chunk_size: 4096,
+        };
+
+        let mut session = session::test::Fake::new();
+        assert!(handle(&mut session, request).is_ok());
+        assert_eq!(session.reply_count(), 0);
+    }
+
+    impl Default for Filter {
+
+        fn default() -> Filter {
+            Filter {
+                perms: String::new(),
+                path: None,
+                min_size: 0,
+                max_size: u64::MAX,
+            }
+        }
+    }
+
+    /// Creates a 4 KiB region with the given permissions and path.
+    fn region(perms: &str, path: Option<&str>) -> Region {
+        Region {
+            start: 0x1000,
+            end: 0x2000,
+            perms: String::from(perms),
+            path: path.map(PathBuf::from),
+        }
+    }
+
+    /// A child process that is killed (and reaped) when dropped.
+    struct Sleep(Child);
+
+    impl Sleep {
+
+        /// Spawns a sleeping child with the given marker in its environment.
+        fn spawn(marker: &str) -> Sleep {
+            let child = Command::new("sleep")
+                .arg("60")
+                .env("RRG_TEST_MARKER", marker)
+                .spawn()
+                .unwrap();
+
+            Sleep(child)
+        }
+
+        fn pid(&self) -> u32 {
+            self.0.id()
+        }
+    }
+
+    impl Drop for Sleep {
+
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+}
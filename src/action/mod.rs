@@ -18,6 +18,15 @@
 #[cfg(target_os = "linux")]
 pub mod filesystems;
 
+#[cfg(target_os = "linux")]
+pub mod processes;
+
+#[cfg(target_os = "linux")]
+pub mod memory;
+
+#[cfg(target_os = "linux")]
+pub mod stats;
+
 #[cfg(target_family = "unix")]
 pub mod interfaces;
 
@@ -30,6 +39,11 @@ pub mod stat;
 pub mod insttime;
 pub mod memsize;
 pub mod finder;
+pub mod hash;
+
+pub mod policy;
+
+use std::path::PathBuf;
 
 use crate::session::{self, Session, Task};
 
@@ -46,6 +60,15 @@ pub trait Request: Sized {
 
     /// A method for converting raw proto messages into structured requests.
     fn from_proto(proto: Self::Proto) -> Result<Self, session::ParseError>;
+
+    /// Returns filesystem paths that the action is going to access.
+    ///
+    /// These are verified against the path constraints of the action policy
+    /// before the request is handed over to the action handler. Actions that
+    /// do not access the filesystem do not need to implement this method.
+    fn paths(&self) -> Vec<PathBuf> {
+        vec!()
+    }
 }
 
 /// Abstraction for action-specific responses.
@@ -97,12 +120,14 @@ impl Response for () {
 /// This method is a mapping between action names (as specified in the protocol)
 /// and action handlers (implemented on the agent).
 ///
-/// If the given action is unknown (or not yet implemented), this function will
-/// return an error.
+/// If the given action is unknown (or not yet implemented) or it is not allowed
+/// by the action policy, this function will return an error.
 pub fn dispatch<'s, S>(action: &str, task: Task<'s, S>) -> session::Result<()>
 where
     S: Session,
 {
+    self::policy::check_action(&crate::config::get().actions, action)?;
+
     match action {
         "SendStartupInfo" => task.execute(self::startup::handle),
         "GetClientInfo" => task.execute(self::metadata::handle),
@@ -111,6 +136,8 @@ where
         "ListNetworkConnections" => task.execute(self::network::handle),
         "GetFileStat" => task.execute(self::stat::handle),
         "GetInstallDate" => task.execute(self::insttime::handle),
+        "FileFinder" => task.execute(self::finder::handle),
+        "HashFile" => task.execute(self::hash::handle),
 
         #[cfg(target_family = "unix")]
         "EnumerateInterfaces" => task.execute(self::interfaces::handle),
@@ -118,6 +145,15 @@ where
         #[cfg(target_os = "linux")]
         "EnumerateFilesystems" => task.execute(self::filesystems::handle),
 
+        #[cfg(target_os = "linux")]
+        "ListProcesses" => task.execute(self::processes::handle),
+
+        #[cfg(target_os = "linux")]
+        "ScanMemory" => task.execute(self::memory::handle),
+
+        #[cfg(target_os = "linux")]
+        "GetClientStats" => task.execute(self::stats::handle),
+
 
         "GetMemorySize" => task.execute(self::memsize::handle),
         action => return Err(session::Error::Dispatch(String::from(action))),
@@ -0,0 +1,218 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+//! Utilities for enforcing the policy of allowed actions.
+//!
+//! Not every deployment wants to expose all the capabilities of the agent to
+//! the server. The policy (specified in the `[actions]` section of the agent
+//! configuration) lists actions that the agent is allowed (or forbidden) to
+//! execute and, optionally, paths that filesystem actions are allowed to touch.
+//!
+//! Note that path constraints are checked against paths given in the request.
+//! Symlinks in these paths are resolved before the check, but actions that
+//! follow symlinks during the traversal (e.g. the finder action with the
+//! corresponding option) can still reach files outside of the allowed paths.
+
+use std::fmt::{Display, Formatter};
+use std::path::{Component, Path, PathBuf};
+
+use crate::config::Actions;
+
+/// Verifies that the policy allows to execute the given action.
+pub fn check_action(policy: &Actions, action: &str) -> Result<(), DeniedError> {
+    let denied = policy.deny.iter().any(|name| name == action);
+    let allowed = match policy.allow {
+        Some(ref allow) => allow.iter().any(|name| name == action),
+        None => true,
+    };
+
+    if allowed && !denied {
+        Ok(())
+    } else {
+        Err(DeniedError::Action(String::from(action)))
+    }
+}
+
+/// Verifies that the policy allows to access all the given paths.
+pub fn check_paths(policy: &Actions, paths: &[PathBuf])
+    -> Result<(), DeniedError>
+{
+    let prefixes = match policy.paths {
+        Some(ref prefixes) => prefixes,
+        None => return Ok(()),
+    };
+
+    for path in paths {
+        if !is_allowed(prefixes, path) {
+            return Err(DeniedError::Path(path.clone()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks whether the path is within any of the allowed prefixes.
+fn is_allowed(prefixes: &[PathBuf], path: &Path) -> bool {
+    // Relative paths and parent directory references could be used to escape
+    // the allowed prefixes, so such paths are rejected upfront.
+    if !path.is_absolute() {
+        return false;
+    }
+    if path.components().any(|component| component == Component::ParentDir) {
+        return false;
+    }
+
+    let path = resolve(path);
+    prefixes.iter().any(|prefix| path.starts_with(resolve(prefix)))
+}
+
+/// Resolves all the symlinks in the given path (if it exists).
+fn resolve(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// An error type for requests refused by the policy.
+#[derive(Debug, PartialEq, Eq)]
+pub enum DeniedError {
+    /// The action is not allowed to be executed.
+    Action(String),
+    /// The action is not allowed to access the path.
+    Path(PathBuf),
+}
+
+impl Display for DeniedError {
+
+    fn fmt(&self, fmt: &mut Formatter) -> std::fmt::Result {
+        use DeniedError::*;
+
+        match *self {
+            Action(ref name) => {
+                write!(fmt, "action '{}' is not allowed", name)
+            }
+            Path(ref path) => {
+                write!(fmt, "access to '{}' is not allowed", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for DeniedError {
+
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_check_action_default() {
+        let policy = Actions::default();
+
+        assert!(check_action(&policy, "Timeline").is_ok());
+    }
+
+    #[test]
+    fn test_check_action_allowed() {
+        let policy = Actions {
+            allow: Some(vec!(String::from("Timeline"))),
+            ..Default::default()
+        };
+
+        assert!(check_action(&policy, "Timeline").is_ok());
+        assert_eq! {
+            check_action(&policy, "ListDirectory"),
+            Err(DeniedError::Action(String::from("ListDirectory")))
+        };
+    }
+
+    #[test]
+    fn test_check_action_denied() {
+        let policy = Actions {
+            deny: vec!(String::from("Timeline")),
+            ..Default::default()
+        };
+
+        assert!(check_action(&policy, "ListDirectory").is_ok());
+        assert_eq! {
+            check_action(&policy, "Timeline"),
+            Err(DeniedError::Action(String::from("Timeline")))
+        };
+    }
+
+    #[test]
+    fn test_check_paths_default() {
+        let policy = Actions::default();
+
+        assert!(check_paths(&policy, &[PathBuf::from("/etc")]).is_ok());
+    }
+
+    #[test]
+    fn test_check_paths_allowed() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tempdir.path().join("foo")).unwrap();
+
+        let policy = Actions {
+            paths: Some(vec!(tempdir.path().join("foo"))),
+            ..Default::default()
+        };
+
+        let paths = [
+            tempdir.path().join("foo"),
+            tempdir.path().join("foo").join("bar"),
+        ];
+        assert!(check_paths(&policy, &paths).is_ok());
+
+        let path = tempdir.path().join("bar");
+        assert_eq! {
+            check_paths(&policy, &[path.clone()]),
+            Err(DeniedError::Path(path))
+        };
+    }
+
+    #[test]
+    fn test_check_paths_parent_dir() {
+        let tempdir = tempfile::tempdir().unwrap();
+
+        let policy = Actions {
+            paths: Some(vec!(tempdir.path().join("foo"))),
+            ..Default::default()
+        };
+
+        let path = tempdir.path().join("foo").join("..").join("bar");
+        assert!(check_paths(&policy, &[path]).is_err());
+    }
+
+    #[test]
+    fn test_check_paths_relative() {
+        let policy = Actions {
+            paths: Some(vec!(PathBuf::from("/"))),
+            ..Default::default()
+        };
+
+        assert!(check_paths(&policy, &[PathBuf::from("foo")]).is_err());
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_check_paths_symlink() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tempdir.path().join("foo")).unwrap();
+        std::fs::create_dir(tempdir.path().join("bar")).unwrap();
+
+        let link = tempdir.path().join("foo").join("link");
+        std::os::unix::fs::symlink(tempdir.path().join("bar"), &link).unwrap();
+
+        let policy = Actions {
+            paths: Some(vec!(tempdir.path().join("foo"))),
+            ..Default::default()
+        };
+
+        assert!(check_paths(&policy, &[link]).is_err());
+    }
+}
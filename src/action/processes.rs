@@ -0,0 +1,467 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+//! A handler and associated types for the process listing action.
+//!
+//! The process listing action returns information about all processes running
+//! on the system (e.g. their command line, owner or resource usage). All the
+//! information is collected directly from the `/proc` filesystem.
+
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use log::warn;
+
+use crate::session::{self, Session};
+
+/// A response type for the process listing action.
+#[derive(Debug)]
+pub struct Response {
+    /// An identifier of the process.
+    pid: u32,
+    /// An identifier of the parent process.
+    ppid: u32,
+    /// A name of the process executable (possibly truncated).
+    name: String,
+    /// A path to the executable of the process (if available).
+    exe: Option<PathBuf>,
+    /// Command-line arguments that the process was invoked with.
+    cmdline: Vec<String>,
+    /// A current working directory of the process (if available).
+    cwd: Option<PathBuf>,
+    /// A human-readable state of the process (e.g. `running`).
+    status: Option<&'static str>,
+    /// Real, effective and saved user identifiers of the process.
+    uids: Option<[u32; 3]>,
+    /// Real, effective and saved group identifiers of the process.
+    gids: Option<[u32; 3]>,
+    /// A time at which the process was started.
+    start_time: Option<SystemTime>,
+    /// A nice value of the process.
+    nice: i32,
+    /// A number of threads of the process.
+    num_threads: u32,
+    /// A CPU time that the process spent in the user mode.
+    user_cpu_time: Duration,
+    /// A CPU time that the process spent in the kernel mode.
+    system_cpu_time: Duration,
+    /// A resident set size of the process (in bytes).
+    rss_size: u64,
+    /// A virtual memory size of the process (in bytes).
+    vms_size: u64,
+    /// Paths of files currently opened by the process.
+    open_files: Vec<PathBuf>,
+}
+
+/// An error type for failures that can occur during the process listing.
+#[derive(Debug)]
+enum Error {
+    /// A failure occurred during the attempt to list the `/proc` directory.
+    ListProc(std::io::Error),
+}
+
+impl std::error::Error for Error {
+
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use Error::*;
+
+        match *self {
+            ListProc(ref error) => Some(error),
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use Error::*;
+
+        match *self {
+            ListProc(ref error) => {
+                write!(fmt, "unable to list processes: {}", error)
+            }
+        }
+    }
+}
+
+impl From<Error> for session::Error {
+
+    fn from(error: Error) -> session::Error {
+        session::Error::action(error)
+    }
+}
+
+/// System-wide information needed to interpret process statistics.
+pub(crate) struct System {
+    /// A number of clock ticks per second (used by CPU time counters).
+    pub(crate) ticks_per_sec: u64,
+    /// A size of the memory page in bytes (used by memory counters).
+    pub(crate) page_size: u64,
+    /// A time at which the system was booted (if available).
+    pub(crate) boot_time: Option<SystemTime>,
+}
+
+/// Handles requests for the process listing action.
+pub fn handle<S: Session>(session: &mut S, _: ()) -> session::Result<()> {
+    let system = System::new();
+
+    let pids = std::fs::read_dir("/proc").map_err(Error::ListProc)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<u32>().ok());
+
+    for pid in pids {
+        let response = match process(pid, &system) {
+            Ok(response) => response,
+            // Processes can terminate at any moment, so it is not a problem
+            // that some of them disappeared while we were listing them.
+            Err(ref error) if error.kind() == ErrorKind::NotFound => continue,
+            Err(error) => {
+                warn!("failed to collect process {} details: {}", pid, error);
+                continue;
+            }
+        };
+
+        session.reply(response)?;
+    }
+
+    Ok(())
+}
+
+impl System {
+
+    /// Collects system-wide information about the currently running system.
+    pub(crate) fn new() -> System {
+        // SAFETY: `sysconf` is always safe to call and returns -1 in case of
+        // errors (which we handle by falling back to common defaults).
+        let ticks_per_sec = match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+            ticks_per_sec if ticks_per_sec > 0 => ticks_per_sec as u64,
+            _ => 100,
+        };
+        let page_size = match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+            page_size if page_size > 0 => page_size as u64,
+            _ => 4096,
+        };
+
+        let boot_time = match std::fs::read_to_string("/proc/stat") {
+            Ok(stat) => parse_boot_time(&stat),
+            Err(error) => {
+                warn!("failed to read system statistics: {}", error);
+                None
+            }
+        };
+
+        System {
+            ticks_per_sec: ticks_per_sec,
+            page_size: page_size,
+            boot_time: boot_time,
+        }
+    }
+}
+
+/// Collects information about the process with the specified identifier.
+fn process(pid: u32, system: &System) -> std::io::Result<Response> {
+    let dir = Path::new("/proc").join(pid.to_string());
+
+    let stat = std::fs::read_to_string(dir.join("stat"))?;
+    let stat = parse_stat(&stat).ok_or_else(|| {
+        std::io::Error::new(ErrorKind::InvalidData, "malformed process stat")
+    })?;
+
+    // Unlike `stat`, files below are not crucial and some of them might not be
+    // accessible (e.g. links of processes owned by other users), so we do not
+    // fail if we cannot read them.
+    let status = std::fs::read_to_string(dir.join("status")).ok();
+
+    let cmdline = std::fs::read(dir.join("cmdline"))
+        .map(|cmdline| parse_cmdline(&cmdline))
+        .unwrap_or_default();
+
+    let start_time = system.boot_time.map(|boot_time| {
+        boot_time + ticks(stat.start_time, system.ticks_per_sec)
+    });
+
+    Ok(Response {
+        pid: pid,
+        ppid: stat.ppid,
+        name: stat.name,
+        exe: std::fs::read_link(dir.join("exe")).ok(),
+        cmdline: cmdline,
+        cwd: std::fs::read_link(dir.join("cwd")).ok(),
+        status: state_name(stat.state),
+        uids: status.as_ref().and_then(|status| parse_ids(status, "Uid:")),
+        gids: status.as_ref().and_then(|status| parse_ids(status, "Gid:")),
+        start_time: start_time,
+        nice: stat.nice,
+        num_threads: stat.num_threads,
+        user_cpu_time: ticks(stat.user_time, system.ticks_per_sec),
+        system_cpu_time: ticks(stat.system_time, system.ticks_per_sec),
+        rss_size: stat.rss_pages.saturating_mul(system.page_size),
+        vms_size: stat.vms_size,
+        open_files: open_files(&dir),
+    })
+}
+
+/// Lists paths of regular files opened by the process in the given directory.
+fn open_files(dir: &Path) -> Vec<PathBuf> {
+    let entries = match std::fs::read_dir(dir.join("fd")) {
+        Ok(entries) => entries,
+        Err(_) => return vec!(),
+    };
+
+    // Descriptors can also point to sockets, pipes and other anonymous inodes
+    // (e.g. `socket:[1337]`). Only actual files have absolute paths.
+    entries
+        .filter_map(|entry| std::fs::read_link(entry.ok()?.path()).ok())
+        .filter(|path| path.is_absolute())
+        .collect()
+}
+
+/// Converts the given number of clock ticks to a duration.
+pub(crate) fn ticks(count: u64, ticks_per_sec: u64) -> Duration {
+    let secs = count / ticks_per_sec;
+    let nanos = (count % ticks_per_sec) * 1_000_000_000 / ticks_per_sec;
+
+    Duration::new(secs, nanos as u32)
+}
+
+/// Process statistics as reported in the `/proc/[pid]/stat` file.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Stat {
+    /// A name of the process executable (possibly truncated).
+    pub(crate) name: String,
+    /// A one-character code of the process state.
+    pub(crate) state: char,
+    /// An identifier of the parent process.
+    pub(crate) ppid: u32,
+    /// A CPU time spent in the user mode (in clock ticks).
+    pub(crate) user_time: u64,
+    /// A CPU time spent in the kernel mode (in clock ticks).
+    pub(crate) system_time: u64,
+    /// A nice value of the process.
+    pub(crate) nice: i32,
+    /// A number of threads of the process.
+    pub(crate) num_threads: u32,
+    /// A time at which the process started after the boot (in clock ticks).
+    pub(crate) start_time: u64,
+    /// A virtual memory size (in bytes).
+    pub(crate) vms_size: u64,
+    /// A resident set size (in pages).
+    pub(crate) rss_pages: u64,
+}
+
+/// Parses contents of the `/proc/[pid]/stat` file.
+///
+/// See the `proc(5)` manual page for the description of the format.
+pub(crate) fn parse_stat(stat: &str) -> Option<Stat> {
+    // The name can contain arbitrary characters (including spaces and parens),
+    // so we have to look for the last closing paren to find where it ends.
+    let name_start = stat.find('(')? + 1;
+    let name_end = stat.rfind(')')?;
+
+    let name = stat.get(name_start..name_end)?;
+    let fields = stat.get(name_end + 1..)?
+        .split_whitespace()
+        .collect::<Vec<_>>();
+
+    // Indices below are shifted by 3 (fields preceding the state) compared to
+    // the numbering used by the manual page.
+    let field = |index: usize| fields.get(index - 3).copied();
+
+    Some(Stat {
+        name: String::from(name),
+        state: field(3)?.chars().next()?,
+        ppid: field(4)?.parse().ok()?,
+        user_time: field(14)?.parse().ok()?,
+        system_time: field(15)?.parse().ok()?,
+        nice: field(19)?.parse().ok()?,
+        num_threads: field(20)?.parse().ok()?,
+        start_time: field(22)?.parse().ok()?,
+        vms_size: field(23)?.parse().ok()?,
+        rss_pages: field(24)?.parse().ok()?,
+    })
+}
+
+/// Parses real, effective and saved identifiers from the given status line.
+///
+/// The `key` should be either `Uid:` or `Gid:`.
+fn parse_ids(status: &str, key: &str) -> Option<[u32; 3]> {
+    let line = status.lines().find(|line| line.starts_with(key))?;
+
+    let mut ids = line[key.len()..].split_whitespace()
+        .map(|id| id.parse().ok());
+
+    Some([ids.next()??, ids.next()??, ids.next()??])
+}
+
+/// Parses contents of the `/proc/[pid]/cmdline` file.
+fn parse_cmdline(cmdline: &[u8]) -> Vec<String> {
+    // Arguments are terminated (not separated) with null bytes.
+    let cmdline = match cmdline.split_last() {
+        Some((b'\0', rest)) => rest,
+        _ => cmdline,
+    };
+    if cmdline.is_empty() {
+        return vec!();
+    }
+
+    cmdline.split(|byte| *byte == b'\0')
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect()
+}
+
+/// Parses the boot time from contents of the `/proc/stat` file.
+fn parse_boot_time(stat: &str) -> Option<SystemTime> {
+    let line = stat.lines().find(|line| line.starts_with("btime "))?;
+    let secs = line["btime ".len()..].trim().parse().ok()?;
+
+    Some(std::time::UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// Returns a human-readable name of the process state code.
+///
+/// The names follow the convention used by the Python agent (psutil).
+fn state_name(state: char) -> Option<&'static str> {
+    match state {
+        'R' => Some("running"),
+        'S' => Some("sleeping"),
+        'D' => Some("disk-sleep"),
+        'Z' => Some("zombie"),
+        'T' => Some("stopped"),
+        't' => Some("tracing-stop"),
+        'X' | 'x' => Some("dead"),
+        'K' => Some("wake-kill"),
+        'W' => Some("waking"),
+        'I' => Some("idle"),
+        'P' => Some("parked"),
+        _ => None,
+    }
+}
+
+impl super::Response for Response {
+
+    const RDF_NAME: Option<&'static str> = Some("Process");
+
+    type Proto = rrg_proto::Process;
+
+    fn into_proto(self) -> rrg_proto::Process {
+        let path = |path: PathBuf| path.to_string_lossy().into_owned();
+
+        let ctime = self.start_time
+            .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|duration| duration.as_micros() as u64);
+
+        rrg_proto::Process {
+            pid: Some(self.pid),
+            ppid: Some(self.ppid),
+            name: Some(self.name),
+            exe: self.exe.map(path),
+            cmdline: self.cmdline,
+            ctime: ctime,
+            real_uid: self.uids.map(|uids| uids[0]),
+            effective_uid: self.uids.map(|uids| uids[1]),
+            saved_uid: self.uids.map(|uids| uids[2]),
+            real_gid: self.gids.map(|gids| gids[0]),
+            effective_gid: self.gids.map(|gids| gids[1]),
+            saved_gid: self.gids.map(|gids| gids[2]),
+            status: self.status.map(String::from),
+            nice: Some(self.nice),
+            cwd: self.cwd.map(path),
+            num_threads: Some(self.num_threads),
+            user_cpu_time: Some(self.user_cpu_time.as_secs_f32()),
+            system_cpu_time: Some(self.system_cpu_time.as_secs_f32()),
+            rss_size: Some(self.rss_size),
+            vms_size: Some(self.vms_size),
+            open_files: self.open_files.into_iter().map(path).collect(),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_handle_current_process() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let tempfile_path = tempdir.path().join("foo");
+        let _tempfile = std::fs::File::create(&tempfile_path).unwrap();
+        let tempfile_path = tempfile_path.canonicalize().unwrap();
+
+        let mut session = session::test::Fake::new();
+        assert!(handle(&mut session, ()).is_ok());
+
+        let process = session.replies::<Response>()
+            .find(|process| process.pid == std::process::id())
+            .unwrap();
+
+        assert!(!process.name.is_empty());
+        assert!(!process.cmdline.is_empty());
+        assert_eq!(process.exe, Some(std::env::current_exe().unwrap()));
+        assert_eq!(process.cwd, Some(std::env::current_dir().unwrap()));
+        assert!(process.num_threads >= 1);
+        assert!(process.rss_size > 0);
+        assert!(process.vms_size > 0);
+        assert!(process.start_time.unwrap() <= SystemTime::now());
+        assert!(process.open_files.contains(&tempfile_path));
+
+        // SAFETY: These functions are always safe to call.
+        let uid = unsafe { libc::getuid() };
+        let gid = unsafe { libc::getgid() };
+        assert_eq!(process.uids.unwrap()[0], uid);
+        assert_eq!(process.gids.unwrap()[0], gid);
+    }
+
+    #[test]
+    fn test_parse_stat() {
+        let stat = "1337 (foo) bar) S 42 1337 1337 0 -1 4194560 1000 0 0 0 \
+                    150 25 0 0 20 -5 3 0 123456 8192000 512 1844674407";
+
+        let stat = parse_stat(stat).unwrap();
+        assert_eq!(stat.name, "foo) bar");
+        assert_eq!(stat.state, 'S');
+        assert_eq!(stat.ppid, 42);
+        assert_eq!(stat.user_time, 150);
+        assert_eq!(stat.system_time, 25);
+        assert_eq!(stat.nice, -5);
+        assert_eq!(stat.num_threads, 3);
+        assert_eq!(stat.start_time, 123456);
+        assert_eq!(stat.vms_size, 8192000);
+        assert_eq!(stat.rss_pages, 512);
+    }
+
+    #[test]
+    fn test_parse_stat_malformed() {
+        assert_eq!(parse_stat("1337 (foo) S 42"), None);
+        assert_eq!(parse_stat("garbage"), None);
+    }
+
+    #[test]
+    fn test_parse_ids() {
+        let status = "Name:\tfoo\n\
+                      Uid:\t1000\t1001\t1002\t1003\n\
+                      Gid:\t7\t8\t9\t10\n";
+
+        assert_eq!(parse_ids(status, "Uid:"), Some([1000, 1001, 1002]));
+        assert_eq!(parse_ids(status, "Gid:"), Some([7, 8, 9]));
+        assert_eq!(parse_ids("Name:\tfoo\n", "Uid:"), None);
+    }
+
+    #[test]
+    fn test_parse_cmdline() {
+        let cmdline = parse_cmdline(b"/bin/foo\0--bar\0baz quux\0");
+        assert_eq!(cmdline, vec!("/bin/foo", "--bar", "baz quux"));
+
+        assert!(parse_cmdline(b"").is_empty());
+    }
+
+    #[test]
+    fn test_ticks() {
+        assert_eq!(ticks(250, 100), Duration::from_millis(2500));
+        assert_eq!(ticks(0, 100), Duration::from_secs(0));
+    }
+}
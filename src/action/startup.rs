@@ -23,6 +23,8 @@ pub struct Response {
     boot_time: SystemTime,
     /// Metadata about the RRG agent.
     metadata: Metadata,
+    /// Number of action handlers that panicked since the agent started.
+    crash_count: usize,
 }
 
 /// Handles requests for the startup action.
@@ -30,6 +32,7 @@ pub fn handle<S: Session>(session: &mut S, _: ()) -> session::Result<()> {
     session.send(session::Sink::STARTUP, Response {
         boot_time: boot_time(),
         metadata: Metadata::from_cargo(),
+        crash_count: session::crash_count(),
     })?;
 
     Ok(())
@@ -47,9 +50,9 @@ impl super::Response for Response {
 
     const RDF_NAME: Option<&'static str> = Some("StartupInfo");
 
-    type Proto = rrg_proto::StartupInfo;
+    type Proto = rrg_proto::rrg::StartupInfo;
 
-    fn into_proto(self) -> rrg_proto::StartupInfo {
+    fn into_proto(self) -> rrg_proto::rrg::StartupInfo {
         let boot_time_micros = match rrg_proto::micros(self.boot_time) {
             Ok(boot_time_micros) => boot_time_micros,
             Err(error) => {
@@ -58,9 +61,19 @@ impl super::Response for Response {
             }
         };
 
-        rrg_proto::StartupInfo {
-            client_info: Some(self.metadata.into()),
+        // GRR has no field for crashes, so we send the RRG extension of the
+        // startup message which carries the client information as raw bytes.
+        let client_info: rrg_proto::ClientInformation = self.metadata.into();
+        let mut client_info_bytes = vec!();
+        if let Err(error) = prost::Message::encode(&client_info,
+                                                   &mut client_info_bytes) {
+            error!("failed to encode client information: {}", error);
+        }
+
+        rrg_proto::rrg::StartupInfo {
+            client_info: Some(client_info_bytes),
             boot_time: Some(boot_time_micros),
+            crash_count: Some(self.crash_count as u64),
         }
     }
 }
@@ -95,4 +108,40 @@ mod tests {
         assert!(response.metadata.version.as_numeric() > 0);
         assert_eq!(response.metadata.name, "rrg");
     }
+
+    #[test]
+    fn test_crash_count() {
+        let mut session = session::test::Fake::new();
+        assert!(handle(&mut session, ()).is_ok());
+
+        assert!(session::catch_panic(|| panic!("foo")).is_err());
+        assert!(handle(&mut session, ()).is_ok());
+
+        let sink = session::Sink::STARTUP;
+        let before = session.response::<Response>(sink, 0).crash_count;
+        let after = session.response::<Response>(sink, 1).crash_count;
+
+        // Tests are run concurrently, so other tests might have increased the
+        // count in the meantime as well.
+        assert!(after > before);
+    }
+
+    #[test]
+    fn test_into_proto() {
+        let response = Response {
+            boot_time: std::time::UNIX_EPOCH,
+            metadata: Metadata::from_cargo(),
+            crash_count: 3,
+        };
+
+        use crate::action::Response as _;
+
+        let proto = response.into_proto();
+        assert_eq!(proto.crash_count, Some(3));
+
+        let client_info: rrg_proto::ClientInformation = {
+            prost::Message::decode(proto.client_info()).unwrap()
+        };
+        assert_eq!(client_info.client_name(), "rrg");
+    }
 }
@@ -71,6 +71,9 @@ pub struct Response {
     /// Additional Linux-specific file flags.
     #[cfg(target_os = "linux")]
     flags_linux: Option<u32>,
+    /// Extended Linux-specific metadata about the file.
+    #[cfg(target_os = "linux")]
+    statx: Option<crate::fs::linux::Statx>,
     // TODO: Add support for collecting file flags on macOS.
 }
 
@@ -139,12 +142,21 @@ where
         vec!()
     };
 
+    #[cfg(target_os = "linux")]
+    let statx = ack! {
+        crate::fs::linux::statx(&request.path, request.follow_symlink),
+        warn: "failed to collect extended metadata for '{}'",
+              request.path.display()
+    }.flatten();
+
     #[cfg(target_os = "linux")]
     let flags_linux = if !metadata.file_type().is_symlink() {
+        // Not all filesystems support the ioctl to get flags, but some of the
+        // flags can also be reported through the extended metadata.
         ack! {
             crate::fs::linux::flags(&request.path),
             warn: "failed to collect flags for '{}'", request.path.display()
-        }
+        }.or_else(|| statx.map(|statx| flags_from_statx(&statx)))
     } else {
         // Flags are available only for non-symlinks. For symlinks, the function
         // would return flags mask for the target file, which can look confusing
@@ -160,6 +172,8 @@ where
         ext_attrs: ext_attrs,
         #[cfg(target_os = "linux")]
         flags_linux: flags_linux,
+        #[cfg(target_os = "linux")]
+        statx: statx,
     };
 
     session.reply(response)?;
@@ -184,28 +198,81 @@ impl super::Request for Request {
             collect_ext_attrs: proto.collect_ext_attrs.unwrap_or(false),
         })
     }
+
+    fn paths(&self) -> Vec<PathBuf> {
+        vec!(self.path.clone())
+    }
 }
 
 impl super::Response for Response {
 
     const RDF_NAME: Option<&'static str> = Some("StatEntry");
 
-    type Proto = rrg_proto::StatEntry;
+    type Proto = rrg_proto::extension::Extended<
+        rrg_proto::StatEntry,
+        rrg_proto::rrg::StatEntryExtension,
+    >;
 
     fn into_proto(self) -> Self::Proto {
         use rrg_proto::convert::IntoLossy as _;
 
-        rrg_proto::StatEntry {
+        let proto: rrg_proto::StatEntry = self.metadata.into_lossy();
+
+        // The standard library might not be able to obtain creation time, but
+        // it is usually available through the extended metadata.
+        #[cfg(target_os = "linux")]
+        let st_btime = proto.st_btime.or_else(|| {
+            self.statx
+                .and_then(|statx| statx.btime)
+                .and_then(|btime| rrg_proto::secs(btime).ok())
+        });
+
+        #[cfg(not(target_os = "linux"))]
+        let st_btime = proto.st_btime;
+
+        #[cfg(target_os = "linux")]
+        let extension = rrg_proto::rrg::StatEntryExtension {
+            mnt_id: self.statx.and_then(|statx| statx.mnt_id),
+        };
+
+        #[cfg(not(target_os = "linux"))]
+        let extension = rrg_proto::rrg::StatEntryExtension::default();
+
+        let message = rrg_proto::StatEntry {
             pathspec: Some(self.path.into()),
             #[cfg(target_family = "unix")]
             ext_attrs: self.ext_attrs.into_iter().map(Into::into).collect(),
             #[cfg(target_os = "linux")]
             st_flags_linux: self.flags_linux,
-            ..self.metadata.into_lossy()
+            st_btime: st_btime,
+            ..proto
+        };
+
+        rrg_proto::extension::Extended {
+            message: message,
+            extension: extension,
         }
     }
 }
 
+/// Converts attributes of the extended metadata to Linux-specific file flags.
+///
+/// Only attributes that have the same values as the corresponding flags are
+/// preserved.
+#[cfg(target_os = "linux")]
+fn flags_from_statx(statx: &crate::fs::linux::Statx) -> u32 {
+    use crate::fs::linux::*;
+
+    const MASK: u64 = STATX_ATTR_COMPRESSED |
+                      STATX_ATTR_IMMUTABLE |
+                      STATX_ATTR_APPEND |
+                      STATX_ATTR_NODUMP |
+                      STATX_ATTR_ENCRYPTED |
+                      STATX_ATTR_VERITY;
+
+    (statx.attributes & MASK) as u32
+}
+
 /// Collects extended attributes of a file specified by the request.
 #[cfg(target_family = "unix")]
 fn ext_attrs(request: &Request) -> Vec<crate::fs::unix::ExtAttr> {
@@ -391,6 +458,81 @@ mod tests {
         assert!(reply.metadata.is_file());
     }
 
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_handle_with_link_statx_on_linux() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let symlink = tempdir.path().join("foo");
+        let target = tempdir.path().join("bar");
+
+        File::create(&target).unwrap();
+        std::os::unix::fs::symlink(&target, &symlink).unwrap();
+
+        let request = Request {
+            path: symlink.clone(),
+            follow_symlink: true,
+            collect_ext_attrs: false,
+        };
+
+        let mut session = session::test::Fake::new();
+        assert!(handle(&mut session, request).is_ok());
+
+        let reply = session.reply::<Response>(0);
+
+        // The kernel might not support `statx`, nothing to check then.
+        if let Some(statx) = reply.statx {
+            let mtime = std::fs::metadata(&target).unwrap().modified().unwrap();
+            assert_eq!(statx.mtime, Some(mtime));
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_into_proto_mnt_id_on_linux() {
+        use crate::action::Response as _;
+
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("foo");
+        File::create(&path).unwrap();
+
+        // The kernel might not support `statx`, nothing to check then.
+        let statx = match crate::fs::linux::statx(&path, false).unwrap() {
+            Some(statx) => statx,
+            None => return,
+        };
+
+        let response = Response {
+            path: path.clone(),
+            metadata: std::fs::symlink_metadata(&path).unwrap(),
+            symlink: None,
+            ext_attrs: vec!(),
+            flags_linux: None,
+            statx: Some(statx),
+        };
+
+        let proto = response.into_proto();
+        assert_eq!(proto.extension.mnt_id, statx.mnt_id);
+        assert_eq!(proto.message.st_size, Some(0));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_flags_from_statx() {
+        use crate::fs::linux::*;
+
+        let statx = Statx {
+            attributes: STATX_ATTR_IMMUTABLE | STATX_ATTR_APPEND | 0x1000,
+            ..Default::default()
+        };
+
+        // https://elixir.bootlin.com/linux/v5.8.14/source/include/uapi/linux/fs.h#L237
+        const FS_IMMUTABLE_FL: u32 = 0x00000010;
+        const FS_APPEND_FL: u32 = 0x00000020;
+
+        let flags = flags_from_statx(&statx);
+        assert_eq!(flags, FS_IMMUTABLE_FL | FS_APPEND_FL);
+    }
+
     #[cfg(all(target_os = "linux", feature = "test-setfattr"))]
     #[test]
     fn test_handle_with_file_ext_attrs_on_linux() {
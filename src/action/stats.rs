@@ -0,0 +1,251 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+//! A handler and associated types for the client stats action.
+//!
+//! The client stats action returns information about resources used by the
+//! agent itself: its memory footprint, network traffic and samples of CPU and
+//! I/O usage collected over time (see the [`stats`] module).
+//!
+//! [`stats`]: ../../stats/index.html
+
+use std::time::SystemTime;
+
+use crate::action::processes::{self, System};
+use crate::session::{self, Session, time_from_micros};
+use crate::stats::Sample;
+
+/// A request type for the client stats action.
+#[derive(Debug, Default)]
+pub struct Request {
+    /// A time before which samples should not be reported.
+    start_time: Option<SystemTime>,
+    /// A time after which samples should not be reported.
+    end_time: Option<SystemTime>,
+}
+
+/// A response type for the client stats action.
+#[derive(Debug)]
+pub struct Response {
+    /// Samples of CPU and I/O usage (from the oldest one).
+    samples: Vec<Sample>,
+    /// A resident set size of the agent process (in bytes).
+    rss_size: u64,
+    /// A virtual memory size of the agent process (in bytes).
+    vms_size: u64,
+    /// A time at which the agent process was started.
+    create_time: Option<SystemTime>,
+    /// A time at which the system was booted.
+    boot_time: Option<SystemTime>,
+    /// A total number of bytes sent to the server.
+    bytes_sent: u64,
+    /// A total number of bytes received from the server.
+    bytes_received: u64,
+}
+
+/// An error type for failures that can occur during the client stats action.
+#[derive(Debug)]
+enum Error {
+    /// A failure occurred during the attempt to read the process stat file.
+    ReadStat(std::io::Error),
+    /// The process stat file has unexpected format.
+    MalformedStat,
+}
+
+impl std::error::Error for Error {
+
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use Error::*;
+
+        match *self {
+            ReadStat(ref error) => Some(error),
+            MalformedStat => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use Error::*;
+
+        match *self {
+            ReadStat(ref error) => {
+                write!(fmt, "unable to read process stat: {}", error)
+            }
+            MalformedStat => {
+                write!(fmt, "malformed process stat")
+            }
+        }
+    }
+}
+
+impl From<Error> for session::Error {
+
+    fn from(error: Error) -> session::Error {
+        session::Error::action(error)
+    }
+}
+
+/// Handles requests for the client stats action.
+pub fn handle<S>(session: &mut S, request: Request) -> session::Result<()>
+where
+    S: Session,
+{
+    let system = System::new();
+
+    let stat = std::fs::read_to_string("/proc/self/stat")
+        .map_err(Error::ReadStat)?;
+    let stat = processes::parse_stat(&stat)
+        .ok_or(Error::MalformedStat)?;
+
+    let create_time = system.boot_time.map(|boot_time| {
+        boot_time + processes::ticks(stat.start_time, system.ticks_per_sec)
+    });
+
+    let samples = crate::stats::samples().into_iter()
+        .filter(|sample| request.contains(sample.time))
+        .collect();
+
+    session.reply(Response {
+        samples: samples,
+        rss_size: stat.rss_pages.saturating_mul(system.page_size),
+        vms_size: stat.vms_size,
+        create_time: create_time,
+        boot_time: system.boot_time,
+        bytes_sent: crate::message::bytes_sent(),
+        bytes_received: crate::message::bytes_received(),
+    })?;
+
+    Ok(())
+}
+
+impl Request {
+
+    /// Checks whether the given time is within the requested time range.
+    fn contains(&self, time: SystemTime) -> bool {
+        let after_start = self.start_time.map_or(true, |start| start <= time);
+        let before_end = self.end_time.map_or(true, |end| time <= end);
+
+        after_start && before_end
+    }
+}
+
+impl super::Request for Request {
+
+    type Proto = rrg_proto::GetClientStatsRequest;
+
+    fn from_proto(proto: Self::Proto) -> Result<Self, session::ParseError> {
+        // Following the GRR convention, zero timestamps mean that the range is
+        // not bounded from the corresponding side.
+        let start_time = match proto.start_time {
+            Some(micros) if micros > 0 => Some(time_from_micros(micros)?),
+            _ => None,
+        };
+        let end_time = match proto.end_time {
+            Some(micros) if micros > 0 => Some(time_from_micros(micros)?),
+            _ => None,
+        };
+
+        Ok(Request {
+            start_time: start_time,
+            end_time: end_time,
+        })
+    }
+}
+
+/// Converts the given time to microseconds since the epoch.
+fn micros(time: SystemTime) -> u64 {
+    time.duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_micros() as u64)
+        .unwrap_or(0)
+}
+
+impl super::Response for Response {
+
+    const RDF_NAME: Option<&'static str> = Some("ClientStats");
+
+    type Proto = rrg_proto::ClientStats;
+
+    fn into_proto(self) -> rrg_proto::ClientStats {
+        use rrg_proto::client_stats::{CpuSample, IoSample};
+
+        let cpu_samples = self.samples.iter()
+            .map(|sample| CpuSample {
+                timestamp: Some(micros(sample.time)),
+                user_cpu_time: Some(sample.user_cpu_time.as_secs_f32()),
+                system_cpu_time: Some(sample.system_cpu_time.as_secs_f32()),
+                cpu_percent: Some(sample.cpu_percent),
+            })
+            .collect();
+
+        let io_samples = self.samples.iter()
+            .map(|sample| IoSample {
+                timestamp: Some(micros(sample.time)),
+                read_bytes: Some(sample.read_bytes),
+                write_bytes: Some(sample.write_bytes),
+                ..Default::default()
+            })
+            .collect();
+
+        rrg_proto::ClientStats {
+            cpu_samples: cpu_samples,
+            rss_size: Some(self.rss_size),
+            vms_size: Some(self.vms_size),
+            bytes_received: Some(self.bytes_received),
+            bytes_sent: Some(self.bytes_sent),
+            io_samples: io_samples,
+            create_time: self.create_time.map(micros),
+            boot_time: self.boot_time.map(micros),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_handle_current_process() {
+        let mut session = session::test::Fake::new();
+        assert!(handle(&mut session, Request::default()).is_ok());
+
+        assert_eq!(session.reply_count(), 1);
+
+        let response = session.reply::<Response>(0);
+        assert!(response.rss_size > 0);
+        assert!(response.vms_size > 0);
+        assert!(response.create_time.is_some());
+        assert!(response.create_time <= Some(SystemTime::now()));
+        assert!(response.boot_time <= response.create_time);
+    }
+
+    #[test]
+    fn test_request_contains_unbounded() {
+        let request = Request::default();
+
+        assert!(request.contains(std::time::UNIX_EPOCH));
+        assert!(request.contains(SystemTime::now()));
+    }
+
+    #[test]
+    fn test_request_contains_bounded() {
+        let now = SystemTime::now();
+
+        let request = Request {
+            start_time: Some(now - Duration::from_secs(60)),
+            end_time: Some(now),
+        };
+
+        assert!(request.contains(now - Duration::from_secs(30)));
+        assert!(request.contains(now));
+        assert!(!request.contains(now - Duration::from_secs(90)));
+        assert!(!request.contains(now + Duration::from_secs(30)));
+    }
+}
@@ -7,17 +7,35 @@
 
 use std::path::PathBuf;
 use std::result::Result;
+use std::time::Duration;
 use std::vec::Vec;
 
+use log::{info, warn};
 use sha2::{Digest, Sha256};
 use rrg_macro::ack;
 use rrg_proto::convert::FromLossy;
+use rrg_proto::extension::Extended;
 
+use crate::fs::{DirPosition, WalkOpts, WalkPosition};
 use crate::session::{self, Session};
+use crate::state::Store;
 
 /// A request type for the timeline action.
+#[derive(Default)]
 pub struct Request {
+    /// A path to the directory to start the timeline at.
     pub root: PathBuf,
+    /// Options restricting which parts of the tree are included.
+    pub opts: WalkOpts,
+    /// A number of threads to walk the tree with (if more than one).
+    ///
+    /// The number is capped by the `max_walk_threads` setting of the agent.
+    /// Note that parallel walks cannot be resumed from checkpoints.
+    pub threads: usize,
+    /// A SHA-256 digest of the serialized request arguments.
+    ///
+    /// It is used to verify that a checkpoint belongs to the same request.
+    pub args_sha256: [u8; 32],
 }
 
 /// A response type for the timeline action.
@@ -86,7 +104,7 @@ impl ChunkId {
     }
 
     /// Converts the chunk identifier into raw bytes of SHA-256 hash.
-    fn to_sha256_bytes(self) -> Vec<u8> {
+    pub(crate) fn to_sha256_bytes(self) -> Vec<u8> {
         self.sha256.to_vec()
     }
 }
@@ -99,41 +117,95 @@ pub struct Chunk {
 impl Chunk {
 
     /// Constructs a chunk from the given blob of bytes.
-    fn from_bytes(data: Vec<u8>) -> Chunk {
+    pub(crate) fn from_bytes(data: Vec<u8>) -> Chunk {
         Chunk {
             data: data,
         }
     }
 
     /// Returns an identifier of the chunk.
-    fn id(&self) -> ChunkId {
+    pub(crate) fn id(&self) -> ChunkId {
         ChunkId::of(&self)
     }
 }
 
-impl FromLossy<crate::fs::Entry> for rrg_proto::TimelineEntry {
+/// A timeline entry extended with Linux-specific metadata.
+pub type Entry = Extended<
+    rrg_proto::TimelineEntry,
+    rrg_proto::rrg::TimelineEntryExtension,
+>;
 
-    fn from_lossy(entry: crate::fs::Entry) -> rrg_proto::TimelineEntry {
+impl FromLossy<crate::fs::Entry> for Entry {
+
+    fn from_lossy(entry: crate::fs::Entry) -> Entry {
         use std::convert::TryFrom as _;
         #[cfg(target_family = "unix")]
         use std::os::unix::fs::MetadataExt as _;
 
-        let atime_nanos = entry.metadata.accessed().ok().and_then(|atime| ack! {
+        // Extended metadata (if available) provides creation time even if the
+        // standard library does not, so we prefer it over the standard one.
+        #[cfg(target_os = "linux")]
+        let (atime, mtime, btime) = {
+            let statx = entry.statx.unwrap_or_default();
+            (
+                statx.atime.or_else(|| entry.metadata.accessed().ok()),
+                statx.mtime.or_else(|| entry.metadata.modified().ok()),
+                statx.btime.or_else(|| entry.metadata.created().ok()),
+            )
+        };
+
+        #[cfg(not(target_os = "linux"))]
+        let (atime, mtime, btime) = (
+            entry.metadata.accessed().ok(),
+            entry.metadata.modified().ok(),
+            entry.metadata.created().ok(),
+        );
+
+        let atime_nanos = atime.and_then(|atime| ack! {
             rrg_proto::nanos(atime),
-            error: "failed to convert access time to seconds"
+            error: "failed to convert access time to nanoseconds"
         });
 
-        let mtime_nanos = entry.metadata.modified().ok().and_then(|mtime| ack! {
+        let mtime_nanos = mtime.and_then(|mtime| ack! {
             rrg_proto::nanos(mtime),
-            error: "failed to convert modification time to seconds"
+            error: "failed to convert modification time to nanoseconds"
         });
 
-        let btime_nanos = entry.metadata.created().ok().and_then(|btime| ack! {
+        let btime_nanos = btime.and_then(|btime| ack! {
             rrg_proto::nanos(btime),
-            error: "failed to convert creation time to seconds"
+            error: "failed to convert creation time to nanoseconds"
         });
 
-        rrg_proto::TimelineEntry {
+        // Inode change time is not exposed as `SystemTime` by the standard
+        // library, so we need to combine seconds and nanoseconds ourselves.
+        #[cfg(target_family = "unix")]
+        let ctime_nanos = {
+            let ctime_secs = entry.metadata.ctime();
+            let ctime_nsec = entry.metadata.ctime_nsec();
+
+            ctime_secs.checked_mul(1_000_000_000)
+                .and_then(|ctime_nanos| ctime_nanos.checked_add(ctime_nsec))
+        };
+
+        #[cfg(target_os = "linux")]
+        let ctime_nanos = entry.statx
+            .and_then(|statx| statx.ctime)
+            .and_then(|ctime| rrg_proto::nanos(ctime).ok())
+            .and_then(|nanos| i64::try_from(nanos).ok())
+            .or(ctime_nanos);
+
+        // Linux file attributes are not the same as the Windows ones that GRR
+        // expects in the timeline entry, so they are sent in the extension.
+        #[cfg(target_os = "linux")]
+        let extension = rrg_proto::rrg::TimelineEntryExtension {
+            linux_attributes: entry.statx.map(|statx| statx.attributes),
+            mnt_id: entry.statx.and_then(|statx| statx.mnt_id),
+        };
+
+        #[cfg(not(target_os = "linux"))]
+        let extension = rrg_proto::rrg::TimelineEntryExtension::default();
+
+        let message = rrg_proto::TimelineEntry {
             path: Some(rrg_proto::path::to_bytes(entry.path)),
             #[cfg(target_family = "unix")]
             mode: Some(i64::from(entry.metadata.mode())),
@@ -149,53 +221,361 @@ impl FromLossy<crate::fs::Entry> for rrg_proto::TimelineEntry {
             atime_ns: atime_nanos.and_then(|nanos| i64::try_from(nanos).ok()),
             mtime_ns: mtime_nanos.and_then(|nanos| i64::try_from(nanos).ok()),
             #[cfg(target_family = "unix")]
-            ctime_ns: Some(entry.metadata.ctime_nsec()),
+            ctime_ns: ctime_nanos,
             btime_ns: btime_nanos.and_then(|nanos| i64::try_from(nanos).ok()),
             // TODO: Export file attributes on Windows.
             ..rrg_proto::TimelineEntry::default()
+        };
+
+        Extended {
+            message: message,
+            extension: extension,
         }
     }
 }
 
+/// A name of the local state store with timeline checkpoints.
+const CHECKPOINT_STORE: &str = "timeline";
+
+/// A period after which checkpoints of timelines that were never retried are
+/// discarded.
+const CHECKPOINT_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
 /// Handles requests for the timeline action.
-pub fn handle<S>(session: &mut S, request: Request) -> session::Result<()>
+///
+/// If the agent is configured with a state directory, progress of sequential
+/// timelines is saved after every chunk sent to the transfer store. Requests
+/// retried within the same session (e.g. after the agent restarted) continue
+/// from the last saved checkpoint instead of starting from scratch.
+pub fn handle<S>(session: &mut S, mut request: Request) -> session::Result<()>
+where
+    S: Session,
+{
+    // The server decides how many threads it would like the walk to use, but
+    // it is up to the agent configuration how much of the host it can take.
+    let max_threads = crate::config::get().max_walk_threads;
+    if request.threads > max_threads {
+        info! {
+            "limiting timeline walk threads from {} to {}",
+            request.threads, max_threads
+        };
+        request.threads = max_threads;
+    }
+
+    let opts = crate::gzchunked::EncodeOpts::default();
+    let store = crate::state::open(CHECKPOINT_STORE);
+
+    handle_with(session, request, opts, store)
+}
+
+/// Handles requests for the timeline action with the given encoding options
+/// and checkpoint store.
+fn handle_with<S>(
+    session: &mut S,
+    request: Request,
+    opts: crate::gzchunked::EncodeOpts,
+    store: Option<Store>,
+) -> session::Result<()>
 where
     S: Session,
 {
-    let entries = crate::fs::walk_dir(&request.root).map_err(Error::WalkDir)?
-        .map(rrg_proto::TimelineEntry::from_lossy);
+    if let Some(ref store) = store {
+        if let Err(error) = store.prune(CHECKPOINT_MAX_AGE) {
+            warn!("failed to prune timeline checkpoints: {}", error);
+        }
+    }
+
+    // Only requests of server flows can be retried and only sequential walks
+    // have a well-defined position that can be resumed from.
+    let checkpoints = match (store, session.id()) {
+        (Some(store), Some(id)) if request.threads <= 1 => Some(Checkpoints {
+            store: store,
+            key: String::from(id),
+            args_sha256: request.args_sha256,
+        }),
+        _ => None,
+    };
 
     let mut response = Response {
         chunk_ids: vec!(),
     };
 
-    for part in crate::gzchunked::encode(entries) {
-        let part = part.map_err(Error::Encode)?;
+    let checkpoint = checkpoints.as_ref().and_then(Checkpoints::load);
+
+    let mut walk = if request.threads > 1 {
+        use crate::fs::parallel::walk_dir_parallel;
+
+        let iter = walk_dir_parallel(&request.root, request.opts,
+                                     request.threads);
+        Walk::Parallel(iter.map_err(Error::WalkDir)?)
+    } else if let Some(checkpoint) = checkpoint {
+        info! {
+            "resuming timeline of '{}' after {} chunks",
+            request.root.display(), checkpoint.chunk_ids.len()
+        };
+        response.chunk_ids = checkpoint.chunk_ids;
+
+        let iter = crate::fs::walk_dir_from(&request.root, request.opts,
+                                            checkpoint.position);
+        Walk::Sequential(iter.map_err(Error::WalkDir)?)
+    } else {
+        let iter = crate::fs::walk_dir_with(&request.root, request.opts);
+        Walk::Sequential(iter.map_err(Error::WalkDir)?)
+    };
 
-        let chunk = Chunk::from_bytes(part);
-        let chunk_id = chunk.id();
+    let mut encoder = crate::gzchunked::Encoder::new(opts);
+
+    // Walking big filesystems can take a lot of time, so we need to signal
+    // that we are alive (and check whether we should continue) for every
+    // entry, not only for every encoded part.
+    while let Some(entry) = walk.next_entry() {
+        session.heartbeat();
+        session.check_cancelled()?;
+
+        let entry = Entry::from_lossy(entry);
+        if let Some(part) = encoder.write(&entry).map_err(Error::Encode)? {
+            send_part(session, &mut response, part)?;
+
+            // The part has just been sent, so the encoder is empty and the
+            // walk position corresponds exactly to what the server has.
+            if let Some(ref checkpoints) = checkpoints {
+                if let Some(position) = walk.position() {
+                    checkpoints.save(&response.chunk_ids, position);
+                }
+            }
+        }
+    }
 
-        session.send(session::Sink::TRANSFER_STORE, chunk)?;
-        response.chunk_ids.push(chunk_id);
+    if let Some(part) = encoder.flush().map_err(Error::Encode)? {
+        send_part(session, &mut response, part)?;
     }
 
     session.reply(response)?;
 
+    if let Some(checkpoints) = checkpoints {
+        checkpoints.remove();
+    }
+
+    Ok(())
+}
+
+/// A walk over the filesystem tree that the timeline is collected for.
+enum Walk {
+    /// A sequential walk (that can be checkpointed).
+    Sequential(crate::fs::WalkDir),
+    /// A parallel walk (that cannot be checkpointed).
+    Parallel(crate::fs::parallel::ParallelWalkDir),
+}
+
+impl Walk {
+
+    /// Yields the next entry of the walk.
+    fn next_entry(&mut self) -> Option<crate::fs::Entry> {
+        match *self {
+            Walk::Sequential(ref mut iter) => iter.next(),
+            Walk::Parallel(ref mut iter) => iter.next(),
+        }
+    }
+
+    /// Returns the current position of the walk (if it can be resumed).
+    fn position(&self) -> Option<WalkPosition> {
+        match *self {
+            Walk::Sequential(ref iter) => Some(iter.position()),
+            Walk::Parallel(_) => None,
+        }
+    }
+}
+
+/// A progress of the timeline saved in a checkpoint.
+struct Checkpoint {
+    /// Identifiers of chunks sent so far.
+    chunk_ids: Vec<ChunkId>,
+    /// A position of the walk after the last chunk sent.
+    position: WalkPosition,
+}
+
+/// A handle to checkpoints of a particular timeline request.
+struct Checkpoints {
+    /// A store that the checkpoints are saved into.
+    store: Store,
+    /// A key (the session identifier) of the checkpoints in the store.
+    key: String,
+    /// A digest of arguments of the request that the checkpoints belong to.
+    args_sha256: [u8; 32],
+}
+
+impl Checkpoints {
+
+    /// Loads the last checkpoint of the request (if there is a valid one).
+    fn load(&self) -> Option<Checkpoint> {
+        let data = match self.store.get(&self.key) {
+            Ok(Some(data)) => data,
+            Ok(None) => return None,
+            Err(error) => {
+                warn! {
+                    "failed to load timeline checkpoint of '{}': {}",
+                    self.key, error
+                };
+                return None;
+            }
+        };
+
+        let proto: rrg_proto::rrg::TimelineCheckpoint = ack! {
+            prost::Message::decode(&data[..]),
+            warn: "malformed timeline checkpoint of '{}'", self.key
+        }?;
+
+        // The server can reuse the session for a request with different
+        // arguments, in which case the checkpoint is of no use.
+        if proto.args_sha256.as_deref() != Some(&self.args_sha256[..]) {
+            info!("discarding stale timeline checkpoint of '{}'", self.key);
+            return None;
+        }
+
+        let checkpoint = Checkpoint::from_proto(proto);
+        if checkpoint.is_none() {
+            warn!("malformed timeline checkpoint of '{}'", self.key);
+        }
+
+        checkpoint
+    }
+
+    /// Saves the given progress as the last checkpoint of the request.
+    ///
+    /// Failures are only logged, as the timeline itself can go on without
+    /// checkpoints just fine.
+    fn save(&self, chunk_ids: &[ChunkId], position: WalkPosition) {
+        let dirs = position.dirs.into_iter()
+            .map(|dir| rrg_proto::rrg::TimelineCheckpointDir {
+                path: Some(rrg_proto::path::to_bytes(dir.path)),
+                depth: Some(dir.depth as u64),
+                consumed: Some(dir.consumed as u64),
+            })
+            .collect();
+
+        let proto = rrg_proto::rrg::TimelineCheckpoint {
+            args_sha256: Some(self.args_sha256.to_vec()),
+            chunk_ids: chunk_ids.iter()
+                .map(|chunk_id| chunk_id.sha256.to_vec())
+                .collect(),
+            root_visited: Some(position.root_visited),
+            dirs: dirs,
+        };
+
+        let mut data = Vec::with_capacity(prost::Message::encoded_len(&proto));
+        if let Err(error) = prost::Message::encode(&proto, &mut data) {
+            warn!("failed to encode timeline checkpoint: {}", error);
+            return;
+        }
+
+        ack! {
+            self.store.put(&self.key, &data),
+            warn: "failed to save timeline checkpoint of '{}'", self.key
+        };
+    }
+
+    /// Removes checkpoints of the request once it is completed.
+    fn remove(self) {
+        ack! {
+            self.store.remove(&self.key),
+            warn: "failed to remove timeline checkpoint of '{}'", self.key
+        };
+    }
+}
+
+impl Checkpoint {
+
+    /// Converts a checkpoint from its proto representation.
+    ///
+    /// `None` is returned if the proto is malformed.
+    fn from_proto(proto: rrg_proto::rrg::TimelineCheckpoint) -> Option<Self> {
+        use std::convert::TryFrom as _;
+
+        let chunk_ids = proto.chunk_ids.iter()
+            .map(|bytes| Some(ChunkId {
+                sha256: <[u8; 32]>::try_from(&bytes[..]).ok()?,
+            }))
+            .collect::<Option<Vec<_>>>()?;
+
+        let dirs = proto.dirs.into_iter()
+            .map(|dir| Some(DirPosition {
+                path: rrg_proto::path::from_bytes(dir.path?),
+                depth: dir.depth? as usize,
+                consumed: dir.consumed? as usize,
+            }))
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Checkpoint {
+            chunk_ids: chunk_ids,
+            position: WalkPosition {
+                root_visited: proto.root_visited?,
+                dirs: dirs,
+            },
+        })
+    }
+}
+
+/// Sends a part of the gzchunked timeline file to the transfer store.
+fn send_part<S>(
+    session: &mut S,
+    response: &mut Response,
+    part: Vec<u8>,
+) -> session::Result<()>
+where
+    S: Session,
+{
+    let chunk = Chunk::from_bytes(part);
+    let chunk_id = chunk.id();
+
+    session.send(session::Sink::TRANSFER_STORE, chunk)?;
+    response.chunk_ids.push(chunk_id);
+
     Ok(())
 }
 
 impl super::Request for Request {
 
-    type Proto = rrg_proto::TimelineArgs;
+    type Proto = rrg_proto::rrg::TimelineArgs;
 
     fn from_proto(proto: Self::Proto) -> Result<Request, session::ParseError> {
+        use super::finder::glob::glob_to_regex;
+        use session::time_from_micros;
+
+        let mut args = Vec::with_capacity(prost::Message::encoded_len(&proto));
+        prost::Message::encode(&proto, &mut args)
+            .map_err(session::ParseError::malformed)?;
+
         let root_bytes = proto.root
             .ok_or(session::MissingFieldError::new("root"))?;
 
+        let excludes = proto.excludes.iter()
+            .map(|glob| glob_to_regex(glob))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let opts = WalkOpts {
+            excludes: excludes,
+            max_depth: proto.max_depth.map(|depth| depth as usize),
+            cross_device: proto.cross_device.unwrap_or(false),
+            min_size: proto.min_size,
+            max_size: proto.max_size,
+            min_mtime: proto.min_mtime.map(time_from_micros).transpose()?,
+            max_mtime: proto.max_mtime.map(time_from_micros).transpose()?,
+            // Timeline entries report all the times and file attributes that
+            // the system provides.
+            extended_metadata: true,
+        };
+
         Ok(Request {
             root: rrg_proto::path::from_bytes(root_bytes),
+            opts: opts,
+            threads: proto.threads.unwrap_or(1) as usize,
+            args_sha256: Sha256::digest(&args).into(),
         })
     }
+
+    fn paths(&self) -> Vec<PathBuf> {
+        vec!(self.root.clone())
+    }
 }
 
 impl super::Response for Response {
@@ -231,7 +611,9 @@ impl super::Response for Chunk {
 mod tests {
 
     use super::*;
+    use crate::action::Request as _;
 
+    use session::Sink;
     use session::test::Fake as Session;
 
     #[test]
@@ -239,7 +621,8 @@ mod tests {
         let tempdir = tempfile::tempdir().unwrap();
 
         let request = Request {
-            root: tempdir.path().join("foo")
+            root: tempdir.path().join("foo"),
+            ..Default::default()
         };
 
         let mut session = Session::new();
@@ -253,6 +636,7 @@ mod tests {
 
         let request = Request {
             root: tempdir_path.clone(),
+            ..Default::default()
         };
 
         let mut session = Session::new();
@@ -272,6 +656,7 @@ mod tests {
 
         let request = Request {
             root: tempdir.path().to_path_buf(),
+            ..Default::default()
         };
 
         let mut session = Session::new();
@@ -296,6 +681,7 @@ mod tests {
 
         let request = Request {
             root: tempdir_path.clone(),
+            ..Default::default()
         };
 
         let mut session = Session::new();
@@ -325,6 +711,7 @@ mod tests {
 
         let request = Request {
             root: root_path.clone(),
+            ..Default::default()
         };
 
         let mut session = Session::new();
@@ -352,6 +739,7 @@ mod tests {
 
         let request = Request {
             root: root_path.clone(),
+            ..Default::default()
         };
 
         let mut session = Session::new();
@@ -378,6 +766,7 @@ mod tests {
 
         let request = Request {
             root: tempdir.path().to_path_buf(),
+            ..Default::default()
         };
 
         let mut session = Session::new();
@@ -402,6 +791,78 @@ mod tests {
         }
     }
 
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_file_times() {
+        use std::os::unix::fs::MetadataExt as _;
+
+        let tempdir = tempfile::tempdir().unwrap();
+        let file_path = tempdir.path().join("foo");
+        std::fs::write(&file_path, b"foo").unwrap();
+
+        let metadata = std::fs::symlink_metadata(&file_path).unwrap();
+
+        let request = Request {
+            root: tempdir.path().to_path_buf(),
+            ..Default::default()
+        };
+
+        let mut session = Session::new();
+        assert!(handle(&mut session, request).is_ok());
+
+        let mut entries = entries(&session);
+        entries.sort_by_key(|entry| entry.path.clone());
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(path(&entries[1]), Some(file_path));
+
+        let mtime = metadata.modified().unwrap();
+        let mtime_nanos = rrg_proto::nanos(mtime).unwrap() as i64;
+        assert_eq!(entries[1].mtime_ns, Some(mtime_nanos));
+
+        let ctime_nanos = metadata.ctime() * 1_000_000_000;
+        let ctime_nanos = ctime_nanos + metadata.ctime_nsec();
+        assert_eq!(entries[1].ctime_ns, Some(ctime_nanos));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_file_extension_on_linux() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let file_path = tempdir.path().join("foo");
+        std::fs::write(&file_path, b"foo").unwrap();
+
+        let request = Request {
+            root: tempdir.path().to_path_buf(),
+            ..Default::default()
+        };
+
+        let mut session = Session::new();
+        assert!(handle(&mut session, request).is_ok());
+
+        let chunks = session.responses::<Chunk>(Sink::TRANSFER_STORE)
+            .map(|chunk| &chunk.data[..]);
+        let entries = crate::gzchunked::decode(chunks)
+            .map(Result::unwrap)
+            .collect::<Vec<Entry>>();
+
+        let entry = entries.iter()
+            .find(|entry| path(&entry.message).as_ref() == Some(&file_path))
+            .unwrap();
+
+        // The original field is meant for Windows attributes, so the Linux
+        // ones are reported only in the extension.
+        assert_eq!(entry.message.attributes, None);
+
+        // The kernel might not support `statx`, nothing more to check then.
+        let statx = crate::fs::linux::statx(&file_path, false).unwrap();
+        if let Some(statx) = statx {
+            let attributes = Some(statx.attributes);
+            assert_eq!(entry.extension.linux_attributes, attributes);
+            assert_eq!(entry.extension.mnt_id, statx.mnt_id);
+        }
+    }
+
     #[test]
     fn test_hardlink_metadata() {
         let tempdir = tempfile::tempdir().unwrap();
@@ -415,6 +876,7 @@ mod tests {
 
         let request = Request {
             root: root_path.clone(),
+            ..Default::default()
         };
 
         let mut session = Session::new();
@@ -432,10 +894,315 @@ mod tests {
         assert_eq!(entries[1].ino, entries[2].ino);
     }
 
+    #[test]
+    fn test_heartbeats() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::File::create(tempdir.path().join("foo")).unwrap();
+        std::fs::File::create(tempdir.path().join("bar")).unwrap();
+
+        let request = Request {
+            root: tempdir.path().to_path_buf(),
+            ..Default::default()
+        };
+
+        let mut session = Session::new();
+        assert!(handle(&mut session, request).is_ok());
+
+        assert_eq!(session.heartbeat_count(), 3);
+    }
+
+    #[test]
+    fn test_cancelled() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::File::create(tempdir.path().join("foo")).unwrap();
+
+        let request = Request {
+            root: tempdir.path().to_path_buf(),
+            ..Default::default()
+        };
+
+        let mut session = Session::new();
+        session.cancel();
+
+        let result = handle(&mut session, request);
+        assert!(matches!(result, Err(session::Error::Cancelled)));
+
+        assert_eq!(session.reply_count(), 0);
+    }
+
+    #[test]
+    fn test_excludes() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tempdir.path().join("foo")).unwrap();
+        std::fs::File::create(tempdir.path().join("foo").join("bar")).unwrap();
+        std::fs::File::create(tempdir.path().join("baz")).unwrap();
+
+        let glob = format!("{}/foo", tempdir.path().display());
+
+        let request = Request::from_proto(rrg_proto::rrg::TimelineArgs {
+            root: Some(rrg_proto::path::to_bytes(tempdir.path().into())),
+            excludes: vec!(glob),
+            ..Default::default()
+        }).unwrap();
+
+        let mut session = Session::new();
+        assert!(handle(&mut session, request).is_ok());
+
+        let mut entries = entries(&session);
+        entries.sort_by_key(|entry| entry.path.clone());
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(path(&entries[0]), Some(tempdir.path().to_path_buf()));
+        assert_eq!(path(&entries[1]), Some(tempdir.path().join("baz")));
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(tempdir.path().join("a").join("b")).unwrap();
+
+        let request = Request::from_proto(rrg_proto::rrg::TimelineArgs {
+            root: Some(rrg_proto::path::to_bytes(tempdir.path().into())),
+            max_depth: Some(1),
+            ..Default::default()
+        }).unwrap();
+
+        let mut session = Session::new();
+        assert!(handle(&mut session, request).is_ok());
+
+        let mut entries = entries(&session);
+        entries.sort_by_key(|entry| entry.path.clone());
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(path(&entries[0]), Some(tempdir.path().to_path_buf()));
+        assert_eq!(path(&entries[1]), Some(tempdir.path().join("a")));
+    }
+
+    #[test]
+    fn test_parallel() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(tempdir.path().join("a").join("b")).unwrap();
+        std::fs::File::create(tempdir.path().join("a").join("c")).unwrap();
+        std::fs::File::create(tempdir.path().join("d")).unwrap();
+
+        let request = Request {
+            root: tempdir.path().to_path_buf(),
+            threads: 4,
+            ..Default::default()
+        };
+
+        let mut session = Session::new();
+        assert!(handle(&mut session, request).is_ok());
+
+        let mut entries = entries(&session);
+        entries.sort_by_key(|entry| entry.path.clone());
+
+        assert_eq!(entries.len(), 5);
+        assert_eq!(path(&entries[0]), Some(tempdir.path().to_path_buf()));
+        assert_eq!(path(&entries[1]), Some(tempdir.path().join("a")));
+        assert_eq!(path(&entries[2]), Some(tempdir.path().join("a").join("b")));
+        assert_eq!(path(&entries[3]), Some(tempdir.path().join("a").join("c")));
+        assert_eq!(path(&entries[4]), Some(tempdir.path().join("d")));
+    }
+
+    #[test]
+    fn test_from_proto_invalid_exclude() {
+        let request = Request::from_proto(rrg_proto::rrg::TimelineArgs {
+            root: Some(rrg_proto::path::to_bytes("/".into())),
+            excludes: vec!(String::from("[z-a]")),
+            ..Default::default()
+        });
+
+        assert!(request.is_err());
+    }
+
+    #[test]
+    fn test_from_proto_extended_metadata() {
+        let request = Request::from_proto(rrg_proto::rrg::TimelineArgs {
+            root: Some(rrg_proto::path::to_bytes("/".into())),
+            ..Default::default()
+        }).unwrap();
+
+        assert!(request.opts.extended_metadata);
+    }
+
+    #[test]
+    fn test_resume_after_interruption() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let root = tempdir.path().join("root");
+        let state = tempdir.path().join("state");
+
+        std::fs::create_dir_all(root.join("a").join("b")).unwrap();
+        std::fs::File::create(root.join("a").join("c")).unwrap();
+        std::fs::File::create(root.join("a").join("d")).unwrap();
+        std::fs::File::create(root.join("a").join("e")).unwrap();
+        std::fs::File::create(root.join("f")).unwrap();
+
+        let request = || Request {
+            root: root.clone(),
+            ..Default::default()
+        };
+
+        let mut session = Interrupted {
+            inner: Session::with_id("foo"),
+            chunks_left: 3,
+        };
+        let store = Some(Store::new(&state));
+        assert!(handle_with(&mut session, request(), opts(), store).is_err());
+
+        let mut resumed = Session::with_id("foo");
+        let store = Some(Store::new(&state));
+        assert!(handle_with(&mut resumed, request(), opts(), store).is_ok());
+
+        // Chunks sent before the interruption should not be sent again.
+        assert_eq!(resumed.response_count(Sink::TRANSFER_STORE), 4);
+
+        let mut entries = chunked_entries(&[&session.inner, &resumed]);
+        entries.sort_by_key(|entry| entry.path.clone());
+
+        assert_eq!(entries.len(), 7);
+        assert_eq!(path(&entries[0]), Some(root.clone()));
+        assert_eq!(path(&entries[1]), Some(root.join("a")));
+        assert_eq!(path(&entries[2]), Some(root.join("a").join("b")));
+        assert_eq!(path(&entries[3]), Some(root.join("a").join("c")));
+        assert_eq!(path(&entries[4]), Some(root.join("a").join("d")));
+        assert_eq!(path(&entries[5]), Some(root.join("a").join("e")));
+        assert_eq!(path(&entries[6]), Some(root.join("f")));
+
+        // The timeline is complete, so the checkpoint should be gone.
+        assert_eq!(Store::new(&state).get("foo").unwrap(), None);
+    }
+
+    #[test]
+    fn test_resume_other_session() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let root = tempdir.path().join("root");
+        let state = tempdir.path().join("state");
+
+        std::fs::create_dir_all(root.join("a")).unwrap();
+        std::fs::File::create(root.join("b")).unwrap();
+
+        let request = || Request {
+            root: root.clone(),
+            ..Default::default()
+        };
+
+        let mut session = Interrupted {
+            inner: Session::with_id("foo"),
+            chunks_left: 1,
+        };
+        let store = Some(Store::new(&state));
+        assert!(handle_with(&mut session, request(), opts(), store).is_err());
+
+        let mut other_session = Session::with_id("bar");
+        let store = Some(Store::new(&state));
+        let result = handle_with(&mut other_session, request(), opts(), store);
+        assert!(result.is_ok());
+
+        let chunk_count = other_session.response_count(Sink::TRANSFER_STORE);
+        assert_eq!(chunk_count, 3);
+    }
+
+    #[test]
+    fn test_resume_other_args() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let root = tempdir.path().join("root");
+        let state = tempdir.path().join("state");
+
+        std::fs::create_dir_all(root.join("a")).unwrap();
+        std::fs::File::create(root.join("b")).unwrap();
+
+        let mut session = Interrupted {
+            inner: Session::with_id("foo"),
+            chunks_left: 1,
+        };
+        let request = Request {
+            root: root.clone(),
+            args_sha256: [0; 32],
+            ..Default::default()
+        };
+        let store = Some(Store::new(&state));
+        assert!(handle_with(&mut session, request, opts(), store).is_err());
+
+        let mut retried_session = Session::with_id("foo");
+        let request = Request {
+            root: root.clone(),
+            args_sha256: [1; 32],
+            ..Default::default()
+        };
+        let store = Some(Store::new(&state));
+        let result = handle_with(&mut retried_session, request, opts(), store);
+        assert!(result.is_ok());
+
+        let chunk_count = retried_session.response_count(Sink::TRANSFER_STORE);
+        assert_eq!(chunk_count, 3);
+    }
+
+    /// A session that fails after sending the given number of chunks.
+    struct Interrupted {
+        inner: Session,
+        chunks_left: usize,
+    }
+
+    impl crate::session::Session for Interrupted {
+
+        fn reply<R>(&mut self, response: R) -> session::Result<()>
+        where
+            R: crate::action::Response + 'static,
+        {
+            use crate::session::Session as _;
+            self.inner.reply(response)
+        }
+
+        fn send<R>(&mut self, sink: Sink, response: R) -> session::Result<()>
+        where
+            R: crate::action::Response + 'static,
+        {
+            use crate::session::Session as _;
+
+            if self.chunks_left == 0 {
+                return Err(session::Error::Cancelled);
+            }
+            self.chunks_left -= 1;
+
+            self.inner.send(sink, response)
+        }
+
+        fn id(&self) -> Option<&str> {
+            use crate::session::Session as _;
+            self.inner.id()
+        }
+    }
+
+    /// Returns encoding options that put every entry into a separate chunk.
+    fn opts() -> crate::gzchunked::EncodeOpts {
+        crate::gzchunked::EncodeOpts {
+            part_size: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Retrieves timeline entries from chunks sent by the given sessions.
+    ///
+    /// Unlike [`entries`], this does not require the sessions to reply with
+    /// identifiers of all the chunks, so it works for interrupted sessions.
+    ///
+    /// [`entries`]: fn.entries.html
+    fn chunked_entries(sessions: &[&Session]) -> Vec<rrg_proto::TimelineEntry> {
+        let chunks = sessions.iter()
+            .flat_map(|session| {
+                session.responses::<Chunk>(Sink::TRANSFER_STORE)
+            })
+            .map(|chunk| &chunk.data[..]);
+
+        crate::gzchunked::decode(chunks)
+            .map(Result::unwrap)
+            .collect()
+    }
+
     /// Retrieves timeline entries from the given session object.
     fn entries(session: &Session) -> Vec<rrg_proto::TimelineEntry> {
         use std::collections::HashMap;
-        use crate::session::Sink;
 
         let chunk_count = session.response_count(Sink::TRANSFER_STORE);
         assert_eq!(session.reply_count(), 1);
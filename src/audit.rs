@@ -0,0 +1,766 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+//! A tamper-evident audit log of executed actions.
+//!
+//! Every action request handled by the agent ends up as one record in the
+//! audit log. Records are stored one per line in the following format:
+//!
+//! ```text
+//! <hash> <record>
+//! ```
+//!
+//! where `<record>` is a JSON object with the details of the request and
+//! `<hash>` is a hex-encoded SHA-256 digest of the exact `<record>` bytes. Each
+//! record contains the hash of the record that precedes it, so the records form
+//! a chain: modifying or removing any record breaks the chain and is detected
+//! by the [`verify`] function.
+//!
+//! The chain continues across rotated files, so the log can be verified only
+//! as a whole (i.e. together with all the rotated files that are still kept).
+//! The ends of the chain are stored in a `<path>.chain` file next to the log:
+//! the hash that the oldest kept record refers to (the last hash of the most
+//! recently discarded file or [`GENESIS`] if nothing has been discarded yet)
+//! and the hash of the most recent record. This way removing the oldest records
+//! or truncating the most recent ones is detected as well.
+//!
+//! Note that the state file is updated after the record is written, so if the
+//! agent crashes in between, the log is reported as broken.
+//!
+//! [`verify`]: fn.verify.html
+//! [`GENESIS`]: constant.GENESIS.html
+
+use std::fmt::{Display, Formatter};
+use std::io::{BufRead as _, BufReader, Write as _};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+use lazy_static::lazy_static;
+use log::error;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+
+use crate::config::Rotation;
+use crate::io::RotatingFile;
+
+/// A hash that the first record in the log refers to as its predecessor.
+pub const GENESIS: &str =
+    "0000000000000000000000000000000000000000000000000000000000000000";
+
+lazy_static! {
+    /// The audit log of the running agent (if enabled).
+    static ref LOG: RwLock<Option<Arc<Log>>> = RwLock::new(None);
+}
+
+/// Installs the audit log of the running agent.
+///
+/// Until this function is called, no records are written.
+pub fn init(log: Log) {
+    *LOG.write().unwrap() = Some(Arc::new(log));
+}
+
+/// Appends the record to the audit log of the running agent (if enabled).
+///
+/// Failing to write the record is logged but otherwise ignored, so that a full
+/// disk does not prevent the agent from working.
+pub fn record(record: Record) {
+    let log = match LOG.read().unwrap().clone() {
+        Some(log) => log,
+        None => return,
+    };
+
+    if let Err(error) = log.append(record) {
+        error!("failed to write the audit record: {}", error);
+    }
+}
+
+/// A single record of the audit log.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Record {
+    /// A hash of the preceding record in the log.
+    ///
+    /// This is filled in automatically when the record is appended to the log.
+    #[serde(default)]
+    pub prev: String,
+    /// A server-issued identifier of the session that requested the action.
+    pub session_id: String,
+    /// A name of the requested action.
+    pub action: String,
+    /// A hex-encoded SHA-256 digest of the serialized request arguments.
+    pub args_digest: String,
+    /// A time at which the agent started to handle the request (RFC 3339).
+    pub start_time: String,
+    /// A time at which the agent finished handling the request (RFC 3339).
+    pub end_time: String,
+    /// A number of bytes that the action sent to the server.
+    pub bytes_sent: u64,
+    /// An outcome of the action execution.
+    pub result: Outcome,
+    /// An error message (if the action did not finish successfully).
+    pub error: Option<String>,
+}
+
+/// An outcome of the action execution.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    /// The action finished successfully.
+    Ok,
+    /// The action failed.
+    Error,
+    /// The request was refused by the action policy.
+    Denied,
+}
+
+/// Computes the digest of serialized request arguments.
+pub fn digest(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+/// A summary of the successfully verified audit log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Summary {
+    /// A number of verified records.
+    pub count: usize,
+    /// A hash of the most recent record (or the anchor if there are none).
+    ///
+    /// It can be compared with a value recorded elsewhere to make sure that the
+    /// log has not been replaced as a whole.
+    pub head: String,
+}
+
+/// An audit log writer.
+pub struct Log {
+    state: Mutex<State>,
+}
+
+/// A mutable state of the audit log writer.
+struct State {
+    /// A path to the (current) log file.
+    path: PathBuf,
+    /// A file that the records are appended to.
+    file: RotatingFile,
+    /// A number of rotated files to keep.
+    max_count: usize,
+    /// Ends of the chain of records in the log.
+    chain: Chain,
+}
+
+/// Ends of the chain of records stored in the log files.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Chain {
+    /// A hash that the oldest record kept in the log refers to.
+    anchor: String,
+    /// A hash of the most recent record in the log.
+    head: String,
+}
+
+impl Log {
+
+    /// Opens the audit log at the given path, creating it if needed.
+    ///
+    /// If `rotation` is specified, the log file is rotated according to it.
+    /// Otherwise, the file grows indefinitely.
+    pub fn open<P>(path: P, rotation: Option<Rotation>)
+        -> std::io::Result<Log>
+    where
+        P: Into<PathBuf>,
+    {
+        let path = path.into();
+
+        // The chain continues from the stored head rather than from the last
+        // record in the file: otherwise truncating the log before the agent
+        // restarts would go unnoticed.
+        let chain = match read_chain(&path)? {
+            Some(chain) => chain,
+            None => {
+                let chain = Chain {
+                    anchor: String::from(GENESIS),
+                    head: String::from(GENESIS),
+                };
+                write_chain(&path, &chain)?;
+                chain
+            }
+        };
+
+        let (max_size, max_count) = match rotation {
+            Some(rotation) => (rotation.max_size, rotation.max_count),
+            None => (u64::MAX, 0),
+        };
+
+        Ok(Log {
+            state: Mutex::new(State {
+                path: path.clone(),
+                file: RotatingFile::open(path, max_size, max_count)?,
+                max_count: max_count,
+                chain: chain,
+            }),
+        })
+    }
+
+    /// Appends the given record to the log.
+    pub fn append(&self, mut record: Record) -> std::io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+
+        record.prev = state.chain.head.clone();
+        let body = serde_json::to_string(&record)?;
+        let hash = digest(body.as_bytes());
+
+        let line = format!("{} {}\n", hash, body);
+
+        let mut chain = Chain {
+            anchor: state.chain.anchor.clone(),
+            head: hash,
+        };
+        if state.file.will_rotate(line.len() as u64) {
+            if let Some(anchor) = state.discarded_hash()? {
+                chain.anchor = anchor;
+            }
+        }
+
+        // The whole line is written at once, so that it is never split between
+        // two files when the log is rotated.
+        state.file.write_all(line.as_bytes())?;
+        state.file.flush()?;
+
+        write_chain(&state.path, &chain)?;
+        state.chain = chain;
+
+        Ok(())
+    }
+}
+
+impl State {
+
+    /// Returns the last hash of the file that the next rotation discards.
+    ///
+    /// If the rotation does not discard any file (because not all rotated files
+    /// exist yet), `None` is returned. `None` is also returned if the hash is
+    /// not known because the log has been tampered with, in which case the
+    /// anchor stays as it is and the log fails the verification anyway.
+    fn discarded_hash(&self) -> std::io::Result<Option<String>> {
+        if self.max_count == 0 {
+            return Ok(Some(self.chain.head.clone()));
+        }
+
+        if !rotated_path(&self.path, self.max_count).exists() {
+            return Ok(None);
+        }
+
+        // The last hash of the discarded file is the one that the first record
+        // of the next file refers to.
+        let next = match self.max_count - 1 {
+            0 => self.path.clone(),
+            idx => rotated_path(&self.path, idx),
+        };
+
+        let file = std::fs::File::open(&next)?;
+        let line = match BufReader::new(file).lines().next() {
+            Some(line) => line?,
+            None => return Ok(None),
+        };
+
+        Ok(parse_line(&line).map(|(_, record)| record.prev))
+    }
+}
+
+/// Verifies integrity of the audit log at the given path.
+///
+/// All the rotated files that are still kept are verified as well, starting
+/// from the oldest one. The chain has to start at the stored anchor and end at
+/// the stored head.
+pub fn verify<P: AsRef<Path>>(path: P) -> Result<Summary, VerifyError> {
+    let path = path.as_ref();
+
+    let chain = match read_chain(path) {
+        Ok(Some(chain)) => chain,
+        // Without the state file we assume a fresh log: if there are any
+        // records, the chain is going to be reported as broken.
+        Ok(None) => Chain {
+            anchor: String::from(GENESIS),
+            head: String::from(GENESIS),
+        },
+        Err(ref error) if error.kind() == std::io::ErrorKind::InvalidData => {
+            return Err(VerifyError::Broken {
+                path: chain_path(path),
+                line: 1,
+            });
+        }
+        Err(error) => return Err(VerifyError::Read(error)),
+    };
+
+    let mut prev = chain.anchor;
+    let mut count = 0;
+
+    for path in files(path) {
+        let file = std::fs::File::open(&path).map_err(VerifyError::Read)?;
+
+        for (idx, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(VerifyError::Read)?;
+            let broken = || VerifyError::Broken {
+                path: path.clone(),
+                line: idx + 1,
+            };
+
+            let (hash, record) = parse_line(&line).ok_or_else(broken)?;
+            if record.prev != prev {
+                return Err(broken());
+            }
+
+            prev = hash;
+            count += 1;
+        }
+    }
+
+    if prev != chain.head {
+        return Err(VerifyError::Truncated {
+            head: chain.head,
+        });
+    }
+
+    Ok(Summary {
+        count: count,
+        head: prev,
+    })
+}
+
+/// Dumps all the records of the audit log at the given path.
+///
+/// Records from rotated files are dumped as well (oldest first). Records are
+/// written as JSON objects, one per line.
+pub fn dump<P, W>(path: P, output: &mut W) -> Result<(), VerifyError>
+where
+    P: AsRef<Path>,
+    W: std::io::Write,
+{
+    for path in files(path.as_ref()) {
+        let file = std::fs::File::open(&path).map_err(VerifyError::Read)?;
+
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(VerifyError::Read)?;
+
+            // The record is dumped as is (without the hash), it is up to the
+            // `verify` function to check whether it can be trusted.
+            let body = match split_line(&line) {
+                Some((_, body)) => body,
+                None => &line,
+            };
+            writeln!(output, "{}", body).map_err(VerifyError::Read)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses a single line of the log, verifying the hash of the record.
+///
+/// The returned hash is the one of the parsed record.
+fn parse_line(line: &str) -> Option<(String, Record)> {
+    let (hash, body) = split_line(line)?;
+    if digest(body.as_bytes()) != hash {
+        return None;
+    }
+
+    let record = serde_json::from_str(body).ok()?;
+    Some((String::from(hash), record))
+}
+
+/// Splits a line of the log into the hash and the record parts.
+fn split_line(line: &str) -> Option<(&str, &str)> {
+    let mut parts = line.splitn(2, ' ');
+    match (parts.next(), parts.next()) {
+        (Some(hash), Some(body)) => Some((hash, body)),
+        _ => None,
+    }
+}
+
+/// Reads the ends of the chain stored for the log at the given path.
+///
+/// If the state file does not exist, `None` is returned.
+fn read_chain(path: &Path) -> std::io::Result<Option<Chain>> {
+    let string = match std::fs::read_to_string(chain_path(path)) {
+        Ok(string) => string,
+        Err(ref error) if error.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(None);
+        }
+        Err(error) => return Err(error),
+    };
+
+    let chain = serde_json::from_str(&string).map_err(|error| {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, error)
+    })?;
+
+    Ok(Some(chain))
+}
+
+/// Stores the ends of the chain for the log at the given path.
+///
+/// The state is written to a temporary file first and then renamed, so that
+/// the stored state is never partially written.
+fn write_chain(path: &Path, chain: &Chain) -> std::io::Result<()> {
+    let path = chain_path(path);
+
+    let mut temp_path = path.clone().into_os_string();
+    temp_path.push(".tmp");
+
+    std::fs::write(&temp_path, serde_json::to_string(chain)?)?;
+    std::fs::rename(&temp_path, &path)
+}
+
+/// Returns a path of the file with the ends of the chain of the given log.
+fn chain_path(path: &Path) -> PathBuf {
+    let mut path = path.to_path_buf().into_os_string();
+    path.push(".chain");
+    PathBuf::from(path)
+}
+
+/// Returns paths of all the files of the log, starting from the oldest one.
+fn files(path: &Path) -> Vec<PathBuf> {
+    let mut files = vec!(path.to_path_buf());
+
+    let mut idx = 1;
+    loop {
+        let rotated = rotated_path(path, idx);
+        if !rotated.exists() {
+            break;
+        }
+
+        files.push(rotated);
+        idx += 1;
+    }
+
+    files.reverse();
+    files
+}
+
+/// Returns a path of the rotated log file with the given index.
+fn rotated_path(path: &Path, idx: usize) -> PathBuf {
+    let mut path = path.to_path_buf().into_os_string();
+    path.push(format!(".{}", idx));
+    PathBuf::from(path)
+}
+
+/// Formats the given bytes as a lowercase hexadecimal string.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// An error type for failures that can occur when verifying the audit log.
+#[derive(Debug)]
+pub enum VerifyError {
+    /// The log could not be read.
+    Read(std::io::Error),
+    /// The chain of records is broken at the specified line.
+    Broken {
+        /// A path to the file with the offending record.
+        path: PathBuf,
+        /// A number of the line with the offending record (starting at 1).
+        line: usize,
+    },
+    /// The chain of records ends before the stored head.
+    Truncated {
+        /// A hash of the most recent record that should be in the log.
+        head: String,
+    },
+}
+
+impl Display for VerifyError {
+
+    fn fmt(&self, fmt: &mut Formatter) -> std::fmt::Result {
+        use VerifyError::*;
+
+        match *self {
+            Read(ref error) => {
+                write!(fmt, "failed to read the audit log: {}", error)
+            }
+            Broken { ref path, line } => {
+                write!(fmt, "audit log tampered with at {}:{}",
+                       path.display(), line)
+            }
+            Truncated { ref head } => {
+                write!(fmt, "audit log truncated (missing record {})", head)
+            }
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {
+
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use VerifyError::*;
+
+        match *self {
+            Read(ref error) => Some(error),
+            Broken { .. } => None,
+            Truncated { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_verify_empty() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("audit.log");
+
+        drop(Log::open(&path, None).unwrap());
+
+        assert_eq!(verify(&path).unwrap().count, 0);
+    }
+
+    #[test]
+    fn test_verify_intact() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("audit.log");
+
+        let log = Log::open(&path, None).unwrap();
+        log.append(record("Foo")).unwrap();
+        log.append(record("Bar")).unwrap();
+        log.append(record("Baz")).unwrap();
+
+        assert_eq!(verify(&path).unwrap().count, 3);
+    }
+
+    #[test]
+    fn test_verify_reopened() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("audit.log");
+
+        let log = Log::open(&path, None).unwrap();
+        log.append(record("Foo")).unwrap();
+        drop(log);
+
+        let log = Log::open(&path, None).unwrap();
+        log.append(record("Bar")).unwrap();
+
+        assert_eq!(verify(&path).unwrap().count, 2);
+    }
+
+    #[test]
+    fn test_verify_rotated() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("audit.log");
+
+        let rotation = Rotation {
+            max_size: 256,
+            max_count: 16,
+        };
+
+        let log = Log::open(&path, Some(rotation)).unwrap();
+        for _ in 0..10 {
+            log.append(record("Foo")).unwrap();
+        }
+
+        assert!(rotated_path(&path, 1).exists());
+        assert_eq!(verify(&path).unwrap().count, 10);
+    }
+
+    #[test]
+    fn test_verify_removed_record() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("audit.log");
+
+        let log = Log::open(&path, None).unwrap();
+        log.append(record("Foo")).unwrap();
+        log.append(record("Bar")).unwrap();
+        log.append(record("Baz")).unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines = content.lines().collect::<Vec<_>>();
+        std::fs::write(&path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+
+        let error = verify(&path).unwrap_err();
+        assert!(matches!(error, VerifyError::Broken { line: 2, .. }));
+    }
+
+    #[test]
+    fn test_verify_modified_record() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("audit.log");
+
+        let log = Log::open(&path, None).unwrap();
+        log.append(record("Foo")).unwrap();
+        log.append(record("Bar")).unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, content.replace("Bar", "Baz")).unwrap();
+
+        let error = verify(&path).unwrap_err();
+        assert!(matches!(error, VerifyError::Broken { line: 2, .. }));
+    }
+
+    #[test]
+    fn test_verify_removed_oldest_record() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("audit.log");
+
+        let log = Log::open(&path, None).unwrap();
+        log.append(record("Foo")).unwrap();
+        log.append(record("Bar")).unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines = content.lines().collect::<Vec<_>>();
+        std::fs::write(&path, format!("{}\n", lines[1])).unwrap();
+
+        let error = verify(&path).unwrap_err();
+        assert!(matches!(error, VerifyError::Broken { line: 1, .. }));
+    }
+
+    #[test]
+    fn test_verify_truncated() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("audit.log");
+
+        let log = Log::open(&path, None).unwrap();
+        log.append(record("Foo")).unwrap();
+        log.append(record("Bar")).unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines = content.lines().collect::<Vec<_>>();
+        std::fs::write(&path, format!("{}\n", lines[0])).unwrap();
+
+        let error = verify(&path).unwrap_err();
+        assert!(matches!(error, VerifyError::Truncated { .. }));
+    }
+
+    #[test]
+    fn test_verify_truncated_and_reopened() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("audit.log");
+
+        let log = Log::open(&path, None).unwrap();
+        log.append(record("Foo")).unwrap();
+        log.append(record("Bar")).unwrap();
+        drop(log);
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines = content.lines().collect::<Vec<_>>();
+        std::fs::write(&path, format!("{}\n", lines[0])).unwrap();
+
+        let log = Log::open(&path, None).unwrap();
+        log.append(record("Baz")).unwrap();
+
+        let error = verify(&path).unwrap_err();
+        assert!(matches!(error, VerifyError::Broken { line: 2, .. }));
+    }
+
+    #[test]
+    fn test_verify_rotated_discarded() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("audit.log");
+
+        let rotation = Rotation {
+            max_size: 256,
+            max_count: 2,
+        };
+
+        let log = Log::open(&path, Some(rotation)).unwrap();
+        for _ in 0..10 {
+            log.append(record("Foo")).unwrap();
+        }
+
+        // Every record is bigger than the maximum size, so each one ends up in
+        // a separate file and only the three most recent ones are kept.
+        assert!(rotated_path(&path, 2).exists());
+        assert!(!rotated_path(&path, 3).exists());
+        assert_eq!(verify(&path).unwrap().count, 3);
+    }
+
+    #[test]
+    fn test_verify_rotated_nothing_kept() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("audit.log");
+
+        let rotation = Rotation {
+            max_size: 256,
+            max_count: 0,
+        };
+
+        let log = Log::open(&path, Some(rotation)).unwrap();
+        for _ in 0..3 {
+            log.append(record("Foo")).unwrap();
+        }
+
+        assert_eq!(verify(&path).unwrap().count, 1);
+    }
+
+    #[test]
+    fn test_verify_removed_oldest_file() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("audit.log");
+
+        let rotation = Rotation {
+            max_size: 256,
+            max_count: 16,
+        };
+
+        let log = Log::open(&path, Some(rotation)).unwrap();
+        for _ in 0..10 {
+            log.append(record("Foo")).unwrap();
+        }
+
+        std::fs::remove_file(rotated_path(&path, 9)).unwrap();
+
+        let error = verify(&path).unwrap_err();
+        assert!(matches!(error, VerifyError::Broken { line: 1, .. }));
+    }
+
+    #[test]
+    fn test_verify_head() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("audit.log");
+
+        let log = Log::open(&path, None).unwrap();
+        assert_eq!(verify(&path).unwrap().head, GENESIS);
+
+        log.append(record("Foo")).unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let (hash, _) = split_line(content.trim_end()).unwrap();
+        assert_eq!(verify(&path).unwrap().head, hash);
+    }
+
+    #[test]
+    fn test_dump() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("audit.log");
+
+        let log = Log::open(&path, None).unwrap();
+        log.append(record("Foo")).unwrap();
+        log.append(record("Bar")).unwrap();
+
+        let mut output = vec!();
+        dump(&path, &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        let records = output.lines()
+            .map(|line| serde_json::from_str::<Record>(line).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].action, "Foo");
+        assert_eq!(records[0].prev, GENESIS);
+        assert_eq!(records[1].action, "Bar");
+    }
+
+    /// Constructs a dummy record of the given action.
+    fn record(action: &str) -> Record {
+        Record {
+            prev: String::new(),
+            session_id: String::from("F:ABCDEF"),
+            action: String::from(action),
+            args_digest: digest(b""),
+            start_time: String::from("2020-01-01T00:00:00Z"),
+            end_time: String::from("2020-01-01T00:00:01Z"),
+            bytes_sent: 1024,
+            result: Outcome::Ok,
+            error: None,
+        }
+    }
+}
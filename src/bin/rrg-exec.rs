@@ -0,0 +1,378 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+//! A binary that executes a single RRG action locally.
+//!
+//! Normally, actions are requested by the GRR server and their results are
+//! sent back through Fleetspeak. This binary runs any action available in the
+//! agent without any of that: the request is read from a file, replies are
+//! printed to the standard output as JSON Lines (or written to a gzchunked
+//! file) and blobs sent to the transfer store are written to a local directory.
+//!
+//! This makes it possible to triage hosts that are not enrolled into any GRR
+//! deployment and to debug actions during development.
+//!
+//! Request arguments are given as a JSON object, a Protocol Buffers text-format
+//! message or a binary-encoded one of the type that the action expects, e.g.
+//! `{"root": "L3Zhcg==", "threads": 4}` or `root: "/var" threads: 4` for the
+//! `Timeline` action. Actions that do not need any arguments can be executed
+//! without specifying them.
+
+use std::fs::File;
+use std::io::Read as _;
+use std::path::PathBuf;
+
+use log::{error, info};
+use serde_json::Value;
+use sha2::{Digest as _, Sha256};
+use structopt::StructOpt;
+
+use rrg::action::timeline::Chunk;
+use rrg::session::{self, Sink};
+
+/// A type for the command-line arguments of the binary.
+#[derive(StructOpt)]
+#[structopt(name = "rrg-exec", about = "Executes an RRG action locally.")]
+struct Opts {
+    /// A name of the action to execute.
+    #[structopt(name = "ACTION",
+                help = "Name of the action to execute (e.g. 'Timeline')")]
+    action: String,
+
+    /// A path to the file with serialized request arguments.
+    #[structopt(long = "args", name = "FILE",
+                help = "File with the request arguments ('-' for stdin)")]
+    args: Option<PathBuf>,
+
+    /// A format of the request arguments.
+    #[structopt(long = "args-format", name = "FORMAT", default_value = "json",
+                help = "Format of the request arguments ('json', 'text' or \
+                        'binary')")]
+    args_format: ArgsFormat,
+
+    /// A name of the message type of the request arguments.
+    #[structopt(long = "args-type", name = "TYPE",
+                help = "Message type of the request arguments (if the action \
+                        is not known to the binary)")]
+    args_type: Option<String>,
+
+    /// A path prefix of gzchunked files to write the replies into.
+    #[structopt(long = "output", name = "PREFIX",
+                help = "Writes replies to gzchunked files instead of stdout")]
+    output: Option<PathBuf>,
+
+    /// A path to the directory to write the transfer store blobs into.
+    #[structopt(long = "blobs", name = "DIRECTORY",
+                help = "Directory to write the transfer store blobs into")]
+    blobs: Option<PathBuf>,
+}
+
+/// A format of the serialized request arguments.
+#[derive(Clone, Copy)]
+enum ArgsFormat {
+    /// A JSON object with fields of the request message.
+    Json,
+    /// A message in the Protocol Buffers text format.
+    Text,
+    /// A binary-encoded Protocol Buffers message.
+    Binary,
+}
+
+impl std::str::FromStr for ArgsFormat {
+
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<ArgsFormat, String> {
+        match string {
+            "json" => Ok(ArgsFormat::Json),
+            "text" => Ok(ArgsFormat::Text),
+            "binary" => Ok(ArgsFormat::Binary),
+            _ => Err(format!("unknown format '{}'", string)),
+        }
+    }
+}
+
+/// A session type that executes the action locally.
+struct Local {
+    /// A destination of the action replies.
+    output: Output,
+    /// A directory into which the transfer store blobs are written (if any).
+    blobs: Option<PathBuf>,
+    /// An identifier that will be assigned to the next reply.
+    next_response_id: u64,
+}
+
+/// A destination of the action replies.
+enum Output {
+    /// Replies are printed to the standard output as JSON Lines.
+    Print,
+    /// Replies are written as `GrrMessage`s to a series of gzchunked files.
+    Gzchunked {
+        /// A path prefix of the output files.
+        prefix: PathBuf,
+        /// An encoder of the replies.
+        encoder: rrg::gzchunked::Encoder<rrg_proto::GrrMessage>,
+        /// A number of output files written so far.
+        part_count: usize,
+    },
+}
+
+impl Local {
+
+    /// Writes the remaining buffered replies (if any).
+    fn finish(&mut self) -> std::io::Result<()> {
+        if let Output::Gzchunked { ref mut encoder, .. } = self.output {
+            if let Some(part) = encoder.flush()? {
+                self.output.write_part(part)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Output {
+
+    /// Writes a single part of the gzchunked output to the next file.
+    fn write_part(&mut self, part: Vec<u8>) -> std::io::Result<()> {
+        if let Output::Gzchunked { prefix, part_count, .. } = self {
+            *part_count += 1;
+
+            let mut path = prefix.clone().into_os_string();
+            path.push(format!(".{}", part_count));
+
+            std::fs::write(&path, part)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl rrg::session::Session for Local {
+
+    fn reply<R>(&mut self, response: R) -> session::Result<()>
+    where
+        R: rrg::action::Response + 'static,
+    {
+        let response_id = self.next_response_id;
+        self.next_response_id += 1;
+
+        let proto = response.into_proto();
+
+        let part = match self.output {
+            Output::Print => {
+                let mut object = serde_json::Map::new();
+                object.insert(String::from("response_id"), response_id.into());
+
+                return print(object, R::RDF_NAME, &proto);
+            }
+            Output::Gzchunked { ref mut encoder, .. } => {
+                let mut data = vec!();
+                prost::Message::encode(&proto, &mut data)?;
+
+                let message = rrg_proto::GrrMessage {
+                    response_id: Some(response_id),
+                    args_rdf_name: R::RDF_NAME.map(String::from),
+                    args: Some(data),
+                    ..Default::default()
+                };
+
+                encoder.write(&message).map_err(session::Error::action)?
+            }
+        };
+
+        if let Some(part) = part {
+            self.output.write_part(part).map_err(session::Error::action)?;
+        }
+
+        Ok(())
+    }
+
+    fn send<R>(&mut self, sink: Sink, response: R) -> session::Result<()>
+    where
+        R: rrg::action::Response + 'static,
+    {
+        let chunk = (&response as &dyn std::any::Any).downcast_ref::<Chunk>();
+
+        let chunk = match (sink, chunk) {
+            (Sink::TRANSFER_STORE, Some(chunk)) => chunk,
+            _ => {
+                let mut object = serde_json::Map::new();
+                let sink = format!("{:?}", sink);
+                object.insert(String::from("sink"), sink.into());
+
+                return print(object, R::RDF_NAME, &response.into_proto());
+            }
+        };
+
+        let blobs = match self.blobs {
+            Some(ref blobs) => blobs,
+            None => {
+                info!("dropping a blob of {} bytes", chunk.data.len());
+                return Ok(());
+            }
+        };
+
+        let name = Sha256::digest(&chunk.data).iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+
+        std::fs::write(blobs.join(name), &chunk.data)
+            .map_err(session::Error::action)?;
+
+        Ok(())
+    }
+}
+
+fn main() {
+    let opts = Opts::from_args();
+
+    simplelog::TermLogger::init(log::LevelFilter::Info, Default::default(),
+                                simplelog::TerminalMode::Stderr)
+        .expect("failed to init logging");
+
+    let data = match opts.args {
+        Some(ref path) => match read_args(path, &opts) {
+            Ok(data) => Some(data),
+            Err(error) => {
+                error!("failed to read the request arguments: {}", error);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    if let Some(ref blobs) = opts.blobs {
+        if let Err(error) = std::fs::create_dir_all(blobs) {
+            error!("failed to create the blob directory: {}", error);
+            std::process::exit(1);
+        }
+    }
+
+    let output = match opts.output {
+        Some(prefix) => Output::Gzchunked {
+            prefix: prefix,
+            encoder: rrg::gzchunked::Encoder::new(Default::default()),
+            part_count: 0,
+        },
+        None => Output::Print,
+    };
+
+    let mut session = Local {
+        output: output,
+        blobs: opts.blobs,
+        next_response_id: 1,
+    };
+
+    let result = rrg::action::dispatch(&opts.action, session::Task {
+        session: &mut session,
+        payload: session::Payload {
+            data: data,
+        },
+    });
+
+    if let Err(error) = session.finish() {
+        error!("failed to write the output: {}", error);
+        std::process::exit(1);
+    }
+
+    match result {
+        Ok(()) => info!("finished executing the '{}' action", opts.action),
+        Err(error) => {
+            error!("failed to execute the '{}' action: {}", opts.action, error);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Reads serialized request arguments from the given path.
+///
+/// The path `-` denotes the standard input. Arguments in the JSON and text
+/// formats are converted to the binary encoding expected by the action.
+fn read_args(path: &PathBuf, opts: &Opts)
+    -> Result<Vec<u8>, Box<dyn std::error::Error>>
+{
+    let mut data = vec!();
+
+    if path.as_os_str() == "-" {
+        std::io::stdin().read_to_end(&mut data)?;
+    } else {
+        File::open(path)?.read_to_end(&mut data)?;
+    }
+
+    if let ArgsFormat::Binary = opts.args_format {
+        return Ok(data);
+    }
+
+    let args_type = match opts.args_type {
+        Some(ref args_type) => args_type.as_str(),
+        None => match args_type(&opts.action) {
+            Some(args_type) => args_type,
+            None => {
+                let error = format!("unknown arguments of '{}'", opts.action);
+                return Err(error.into());
+            }
+        },
+    };
+
+    match opts.args_format {
+        ArgsFormat::Json => {
+            let value = serde_json::from_slice::<Value>(&data)?;
+            Ok(rrg_proto::json::from_json(args_type, &value)?)
+        }
+        ArgsFormat::Text => {
+            let text = std::str::from_utf8(&data)?;
+            Ok(rrg_proto::text::from_text(args_type, text)?)
+        }
+        ArgsFormat::Binary => Ok(data),
+    }
+}
+
+/// Returns the name of the message type of arguments of the given action.
+///
+/// Actions that do not take any arguments are not listed here.
+fn args_type(action: &str) -> Option<&'static str> {
+    match action {
+        "ListDirectory" => Some("grr.ListDirRequest"),
+        "Timeline" => Some("rrg.TimelineArgs"),
+        "ListNetworkConnections" => Some("grr.ListNetworkConnectionsArgs"),
+        "GetFileStat" => Some("grr.GetFileStatRequest"),
+        "FileFinder" => Some("grr.FileFinderArgs"),
+        "HashFile" => Some("grr.FingerprintRequest"),
+        "ScanMemory" => Some("rrg.ScanMemoryArgs"),
+        "GetClientStats" => Some("grr.GetClientStatsRequest"),
+        _ => None,
+    }
+}
+
+/// Prints the given response (with extra fields) as a single line of JSON.
+///
+/// Responses of types that are not known to the JSON converter (e.g. plain
+/// integers) are printed in their debug representation instead.
+fn print<M>(mut object: serde_json::Map<String, Value>,
+            rdf_name: Option<&str>, proto: &M) -> session::Result<()>
+where
+    M: prost::Message,
+{
+    let mut data = vec!();
+    prost::Message::encode(proto, &mut data)?;
+
+    let json = rdf_name.map(|name| rrg_proto::json::to_json(name, &data));
+
+    if let Some(rdf_name) = rdf_name {
+        object.insert(String::from("type"), rdf_name.into());
+    }
+
+    match json {
+        Some(Ok(json)) => object.insert(String::from("data"), json),
+        _ => {
+            let debug = format!("{:?}", proto);
+            object.insert(String::from("debug"), debug.into())
+        }
+    };
+
+    println!("{}", Value::Object(object));
+    Ok(())
+}
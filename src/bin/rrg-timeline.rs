@@ -0,0 +1,93 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+//! A binary that converts gzchunked timelines into other formats.
+//!
+//! The timeline action sends its results as a series of gzchunked parts of
+//! `TimelineEntry` messages (e.g. blobs written to a local directory by the
+//! `rrg-exec` binary). This binary decodes such parts and writes the timeline
+//! as a Sleuthkit bodyfile, a mactime-style CSV or JSON Lines, so that it can
+//! be analyzed with standard forensic tooling.
+//!
+//! Parts have to be given in the same order in which they were produced.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::PathBuf;
+
+use log::{error, info};
+use structopt::StructOpt;
+
+use rrg::export::Format;
+
+/// A type for the command-line arguments of the binary.
+#[derive(StructOpt)]
+#[structopt(name = "rrg-timeline",
+            about = "Converts gzchunked timelines into other formats.")]
+struct Opts {
+    /// Paths to the gzchunked timeline parts (in order).
+    #[structopt(name = "PART", required = true,
+                help = "Gzchunked timeline parts in the order of production")]
+    parts: Vec<PathBuf>,
+
+    /// A format to convert the timeline to.
+    #[structopt(long = "format", name = "FORMAT", default_value = "bodyfile",
+                help = "Output format ('bodyfile', 'csv' or 'jsonl')")]
+    format: Format,
+
+    /// A path to the file to write the converted timeline into.
+    #[structopt(long = "output", name = "FILE",
+                help = "File to write the output into instead of stdout")]
+    output: Option<PathBuf>,
+}
+
+fn main() {
+    let opts = Opts::from_args();
+
+    simplelog::TermLogger::init(log::LevelFilter::Info, Default::default(),
+                                simplelog::TerminalMode::Stderr)
+        .expect("failed to init logging");
+
+    let parts = match open_parts(&opts.parts) {
+        Ok(parts) => parts,
+        Err(error) => {
+            error!("failed to open timeline parts: {}", error);
+            std::process::exit(1);
+        }
+    };
+
+    let entries = rrg::gzchunked::decode(parts.into_iter());
+
+    let output: Box<dyn Write> = match opts.output {
+        Some(ref path) => match File::create(path) {
+            Ok(file) => Box::new(file),
+            Err(error) => {
+                error!("failed to create '{}': {}", path.display(), error);
+                std::process::exit(1);
+            }
+        },
+        None => Box::new(std::io::stdout()),
+    };
+
+    let output = BufWriter::new(output);
+
+    match rrg::export::export(entries, opts.format, output) {
+        Ok(()) => info!("converted {} timeline part(s)", opts.parts.len()),
+        Err(error) => {
+            error!("failed to convert the timeline: {}", error);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Opens all the timeline parts at the given paths.
+///
+/// Parts are opened upfront so that missing files are reported before any
+/// output is produced.
+fn open_parts(paths: &[PathBuf]) -> std::io::Result<Vec<BufReader<File>>> {
+    paths.iter()
+        .map(|path| File::open(path).map(BufReader::new))
+        .collect()
+}
@@ -80,6 +80,25 @@ where
     }
 }
 
+/// Appends a single protobuf message in the chunked format to the buffer.
+///
+/// This is a low-level building block of the streaming encoder that can be
+/// used by other encoders that need finer control over the output (e.g. the
+/// push-based encoder of the [gzchunked] format).
+///
+/// [gzchunked]: crate::gzchunked
+pub fn encode_into<M>(buf: &mut Vec<u8>, msg: &M) -> std::io::Result<()>
+where
+    M: prost::Message,
+{
+    use byteorder::WriteBytesExt as _;
+
+    buf.write_u64::<BigEndian>(msg.encoded_len() as u64)?;
+    msg.encode(buf)?;
+
+    Ok(())
+}
+
 /// Streaming encoder for the chunked format.
 ///
 /// It implements the `Read` trait, lazily polling the underlying iterator over
@@ -105,18 +124,13 @@ where
 
     /// Pulls another message from the underlying iterator.
     fn pull(&mut self) -> std::io::Result<()> {
-        use byteorder::WriteBytesExt as _;
-
         let msg = match self.iter.next() {
             Some(msg) => msg,
             None => return Ok(()),
         };
 
         self.cur.get_mut().clear();
-        self.cur.set_position(0);
-
-        self.cur.write_u64::<BigEndian>(msg.encoded_len() as u64)?;
-        msg.encode(&mut self.cur.get_mut())?;
+        encode_into(self.cur.get_mut(), &msg)?;
         self.cur.set_position(0);
 
         Ok(())
@@ -0,0 +1,716 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+//! Persistent configuration of the agent.
+//!
+//! Settings that should survive agent restarts (e.g. in fleet deployments) are
+//! kept in a TOML configuration file. Settings that are also available as
+//! command-line flags can be overridden that way, e.g. to temporarily increase
+//! log verbosity without touching the file.
+//!
+//! An example configuration file looks like this:
+//!
+//! ```toml
+//! heartbeat_rate = "5s"
+//! pool_size = 4
+//! max_walk_threads = 8
+//! labels = ["production", "web"]
+//! state_dir = "/var/lib/rrg"
+//!
+//! [log]
+//! verbosity = "info"
+//! file = "/var/log/rrg.log"
+//! max_size = 10485760
+//! max_count = 5
+//!
+//! [audit]
+//! file = "/var/log/rrg-audit.log"
+//! max_size = 10485760
+//!
+//! [actions]
+//! deny = ["ScanMemory"]
+//! paths = ["/home", "/var/log"]
+//!
+//! [limits]
+//! cpu_time = "10m"
+//! network_bytes = 1073741824
+//! runtime = "1h"
+//! ```
+//!
+//! The configuration is loaded and validated once at the agent startup. After
+//! that, it is available (read-only) to all the actions through the [`get`]
+//! function.
+//!
+//! [`get`]: fn.get.html
+
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use serde::Deserialize;
+
+use crate::opts::{Opts, Verbosity};
+use crate::session::Limits;
+
+lazy_static! {
+    /// The configuration of the running agent.
+    static ref CONFIG: RwLock<Arc<Config>> = {
+        RwLock::new(Arc::new(Config::default()))
+    };
+}
+
+/// Installs the configuration of the running agent.
+///
+/// This function should be called once at the agent startup. Until then, the
+/// default configuration is used.
+pub fn init(config: Config) {
+    *CONFIG.write().unwrap() = Arc::new(config);
+}
+
+/// Returns the configuration of the running agent.
+pub fn get() -> Arc<Config> {
+    CONFIG.read().unwrap().clone()
+}
+
+/// Complete configuration of the agent.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// A level of log verbosity.
+    pub log_verbosity: Verbosity,
+    /// A path to the file to log into (if any).
+    pub log_file: Option<PathBuf>,
+    /// A policy of rotating the log file (if the file should be rotated).
+    pub log_rotation: Option<Rotation>,
+    /// A path to the audit log file (if action auditing is enabled).
+    pub audit_file: Option<PathBuf>,
+    /// A policy of rotating the audit log file (if it should be rotated).
+    pub audit_rotation: Option<Rotation>,
+    /// A frequency of heartbeat messages to send to the Fleetspeak client.
+    pub heartbeat_rate: Duration,
+    /// A number of worker threads executing actions concurrently.
+    pub pool_size: usize,
+    /// A maximum number of threads that a single action can use to walk the
+    /// filesystem (regardless of how many the server requests).
+    pub max_walk_threads: usize,
+    /// Labels of the client to report to the server.
+    pub labels: Vec<String>,
+    /// A path to the directory where actions can persist their state (e.g. to
+    /// resume after the agent restarts).
+    pub state_dir: Option<PathBuf>,
+    /// Actions that the agent is allowed to execute.
+    pub actions: Actions,
+    /// Resource limits imposed on every action execution.
+    ///
+    /// These are applied on top of the limits requested by the server, so the
+    /// stricter of the two is always used.
+    pub limits: Limits,
+}
+
+/// A policy of rotating the log file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rotation {
+    /// A size (in bytes) after which the log file is rotated.
+    pub max_size: u64,
+    /// A number of rotated log files to keep.
+    pub max_count: usize,
+}
+
+/// Lists of actions that the agent is allowed or forbidden to execute.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Actions {
+    /// Names of actions that are allowed (if `None`, all actions are).
+    pub allow: Option<Vec<String>>,
+    /// Names of actions that are forbidden (even if explicitly allowed).
+    pub deny: Vec<String>,
+    /// Paths that filesystem actions are allowed to access (if `None`, there
+    /// are no restrictions).
+    pub paths: Option<Vec<PathBuf>>,
+}
+
+impl Default for Config {
+
+    fn default() -> Config {
+        Config {
+            log_verbosity: Verbosity::default(),
+            log_file: None,
+            log_rotation: None,
+            audit_file: None,
+            audit_rotation: None,
+            heartbeat_rate: Duration::from_secs(5),
+            pool_size: 4,
+            max_walk_threads: 8,
+            labels: vec!(),
+            state_dir: None,
+            actions: Actions::default(),
+            limits: Limits::default(),
+        }
+    }
+}
+
+impl Config {
+
+    /// Loads the configuration specified by the command-line arguments.
+    ///
+    /// The configuration file (if any) is read first and then the flags that
+    /// were explicitly given on the command line are applied on top of it.
+    pub fn from_opts(opts: &Opts) -> Result<Config, Error> {
+        // The file is validated only once the flags are applied, as these can
+        // supply values that the file relies on (e.g. the log file).
+        let mut config = match opts.config {
+            Some(ref path) => {
+                let string = std::fs::read_to_string(path)
+                    .map_err(Error::Read)?;
+                Config::parse_unvalidated(&string)?
+            }
+            None => Config::default(),
+        };
+
+        if let Some(verbosity) = opts.log_verbosity {
+            config.log_verbosity = verbosity;
+        }
+        if let Some(ref path) = opts.log_file {
+            config.log_file = Some(path.clone());
+        }
+        if let Some(rate) = opts.heartbeat_rate {
+            config.heartbeat_rate = rate;
+        }
+        if let Some(size) = opts.pool_size {
+            config.pool_size = size;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Loads the configuration from the file at the given path.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Config, Error> {
+        let string = std::fs::read_to_string(path).map_err(Error::Read)?;
+        Config::parse(&string)
+    }
+
+    /// Parses the configuration from the given TOML string.
+    pub fn parse(string: &str) -> Result<Config, Error> {
+        let config = Config::parse_unvalidated(string)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Parses the configuration without checking the mergeable values.
+    fn parse_unvalidated(string: &str) -> Result<Config, Error> {
+        let raw = toml::from_str::<RawConfig>(string).map_err(Error::Parse)?;
+
+        let mut config = Config::default();
+
+        if let Some(verbosity) = raw.log.verbosity {
+            config.log_verbosity = verbosity.parse()
+                .map_err(|error| invalid("log.verbosity", error))?;
+        }
+        config.log_file = raw.log.file;
+        config.log_rotation = rotation(
+            ("log.max_size", raw.log.max_size),
+            ("log.max_count", raw.log.max_count),
+        )?;
+
+        config.audit_file = raw.audit.file;
+        config.audit_rotation = rotation(
+            ("audit.max_size", raw.audit.max_size),
+            ("audit.max_count", raw.audit.max_count),
+        )?;
+
+        if let Some(rate) = raw.heartbeat_rate {
+            config.heartbeat_rate = humantime::parse_duration(&rate)
+                .map_err(|error| invalid("heartbeat_rate", error))?;
+        }
+        if let Some(size) = raw.pool_size {
+            config.pool_size = size;
+        }
+        if let Some(threads) = raw.max_walk_threads {
+            config.max_walk_threads = threads;
+        }
+
+        for label in &raw.labels {
+            if label.trim().is_empty() {
+                return Err(invalid("labels", "labels cannot be blank"));
+            }
+        }
+        config.labels = raw.labels;
+
+        if let Some(ref path) = raw.state_dir {
+            if !path.is_absolute() {
+                let reason = format!("'{}' is not absolute", path.display());
+                return Err(invalid("state_dir", reason));
+            }
+        }
+        config.state_dir = raw.state_dir;
+
+        if let Some(ref allow) = raw.actions.allow {
+            let deny = &raw.actions.deny;
+            let action = allow.iter().find(|action| deny.contains(action));
+            if let Some(action) = action {
+                let reason = format!("'{}' is also denied", action);
+                return Err(invalid("actions.allow", reason));
+            }
+        }
+        for path in raw.actions.paths.iter().flatten() {
+            use std::path::Component::ParentDir;
+
+            if !path.is_absolute() {
+                let reason = format!("'{}' is not absolute", path.display());
+                return Err(invalid("actions.paths", reason));
+            }
+            if path.components().any(|component| component == ParentDir) {
+                let reason = format!("'{}' is not normalized", path.display());
+                return Err(invalid("actions.paths", reason));
+            }
+        }
+        config.actions = Actions {
+            allow: raw.actions.allow,
+            deny: raw.actions.deny,
+            paths: raw.actions.paths,
+        };
+
+        if let Some(time) = raw.limits.cpu_time {
+            let time = parse_duration("limits.cpu_time", &time)?;
+            config.limits.cpu_time = Some(time);
+        }
+        match raw.limits.network_bytes {
+            Some(0) => {
+                return Err(invalid("limits.network_bytes", "must be positive"));
+            }
+            bytes => config.limits.network_bytes = bytes,
+        }
+        if let Some(time) = raw.limits.runtime {
+            let time = parse_duration("limits.runtime", &time)?;
+            config.limits.runtime = Some(time);
+        }
+
+        Ok(config)
+    }
+
+    /// Verifies the values that can be given both in the file and as flags.
+    ///
+    /// This is done after the configuration is merged, so that the same rules
+    /// apply no matter where the value comes from.
+    fn validate(&self) -> Result<(), Error> {
+        if self.heartbeat_rate == Duration::from_secs(0) {
+            return Err(invalid("heartbeat_rate", "must be positive"));
+        }
+        if self.pool_size == 0 {
+            return Err(invalid("pool_size", "must be positive"));
+        }
+        if self.max_walk_threads == 0 {
+            return Err(invalid("max_walk_threads", "must be positive"));
+        }
+        if self.log_rotation.is_some() && self.log_file.is_none() {
+            return Err(invalid("log.max_size", "requires log.file"));
+        }
+        if self.audit_rotation.is_some() && self.audit_file.is_none() {
+            return Err(invalid("audit.max_size", "requires audit.file"));
+        }
+
+        Ok(())
+    }
+}
+
+/// A raw configuration as specified in the configuration file.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawConfig {
+    heartbeat_rate: Option<String>,
+    pool_size: Option<usize>,
+    max_walk_threads: Option<usize>,
+    labels: Vec<String>,
+    state_dir: Option<PathBuf>,
+    log: RawLog,
+    audit: RawAudit,
+    actions: RawActions,
+    limits: RawLimits,
+}
+
+/// A raw `[log]` section of the configuration file.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawLog {
+    verbosity: Option<String>,
+    file: Option<PathBuf>,
+    max_size: Option<u64>,
+    max_count: Option<usize>,
+}
+
+/// A raw `[audit]` section of the configuration file.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawAudit {
+    file: Option<PathBuf>,
+    max_size: Option<u64>,
+    max_count: Option<usize>,
+}
+
+/// A raw `[actions]` section of the configuration file.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawActions {
+    allow: Option<Vec<String>>,
+    deny: Vec<String>,
+    paths: Option<Vec<PathBuf>>,
+}
+
+/// A raw `[limits]` section of the configuration file.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawLimits {
+    cpu_time: Option<String>,
+    network_bytes: Option<u64>,
+    runtime: Option<String>,
+}
+
+/// Validates a rotation policy given as its maximum size and file count.
+///
+/// Both values are given together with their keys for error reporting.
+fn rotation(max_size: (&'static str, Option<u64>),
+            max_count: (&'static str, Option<usize>))
+    -> Result<Option<Rotation>, Error>
+{
+    let (size_key, max_size) = max_size;
+    let (count_key, max_count) = max_count;
+
+    match (max_size, max_count) {
+        (None, None) => Ok(None),
+        (Some(0), _) => Err(invalid(size_key, "must be positive")),
+        (Some(max_size), max_count) => Ok(Some(Rotation {
+            max_size: max_size,
+            max_count: max_count.unwrap_or(1),
+        })),
+        (None, Some(_)) => {
+            Err(invalid(count_key, format!("requires {}", size_key)))
+        }
+    }
+}
+
+/// Parses a human-readable, positive duration (e.g. `10m` or `1h 30m`).
+fn parse_duration(key: &'static str, string: &str) -> Result<Duration, Error> {
+    match humantime::parse_duration(string) {
+        Ok(duration) if duration == Duration::from_secs(0) => {
+            Err(invalid(key, "must be positive"))
+        }
+        Ok(duration) => Ok(duration),
+        Err(error) => Err(invalid(key, error)),
+    }
+}
+
+/// Constructs an error for an invalid value of the given key.
+fn invalid<S: ToString>(key: &'static str, reason: S) -> Error {
+    Error::Invalid {
+        key: key,
+        reason: reason.to_string(),
+    }
+}
+
+/// An error type for failures that can occur when loading the configuration.
+#[derive(Debug)]
+pub enum Error {
+    /// The configuration file could not be read.
+    Read(std::io::Error),
+    /// The configuration file is not a valid TOML file (or has unknown keys).
+    Parse(toml::de::Error),
+    /// The value of the particular key is invalid.
+    Invalid {
+        /// A key of the invalid value (e.g. `log.max_size`).
+        key: &'static str,
+        /// An explanation why the value is invalid.
+        reason: String,
+    },
+}
+
+impl Display for Error {
+
+    fn fmt(&self, fmt: &mut Formatter) -> std::fmt::Result {
+        use Error::*;
+
+        match *self {
+            Read(ref error) => {
+                write!(fmt, "failed to read the config file: {}", error)
+            }
+            Parse(ref error) => {
+                write!(fmt, "failed to parse the config file: {}", error)
+            }
+            Invalid { key, ref reason } => {
+                write!(fmt, "invalid value of '{}': {}", key, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use Error::*;
+
+        match *self {
+            Read(ref error) => Some(error),
+            Parse(ref error) => Some(error),
+            Invalid { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use structopt::StructOpt as _;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_empty() {
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn test_parse_all() {
+        let config = Config::parse(r#"
+            heartbeat_rate = "10s"
+            pool_size = 8
+            max_walk_threads = 16
+            labels = ["foo", "bar"]
+            state_dir = "/var/lib/rrg"
+
+            [log]
+            verbosity = "debug"
+            file = "/var/log/rrg.log"
+            max_size = 1024
+            max_count = 3
+
+            [audit]
+            file = "/var/log/rrg-audit.log"
+            max_size = 2048
+
+            [actions]
+            allow = ["GetClientInfo", "Timeline"]
+            deny = ["ScanMemory"]
+            paths = ["/home", "/var/log"]
+
+            [limits]
+            cpu_time = "1m"
+            network_bytes = 4096
+            runtime = "1h"
+        "#).unwrap();
+
+        assert_eq!(config.log_verbosity.level(), log::LevelFilter::Debug);
+        assert_eq!(config.log_file, Some(PathBuf::from("/var/log/rrg.log")));
+        assert_eq!(config.log_rotation, Some(Rotation {
+            max_size: 1024,
+            max_count: 3,
+        }));
+        assert_eq! {
+            config.audit_file,
+            Some(PathBuf::from("/var/log/rrg-audit.log"))
+        };
+        assert_eq!(config.audit_rotation, Some(Rotation {
+            max_size: 2048,
+            max_count: 1,
+        }));
+        assert_eq!(config.heartbeat_rate, Duration::from_secs(10));
+        assert_eq!(config.pool_size, 8);
+        assert_eq!(config.max_walk_threads, 16);
+        assert_eq!(config.labels, vec!("foo", "bar"));
+        assert_eq!(config.state_dir, Some(PathBuf::from("/var/lib/rrg")));
+        assert_eq!(config.actions.allow, Some(vec! {
+            String::from("GetClientInfo"),
+            String::from("Timeline"),
+        }));
+        assert_eq!(config.actions.deny, vec!("ScanMemory"));
+        assert_eq!(config.actions.paths, Some(vec! {
+            PathBuf::from("/home"),
+            PathBuf::from("/var/log"),
+        }));
+        assert_eq!(config.limits, Limits {
+            cpu_time: Some(Duration::from_secs(60)),
+            network_bytes: Some(4096),
+            runtime: Some(Duration::from_secs(3600)),
+        });
+    }
+
+    #[test]
+    fn test_parse_unknown_key() {
+        let error = Config::parse("foo = 42").unwrap_err();
+        assert!(matches!(error, Error::Parse(_)));
+    }
+
+    #[test]
+    fn test_parse_invalid_verbosity() {
+        let error = Config::parse(r#"
+            [log]
+            verbosity = "loud"
+        "#).unwrap_err();
+
+        assert!(matches!(error, Error::Invalid { key: "log.verbosity", .. }));
+    }
+
+    #[test]
+    fn test_parse_zero_pool_size() {
+        let error = Config::parse("pool_size = 0").unwrap_err();
+        assert!(matches!(error, Error::Invalid { key: "pool_size", .. }));
+    }
+
+    #[test]
+    fn test_parse_rotation_without_file() {
+        let error = Config::parse(r#"
+            [log]
+            max_size = 1024
+        "#).unwrap_err();
+
+        assert!(matches!(error, Error::Invalid { key: "log.max_size", .. }));
+    }
+
+    #[test]
+    fn test_parse_audit_rotation_without_size() {
+        let error = Config::parse(r#"
+            [audit]
+            file = "/var/log/rrg-audit.log"
+            max_count = 3
+        "#).unwrap_err();
+
+        assert!(matches!(error, Error::Invalid { key: "audit.max_count", .. }));
+    }
+
+    #[test]
+    fn test_parse_allowed_and_denied() {
+        let error = Config::parse(r#"
+            [actions]
+            allow = ["Timeline"]
+            deny = ["Timeline"]
+        "#).unwrap_err();
+
+        assert!(matches!(error, Error::Invalid { key: "actions.allow", .. }));
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_parse_relative_path() {
+        let error = Config::parse(r#"
+            [actions]
+            paths = ["home"]
+        "#).unwrap_err();
+
+        assert!(matches!(error, Error::Invalid { key: "actions.paths", .. }));
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_parse_relative_state_dir() {
+        let error = Config::parse(r#"state_dir = "rrg""#).unwrap_err();
+        assert!(matches!(error, Error::Invalid { key: "state_dir", .. }));
+    }
+
+    #[test]
+    fn test_parse_invalid_duration() {
+        let error = Config::parse(r#"
+            [limits]
+            runtime = "forever"
+        "#).unwrap_err();
+
+        assert!(matches!(error, Error::Invalid { key: "limits.runtime", .. }));
+    }
+
+    #[test]
+    fn test_from_opts_overrides() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("rrg.toml");
+        std::fs::write(&path, r#"
+            pool_size = 8
+            labels = ["foo"]
+        "#).unwrap();
+
+        let opts = Opts::from_iter(&[
+            "rrg",
+            "--config", path.to_str().unwrap(),
+            "--pool-size", "2",
+        ]);
+
+        let config = Config::from_opts(&opts).unwrap();
+        assert_eq!(config.pool_size, 2);
+        assert_eq!(config.labels, vec!("foo"));
+    }
+
+    #[test]
+    fn test_from_opts_rotation_with_log_file_flag() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("rrg.toml");
+        std::fs::write(&path, r#"
+            [log]
+            max_size = 1024
+        "#).unwrap();
+
+        let log_path = tempdir.path().join("rrg.log");
+
+        let opts = Opts::from_iter(&[
+            "rrg",
+            "--config", path.to_str().unwrap(),
+            "--log-file", log_path.to_str().unwrap(),
+        ]);
+
+        let config = Config::from_opts(&opts).unwrap();
+        assert_eq!(config.log_file, Some(log_path));
+        assert_eq!(config.log_rotation.map(|rotation| rotation.max_size),
+                   Some(1024));
+    }
+
+    #[test]
+    fn test_from_opts_rotation_without_file() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("rrg.toml");
+        std::fs::write(&path, r#"
+            [log]
+            max_size = 1024
+        "#).unwrap();
+
+        let opts = Opts::from_iter(&[
+            "rrg",
+            "--config", path.to_str().unwrap(),
+        ]);
+
+        let error = Config::from_opts(&opts).unwrap_err();
+        assert!(matches!(error, Error::Invalid { key: "log.max_size", .. }));
+    }
+
+    #[test]
+    fn test_parse_zero_max_walk_threads() {
+        let error = Config::parse("max_walk_threads = 0").unwrap_err();
+
+        let key = "max_walk_threads";
+        assert!(matches!(error, Error::Invalid { key: k, .. } if k == key));
+    }
+
+    #[test]
+    fn test_parse_zero_heartbeat_rate() {
+        let error = Config::parse(r#"heartbeat_rate = "0s""#).unwrap_err();
+        assert!(matches!(error, Error::Invalid { key: "heartbeat_rate", .. }));
+    }
+
+    #[test]
+    fn test_from_opts_zero_heartbeat_rate() {
+        let opts = Opts::from_iter(&["rrg", "--heartbeat-rate", "0s"]);
+
+        let error = Config::from_opts(&opts).unwrap_err();
+        assert!(matches!(error, Error::Invalid { key: "heartbeat_rate", .. }));
+    }
+
+    #[test]
+    fn test_from_opts_missing_file() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("rrg.toml");
+
+        let opts = Opts::from_iter(&[
+            "rrg",
+            "--config", path.to_str().unwrap(),
+        ]);
+
+        let error = Config::from_opts(&opts).unwrap_err();
+        assert!(matches!(error, Error::Read(_)));
+    }
+}
@@ -0,0 +1,615 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+//! Conversion of timelines into formats understood by other forensic tools.
+//!
+//! The timeline action produces a gzchunked stream of `TimelineEntry` messages
+//! which is not really usable outside of GRR. This module converts such streams
+//! into one of the following formats:
+//!
+//!   * [bodyfile] (as produced by Sleuthkit's `fls -m`) that can be fed into
+//!     Plaso or the `mactime` tool,
+//!   * mactime-style CSV, with a row for every distinct timestamp of a file
+//!     and rows sorted chronologically, that can be loaded into spreadsheets,
+//!   * [JSON Lines] with one JSON object per timeline entry.
+//!
+//! In all the formats, file modes are rendered symbolically (like `ls -l` does)
+//! and paths are rendered lossily (i.e. invalid Unicode sequences are replaced
+//! with the replacement character).
+//!
+//! [bodyfile]: https://wiki.sleuthkit.org/index.php?title=Body_file
+//! [JSON Lines]: https://jsonlines.org
+
+use std::io::Write;
+
+use serde::Serialize;
+
+/// Output formats that timelines can be exported to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// The Sleuthkit bodyfile format (version 3).
+    Bodyfile,
+    /// A mactime-style CSV file sorted by time.
+    Csv,
+    /// A JSON object per line.
+    Jsonl,
+}
+
+impl std::str::FromStr for Format {
+
+    type Err = ParseFormatError;
+
+    fn from_str(string: &str) -> Result<Format, ParseFormatError> {
+        match string {
+            "bodyfile" => Ok(Format::Bodyfile),
+            "csv" => Ok(Format::Csv),
+            "jsonl" => Ok(Format::Jsonl),
+            _ => Err(ParseFormatError::new(string)),
+        }
+    }
+}
+
+/// Writes the given timeline entries to the output in the specified format.
+///
+/// Entries are usually obtained by decoding a gzchunked timeline with the
+/// [`gzchunked::decode`] function. Errors of the input iterator are propagated.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+///
+/// use rrg::export::Format;
+///
+/// let parts = ["timeline.1", "timeline.2"].iter()
+///     .map(|path| File::open(path).unwrap());
+///
+/// let entries = rrg::gzchunked::decode(parts);
+/// let output = std::io::stdout();
+///
+/// rrg::export::export(entries, Format::Bodyfile, output.lock()).unwrap();
+/// ```
+///
+/// [`gzchunked::decode`]: ../gzchunked/fn.decode.html
+pub fn export<I, W>(
+    entries: I,
+    format: Format,
+    output: W,
+) -> std::io::Result<()>
+where
+    I: Iterator<Item = std::io::Result<rrg_proto::TimelineEntry>>,
+    W: Write,
+{
+    match format {
+        Format::Bodyfile => write_bodyfile(entries, output),
+        Format::Csv => write_csv(entries, output),
+        Format::Jsonl => write_jsonl(entries, output),
+    }
+}
+
+/// Writes the given timeline entries to the output in the bodyfile format.
+///
+/// Every entry becomes one line of the following form (with times given in
+/// seconds since the epoch and the MD5 digest always being zero):
+///
+/// ```text
+/// MD5|name|inode|mode_as_string|UID|GID|size|atime|mtime|ctime|crtime
+/// ```
+///
+/// Pipes, backslashes and line breaks in names are escaped with a backslash.
+/// Values that are not available are reported as zeros.
+pub fn write_bodyfile<I, W>(entries: I, mut output: W) -> std::io::Result<()>
+where
+    I: Iterator<Item = std::io::Result<rrg_proto::TimelineEntry>>,
+    W: Write,
+{
+    for entry in entries {
+        let entry = entry?;
+
+        writeln! {
+            output,
+            concat! {
+                "0|{name}|{ino}|{mode}|{uid}|{gid}|{size}|",
+                "{atime}|{mtime}|{ctime}|{btime}",
+            },
+            name = escape_bodyfile(&path(&entry)),
+            ino = entry.ino.unwrap_or(0),
+            mode = mode_string(entry.mode.unwrap_or(0) as u32),
+            uid = entry.uid.unwrap_or(0),
+            gid = entry.gid.unwrap_or(0),
+            size = entry.size.unwrap_or(0),
+            atime = entry.atime_ns.map_or(0, secs),
+            mtime = entry.mtime_ns.map_or(0, secs),
+            ctime = entry.ctime_ns.map_or(0, secs),
+            btime = entry.btime_ns.map_or(0, secs),
+        }?;
+    }
+
+    output.flush()
+}
+
+/// Writes the given timeline entries to the output as a mactime-style CSV.
+///
+/// Just like the output of the `mactime -d` command, the file has a row for
+/// every distinct timestamp (with a second precision) of every entry. The type
+/// column says which of the modification, access, inode change and birth times
+/// (`macb`) the row corresponds to. Rows are sorted by time (in UTC).
+///
+/// Note that sorting requires all the entries to be kept in memory.
+pub fn write_csv<I, W>(entries: I, mut output: W) -> std::io::Result<()>
+where
+    I: Iterator<Item = std::io::Result<rrg_proto::TimelineEntry>>,
+    W: Write,
+{
+    let entries = entries.collect::<std::io::Result<Vec<_>>>()?;
+
+    let mut rows = vec!();
+    for (index, entry) in entries.iter().enumerate() {
+        let times = [entry.mtime_ns, entry.atime_ns, entry.ctime_ns,
+                     entry.btime_ns];
+        let times = times.iter()
+            .map(|time| time.map(secs))
+            .collect::<Vec<_>>();
+
+        let mut distinct_times = times.iter().flatten().copied()
+            .collect::<Vec<_>>();
+        distinct_times.sort();
+        distinct_times.dedup();
+
+        for time in distinct_times {
+            let kind = times.iter().zip("macb".chars())
+                .map(|(&entry_time, flag)| {
+                    if entry_time == Some(time) { flag } else { '.' }
+                })
+                .collect::<String>();
+
+            rows.push((time, index, kind));
+        }
+    }
+
+    // Rows with the same time are kept in the order in which the entries
+    // appeared in the timeline.
+    rows.sort_by_key(|&(time, index, _)| (time, index));
+
+    writeln!(output, "Date,Size,Type,Mode,UID,GID,Meta,File Name")?;
+    for (time, index, kind) in rows {
+        let entry = &entries[index];
+
+        writeln! {
+            output,
+            "{date},{size},{kind},{mode},{uid},{gid},{ino},{name}",
+            date = date(time),
+            size = entry.size.unwrap_or(0),
+            kind = kind,
+            mode = mode_string(entry.mode.unwrap_or(0) as u32),
+            uid = entry.uid.unwrap_or(0),
+            gid = entry.gid.unwrap_or(0),
+            ino = entry.ino.unwrap_or(0),
+            name = escape_csv(&path(entry)),
+        }?;
+    }
+
+    output.flush()
+}
+
+/// Writes the given timeline entries to the output as JSON Lines.
+///
+/// Every entry becomes a JSON object with the same fields as the timeline
+/// entry (except for the mode, which is rendered symbolically and the path,
+/// which is rendered as a string). Values that are not available are omitted.
+pub fn write_jsonl<I, W>(entries: I, mut output: W) -> std::io::Result<()>
+where
+    I: Iterator<Item = std::io::Result<rrg_proto::TimelineEntry>>,
+    W: Write,
+{
+    for entry in entries {
+        let entry = entry?;
+
+        let json = JsonEntry {
+            path: path(&entry),
+            mode: entry.mode.map(|mode| mode_string(mode as u32)),
+            size: entry.size,
+            dev: entry.dev,
+            ino: entry.ino,
+            uid: entry.uid,
+            gid: entry.gid,
+            atime_ns: entry.atime_ns,
+            mtime_ns: entry.mtime_ns,
+            ctime_ns: entry.ctime_ns,
+            btime_ns: entry.btime_ns,
+            attributes: entry.attributes,
+        };
+
+        serde_json::to_writer(&mut output, &json)?;
+        writeln!(output)?;
+    }
+
+    output.flush()
+}
+
+/// Renders the given file mode symbolically (e.g. `drwxr-xr-x`).
+///
+/// The rendering is the same as the one used by the `ls -l` command: the first
+/// character denotes the file type and the remaining nine characters denote
+/// permissions of the owner, the group and others (including the setuid,
+/// setgid and sticky bits).
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(rrg::export::mode_string(0o100644), "-rw-r--r--");
+/// assert_eq!(rrg::export::mode_string(0o041777), "drwxrwxrwt");
+/// ```
+pub fn mode_string(mode: u32) -> String {
+    // These are the same on all Unix-like systems, but the `libc` crate does
+    // not define them on Windows (where timelines can be exported as well).
+    const S_IFMT: u32 = 0o170000;
+    const S_IFSOCK: u32 = 0o140000;
+    const S_IFLNK: u32 = 0o120000;
+    const S_IFREG: u32 = 0o100000;
+    const S_IFBLK: u32 = 0o060000;
+    const S_IFDIR: u32 = 0o040000;
+    const S_IFCHR: u32 = 0o020000;
+    const S_IFIFO: u32 = 0o010000;
+
+    const S_ISUID: u32 = 0o4000;
+    const S_ISGID: u32 = 0o2000;
+    const S_ISVTX: u32 = 0o1000;
+
+    let kind = match mode & S_IFMT {
+        S_IFSOCK => 's',
+        S_IFLNK => 'l',
+        S_IFREG => '-',
+        S_IFBLK => 'b',
+        S_IFDIR => 'd',
+        S_IFCHR => 'c',
+        S_IFIFO => 'p',
+        _ => '?',
+    };
+
+    // Renders the execute bit, taking the special bit into account.
+    let exec = |exec_bit: u32, special_bit: u32, set: char, unset: char| {
+        match (mode & exec_bit != 0, mode & special_bit != 0) {
+            (true, true) => set,
+            (false, true) => unset,
+            (true, false) => 'x',
+            (false, false) => '-',
+        }
+    };
+
+    let flag = |bit: u32, flag: char| if mode & bit != 0 { flag } else { '-' };
+
+    let mut string = String::with_capacity(10);
+    string.push(kind);
+    string.push(flag(0o400, 'r'));
+    string.push(flag(0o200, 'w'));
+    string.push(exec(0o100, S_ISUID, 's', 'S'));
+    string.push(flag(0o040, 'r'));
+    string.push(flag(0o020, 'w'));
+    string.push(exec(0o010, S_ISGID, 's', 'S'));
+    string.push(flag(0o004, 'r'));
+    string.push(flag(0o002, 'w'));
+    string.push(exec(0o001, S_ISVTX, 't', 'T'));
+    string
+}
+
+/// A JSON representation of a timeline entry.
+#[derive(Serialize)]
+struct JsonEntry {
+    path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dev: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ino: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uid: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gid: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    atime_ns: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mtime_ns: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ctime_ns: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    btime_ns: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attributes: Option<u64>,
+}
+
+/// Renders the path of the given timeline entry as a string.
+fn path(entry: &rrg_proto::TimelineEntry) -> String {
+    let bytes = entry.path.clone().unwrap_or_default();
+    rrg_proto::path::from_bytes(bytes).to_string_lossy().into_owned()
+}
+
+/// Converts the given number of nanoseconds since the epoch to seconds.
+///
+/// The result is rounded towards negative infinity (so that times before the
+/// epoch are rendered correctly).
+fn secs(nanos: i64) -> i64 {
+    nanos.div_euclid(1_000_000_000)
+}
+
+/// Renders the given number of seconds since the epoch as an ISO 8601 date.
+fn date(secs: i64) -> String {
+    match chrono::NaiveDateTime::from_timestamp_opt(secs, 0) {
+        Some(time) => time.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+        None => secs.to_string(),
+    }
+}
+
+/// Escapes characters that have a special meaning in the bodyfile format.
+fn escape_bodyfile(string: &str) -> String {
+    let mut result = String::with_capacity(string.len());
+    for char in string.chars() {
+        match char {
+            '\\' => result.push_str("\\\\"),
+            '|' => result.push_str("\\|"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            _ => result.push(char),
+        }
+    }
+
+    result
+}
+
+/// Quotes the given CSV field if needed (as described in RFC 4180).
+fn escape_csv(string: &str) -> String {
+    if string.contains(|char| matches!(char, ',' | '"' | '\n' | '\r')) {
+        format!("\"{}\"", string.replace('"', "\"\""))
+    } else {
+        String::from(string)
+    }
+}
+
+/// An error type for failures related to parsing export format names.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseFormatError {
+    string: String,
+}
+
+impl ParseFormatError {
+
+    /// Constructs a new error indicating failure of parsing given string.
+    fn new<S: Into<String>>(string: S) -> ParseFormatError {
+        ParseFormatError {
+            string: string.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for ParseFormatError {
+
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "invalid export format: '{}'", self.string)
+    }
+}
+
+impl std::error::Error for ParseFormatError {
+
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!("bodyfile".parse(), Ok(Format::Bodyfile));
+        assert_eq!("csv".parse(), Ok(Format::Csv));
+        assert_eq!("jsonl".parse(), Ok(Format::Jsonl));
+        assert!("xml".parse::<Format>().is_err());
+    }
+
+    #[test]
+    fn test_mode_string_regular() {
+        assert_eq!(mode_string(0o100644), "-rw-r--r--");
+        assert_eq!(mode_string(0o100755), "-rwxr-xr-x");
+        assert_eq!(mode_string(0o100000), "----------");
+    }
+
+    #[test]
+    fn test_mode_string_types() {
+        assert_eq!(mode_string(0o040755), "drwxr-xr-x");
+        assert_eq!(mode_string(0o120777), "lrwxrwxrwx");
+        assert_eq!(mode_string(0o020620), "crw--w----");
+        assert_eq!(mode_string(0o060660), "brw-rw----");
+        assert_eq!(mode_string(0o010644), "prw-r--r--");
+        assert_eq!(mode_string(0o140755), "srwxr-xr-x");
+        assert_eq!(mode_string(0o000644), "?rw-r--r--");
+    }
+
+    #[test]
+    fn test_mode_string_special_bits() {
+        assert_eq!(mode_string(0o104755), "-rwsr-xr-x");
+        assert_eq!(mode_string(0o104644), "-rwSr--r--");
+        assert_eq!(mode_string(0o102755), "-rwxr-sr-x");
+        assert_eq!(mode_string(0o102745), "-rwxr-Sr-x");
+        assert_eq!(mode_string(0o041777), "drwxrwxrwt");
+        assert_eq!(mode_string(0o041776), "drwxrwxrwT");
+    }
+
+    #[test]
+    fn test_write_bodyfile() {
+        let entries = vec! {
+            entry("/foo/bar", 0o100644, 1_600_000_000_500_000_000),
+            entry("/foo|baz", 0o040755, -1_500_000_000),
+        };
+
+        let mut output = vec!();
+        write_bodyfile(entries.into_iter().map(Ok), &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output, "\
+0|/foo/bar|42|-rw-r--r--|1000|1000|1337|1600000000|1600000000|1600000000|0
+0|/foo\\|baz|42|drwxr-xr-x|1000|1000|1337|-2|-2|-2|0
+");
+    }
+
+    #[test]
+    fn test_write_bodyfile_missing_values() {
+        let entries = vec! {
+            rrg_proto::TimelineEntry {
+                path: Some(rrg_proto::path::to_bytes("/foo".into())),
+                ..Default::default()
+            },
+        };
+
+        let mut output = vec!();
+        write_bodyfile(entries.into_iter().map(Ok), &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output, "0|/foo|0|?---------|0|0|0|0|0|0|0\n");
+    }
+
+    #[test]
+    fn test_write_bodyfile_error() {
+        let entries = vec! {
+            Ok(entry("/foo", 0o100644, 0)),
+            Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "bar")),
+        };
+
+        let mut output = vec!();
+        assert!(write_bodyfile(entries.into_iter(), &mut output).is_err());
+    }
+
+    #[test]
+    fn test_write_csv_sorted() {
+        let mut foo = entry("/foo", 0o100644, 0);
+        foo.atime_ns = Some(2_000_000_000);
+        foo.mtime_ns = Some(1_000_000_000);
+        foo.ctime_ns = Some(1_000_000_000);
+        foo.btime_ns = None;
+
+        let mut bar = entry("/bar, baz", 0o100600, 0);
+        bar.atime_ns = Some(1_500_000_000);
+        bar.mtime_ns = Some(1_500_000_000);
+        bar.ctime_ns = Some(1_500_000_000);
+        bar.btime_ns = Some(1_500_000_000);
+
+        let entries = vec!(foo, bar);
+
+        let mut output = vec!();
+        write_csv(entries.into_iter().map(Ok), &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output, "\
+Date,Size,Type,Mode,UID,GID,Meta,File Name
+1970-01-01T00:00:01Z,1337,m.c.,-rw-r--r--,1000,1000,42,/foo
+1970-01-01T00:00:01Z,1337,macb,-rw-------,1000,1000,42,\"/bar, baz\"
+1970-01-01T00:00:02Z,1337,.a..,-rw-r--r--,1000,1000,42,/foo
+");
+    }
+
+    #[test]
+    fn test_write_csv_empty() {
+        let mut output = vec!();
+        write_csv(std::iter::empty(), &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output, "Date,Size,Type,Mode,UID,GID,Meta,File Name\n");
+    }
+
+    #[test]
+    fn test_write_jsonl() {
+        let entries = vec! {
+            entry("/foo", 0o100644, 1_000_000_000),
+            rrg_proto::TimelineEntry {
+                path: Some(rrg_proto::path::to_bytes("/bar".into())),
+                ..Default::default()
+            },
+        };
+
+        let mut output = vec!();
+        write_jsonl(entries.into_iter().map(Ok), &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        let lines = output.lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect::<Vec<serde_json::Value>>();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["path"], "/foo");
+        assert_eq!(lines[0]["mode"], "-rw-r--r--");
+        assert_eq!(lines[0]["size"], 1337);
+        assert_eq!(lines[0]["mtime_ns"], 1_000_000_000);
+        assert_eq!(lines[1]["path"], "/bar");
+        assert!(lines[1].get("mode").is_none());
+    }
+
+    #[test]
+    fn test_export_gzchunked() {
+        let entries = vec! {
+            entry("/foo", 0o100644, 0),
+            entry("/bar", 0o100644, 0),
+        };
+
+        let parts = crate::gzchunked::encode(entries.into_iter())
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+
+        let entries = crate::gzchunked::decode(parts.iter().map(Vec::as_slice));
+
+        let mut output = vec!();
+        export(entries, Format::Bodyfile, &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        let names = output.lines()
+            .map(|line| line.split('|').nth(1).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(names, vec!("/foo", "/bar"));
+    }
+
+    #[test]
+    fn test_escape_bodyfile() {
+        assert_eq!(escape_bodyfile("foo"), "foo");
+        assert_eq!(escape_bodyfile("foo|bar"), "foo\\|bar");
+        assert_eq!(escape_bodyfile("foo\\bar"), "foo\\\\bar");
+        assert_eq!(escape_bodyfile("foo\nbar"), "foo\\nbar");
+    }
+
+    #[test]
+    fn test_escape_csv() {
+        assert_eq!(escape_csv("foo"), "foo");
+        assert_eq!(escape_csv("foo,bar"), "\"foo,bar\"");
+        assert_eq!(escape_csv("foo\"bar"), "\"foo\"\"bar\"");
+    }
+
+    #[test]
+    fn test_secs() {
+        assert_eq!(secs(0), 0);
+        assert_eq!(secs(1_999_999_999), 1);
+        assert_eq!(secs(-1), -1);
+    }
+
+    /// Constructs a timeline entry with all the times set to the given value.
+    fn entry(path: &str, mode: i64, time: i64) -> rrg_proto::TimelineEntry {
+        rrg_proto::TimelineEntry {
+            path: Some(rrg_proto::path::to_bytes(PathBuf::from(path))),
+            mode: Some(mode),
+            size: Some(1337),
+            ino: Some(42),
+            uid: Some(1000),
+            gid: Some(1000),
+            atime_ns: Some(time),
+            mtime_ns: Some(time),
+            ctime_ns: Some(time),
+            ..Default::default()
+        }
+    }
+}
@@ -5,7 +5,11 @@
 
 //! Linux-specific utilities for working with the filesystem.
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
+
+use log::warn;
 
 // TODO: Document behaviour for symlinks.
 /// Collects extended flags of the specified file.
@@ -48,6 +52,341 @@ pub fn flags<P>(path: P) -> std::io::Result<u32> where
     }
 }
 
+/// A flag of a compressed file (as reported in `Statx::attributes`).
+pub const STATX_ATTR_COMPRESSED: u64 = 0x00000004;
+
+/// A flag of an immutable file (as reported in `Statx::attributes`).
+pub const STATX_ATTR_IMMUTABLE: u64 = 0x00000010;
+
+/// A flag of an append-only file (as reported in `Statx::attributes`).
+pub const STATX_ATTR_APPEND: u64 = 0x00000020;
+
+/// A flag of a file not to be dumped (as reported in `Statx::attributes`).
+pub const STATX_ATTR_NODUMP: u64 = 0x00000040;
+
+/// A flag of an encrypted file (as reported in `Statx::attributes`).
+pub const STATX_ATTR_ENCRYPTED: u64 = 0x00000800;
+
+/// A flag of a file protected with fs-verity (as reported in
+/// `Statx::attributes`).
+pub const STATX_ATTR_VERITY: u64 = 0x00100000;
+
+/// Extended metadata of a file as reported by the `statx` system call.
+///
+/// Unlike the standard `Metadata`, it exposes file attributes (e.g. whether a
+/// file is immutable) and the identifier of the mount that the file belongs to.
+/// All the times are reported with the full nanosecond precision.
+///
+/// Fields that the filesystem did not report are `None`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Statx {
+    /// A time at which the file was last accessed.
+    pub atime: Option<SystemTime>,
+    /// A time at which the file contents were last modified.
+    pub mtime: Option<SystemTime>,
+    /// A time at which the file metadata was last changed.
+    pub ctime: Option<SystemTime>,
+    /// A time at which the file was created.
+    pub btime: Option<SystemTime>,
+    /// File attributes (a mask of `STATX_ATTR_*` flags).
+    ///
+    /// Note that the values of the flags are the same as the values of the
+    /// corresponding flags returned by the [`flags`] function.
+    ///
+    /// [`flags`]: fn.flags.html
+    pub attributes: u64,
+    /// A mask of attributes that the filesystem supports.
+    pub attributes_mask: u64,
+    /// An identifier of the mount that the file belongs to.
+    pub mnt_id: Option<u64>,
+}
+
+/// Whether the kernel is known not to support the `statx` system call.
+static STATX_UNSUPPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Collects extended metadata of the specified file.
+///
+/// If `follow_symlinks` is set, metadata of the symlink target is returned
+/// (like `std::fs::metadata` does). Otherwise, metadata of the symlink itself
+/// is returned (like `std::fs::symlink_metadata` does).
+///
+/// The `statx` system call is available since Linux 4.11. On older kernels (or
+/// if the call is blocked, e.g. by a seccomp filter of a container runtime),
+/// `None` is returned and the caller should fall back to the standard metadata.
+///
+/// # Examples
+///
+/// ```no_run
+/// use rrg::fs::linux::{statx, STATX_ATTR_IMMUTABLE};
+///
+/// if let Some(statx) = statx("/bin/sh", true).unwrap() {
+///     assert_eq!(statx.attributes & STATX_ATTR_IMMUTABLE, 0);
+/// }
+/// ```
+pub fn statx<P>(path: P, follow_symlinks: bool)
+    -> std::io::Result<Option<Statx>>
+where
+    P: AsRef<Path>,
+{
+    statx_raw_fd(libc::AT_FDCWD, path.as_ref(), follow_symlinks)
+}
+
+/// Collects extended metadata of a file relative to the given directory.
+///
+/// This works like [`statx`] but `name` is resolved relative to `dir` rather
+/// than to the current working directory. This way, when walking a directory,
+/// the kernel does not have to resolve the full path of every entry again and
+/// the metadata is guaranteed to come from the directory that was listed.
+///
+/// [`statx`]: fn.statx.html
+///
+/// # Examples
+///
+/// ```no_run
+/// let dir = std::fs::File::open("/bin").unwrap();
+/// if let Some(statx) = rrg::fs::linux::statx_at(&dir, "sh", true).unwrap() {
+///     println!("mount id: {:?}", statx.mnt_id);
+/// }
+/// ```
+pub fn statx_at<P>(dir: &std::fs::File, name: P, follow_symlinks: bool)
+    -> std::io::Result<Option<Statx>>
+where
+    P: AsRef<Path>,
+{
+    use std::os::unix::io::AsRawFd as _;
+
+    statx_raw_fd(dir.as_raw_fd(), name.as_ref(), follow_symlinks)
+}
+
+/// Collects extended metadata of a file relative to the given raw descriptor.
+fn statx_raw_fd(dirfd: libc::c_int, path: &Path, follow_symlinks: bool)
+    -> std::io::Result<Option<Statx>>
+{
+    use std::os::unix::ffi::OsStrExt as _;
+
+    if STATX_UNSUPPORTED.load(Ordering::Relaxed) {
+        return Ok(None);
+    }
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|error| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, error)
+        })?;
+
+    let flags = if follow_symlinks { 0 } else { libc::AT_SYMLINK_NOFOLLOW };
+
+    let raw = match statx_syscall(dirfd, &path, flags) {
+        Ok(raw) => raw,
+        Err(error) if is_statx_unsupported(&error) => {
+            STATX_UNSUPPORTED.store(true, Ordering::Relaxed);
+            return Ok(None);
+        }
+        Err(error) => return Err(error),
+    };
+
+    let time = |flag: u32, timestamp: RawStatxTimestamp| {
+        if raw.stx_mask & flag == 0 {
+            return None;
+        }
+
+        let nanos = Duration::from_nanos(u64::from(timestamp.tv_nsec));
+        if timestamp.tv_sec >= 0 {
+            let secs = Duration::from_secs(timestamp.tv_sec as u64);
+            std::time::UNIX_EPOCH.checked_add(secs + nanos)
+        } else {
+            let secs = timestamp.tv_sec.wrapping_neg() as u64;
+            let secs = Duration::from_secs(secs);
+            std::time::UNIX_EPOCH.checked_sub(secs)?.checked_add(nanos)
+        }
+    };
+
+    let mnt_id = if raw.stx_mask & STATX_MNT_ID != 0 {
+        Some(raw.stx_mnt_id)
+    } else {
+        None
+    };
+
+    Ok(Some(Statx {
+        atime: time(STATX_ATIME, raw.stx_atime),
+        mtime: time(STATX_MTIME, raw.stx_mtime),
+        ctime: time(STATX_CTIME, raw.stx_ctime),
+        btime: time(STATX_BTIME, raw.stx_btime),
+        attributes: raw.stx_attributes & raw.stx_attributes_mask,
+        attributes_mask: raw.stx_attributes_mask,
+        mnt_id: mnt_id,
+    }))
+}
+
+/// Determines whether the given `statx` failure means the call is unavailable.
+///
+/// `ENOSYS` is reported by kernels that do not implement the call at all. A
+/// seccomp filter blocking the call reports `EPERM` instead, but so can some
+/// filesystems (e.g. FUSE ones) or security modules for a particular file. To
+/// tell these apart, we probe the call once more on the root directory which
+/// any process is allowed to inspect.
+fn is_statx_unsupported(error: &std::io::Error) -> bool {
+    match error.raw_os_error() {
+        Some(libc::ENOSYS) => true,
+        Some(libc::EPERM) => {
+            // The literal has no interior null bytes, so it cannot fail.
+            let root = std::ffi::CString::new("/").unwrap();
+            match statx_syscall(libc::AT_FDCWD, &root, 0) {
+                Ok(_) => false,
+                Err(error) => matches! {
+                    error.raw_os_error(),
+                    Some(libc::ENOSYS) | Some(libc::EPERM)
+                },
+            }
+        }
+        _ => false,
+    }
+}
+
+/// Invokes the `statx` system call and returns the raw kernel structure.
+fn statx_syscall(dirfd: libc::c_int, path: &std::ffi::CStr, flags: libc::c_int)
+    -> std::io::Result<RawStatx>
+{
+    let mut raw = std::mem::MaybeUninit::<RawStatx>::zeroed();
+
+    // SAFETY: We pass a valid null-terminated path and a pointer to a buffer
+    // of the size expected by the kernel. The buffer is read only if the call
+    // succeeded (and it is zeroed anyway, so any bit pattern is valid). The
+    // caller guarantees that the descriptor is valid for the whole call.
+    unsafe {
+        let code = libc::syscall(
+            libc::SYS_statx,
+            dirfd,
+            path.as_ptr(),
+            flags,
+            STATX_BASIC_STATS | STATX_BTIME | STATX_MNT_ID,
+            raw.as_mut_ptr(),
+        );
+        if code != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        Ok(raw.assume_init())
+    }
+}
+
+// https://elixir.bootlin.com/linux/v5.8.14/source/include/uapi/linux/stat.h
+const STATX_ATIME: u32 = 0x00000020;
+const STATX_MTIME: u32 = 0x00000040;
+const STATX_CTIME: u32 = 0x00000080;
+const STATX_BASIC_STATS: u32 = 0x000007ff;
+const STATX_BTIME: u32 = 0x00000800;
+const STATX_MNT_ID: u32 = 0x00001000;
+
+/// A timestamp as defined by the `statx_timestamp` kernel structure.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RawStatxTimestamp {
+    tv_sec: i64,
+    tv_nsec: u32,
+    __reserved: i32,
+}
+
+/// Metadata as defined by the `statx` kernel structure.
+///
+/// The structure is defined here (rather than taken from the `libc` crate) so
+/// that we do not depend on a particular version of the C library.
+#[repr(C)]
+struct RawStatx {
+    stx_mask: u32,
+    stx_blksize: u32,
+    stx_attributes: u64,
+    stx_nlink: u32,
+    stx_uid: u32,
+    stx_gid: u32,
+    stx_mode: u16,
+    __spare0: [u16; 1],
+    stx_ino: u64,
+    stx_size: u64,
+    stx_blocks: u64,
+    stx_attributes_mask: u64,
+    stx_atime: RawStatxTimestamp,
+    stx_btime: RawStatxTimestamp,
+    stx_ctime: RawStatxTimestamp,
+    stx_mtime: RawStatxTimestamp,
+    stx_rdev_major: u32,
+    stx_rdev_minor: u32,
+    stx_dev_major: u32,
+    stx_dev_minor: u32,
+    stx_mnt_id: u64,
+    __spare2: u64,
+    __spare3: [u64; 12],
+}
+
+/// Information about a filesystem mounted in the system.
+#[derive(Clone, Debug)]
+pub struct Mount {
+    /// A type of the mounted filesystem (e.g. `ext4` or `nfs`).
+    pub fs_type: String,
+    /// A path at which the filesystem is mounted.
+    pub path: PathBuf,
+}
+
+impl Mount {
+
+    /// Checks whether the mounted filesystem is a network filesystem.
+    ///
+    /// Note that this is based purely on the filesystem type and uses a list
+    /// of well-known network filesystems. Exotic filesystems might not be
+    /// recognized correctly.
+    pub fn is_remote(&self) -> bool {
+        const REMOTE_FS_TYPES: &[&str] = &[
+            "9p", "afs", "ceph", "cifs", "coda", "fuse.sshfs", "glusterfs",
+            "lustre", "ncpfs", "nfs", "nfs4", "smb3", "smbfs", "sshfs",
+        ];
+
+        REMOTE_FS_TYPES.contains(&self.fs_type.as_str())
+    }
+
+    /// Returns an identifier of the device the filesystem is mounted on.
+    ///
+    /// The identifier is the same as the one reported in the `st_dev` field of
+    /// files on this filesystem. Note that obtaining it requires a call to the
+    /// mounted filesystem, which can be slow in case of network filesystems.
+    pub fn dev(&self) -> std::io::Result<u64> {
+        use std::os::unix::fs::MetadataExt as _;
+
+        Ok(std::fs::metadata(&self.path)?.dev())
+    }
+}
+
+/// Returns a list of filesystems mounted in the system.
+///
+/// The list is obtained by parsing the `/proc/mounts` file. Entries that cannot
+/// be parsed (e.g. because their paths are not valid Unicode) are skipped.
+///
+/// # Examples
+///
+/// ```no_run
+/// for mount in rrg::fs::linux::mounts().unwrap() {
+///     println!("{} ({})", mount.path.display(), mount.fs_type);
+/// }
+/// ```
+pub fn mounts() -> std::io::Result<Vec<Mount>> {
+    let mut mounts = vec!();
+
+    for mount_info in proc_mounts::MountIter::new()? {
+        let mount_info = match mount_info {
+            Ok(mount_info) => mount_info,
+            Err(error) => {
+                warn!("failed to parse mount information: {}", error);
+                continue;
+            }
+        };
+
+        mounts.push(Mount {
+            fs_type: mount_info.fstype,
+            path: mount_info.dest,
+        });
+    }
+
+    Ok(mounts)
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -82,4 +421,150 @@ mod tests {
         let flags = flags(tempdir.path().join("foo")).unwrap();
         assert_eq!(flags & FS_NOATIME_FL as u32, FS_NOATIME_FL as u32);
     }
+
+    #[test]
+    fn test_raw_statx_size() {
+        assert_eq!(std::mem::size_of::<RawStatx>(), 256);
+    }
+
+    #[test]
+    fn test_statx_non_existing() {
+        let tempdir = tempfile::tempdir().unwrap();
+
+        let path = tempdir.path().join("foo");
+        if let Err(error) = statx(path, false) {
+            assert_eq!(error.kind(), std::io::ErrorKind::NotFound);
+        }
+    }
+
+    #[test]
+    fn test_statx_times() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("foo");
+        std::fs::write(&path, b"foo").unwrap();
+
+        let statx = match statx(&path, false).unwrap() {
+            Some(statx) => statx,
+            // The kernel does not support `statx`, nothing to check.
+            None => return,
+        };
+
+        let metadata = std::fs::symlink_metadata(&path).unwrap();
+        assert_eq!(statx.mtime, Some(metadata.modified().unwrap()));
+        assert_eq!(statx.atime, Some(metadata.accessed().unwrap()));
+        assert!(statx.ctime.is_some());
+    }
+
+    #[test]
+    fn test_statx_symlink() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let target = tempdir.path().join("foo");
+        let symlink = tempdir.path().join("bar");
+
+        std::fs::write(&target, b"foo").unwrap();
+        std::os::unix::fs::symlink(&target, &symlink).unwrap();
+
+        let statx_nofollow = statx(&symlink, false).unwrap();
+        if let Some(statx) = statx_nofollow {
+            let metadata = std::fs::symlink_metadata(&symlink).unwrap();
+            assert_eq!(statx.mtime, Some(metadata.modified().unwrap()));
+        }
+
+        let statx_follow = statx(&symlink, true).unwrap();
+        if let Some(statx) = statx_follow {
+            let metadata = std::fs::metadata(&target).unwrap();
+            assert_eq!(statx.mtime, Some(metadata.modified().unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_statx_at() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("foo");
+        std::fs::write(&path, b"foo").unwrap();
+
+        let dir = File::open(tempdir.path()).unwrap();
+        let statx_at = statx_at(&dir, "foo", false).unwrap();
+        assert_eq!(statx_at, statx(&path, false).unwrap());
+    }
+
+    #[test]
+    fn test_statx_at_non_existing() {
+        let tempdir = tempfile::tempdir().unwrap();
+
+        let dir = File::open(tempdir.path()).unwrap();
+        if let Err(error) = statx_at(&dir, "foo", false) {
+            assert_eq!(error.kind(), std::io::ErrorKind::NotFound);
+        }
+    }
+
+    #[test]
+    fn test_statx_permission_denied_not_latched() {
+        let error = std::io::Error::from_raw_os_error(libc::EPERM);
+
+        // The root directory can always be inspected, so unless `statx` is
+        // blocked altogether, a failure on some other file must not disable
+        // the call for all the subsequent ones.
+        let root = std::ffi::CString::new("/").unwrap();
+        if statx_syscall(libc::AT_FDCWD, &root, 0).is_ok() {
+            assert!(!is_statx_unsupported(&error));
+        }
+    }
+
+    #[test]
+    fn test_statx_attributes_consistent_with_flags() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("foo");
+        std::fs::write(&path, b"foo").unwrap();
+
+        let flags = match flags(&path) {
+            Ok(flags) => u64::from(flags),
+            // The filesystem does not support flags, nothing to compare.
+            Err(_) => return,
+        };
+        let statx = match statx(&path, false).unwrap() {
+            Some(statx) => statx,
+            None => return,
+        };
+
+        const MASK: u64 = STATX_ATTR_IMMUTABLE | STATX_ATTR_APPEND;
+        let mask = MASK & statx.attributes_mask;
+        assert_eq!(statx.attributes & mask, flags & mask);
+    }
+
+    #[test]
+    fn test_mounts_root() {
+        let mounts = mounts().unwrap();
+        assert!(mounts.iter().any(|mount| mount.path == Path::new("/")));
+    }
+
+    #[test]
+    fn test_mount_dev() {
+        use std::os::unix::fs::MetadataExt as _;
+
+        let tempdir = tempfile::tempdir().unwrap();
+
+        let mount = Mount {
+            fs_type: String::from("tmpfs"),
+            path: tempdir.path().to_path_buf(),
+        };
+
+        let dev = std::fs::metadata(tempdir.path()).unwrap().dev();
+        assert_eq!(mount.dev().unwrap(), dev);
+    }
+
+    #[test]
+    fn test_mount_is_remote() {
+        let mount = Mount {
+            fs_type: String::from("nfs4"),
+            path: PathBuf::from("/mnt/foo"),
+        };
+        assert!(mount.is_remote());
+
+        let mount = Mount {
+            fs_type: String::from("ext4"),
+            path: PathBuf::from("/"),
+        };
+        assert!(!mount.is_remote());
+    }
 }
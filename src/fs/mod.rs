@@ -9,10 +9,12 @@
 //! standard `std::fs` module. All functions are portable and should work on all
 //! supported platforms (perhaps with limited capabilities).
 
-use std::fs::Metadata;
+use std::fs::{File, Metadata};
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 use log::warn;
+use regex::Regex;
 
 #[cfg(target_os = "linux")]
 pub mod linux;
@@ -20,6 +22,8 @@ pub mod linux;
 #[cfg(target_family = "unix")]
 pub mod unix;
 
+pub mod parallel;
+
 /// A path to a filesystem item and associated metadata.
 ///
 /// This type is very similar to standard `DirEntry` but its `metadata` property
@@ -29,6 +33,160 @@ pub struct Entry {
     pub path: PathBuf,
     /// Metadata associated with the item.
     pub metadata: Metadata,
+    /// Extended metadata associated with the item (if available).
+    ///
+    /// Extended metadata is collected only by walks that explicitly ask for it
+    /// (see the `extended_metadata` field of [`WalkOpts`]) and is not available
+    /// on kernels that do not support the `statx` system call.
+    ///
+    /// [`WalkOpts`]: struct.WalkOpts.html
+    #[cfg(target_os = "linux")]
+    pub statx: Option<linux::Statx>,
+}
+
+impl Entry {
+
+    /// Creates a new entry for the given path and its metadata.
+    ///
+    /// The metadata is expected not to follow symlinks (i.e. it should be
+    /// obtained through `std::fs::symlink_metadata` or equivalent). No extended
+    /// metadata is collected, as this requires an additional system call.
+    pub fn new(path: PathBuf, metadata: Metadata) -> Entry {
+        Entry {
+            path: path,
+            metadata: metadata,
+            #[cfg(target_os = "linux")] statx: None,
+        }
+    }
+
+    /// Collects extended metadata of the item.
+    ///
+    /// If `dir` is given, it has to be the directory containing the item and
+    /// the metadata is collected relative to it (so the kernel does not need
+    /// to resolve the full path again). Otherwise, the full path is used.
+    #[cfg(target_os = "linux")]
+    fn collect_statx(&mut self, dir: Option<&File>) {
+        let result = match (dir, self.path.file_name()) {
+            (Some(dir), Some(name)) => linux::statx_at(dir, name, false),
+            _ => linux::statx(&self.path, false),
+        };
+
+        self.statx = match result {
+            Ok(statx) => statx,
+            Err(error) => {
+                warn!("failed to statx '{}': {}", self.path.display(), error);
+                None
+            }
+        };
+    }
+
+    /// Collects extended metadata of the item.
+    ///
+    /// Extended metadata is available only on Linux, so this is a no-op.
+    #[cfg(not(target_os = "linux"))]
+    fn collect_statx(&mut self, _dir: Option<&File>) {
+    }
+}
+
+/// Options that customize the recursive walk over directories.
+///
+/// The default options make the walk visit everything below the root, without
+/// crossing device boundaries.
+#[derive(Clone, Debug, Default)]
+pub struct WalkOpts {
+    /// Patterns of paths that should be skipped (together with everything
+    /// below them).
+    ///
+    /// Patterns are matched against the whole path of every entry below the
+    /// root (e.g. `^/proc$` skips the `/proc` directory).
+    pub excludes: Vec<Regex>,
+    /// A maximum depth (relative to the root) of entries to yield.
+    pub max_depth: Option<usize>,
+    /// Whether to descend into directories mounted to other devices than the
+    /// root.
+    pub cross_device: bool,
+    /// A minimum size (in bytes) of entries to yield.
+    pub min_size: Option<u64>,
+    /// A maximum size (in bytes) of entries to yield.
+    pub max_size: Option<u64>,
+    /// A minimum modification time of entries to yield.
+    pub min_mtime: Option<SystemTime>,
+    /// A maximum modification time of entries to yield.
+    pub max_mtime: Option<SystemTime>,
+    /// Whether to collect extended metadata (see [`Entry::statx`]) of yielded
+    /// entries.
+    ///
+    /// This costs an additional system call for every yielded entry, so it
+    /// should be requested only if the extended metadata is actually used.
+    ///
+    /// [`Entry::statx`]: struct.Entry.html#structfield.statx
+    pub extended_metadata: bool,
+}
+
+impl WalkOpts {
+
+    /// Checks whether the entry at the given path should be skipped.
+    fn is_excluded(&self, path: &Path) -> bool {
+        if self.excludes.is_empty() {
+            return false;
+        }
+
+        let path = path.to_string_lossy();
+        self.excludes.iter().any(|regex| regex.is_match(&path))
+    }
+
+    /// Checks whether the given entry passes the size and time filters.
+    fn matches(&self, entry: &Entry) -> bool {
+        let size = entry.metadata.len();
+        if self.min_size.map_or(false, |min_size| size < min_size) {
+            return false;
+        }
+        if self.max_size.map_or(false, |max_size| size > max_size) {
+            return false;
+        }
+
+        if self.min_mtime.is_none() && self.max_mtime.is_none() {
+            return true;
+        }
+
+        // If the time filter is specified but the modification time is not
+        // available, we cannot tell whether the entry matches, so we err on
+        // the side of yielding less.
+        let mtime = match entry.metadata.modified() {
+            Ok(mtime) => mtime,
+            Err(_) => return false,
+        };
+
+        self.min_mtime.map_or(true, |min_mtime| min_mtime <= mtime) &&
+        self.max_mtime.map_or(true, |max_mtime| mtime <= max_mtime)
+    }
+}
+
+/// A position of a recursive walk over directories.
+///
+/// Positions make it possible to resume the walk later (even in a different
+/// process) with the [`walk_dir_from`] function. A position of a walk can be
+/// obtained with the [`WalkDir::position`] method.
+///
+/// [`walk_dir_from`]: fn.walk_dir_from.html
+/// [`WalkDir::position`]: struct.WalkDir.html#method.position
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WalkPosition {
+    /// Whether the root has been already visited.
+    pub root_visited: bool,
+    /// Positions within the directories being listed (from the outermost one).
+    pub dirs: Vec<DirPosition>,
+}
+
+/// A position of a listing of a single directory within a recursive walk.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirPosition {
+    /// A path to the directory being listed.
+    pub path: PathBuf,
+    /// A depth (relative to the root) of entries of the directory.
+    pub depth: usize,
+    /// A number of directory entries that have been already consumed.
+    pub consumed: usize,
 }
 
 /// Returns a deep iterator over entries within a directory.
@@ -60,18 +218,138 @@ pub struct Entry {
 /// assert!(items.contains(&PathBuf::from("/usr/lib")));
 /// ```
 pub fn walk_dir<P: AsRef<Path>>(root: P) -> std::io::Result<WalkDir> {
+    walk_dir_with(root, WalkOpts::default())
+}
+
+/// Returns a deep iterator over entries within a directory with custom options.
+///
+/// This function is just like [`walk_dir`] but allows to skip certain parts of
+/// the directory tree (e.g. pseudo-filesystems like `/proc`), limit the depth
+/// of the walk or yield only entries that match certain criteria. See the
+/// [`WalkOpts`] type for the description of available options.
+///
+/// Note that the size and time filters only affect which entries are yielded:
+/// directories that do not match them are still descended into.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::path::PathBuf;
+///
+/// let opts = rrg::fs::WalkOpts {
+///     max_depth: Some(1),
+///     ..Default::default()
+/// };
+///
+/// let iter = rrg::fs::walk_dir_with("/", opts).unwrap();
+///
+/// let items = iter.map(|entry| entry.path).collect::<Vec<_>>();
+/// assert!(items.contains(&PathBuf::from("/usr")));
+/// assert!(!items.contains(&PathBuf::from("/usr/bin")));
+/// ```
+///
+/// [`walk_dir`]: fn.walk_dir.html
+/// [`WalkOpts`]: struct.WalkOpts.html
+pub fn walk_dir_with<P>(root: P, opts: WalkOpts) -> std::io::Result<WalkDir>
+where
+    P: AsRef<Path>,
+{
     let metadata = std::fs::symlink_metadata(&root)?;
-    let pending = vec!(list_dir(&root)?);
+    let mut pending = vec!((list_dir_with(&root, &opts)?, 1));
+
+    // The root is listed even if its children should not be visited, so that
+    // the walk fails the same way regardless of the depth limit.
+    if opts.max_depth == Some(0) {
+        pending.clear();
+    }
 
     #[cfg(target_family = "unix")]
     let dev = std::os::unix::fs::MetadataExt::dev(&metadata);
 
+    let mut root = Entry::new(root.as_ref().to_path_buf(), metadata);
+    if opts.extended_metadata {
+        root.collect_statx(None);
+    }
+
     Ok(WalkDir {
-        root: Some(Entry {
-            path: root.as_ref().to_path_buf(),
-            metadata: metadata,
-        }),
+        root: Some(root),
         pending: pending,
+        opts: opts,
+        #[cfg(target_family = "unix")] dev: dev,
+    })
+}
+
+/// Returns a deep iterator over entries within a directory resumed from the
+/// given position.
+///
+/// This function is just like [`walk_dir_with`] but instead of starting from
+/// the root, the walk continues from the point described by `position` (which
+/// should have been obtained from a walk with the same root and options).
+///
+/// Note that a walk resumed this way yields exactly the entries that remained
+/// to be yielded only if the directories have not changed in the meantime. If
+/// they did, some entries can be yielded twice or not at all. Directories that
+/// cannot be listed anymore are skipped.
+///
+/// # Examples
+///
+/// ```no_run
+/// let mut iter = rrg::fs::walk_dir("/").unwrap();
+/// iter.next();
+///
+/// let position = iter.position();
+/// let opts = rrg::fs::WalkOpts::default();
+///
+/// let iter = rrg::fs::walk_dir_from("/", opts, position).unwrap();
+/// for entry in iter {
+///     println!("{}", entry.path.display());
+/// }
+/// ```
+///
+/// [`walk_dir_with`]: fn.walk_dir_with.html
+pub fn walk_dir_from<P>(
+    root: P,
+    opts: WalkOpts,
+    position: WalkPosition,
+) -> std::io::Result<WalkDir>
+where
+    P: AsRef<Path>,
+{
+    let metadata = std::fs::symlink_metadata(&root)?;
+
+    let mut pending = vec!();
+    for dir in position.dirs {
+        let mut iter = match list_dir_with(&dir.path, &opts) {
+            Ok(iter) => iter,
+            Err(error) => {
+                warn!("failed to read '{}': {}", dir.path.display(), error);
+                continue;
+            }
+        };
+        iter.skip_consumed(dir.consumed);
+
+        pending.push((iter, dir.depth));
+    }
+
+    // Even if the root has been visited already, we still need to know the
+    // device it belongs to not to cross device boundaries.
+    #[cfg(target_family = "unix")]
+    let dev = std::os::unix::fs::MetadataExt::dev(&metadata);
+
+    let root = if position.root_visited {
+        None
+    } else {
+        let mut root = Entry::new(root.as_ref().to_path_buf(), metadata);
+        if opts.extended_metadata {
+            root.collect_statx(None);
+        }
+        Some(root)
+    };
+
+    Ok(WalkDir {
+        root: root,
+        pending: pending,
+        opts: opts,
         #[cfg(target_family = "unix")] dev: dev,
     })
 }
@@ -101,38 +379,82 @@ pub fn walk_dir<P: AsRef<Path>>(root: P) -> std::io::Result<WalkDir> {
 /// assert!(items.contains(&PathBuf::from("/tmp")));
 /// ```
 pub fn list_dir<P: AsRef<Path>>(path: P) -> std::io::Result<ListDir> {
-    let iter = std::fs::read_dir(path)?;
+    let iter = std::fs::read_dir(&path)?;
 
     Ok(ListDir {
         iter: iter,
+        path: path.as_ref().to_path_buf(),
+        consumed: 0,
+        dir: None,
     })
 }
 
+/// Returns a shallow iterator over entries within a directory being walked.
+///
+/// If the walk needs extended metadata, the directory is kept open, so that
+/// the metadata of its entries can be collected relative to it.
+fn list_dir_with<P>(path: P, opts: &WalkOpts) -> std::io::Result<ListDir>
+where
+    P: AsRef<Path>,
+{
+    let mut iter = list_dir(&path)?;
+    if cfg!(target_os = "linux") && opts.extended_metadata {
+        iter.dir = Some(File::open(&path)?);
+    }
+
+    Ok(iter)
+}
+
 /// Iterator over entries in all subdirectories.
 ///
 /// This iterator will recursively descent to all subdirectories and yield
 /// entries for every file encountered along the way. However, during the
-/// traversal it will not cross device boundaries and enter symlinked
-/// directories.
+/// traversal it will not enter symlinked directories and (unless explicitly
+/// requested) cross device boundaries.
 ///
 /// Note that this iterator always returns an entry. All errors are simply
 /// swallowed.
 ///
-/// The iterator can be constructed with the [`walk_dir`] function.
+/// The iterator can be constructed with the [`walk_dir`] and [`walk_dir_with`]
+/// functions.
 ///
 /// [`walk_dir`]: fn.walk_dir.html
+/// [`walk_dir_with`]: fn.walk_dir_with.html
 pub struct WalkDir {
     root: Option<Entry>,
-    pending: Vec<ListDir>,
+    /// Iterators over directories being walked with the depth of their entries.
+    pending: Vec<(ListDir, usize)>,
+    opts: WalkOpts,
     #[cfg(target_family = "unix")] dev: u64,
 }
 
 impl WalkDir {
 
-    fn push(&mut self, entry: &Entry) {
-        match list_dir(&entry.path) {
+    /// Returns the current position of the walk.
+    ///
+    /// The position describes which entries remain to be yielded and can be
+    /// used to resume the walk later with the [`walk_dir_from`] function.
+    ///
+    /// [`walk_dir_from`]: fn.walk_dir_from.html
+    pub fn position(&self) -> WalkPosition {
+        let dirs = self.pending.iter()
+            .map(|&(ref iter, depth)| DirPosition {
+                path: iter.path.clone(),
+                depth: depth,
+                consumed: iter.consumed,
+            })
+            .collect();
+
+        WalkPosition {
+            root_visited: self.root.is_none(),
+            dirs: dirs,
+        }
+    }
+
+    fn push(&mut self, entry: &Entry, depth: usize) {
+        match list_dir_with(&entry.path, &self.opts) {
             Ok(iter) => {
-                self.pending.push(iter);
+                self.pending.push((iter, depth));
             },
             Err(error) => {
                 warn!("failed to read '{}': {}", entry.path.display(), error);
@@ -140,10 +462,10 @@ impl WalkDir {
         }
     }
 
-    fn pop(&mut self) -> Option<Entry> {
-        while let Some(iter) = self.pending.last_mut() {
-            for entry in iter {
-                return Some(entry);
+    fn pop(&mut self) -> Option<(Entry, usize)> {
+        while let Some((iter, depth)) = self.pending.last_mut() {
+            if let Some(entry) = iter.next() {
+                return Some((entry, *depth));
             }
 
             self.pending.pop();
@@ -152,6 +474,15 @@ impl WalkDir {
         None
     }
 
+    /// Checks whether the walk should descend into the given directory entry.
+    fn should_descend(&self, entry: &Entry, depth: usize) -> bool {
+        if self.opts.max_depth.map_or(false, |max_depth| depth >= max_depth) {
+            return false;
+        }
+
+        self.opts.cross_device || self.same_dev(entry)
+    }
+
     #[cfg(target_family = "unix")]
     fn same_dev(&self, entry: &Entry) -> bool {
         self.dev == std::os::unix::fs::MetadataExt::dev(&entry.metadata)
@@ -168,17 +499,38 @@ impl std::iter::Iterator for WalkDir {
     type Item = Entry;
 
     fn next(&mut self) -> Option<Entry> {
-        if self.root.is_some() {
-            return self.root.take();
+        if let Some(root) = self.root.take() {
+            if self.opts.matches(&root) {
+                return Some(root);
+            }
         }
 
-        let entry = self.pop()?;
+        loop {
+            let (mut entry, depth) = self.pop()?;
 
-        if entry.metadata.is_dir() && self.same_dev(&entry) {
-            self.push(&entry);
-        }
+            if self.opts.is_excluded(&entry.path) {
+                continue;
+            }
+
+            let matches = self.opts.matches(&entry);
 
-        Some(entry)
+            // Extended metadata is collected only for entries that are going
+            // to be yielded. The entry comes from the last pending directory,
+            // so it has to be done before we push its own listing.
+            if matches && self.opts.extended_metadata {
+                let dir = self.pending.last()
+                    .and_then(|(iter, _)| iter.dir.as_ref());
+                entry.collect_statx(dir);
+            }
+
+            if entry.metadata.is_dir() && self.should_descend(&entry, depth) {
+                self.push(&entry, depth + 1);
+            }
+
+            if matches {
+                return Some(entry);
+            }
+        }
     }
 }
 
@@ -196,6 +548,24 @@ impl std::iter::Iterator for WalkDir {
 /// [`list_dir`]: fn.list_dir.html
 pub struct ListDir {
     iter: std::fs::ReadDir,
+    /// A path to the directory being listed.
+    path: PathBuf,
+    /// A number of directory entries consumed so far (including the invalid
+    /// ones).
+    consumed: usize,
+    /// The directory being listed, if it is needed to collect extended
+    /// metadata of its entries.
+    dir: Option<File>,
+}
+
+impl ListDir {
+
+    /// Skips the given number of directory entries (including invalid ones).
+    fn skip_consumed(&mut self, count: usize) {
+        for _ in self.iter.by_ref().take(count) {
+            self.consumed += 1;
+        }
+    }
 }
 
 impl std::iter::Iterator for ListDir {
@@ -204,6 +574,8 @@ impl std::iter::Iterator for ListDir {
 
     fn next(&mut self) -> Option<Entry> {
         for entry in &mut self.iter {
+            self.consumed += 1;
+
             let entry = match entry {
                 Ok(entry) => entry,
                 Err(error) => {
@@ -221,10 +593,7 @@ impl std::iter::Iterator for ListDir {
                 },
             };
 
-            return Some(Entry {
-                path: path,
-                metadata: metadata,
-            });
+            return Some(Entry::new(path, metadata));
         }
 
         None
@@ -558,4 +927,238 @@ mod tests {
         assert_eq!(results.len(), 2);
         assert_eq!(results[1].metadata.len(), 9);
     }
+
+    #[test]
+    fn test_walk_dir_with_excludes() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tempdir.path().join("foo")).unwrap();
+        std::fs::create_dir(tempdir.path().join("bar")).unwrap();
+        File::create(tempdir.path().join("foo").join("abc")).unwrap();
+        File::create(tempdir.path().join("bar").join("def")).unwrap();
+
+        let opts = WalkOpts {
+            excludes: vec!(Regex::new("/foo$").unwrap()),
+            ..Default::default()
+        };
+
+        let mut results = walk_dir_with(&tempdir, opts).unwrap()
+            .collect::<Vec<_>>();
+        results.sort_by_key(|entry| entry.path.clone());
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].path, tempdir.path());
+        assert_eq!(results[1].path, tempdir.path().join("bar"));
+        assert_eq!(results[2].path, tempdir.path().join("bar").join("def"));
+    }
+
+    #[test]
+    fn test_walk_dir_with_max_depth() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(tempdir.path().join("a").join("b")).unwrap();
+        File::create(tempdir.path().join("a").join("b").join("c")).unwrap();
+
+        let opts = WalkOpts {
+            max_depth: Some(2),
+            ..Default::default()
+        };
+
+        let mut results = walk_dir_with(&tempdir, opts).unwrap()
+            .collect::<Vec<_>>();
+        results.sort_by_key(|entry| entry.path.clone());
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].path, tempdir.path());
+        assert_eq!(results[1].path, tempdir.path().join("a"));
+        assert_eq!(results[2].path, tempdir.path().join("a").join("b"));
+    }
+
+    #[test]
+    fn test_walk_dir_with_zero_max_depth() {
+        let tempdir = tempfile::tempdir().unwrap();
+        File::create(tempdir.path().join("foo")).unwrap();
+
+        let opts = WalkOpts {
+            max_depth: Some(0),
+            ..Default::default()
+        };
+
+        let results = walk_dir_with(&tempdir, opts).unwrap()
+            .collect::<Vec<_>>();
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].path, tempdir.path());
+    }
+
+    #[test]
+    fn test_walk_dir_with_size_filter() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tempdir.path().join("dir")).unwrap();
+        std::fs::write(tempdir.path().join("dir").join("foo"), b"1").unwrap();
+        std::fs::write(tempdir.path().join("dir").join("bar"), b"12345")
+            .unwrap();
+
+        let opts = WalkOpts {
+            min_size: Some(2),
+            max_size: Some(8),
+            ..Default::default()
+        };
+
+        let paths = walk_dir_with(&tempdir, opts).unwrap()
+            .map(|entry| entry.path)
+            .collect::<Vec<_>>();
+
+        // Directories are still descended into even if they are filtered out.
+        assert!(paths.contains(&tempdir.path().join("dir").join("bar")));
+        assert!(!paths.contains(&tempdir.path().join("dir").join("foo")));
+    }
+
+    #[test]
+    fn test_walk_dir_with_mtime_filter() {
+        use std::time::Duration;
+
+        let tempdir = tempfile::tempdir().unwrap();
+        File::create(tempdir.path().join("foo")).unwrap();
+
+        let now = SystemTime::now();
+
+        let opts = WalkOpts {
+            min_mtime: Some(now - Duration::from_secs(60 * 60)),
+            ..Default::default()
+        };
+
+        let paths = walk_dir_with(&tempdir, opts).unwrap()
+            .map(|entry| entry.path)
+            .collect::<Vec<_>>();
+        assert!(paths.contains(&tempdir.path().join("foo")));
+
+        let opts = WalkOpts {
+            max_mtime: Some(now - Duration::from_secs(60 * 60)),
+            ..Default::default()
+        };
+
+        let paths = walk_dir_with(&tempdir, opts).unwrap()
+            .map(|entry| entry.path)
+            .collect::<Vec<_>>();
+        assert!(paths.is_empty());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_walk_dir_without_extended_metadata() {
+        let tempdir = tempfile::tempdir().unwrap();
+        File::create(tempdir.path().join("foo")).unwrap();
+
+        let mut iter = walk_dir(&tempdir).unwrap();
+        assert!(iter.all(|entry| entry.statx.is_none()));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_walk_dir_with_extended_metadata() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tempdir.path().join("dir")).unwrap();
+        std::fs::write(tempdir.path().join("dir").join("foo"), b"foo").unwrap();
+
+        let opts = WalkOpts {
+            extended_metadata: true,
+            ..Default::default()
+        };
+
+        let entries = walk_dir_with(&tempdir, opts).unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(entries.len(), 3);
+
+        for entry in entries {
+            // The kernel might not support `statx`, in which case the entries
+            // have no extended metadata and there is nothing to compare.
+            let statx = linux::statx(&entry.path, false).unwrap();
+            assert_eq!(entry.statx.map(|statx| statx.mtime),
+                       statx.map(|statx| statx.mtime));
+        }
+    }
+
+    #[test]
+    fn test_walk_dir_from_every_position() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(tempdir.path().join("a").join("b")).unwrap();
+        std::fs::create_dir_all(tempdir.path().join("c")).unwrap();
+        File::create(tempdir.path().join("a").join("b").join("d")).unwrap();
+        File::create(tempdir.path().join("a").join("e")).unwrap();
+        File::create(tempdir.path().join("c").join("f")).unwrap();
+        File::create(tempdir.path().join("g")).unwrap();
+
+        let paths = walk_dir(&tempdir).unwrap()
+            .map(|entry| entry.path)
+            .collect::<Vec<_>>();
+        assert_eq!(paths.len(), 8);
+
+        for count in 0..=paths.len() {
+            let mut iter = walk_dir(&tempdir).unwrap();
+
+            let mut resumed_paths = iter.by_ref()
+                .take(count)
+                .map(|entry| entry.path)
+                .collect::<Vec<_>>();
+
+            let position = iter.position();
+            drop(iter);
+
+            let opts = WalkOpts::default();
+            let iter = walk_dir_from(&tempdir, opts, position).unwrap();
+            resumed_paths.extend(iter.map(|entry| entry.path));
+
+            assert_eq!(resumed_paths, paths);
+        }
+    }
+
+    #[test]
+    fn test_walk_dir_from_finished_position() {
+        let tempdir = tempfile::tempdir().unwrap();
+        File::create(tempdir.path().join("a")).unwrap();
+
+        let mut iter = walk_dir(&tempdir).unwrap();
+        for _ in iter.by_ref() {
+        }
+
+        let position = iter.position();
+        assert!(position.root_visited);
+
+        let opts = WalkOpts::default();
+        let mut iter = walk_dir_from(&tempdir, opts, position).unwrap();
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_walk_dir_from_removed_dir() {
+        let tempdir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tempdir.path().join("a")).unwrap();
+        File::create(tempdir.path().join("b")).unwrap();
+
+        let position = WalkPosition {
+            root_visited: true,
+            dirs: vec! {
+                DirPosition {
+                    path: tempdir.path().to_path_buf(),
+                    depth: 1,
+                    consumed: 0,
+                },
+                DirPosition {
+                    path: tempdir.path().join("c"),
+                    depth: 2,
+                    consumed: 0,
+                },
+            },
+        };
+
+        let opts = WalkOpts::default();
+        let mut paths = walk_dir_from(&tempdir, opts, position).unwrap()
+            .map(|entry| entry.path)
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        assert_eq!(paths, vec! {
+            tempdir.path().join("a"),
+            tempdir.path().join("b"),
+        });
+    }
 }
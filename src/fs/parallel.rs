@@ -0,0 +1,617 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+//! A parallel variant of the recursive directory walk.
+//!
+//! On big filesystems the sequential walk is bound by the latency of listing
+//! directories and collecting metadata of their entries rather than by the
+//! throughput of the storage. The parallel walk spreads these calls over a
+//! small pool of threads: every thread takes a directory from the shared queue,
+//! lists and stats all its entries and queues its subdirectories for others.
+//!
+//! Metadata of entries is collected relative to the descriptor of the directory
+//! being listed (on Linux the standard library uses `fstatat` for that), so the
+//! kernel does not have to resolve the whole path for every entry. Directories
+//! themselves are still opened by their full paths, though.
+//!
+//! Entries are sent to the consumer in batches through a bounded channel, so
+//! the memory usage stays flat even if the consumer is slower than the walk.
+//! Entries of a single directory are always yielded contiguously and in the
+//! order of their names (even if they do not fit into a single batch), but the
+//! order in which directories are yielded is not deterministic.
+//!
+//! CPU time of the walker threads is charged to the action execution that
+//! started the walk (if any), so that it counts towards the CPU time limit.
+
+use std::fs::{DirEntry, Metadata};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, SendError, SyncSender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+
+use log::warn;
+
+use crate::session::{CpuAccount, CpuMeter};
+
+use super::{Entry, WalkOpts};
+
+/// A maximum number of entries sent to the consumer at once.
+const BATCH_SIZE: usize = 1024;
+
+/// A number of batches (per walker thread) that can wait for the consumer.
+const BATCHES_PER_THREAD: usize = 2;
+
+/// Returns a deep iterator over entries within a directory walked in parallel.
+///
+/// This function is just like [`walk_dir_with`] except that the directory tree
+/// is walked by the given number of threads. See the [module documentation]
+/// for the details about the order of yielded entries.
+///
+/// # Errors
+///
+/// Just like with the sequential walk, errors encountered along the way are
+/// ignored, unless they happen when collecting information about the root
+/// folder (or when spawning the walker threads).
+///
+/// [`walk_dir_with`]: ../fn.walk_dir_with.html
+/// [module documentation]: index.html
+pub fn walk_dir_parallel<P>(root: P, opts: WalkOpts, threads: usize)
+    -> std::io::Result<ParallelWalkDir>
+where
+    P: AsRef<Path>,
+{
+    let metadata = std::fs::symlink_metadata(&root)?;
+    let mut root = Entry::new(root.as_ref().to_path_buf(), metadata);
+    if opts.extended_metadata {
+        root.collect_statx(None);
+    }
+
+    // The root is listed by one of the walker threads, but we want the walk to
+    // fail upfront (like the sequential one does) if it cannot be listed.
+    std::fs::read_dir(&root.path)?;
+
+    let mut jobs = vec!();
+    if opts.max_depth != Some(0) {
+        jobs.push(Job {
+            path: root.path.clone(),
+            depth: 1,
+        });
+    }
+
+    let threads = std::cmp::max(threads, 1);
+    let capacity = threads * BATCHES_PER_THREAD;
+    let (sender, receiver) = std::sync::mpsc::sync_channel(capacity);
+
+    let shared = Arc::new(Shared {
+        queue: Mutex::new(Queue {
+            jobs: jobs,
+            active: 0,
+            stopped: false,
+        }),
+        changed: Condvar::new(),
+        sending: Mutex::new(()),
+        root_metadata: root.metadata.clone(),
+        opts: opts,
+    });
+
+    let mut walk = ParallelWalkDir {
+        root: Some(root).filter(|root| shared.opts.matches(root)),
+        batch: vec!().into_iter(),
+        receiver: Some(receiver),
+        shared: shared.clone(),
+        workers: vec!(),
+    };
+
+    let account = CpuAccount::current();
+
+    for id in 0..threads {
+        let shared = shared.clone();
+        let sender = sender.clone();
+        let account = account.clone();
+
+        // If spawning fails, the walk is dropped which stops the workers that
+        // have been spawned so far.
+        let worker = std::thread::Builder::new()
+            .name(format!("rrg-walker-{}", id))
+            .spawn(move || {
+                let meter = account.map(CpuMeter::start);
+                work(&shared, &sender, meter)
+            })?;
+
+        walk.workers.push(worker);
+    }
+
+    Ok(walk)
+}
+
+/// Iterator over entries in all subdirectories walked in parallel.
+///
+/// The iterator can be constructed with the [`walk_dir_parallel`] function.
+/// Dropping the iterator stops the walker threads (and waits for them).
+///
+/// [`walk_dir_parallel`]: fn.walk_dir_parallel.html
+pub struct ParallelWalkDir {
+    /// The root entry (if it has not been yielded yet).
+    root: Option<Entry>,
+    /// Remaining entries of the batch being yielded.
+    batch: std::vec::IntoIter<Entry>,
+    /// A receiving end of the batch channel (only `None` when dropped).
+    receiver: Option<Receiver<Vec<Entry>>>,
+    /// A state shared with the walker threads.
+    shared: Arc<Shared>,
+    /// Handles to all the walker threads.
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl std::iter::Iterator for ParallelWalkDir {
+
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Entry> {
+        if let Some(root) = self.root.take() {
+            return Some(root);
+        }
+
+        loop {
+            if let Some(entry) = self.batch.next() {
+                return Some(entry);
+            }
+
+            // The channel is disconnected once all the walkers are done, which
+            // marks the end of the walk.
+            let batch = self.receiver.as_ref()?.recv().ok()?;
+            self.batch = batch.into_iter();
+        }
+    }
+}
+
+impl Drop for ParallelWalkDir {
+
+    fn drop(&mut self) {
+        // Walkers stop once they finish the directory they are working on or
+        // once they fail to send a batch to the dropped receiver.
+        self.shared.stop();
+        self.receiver.take();
+
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                warn!("directory walker thread panicked");
+            }
+        }
+    }
+}
+
+/// A directory to be listed by one of the walkers.
+struct Job {
+    /// A path to the directory.
+    path: PathBuf,
+    /// A depth (relative to the root) of entries of the directory.
+    depth: usize,
+}
+
+/// A state of the walk shared between the walker threads.
+struct Shared {
+    /// A queue of directories to be listed.
+    queue: Mutex<Queue>,
+    /// A condition signalled whenever the queue changes.
+    changed: Condvar,
+    /// A lock held by the walker sending entries of a directory.
+    ///
+    /// Entries of big directories are sent in many batches and the lock is
+    /// held until the last one is sent, so that batches of other directories
+    /// do not end up in between.
+    sending: Mutex<()>,
+    /// Metadata of the root of the walk.
+    root_metadata: Metadata,
+    /// Options of the walk.
+    opts: WalkOpts,
+}
+
+/// A queue of directories to be listed.
+struct Queue {
+    /// Directories waiting to be listed.
+    ///
+    /// The queue is processed in the last-in, first-out order (i.e. the walk
+    /// is roughly depth-first), which keeps the number of waiting directories
+    /// low.
+    jobs: Vec<Job>,
+    /// A number of directories being listed at the moment.
+    active: usize,
+    /// Whether the walk has been stopped before finishing.
+    stopped: bool,
+}
+
+impl Shared {
+
+    /// Waits for the next directory to list.
+    ///
+    /// `None` is returned once there is nothing more to list, i.e. when the
+    /// queue is empty and no walker can add anything to it anymore.
+    fn next_job(&self) -> Option<Job> {
+        let mut queue = self.queue.lock().unwrap();
+
+        loop {
+            if queue.stopped {
+                return None;
+            }
+
+            if let Some(job) = queue.jobs.pop() {
+                queue.active += 1;
+                return Some(job);
+            }
+
+            if queue.active == 0 {
+                return None;
+            }
+
+            queue = self.changed.wait(queue).unwrap();
+        }
+    }
+
+    /// Queues the given directory to be listed.
+    fn push_job(&self, job: Job) {
+        self.queue.lock().unwrap().jobs.push(job);
+        self.changed.notify_one();
+    }
+
+    /// Marks a directory obtained with `next_job` as listed.
+    fn finish_job(&self) {
+        self.queue.lock().unwrap().active -= 1;
+
+        // Once the last directory is listed, all the idle walkers have to wake
+        // up to finish.
+        self.changed.notify_all();
+    }
+
+    /// Stops the walk, making all the walkers finish as soon as possible.
+    fn stop(&self) {
+        self.queue.lock().unwrap().stopped = true;
+        self.changed.notify_all();
+    }
+
+    /// Checks whether the walk should descend into the given directory entry.
+    fn should_descend(&self, entry: &Entry, depth: usize) -> bool {
+        if self.opts.max_depth.map_or(false, |max_depth| depth >= max_depth) {
+            return false;
+        }
+
+        self.opts.cross_device || same_dev(&self.root_metadata, &entry.metadata)
+    }
+}
+
+/// Lists directories from the shared queue until the walk is finished.
+///
+/// The CPU time used by the walker is charged to the given meter (if any)
+/// after every listed directory.
+fn work(
+    shared: &Shared,
+    sender: &SyncSender<Vec<Entry>>,
+    mut meter: Option<CpuMeter>,
+) {
+    while let Some(job) = shared.next_job() {
+        let result = list(shared, sender, &job);
+        shared.finish_job();
+
+        if let Some(ref mut meter) = meter {
+            meter.update();
+        }
+
+        // Failing to send means that the consumer is gone, so there is no
+        // point in walking any further.
+        if result.is_err() {
+            shared.stop();
+        }
+    }
+}
+
+/// Lists a single directory, sending its entries to the consumer.
+fn list(
+    shared: &Shared,
+    sender: &SyncSender<Vec<Entry>>,
+    job: &Job,
+) -> Result<(), SendError<Vec<Entry>>> {
+    let iter = match std::fs::read_dir(&job.path) {
+        Ok(iter) => iter,
+        Err(error) => {
+            warn!("failed to read '{}': {}", job.path.display(), error);
+            return Ok(());
+        }
+    };
+
+    // If extended metadata is needed, it is collected relative to the listed
+    // directory rather than through the full path of every entry.
+    let dir = if cfg!(target_os = "linux") && shared.opts.extended_metadata {
+        match std::fs::File::open(&job.path) {
+            Ok(dir) => Some(dir),
+            Err(error) => {
+                warn!("failed to open '{}': {}", job.path.display(), error);
+                return Ok(());
+            }
+        }
+    } else {
+        None
+    };
+
+    // Only names are collected upfront (to sort them), metadata is collected
+    // entry by entry so that big directories do not blow up the memory usage.
+    let mut dir_entries = iter.filter_map(|dir_entry| match dir_entry {
+        Ok(dir_entry) => Some(dir_entry),
+        Err(error) => {
+            warn!("directory iteration error: {}", error);
+            None
+        }
+    }).collect::<Vec<_>>();
+    dir_entries.sort_by_key(DirEntry::file_name);
+
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let mut sending = None;
+
+    for dir_entry in dir_entries {
+        let path = dir_entry.path();
+        if shared.opts.is_excluded(&path) {
+            continue;
+        }
+
+        let metadata = match dir_entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(error) => {
+                warn!("failed to stat '{}': {}", path.display(), error);
+                continue;
+            }
+        };
+
+        let mut entry = Entry::new(path, metadata);
+
+        if entry.metadata.is_dir() && shared.should_descend(&entry, job.depth) {
+            shared.push_job(Job {
+                path: entry.path.clone(),
+                depth: job.depth + 1,
+            });
+        }
+
+        if shared.opts.matches(&entry) {
+            if shared.opts.extended_metadata {
+                entry.collect_statx(dir.as_ref());
+            }
+            batch.push(entry);
+        }
+
+        if batch.len() == BATCH_SIZE {
+            if sending.is_none() {
+                sending = Some(shared.sending.lock().unwrap());
+            }
+
+            let next = Vec::with_capacity(BATCH_SIZE);
+            sender.send(std::mem::replace(&mut batch, next))?;
+        }
+    }
+
+    if !batch.is_empty() {
+        if sending.is_none() {
+            sending = Some(shared.sending.lock().unwrap());
+        }
+
+        sender.send(batch)?;
+    }
+
+    // The lock is held until the very last batch of the directory is sent.
+    drop(sending);
+
+    Ok(())
+}
+
+#[cfg(target_family = "unix")]
+fn same_dev(root: &Metadata, metadata: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt as _;
+
+    root.dev() == metadata.dev()
+}
+
+#[cfg(target_family = "windows")]
+fn same_dev(_root: &Metadata, _metadata: &Metadata) -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs::File;
+
+    use regex::Regex;
+
+    use super::*;
+
+    #[test]
+    fn test_walk_dir_parallel_non_existing() {
+        let tempdir = tempfile::tempdir().unwrap();
+
+        let iter = walk_dir_parallel(tempdir.path().join("foo"), opts(), 4);
+        assert!(iter.is_err());
+    }
+
+    #[test]
+    fn test_walk_dir_parallel_empty() {
+        let tempdir = tempfile::tempdir().unwrap();
+
+        let mut iter = walk_dir_parallel(&tempdir, opts(), 4).unwrap();
+
+        let entry = iter.next().unwrap();
+        assert_eq!(entry.path, tempdir.path());
+        assert!(entry.metadata.is_dir());
+
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_walk_dir_parallel_same_as_sequential() {
+        let tempdir = tempfile::tempdir().unwrap();
+        tree(tempdir.path(), 3, 4);
+
+        let mut sequential = crate::fs::walk_dir(&tempdir).unwrap()
+            .map(|entry| entry.path)
+            .collect::<Vec<_>>();
+        sequential.sort();
+
+        let mut parallel = walk_dir_parallel(&tempdir, opts(), 4).unwrap()
+            .map(|entry| entry.path)
+            .collect::<Vec<_>>();
+        parallel.sort();
+
+        assert_eq!(parallel.len(), 1 + 4 + 4 * 4 + 4 * 4 * 4);
+        assert_eq!(parallel, sequential);
+    }
+
+    #[test]
+    fn test_walk_dir_parallel_order_within_dir() {
+        let tempdir = tempfile::tempdir().unwrap();
+        tree(tempdir.path(), 2, 8);
+
+        let paths = walk_dir_parallel(&tempdir, opts(), 4).unwrap()
+            .map(|entry| entry.path)
+            .collect::<Vec<_>>();
+
+        let dirs = std::iter::once(tempdir.path().to_path_buf())
+            .chain((0..8).map(|idx| tempdir.path().join(idx.to_string())));
+
+        for dir in dirs {
+            assert_contiguous(&paths, &dir, 8);
+        }
+    }
+
+    #[test]
+    fn test_walk_dir_parallel_order_within_big_dirs() {
+        let tempdir = tempfile::tempdir().unwrap();
+
+        // Every directory needs more than one batch, so the walkers listing
+        // them concurrently all have many batches to send.
+        let count = BATCH_SIZE + BATCH_SIZE / 2;
+        for dir in 0..4 {
+            let dir = tempdir.path().join(dir.to_string());
+            std::fs::create_dir(&dir).unwrap();
+
+            for idx in 0..count {
+                File::create(dir.join(format!("{:05}", idx))).unwrap();
+            }
+        }
+
+        let paths = walk_dir_parallel(&tempdir, opts(), 4).unwrap()
+            .map(|entry| entry.path)
+            .collect::<Vec<_>>();
+
+        for dir in 0..4 {
+            assert_contiguous(&paths, &tempdir.path().join(dir.to_string()),
+                              count);
+        }
+    }
+
+    #[test]
+    fn test_walk_dir_parallel_big_dir() {
+        let tempdir = tempfile::tempdir().unwrap();
+        for idx in 0..(BATCH_SIZE * 2 + 1) {
+            File::create(tempdir.path().join(format!("{:05}", idx))).unwrap();
+        }
+
+        let paths = walk_dir_parallel(&tempdir, opts(), 2).unwrap()
+            .map(|entry| entry.path)
+            .collect::<Vec<_>>();
+
+        assert_eq!(paths.len(), BATCH_SIZE * 2 + 2);
+        assert_eq!(paths[0], tempdir.path());
+        assert!(paths[1..].windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn test_walk_dir_parallel_with_opts() {
+        let tempdir = tempfile::tempdir().unwrap();
+        tree(tempdir.path(), 3, 2);
+
+        let opts = WalkOpts {
+            excludes: vec!(Regex::new("/0$").unwrap()),
+            max_depth: Some(2),
+            ..Default::default()
+        };
+
+        let mut paths = walk_dir_parallel(&tempdir, opts, 4).unwrap()
+            .map(|entry| entry.path)
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        assert_eq!(paths, vec! {
+            tempdir.path().to_path_buf(),
+            tempdir.path().join("1"),
+            tempdir.path().join("1").join("1"),
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_walk_dir_parallel_with_extended_metadata() {
+        let tempdir = tempfile::tempdir().unwrap();
+        tree(tempdir.path(), 2, 2);
+
+        let opts = WalkOpts {
+            extended_metadata: true,
+            ..Default::default()
+        };
+
+        let entries = walk_dir_parallel(&tempdir, opts, 2).unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(entries.len(), 7);
+
+        for entry in entries {
+            // The kernel might not support `statx`, in which case the entries
+            // have no extended metadata and there is nothing to compare.
+            let statx = crate::fs::linux::statx(&entry.path, false).unwrap();
+            assert_eq!(entry.statx.map(|statx| statx.mtime),
+                       statx.map(|statx| statx.mtime));
+        }
+    }
+
+    #[test]
+    fn test_walk_dir_parallel_dropped_early() {
+        let tempdir = tempfile::tempdir().unwrap();
+        tree(tempdir.path(), 3, 8);
+
+        let mut iter = walk_dir_parallel(&tempdir, opts(), 4).unwrap();
+        assert!(iter.next().is_some());
+        assert!(iter.next().is_some());
+
+        // This should not hang even though the walk is not finished.
+        drop(iter);
+    }
+
+    /// Asserts that children of the given directory are yielded contiguously
+    /// and sorted by their names.
+    fn assert_contiguous(paths: &[PathBuf], dir: &Path, count: usize) {
+        let children = paths.iter()
+            .enumerate()
+            .filter(|(_, path)| path.parent() == Some(dir))
+            .collect::<Vec<_>>();
+        assert_eq!(children.len(), count);
+
+        for pair in children.windows(2) {
+            assert_eq!(pair[0].0 + 1, pair[1].0);
+            assert!(pair[0].1 < pair[1].1);
+        }
+    }
+
+    /// Returns default walk options.
+    fn opts() -> WalkOpts {
+        WalkOpts::default()
+    }
+
+    /// Creates a tree of directories of the given depth and width.
+    fn tree(root: &Path, depth: usize, width: usize) {
+        if depth == 0 {
+            return;
+        }
+
+        for idx in 0..width {
+            let path = root.join(idx.to_string());
+            std::fs::create_dir(&path).unwrap();
+            tree(&path, depth - 1, width);
+        }
+    }
+}
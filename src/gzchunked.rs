@@ -216,6 +216,80 @@ where
     }
 }
 
+/// Push-based encoder for the gzchunked format.
+///
+/// Unlike [`Encode`], this encoder does not own the source of the messages.
+/// Instead, messages are pushed one by one and finished parts are handed back
+/// as soon as they are ready. This is useful when the caller needs to do some
+/// other work (e.g. send heartbeat signals) between consecutive messages.
+///
+/// Note that unlike [`Encode`], this encoder never splits a message between
+/// two parts.
+///
+/// [`Encode`]: struct.Encode.html
+pub struct Encoder<M> {
+    encoder: flate2::write::GzEncoder<Vec<u8>>,
+    opts: EncodeOpts,
+    /// A buffer for the chunked encoding of a single message.
+    buf: Vec<u8>,
+    /// Whether any message has been written to the current part.
+    is_empty: bool,
+    marker: std::marker::PhantomData<M>,
+}
+
+impl<M> Encoder<M>
+where
+    M: prost::Message,
+{
+    /// Creates a new encoder instance with the specified options.
+    pub fn new(opts: EncodeOpts) -> Encoder<M> {
+        Encoder {
+            encoder: Encoder::<M>::gz_encoder(opts),
+            opts: opts,
+            buf: vec!(),
+            is_empty: true,
+            marker: std::marker::PhantomData,
+        }
+    }
+
+    /// Encodes the given message, yielding the current part if it is full.
+    pub fn write(&mut self, msg: &M) -> std::io::Result<Option<Vec<u8>>> {
+        use std::io::Write as _;
+
+        self.buf.clear();
+        crate::chunked::encode_into(&mut self.buf, msg)?;
+        self.encoder.write_all(&self.buf)?;
+        self.is_empty = false;
+
+        if self.encoder.get_ref().len() as u64 >= self.opts.part_size {
+            self.flush()
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Finishes the current part (if any messages have been written to it).
+    ///
+    /// This should be called once all the messages have been written, so that
+    /// the last (possibly not full) part is not lost.
+    pub fn flush(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        if self.is_empty {
+            return Ok(None);
+        }
+
+        let encoder = Encoder::<M>::gz_encoder(self.opts);
+        let encoder = std::mem::replace(&mut self.encoder, encoder);
+        self.is_empty = true;
+
+        Ok(Some(encoder.finish()?))
+    }
+
+    /// Creates a gzip encoder for a new part of the output file.
+    fn gz_encoder(opts: EncodeOpts) -> flate2::write::GzEncoder<Vec<u8>> {
+        flate2::write::GzEncoder::new(vec!(), opts.compression.0)
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -347,4 +421,38 @@ mod tests {
 
         assert!(iter.all(|item| item == sample));
     }
+
+    #[test]
+    fn test_encoder_with_no_items() {
+        let mut encoder = Encoder::<String>::new(EncodeOpts::default());
+
+        assert_eq!(encoder.flush().unwrap(), None);
+    }
+
+    #[test]
+    fn test_encoder_and_decode_with_many_items() {
+        let sample = rand::random::<[u8; 32]>().to_vec();
+
+        let opts = EncodeOpts {
+            compression: Compression::none(),
+            part_size: 4 * 1024,
+        };
+
+        let mut encoder = Encoder::new(opts);
+        let mut chunks = vec!();
+        for _ in 0..32 * 1024 {
+            chunks.extend(encoder.write(&sample).unwrap());
+        }
+        chunks.extend(encoder.flush().unwrap());
+
+        assert!(chunks.len() > 1);
+        assert_eq!(encoder.flush().unwrap(), None);
+
+        let items = decode::<_, Vec<u8>>(chunks.iter().map(Vec::as_slice))
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+
+        assert_eq!(items.len(), 32 * 1024);
+        assert!(items.iter().all(|item| *item == sample));
+    }
 }
@@ -10,6 +10,7 @@
 //! traits.
 
 use std::io::{Read, Write, Result};
+use std::path::PathBuf;
 
 // The same as in the Rust's standard library.
 const DEFAULT_BUF_SIZE: usize = 8 * 1024;
@@ -145,6 +146,115 @@ where
     }
 }
 
+/// A file writer that rotates the file once it grows too big.
+///
+/// Once writing to the file would make it exceed the size limit, the file is
+/// renamed to `<path>.1` (shifting previously rotated files to `<path>.2`,
+/// `<path>.3` and so on) and a new, empty file is created in its place. Only
+/// the specified number of rotated files is kept, older ones are removed.
+///
+/// Note that a single write is never split between files, so a file can still
+/// exceed the limit if a single write is bigger than the limit itself.
+pub struct RotatingFile {
+    /// A path to the file being written to.
+    path: PathBuf,
+    /// A handle to the file being written to.
+    file: std::fs::File,
+    /// A current size of the file.
+    size: u64,
+    /// A size after which the file is rotated.
+    max_size: u64,
+    /// A number of rotated files to keep.
+    max_count: usize,
+}
+
+impl RotatingFile {
+
+    /// Opens a file at the given path for appending (creating it if needed).
+    pub fn open<P>(path: P, max_size: u64, max_count: usize)
+        -> Result<RotatingFile>
+    where
+        P: Into<PathBuf>,
+    {
+        let path = path.into();
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(RotatingFile {
+            path: path,
+            file: file,
+            size: size,
+            max_size: max_size,
+            max_count: max_count,
+        })
+    }
+
+    /// Checks whether writing the given number of bytes rotates the file.
+    pub fn will_rotate(&self, len: u64) -> bool {
+        self.size > 0 && self.size + len > self.max_size
+    }
+
+    /// Returns a path of the rotated file with the given index.
+    fn rotated_path(&self, idx: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", idx));
+        PathBuf::from(path)
+    }
+
+    /// Shifts all the rotated files and starts a new, empty file.
+    fn rotate(&mut self) -> Result<()> {
+        use std::io::ErrorKind::NotFound;
+
+        // Some of the rotated files may not exist yet (e.g. if the file has not
+        // been rotated that many times), which is perfectly fine.
+        let ignore_not_found = |result: Result<()>| match result {
+            Err(ref error) if error.kind() == NotFound => Ok(()),
+            result => result,
+        };
+
+        if self.max_count == 0 {
+            ignore_not_found(std::fs::remove_file(&self.path))?;
+        } else {
+            let oldest = self.rotated_path(self.max_count);
+            ignore_not_found(std::fs::remove_file(&oldest))?;
+
+            for idx in (1..self.max_count).rev() {
+                let from = self.rotated_path(idx);
+                let to = self.rotated_path(idx + 1);
+                ignore_not_found(std::fs::rename(&from, &to))?;
+            }
+
+            std::fs::rename(&self.path, self.rotated_path(1))?;
+        }
+
+        self.file = std::fs::File::create(&self.path)?;
+        self.size = 0;
+
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if self.will_rotate(buf.len() as u64) {
+            self.rotate()?;
+        }
+
+        let len = self.file.write(buf)?;
+        self.size += len as u64;
+
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.file.flush()
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -240,4 +350,49 @@ mod tests {
 
         assert_eq!(buf, b"foobarbaz");
     }
+
+    #[test]
+    fn test_rotating_file_below_limit() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("log");
+
+        let mut file = RotatingFile::open(&path, 8, 2).unwrap();
+        file.write_all(b"foo").unwrap();
+        file.write_all(b"bar").unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"foobar");
+        assert!(!tempdir.path().join("log.1").exists());
+    }
+
+    #[test]
+    fn test_rotating_file_above_limit() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("log");
+
+        let mut file = RotatingFile::open(&path, 4, 2).unwrap();
+        file.write_all(b"foo").unwrap();
+        file.write_all(b"bar").unwrap();
+        file.write_all(b"baz").unwrap();
+        file.write_all(b"quux").unwrap();
+
+        let read = |name| std::fs::read(tempdir.path().join(name)).unwrap();
+        assert_eq!(read("log"), b"quux");
+        assert_eq!(read("log.1"), b"baz");
+        assert_eq!(read("log.2"), b"bar");
+        assert!(!tempdir.path().join("log.3").exists());
+    }
+
+    #[test]
+    fn test_rotating_file_appends() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("log");
+        std::fs::write(&path, b"foo").unwrap();
+
+        let mut file = RotatingFile::open(&path, 4, 1).unwrap();
+        file.write_all(b"bar").unwrap();
+
+        let rotated = tempdir.path().join("log.1");
+        assert_eq!(std::fs::read(&path).unwrap(), b"bar");
+        assert_eq!(std::fs::read(&rotated).unwrap(), b"foo");
+    }
 }
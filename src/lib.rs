@@ -4,35 +4,65 @@
 // in the LICENSE file or at https://opensource.org/licenses/MIT.
 
 pub mod action;
+pub mod audit;
+pub mod config;
+pub mod export;
 pub mod fs;
 pub mod io;
 pub mod message;
 pub mod metadata;
 pub mod opts;
+pub mod pool;
 pub mod session;
+pub mod state;
+#[cfg(target_os = "linux")]
+pub mod stats;
+pub mod transport;
 
 // Consider moving these to a separate submodule.
 pub mod chunked;
 pub mod gzchunked;
 
-use crate::opts::{Opts};
+use crate::config::Config;
 
 /// Enters the agent's main loop and waits for messages.
 ///
 /// It will poll for messages from the GRR server and should consume very few
-/// resources when idling. Once it picks a message, it dispatches it to an
-/// appropriate action handler (which should take care of sending heartbeat
-/// signals if expected to be long-running) and goes back to idling when action
-/// execution is finished.
+/// resources when idling. Once it picks a message, it hands it over to a pool
+/// of worker threads that dispatch it to an appropriate action handler (which
+/// should take care of sending heartbeat signals if expected to be
+/// long-running) and immediately goes back to polling for more messages. Thus,
+/// long-running actions do not block execution of other requests (as long as
+/// there are idle workers in the pool).
 ///
 /// This function never terminates and panics only if something went very wrong
-/// (e.g. the Fleetspeak connection has been broken). All non-critical errors
-/// are going to be handled carefully, notifying the server about the failure if
-/// appropriate.
-pub fn listen(opts: &Opts) {
+/// (e.g. the connection with the server has been broken). All non-critical
+/// errors are going to be handled carefully, notifying the server about the
+/// failure if appropriate.
+///
+/// Messages are exchanged through the transport installed beforehand with
+/// [`message::init`].
+pub fn listen(config: &Config) {
+    let pool = pool::Pool::new(config.pool_size);
+
     loop {
-        if let Some(message) = message::collect(&opts) {
-            session::handle(message);
+        let message = match message::collect(config) {
+            Some(message) => message,
+            None => continue,
+        };
+
+        // Cancellation requests have to bypass the pool, as otherwise they
+        // would wait for the very actions they are supposed to cancel.
+        if session::is_cancellation(&message) {
+            session::cancel(message);
+            continue;
         }
+
+        // Requests are registered before being queued, so that they can be
+        // cancelled even if they wait for an idle worker for a long time.
+        let registration = session::register(&message);
+
+        let config = config.clone();
+        pool.execute(move || session::handle(message, registration, &config));
     }
 }
@@ -3,20 +3,44 @@
 // Use of this source code is governed by an MIT-style license that can be found
 // in the LICENSE file or at https://opensource.org/licenses/MIT.
 
-use std::fs::File;
+use std::fs::OpenOptions;
 
 use log::{error, info};
 
 use rrg::action;
+use rrg::audit;
+use rrg::config::{self, Config};
+use rrg::io::RotatingFile;
 use rrg::session;
 use rrg::opts::{self, Opts};
 
 fn main() {
     let opts = opts::from_args();
-    init(&opts);
 
-    fleetspeak::startup(env!("CARGO_PKG_VERSION"))
-        .expect("failed to initialize Fleetspeak connection");
+    let config = match Config::from_opts(&opts) {
+        Ok(config) => config,
+        Err(error) => {
+            // Logging is not initialized yet (as it is configured as well), so
+            // the standard error is the only place where we can report it.
+            eprintln!("invalid configuration: {}", error);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(ref command) = opts.command {
+        std::process::exit(run(command, &config));
+    }
+
+    init(&opts, &config);
+
+    let transport = rrg::transport::connect(&opts)
+        .expect("failed to initialize the transport");
+    rrg::message::init(transport);
+
+    #[cfg(target_os = "linux")]
+    if let Err(error) = rrg::stats::start() {
+        error!("failed to start sampling resource usage: {}", error);
+    }
 
     match action::startup::handle(&mut session::Adhoc, ()) {
         Err(error) => {
@@ -27,32 +51,96 @@ fn main() {
         }
     }
 
-    rrg::listen(&opts);
+    rrg::listen(&config);
+}
+
+fn init(opts: &Opts, config: &Config) {
+    init_log(opts, config);
+    init_audit(config);
+    config::init(config.clone());
+}
+
+fn init_audit(config: &Config) {
+    if let Some(path) = &config.audit_file {
+        let log = audit::Log::open(path, config.audit_rotation)
+            .expect("failed to open the audit log");
+
+        audit::init(log);
+    }
+}
+
+fn run(command: &opts::Command, config: &Config) -> i32 {
+    match command {
+        opts::Command::Audit(command) => run_audit(command, config),
+    }
 }
 
-fn init(opts: &Opts) {
-    init_log(opts);
+fn run_audit(command: &opts::Audit, config: &Config) -> i32 {
+    use opts::Audit;
+
+    let file = match command {
+        Audit::Verify { file } | Audit::Dump { file } => file,
+    };
+    let path = match file.as_ref().or(config.audit_file.as_ref()) {
+        Some(path) => path,
+        None => {
+            eprintln!("no audit log file specified");
+            return 2;
+        }
+    };
+
+    let result = match command {
+        Audit::Verify { .. } => audit::verify(path).map(|summary| {
+            println!("audit log is intact ({} records, head {})",
+                     summary.count, summary.head);
+        }),
+        Audit::Dump { .. } => audit::dump(path, &mut std::io::stdout()),
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(error) => {
+            eprintln!("{}", error);
+            1
+        }
+    }
 }
 
-fn init_log(opts: &Opts) {
-    let level = opts.log_verbosity.level();
+fn init_log(opts: &Opts, config: &Config) {
+    let level = config.log_verbosity.level();
 
     let mut loggers = Vec::<Box<dyn simplelog::SharedLogger>>::new();
 
     if let Some(stream) = &opts.log_stream {
-        let config = Default::default();
-        let logger = simplelog::TermLogger::new(level, config, stream.mode())
+        let log_config = Default::default();
+        let mode = stream.mode();
+        let logger = simplelog::TermLogger::new(level, log_config, mode)
             .expect("failed to create a terminal logger");
 
         loggers.push(logger);
     }
 
-    if let Some(path) = &opts.log_file {
-        let file = File::create(path)
-            .expect("failed to create the log file");
-
-        let config = Default::default();
-        let logger = simplelog::WriteLogger::new(level, config, file);
+    if let Some(path) = &config.log_file {
+        let log_config = Default::default();
+
+        let logger = match config.log_rotation {
+            Some(rotation) => {
+                let file = RotatingFile::open(path, rotation.max_size,
+                                              rotation.max_count)
+                    .expect("failed to open the log file");
+
+                simplelog::WriteLogger::new(level, log_config, file)
+            }
+            None => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .expect("failed to open the log file");
+
+                simplelog::WriteLogger::new(level, log_config, file)
+            }
+        };
 
         loggers.push(logger);
     }
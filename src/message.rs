@@ -3,60 +3,95 @@
 // Use of this source code is governed by an MIT-style license that can be found
 // in the LICENSE file or at https://opensource.org/licenses/MIT.
 
-use fleetspeak::Packet;
-use log::{error, warn};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
 
-use crate::opts::Opts;
+use lazy_static::lazy_static;
+use log::error;
+
+use crate::config::Config;
+use crate::transport::{ReadError, Transport};
+
+lazy_static! {
+    /// A transport used to communicate with the server.
+    ///
+    /// It is installed once at the agent startup and is shared by the main
+    /// loop and all the workers.
+    static ref TRANSPORT: RwLock<Option<Arc<dyn Transport>>> = {
+        RwLock::new(None)
+    };
+}
+
+/// A total number of bytes of all the messages sent to the server.
+static BYTES_SENT: AtomicU64 = AtomicU64::new(0);
+
+/// A total number of bytes of all the messages received from the server.
+static BYTES_RECEIVED: AtomicU64 = AtomicU64::new(0);
+
+/// Installs the transport through which all the messages are exchanged.
+///
+/// This function should be called exactly once, before any message is sent or
+/// collected.
+pub fn init(transport: Box<dyn Transport>) {
+    *TRANSPORT.write().unwrap() = Some(Arc::from(transport));
+}
+
+/// Returns the installed transport.
+///
+/// The transport is cloned out of the lock, so that waiting for messages does
+/// not block sending them.
+fn transport() -> Arc<dyn Transport> {
+    TRANSPORT.read().unwrap().clone()
+        .expect("transport used before initialization")
+}
+
+/// Returns the total number of bytes sent to the server so far.
+pub fn bytes_sent() -> u64 {
+    BYTES_SENT.load(Ordering::Relaxed)
+}
+
+/// Returns the total number of bytes received from the server so far.
+pub fn bytes_received() -> u64 {
+    BYTES_RECEIVED.load(Ordering::Relaxed)
+}
 
 pub fn send(message: rrg_proto::GrrMessage) {
-        let packet = Packet {
-            service: String::from("GRR"),
-            kind: Some(String::from("GrrMessage")),
-            data: message,
-        };
-
-        if let Err(error) = fleetspeak::send(packet) {
-            // If we failed to deliver the message through Fleetspeak, it means
-            // that our communication is broken (e.g. the pipe was closed) and
-            // the agent should be killed.
-            panic!("message delivery failure: {}", error)
-        };
-}
-
-pub fn collect(opts: &Opts) -> Option<rrg_proto::GrrMessage> {
-    use fleetspeak::ReadError::*;
-
-    let packet = match fleetspeak::collect(opts.heartbeat_rate) {
-        Ok(packet) => packet,
-        Err(Malformed(error)) => {
-            error!("received a malformed message: {}", error);
-            return None;
-        }
-        Err(Decode(error)) => {
-            error!("failed to decode a message: {}", error);
-            return None;
-        }
-        Err(error) => {
-            // If we failed to collect the message because of I/O error or magic
-            // check, it means that our communication is broken (e.g. the pipe
-            // was closed) and the agent should be killed.
-            panic!("failed to collect a message: {}", error)
-        }
+    let len = prost::Message::encoded_len(&message) as u64;
+    BYTES_SENT.fetch_add(len, Ordering::Relaxed);
+
+    if let Err(error) = transport().send(message) {
+        // If we failed to deliver the message, it means that our communication
+        // is broken (e.g. the pipe was closed) and the agent should be killed.
+        // This must not be mistaken for a failure of the action that sent it.
+        crate::session::fatal(format!("message delivery failure: {}", error))
     };
+}
 
-    if packet.service != "GRR" {
-        warn!("message send by '{}' service (instead of GRR)", packet.service);
-    }
+pub fn heartbeat() {
+    if let Err(error) = transport().heartbeat() {
+        // Just as with messages, failing to deliver the heartbeat signal
+        // means that our communication is broken.
+        crate::session::fatal(format!("heartbeat delivery failure: {}", error))
+    };
+}
 
-    match packet.kind {
-        Some(ref kind) if kind != "GrrMessage" => {
-            warn!("message with unrecognized type '{}'", kind);
+pub fn collect(config: &Config) -> Option<rrg_proto::GrrMessage> {
+    match transport().collect(config.heartbeat_rate) {
+        Ok(message) => {
+            let len = prost::Message::encoded_len(&message) as u64;
+            BYTES_RECEIVED.fetch_add(len, Ordering::Relaxed);
+
+            Some(message)
         }
-        Some(_) => (),
-        None => {
-            warn!("message with missing type specification");
+        Err(ReadError::Malformed(error)) => {
+            error!("received a malformed message: {}", error);
+            None
+        }
+        Err(ReadError::Io(error)) => {
+            // If we failed to collect the message because of I/O error, it
+            // means that our communication is broken (e.g. the pipe was closed)
+            // and the agent should be killed.
+            panic!("failed to collect a message: {}", error)
         }
     }
-
-    Some(packet.data)
 }
@@ -11,6 +11,8 @@ pub struct Metadata {
     pub description: String,
     /// Version of the RRG agent.
     pub version: Version,
+    /// Labels of the client specified in the agent configuration.
+    pub labels: Vec<String>,
 }
 
 impl Metadata {
@@ -24,6 +26,7 @@ impl Metadata {
             name: String::from(env!("CARGO_PKG_NAME")),
             description: String::from(env!("CARGO_PKG_DESCRIPTION")),
             version: Version::from_cargo(),
+            labels: crate::config::get().labels.clone(),
         }
     }
 }
@@ -88,11 +91,11 @@ impl Version {
 impl Into<rrg_proto::ClientInformation> for Metadata {
 
     fn into(self) -> rrg_proto::ClientInformation {
-        // TODO: Add support for labels.
         rrg_proto::ClientInformation {
             client_name: Some(self.name),
             client_version: Some(self.version.as_numeric()),
             client_description: Some(self.description),
+            labels: self.labels,
             ..Default::default()
         }
     }
@@ -13,36 +13,96 @@
 //! shared through the entire lifetime of a program and explicitly passed to
 //! functions that care about it.
 //!
+//! Note that most of the settings can be also specified in the configuration
+//! file. The two sources are merged into the [`Config`] object which is what
+//! the rest of the agent should use.
+//!
 //! [`from_args`]: fn.from_args.html
+//! [`Config`]: ../config/struct.Config.html
 
 use std::time::Duration;
 use std::path::PathBuf;
 
 use structopt::StructOpt;
 
-#[derive(StructOpt)]
+#[derive(Clone, StructOpt)]
 #[structopt(name = "RRG", about = "A GRR agent rewritten in Rust.")]
 pub struct Opts {
-    /// A level of log verbosity.
-    #[structopt(long="log-verbosity", name="LEVEL", default_value="info",
+    /// A path to the configuration file.
+    #[structopt(long="config", name="CONFIG",
+                help="Specifies the path to the configuration file")]
+    pub config: Option<PathBuf>,
+
+    /// A level of log verbosity (overrides the configuration file).
+    #[structopt(long="log-verbosity", name="LEVEL",
                 help="Specifies the level of log verbosity")]
-    pub log_verbosity: Verbosity,
+    pub log_verbosity: Option<Verbosity>,
 
     /// A standard stream to log into.
     #[structopt(long="log-stream", name="STREAM",
                 help="Enables logging to the specified standard stream")]
     pub log_stream: Option<Stream>,
 
-    /// A path to the file to log into.
+    /// A path to the file to log into (overrides the configuration file).
     #[structopt(long="log-file", name="FILE",
                 help="Enables logging to the specified file")]
     pub log_file: Option<PathBuf>,
 
-    /// A frequency of heartbeat messages to send to the Fleetspeak client.
-    #[structopt(long="heartbeat-rate", name="DURATION", default_value="5s",
+    /// A frequency of heartbeat messages to send to the Fleetspeak client
+    /// (overrides the configuration file).
+    #[structopt(long="heartbeat-rate", name="DURATION",
                 parse(try_from_str = humantime::parse_duration),
                 help="Specifies the frequency of heartbeat messages")]
-    pub heartbeat_rate: Duration,
+    pub heartbeat_rate: Option<Duration>,
+
+    /// A number of worker threads executing actions concurrently (overrides
+    /// the configuration file).
+    #[structopt(long="pool-size", name="SIZE",
+                parse(try_from_str = parse_pool_size),
+                help="Specifies the number of actions executed concurrently")]
+    pub pool_size: Option<usize>,
+
+    /// A transport used to communicate with the server.
+    ///
+    /// Possible values are `fleetspeak` (the default), `stdio` (for messages
+    /// exchanged over the standard streams) and `socket:PATH` (for messages
+    /// exchanged over the Unix socket at the given path).
+    #[structopt(long="transport", name="TRANSPORT", default_value="fleetspeak",
+                help="Specifies the transport used to talk to the server")]
+    pub transport: Transport,
+
+    /// A local command to run instead of starting the agent.
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// A local command that can be run instead of starting the agent.
+#[derive(Clone, Debug, StructOpt)]
+pub enum Command {
+    /// Inspects the audit log of executed actions.
+    #[structopt(name="audit")]
+    Audit(Audit),
+}
+
+/// A command for inspecting the audit log.
+#[derive(Clone, Debug, StructOpt)]
+pub enum Audit {
+    /// Verifies that the audit log has not been tampered with.
+    #[structopt(name="verify")]
+    Verify {
+        /// A path to the audit log (overrides the configuration file).
+        #[structopt(long="file", name="FILE",
+                    help="Specifies the path to the audit log")]
+        file: Option<PathBuf>,
+    },
+    /// Prints all the records of the audit log as JSON objects.
+    #[structopt(name="dump")]
+    Dump {
+        /// A path to the audit log (overrides the configuration file).
+        #[structopt(long="file", name="FILE",
+                    help="Specifies the path to the audit log")]
+        file: Option<PathBuf>,
+    },
 }
 
 /// Parses command-line arguments.
@@ -56,6 +116,15 @@ pub fn from_args() -> Opts {
     Opts::from_args()
 }
 
+/// Parses the size of the worker pool, rejecting empty pools.
+fn parse_pool_size(string: &str) -> Result<usize, String> {
+    match string.parse::<usize>() {
+        Ok(0) => Err(String::from("pool size must be positive")),
+        Ok(size) => Ok(size),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
 /// A type representing level of log verbosity.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Verbosity {
@@ -70,6 +139,15 @@ impl Verbosity {
     }
 }
 
+impl Default for Verbosity {
+
+    fn default() -> Verbosity {
+        Verbosity {
+            level: log::LevelFilter::Info,
+        }
+    }
+}
+
 impl std::str::FromStr for Verbosity {
 
     type Err = ParseVerbosityError;
@@ -127,6 +205,37 @@ impl std::str::FromStr for Stream {
     }
 }
 
+/// A type listing different transports for communicating with the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transport {
+    /// Messages are exchanged through the Fleetspeak client.
+    Fleetspeak,
+    /// Messages are exchanged over the standard input and output.
+    Stdio,
+    /// Messages are exchanged over the Unix socket at the given path.
+    Socket(PathBuf),
+}
+
+impl std::str::FromStr for Transport {
+
+    type Err = ParseTransportError;
+
+    fn from_str(string: &str) -> Result<Transport, ParseTransportError> {
+        match string {
+            "fleetspeak" => return Ok(Transport::Fleetspeak),
+            "stdio" => return Ok(Transport::Stdio),
+            _ => (),
+        }
+
+        match string.strip_prefix("socket:") {
+            Some(path) if !path.is_empty() => {
+                Ok(Transport::Socket(PathBuf::from(path)))
+            }
+            _ => Err(ParseTransportError::new(string)),
+        }
+    }
+}
+
 /// An error type for failures related to parsing of verbosity levels.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct ParseVerbosityError {
@@ -186,3 +295,33 @@ impl std::error::Error for ParseStreamError {
         None
     }
 }
+
+/// An error type for failures related to parsing transport specifications.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseTransportError {
+    string: String,
+}
+
+impl ParseTransportError {
+
+    /// Constructs a new error indicating failure of parsing given string.
+    fn new<S: Into<String>>(string: S) -> ParseTransportError {
+        ParseTransportError {
+            string: string.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for ParseTransportError {
+
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "invalid transport: '{}'", self.string)
+    }
+}
+
+impl std::error::Error for ParseTransportError {
+
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
@@ -0,0 +1,174 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+//! A simple pool of worker threads for concurrent action execution.
+//!
+//! The agent main loop collects action requests and hands them over to the
+//! pool. Requests are executed by a fixed number of worker threads, so that a
+//! long-running action (e.g. a timeline of the whole filesystem) does not delay
+//! quick ones. If all workers are busy, requests are queued and executed once
+//! some worker becomes available.
+
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::JoinHandle;
+
+use log::error;
+
+/// A unit of work to be executed by one of the workers.
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A pool of worker threads executing submitted jobs.
+///
+/// Dropping the pool waits for all the submitted jobs to finish.
+pub struct Pool {
+    /// A sending end of the job queue (only `None` when the pool is dropped).
+    sender: Option<mpsc::Sender<Job>>,
+    /// Handles to all the worker threads.
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Pool {
+
+    /// Creates a new pool with the specified number of worker threads.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `size` is zero or if it is not possible to
+    /// spawn worker threads.
+    pub fn new(size: usize) -> Pool {
+        assert!(size > 0, "worker pool cannot be empty");
+
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = (0..size).map(|id| {
+            let receiver = receiver.clone();
+
+            std::thread::Builder::new()
+                .name(format!("rrg-worker-{}", id))
+                .spawn(move || work(&receiver))
+                .expect("failed to spawn a worker thread")
+        }).collect();
+
+        Pool {
+            sender: Some(sender),
+            workers: workers,
+        }
+    }
+
+    /// Schedules the job to be executed by one of the workers.
+    pub fn execute<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let sender = self.sender.as_ref()
+            .expect("pool sender used after drop");
+
+        // Workers only exit when the sender is dropped (or when one of them
+        // takes the whole agent down), so the queue is always open here.
+        sender.send(Box::new(job))
+            .expect("worker pool queue has been closed");
+    }
+}
+
+impl Drop for Pool {
+
+    fn drop(&mut self) {
+        // Closing the queue makes all the workers exit once they finish with
+        // the jobs that are still queued.
+        drop(self.sender.take());
+
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                error!("worker thread exited abnormally");
+            }
+        }
+    }
+}
+
+/// Executes jobs from the queue until it is closed.
+fn work(receiver: &Mutex<mpsc::Receiver<Job>>) {
+    loop {
+        // The lock guard is a temporary, so it is released before the job is
+        // executed and other workers can pick up jobs in the meantime.
+        let job = match receiver.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(mpsc::RecvError) => return,
+        };
+
+        let job = std::panic::AssertUnwindSafe(job);
+        if std::panic::catch_unwind(job).is_err() {
+            // Panics indicate critical failures (e.g. a broken connection with
+            // Fleetspeak) that used to take the agent down when actions were
+            // executed on the main thread. We keep this behaviour, as otherwise
+            // a dead worker would just silently shrink the pool.
+            error!("worker thread panicked, shutting down the agent");
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Barrier;
+
+    use super::*;
+
+    #[test]
+    fn test_execute_all_jobs() {
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        let pool = Pool::new(4);
+        for _ in 0..100 {
+            let counter = counter.clone();
+            pool.execute(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        drop(pool);
+
+        assert_eq!(counter.load(Ordering::SeqCst), 100);
+    }
+
+    #[test]
+    fn test_execute_concurrently() {
+        // If the jobs were not executed concurrently, they would wait for each
+        // other at the barrier forever.
+        let barrier = Arc::new(Barrier::new(3));
+
+        let pool = Pool::new(3);
+        for _ in 0..3 {
+            let barrier = barrier.clone();
+            pool.execute(move || {
+                barrier.wait();
+            });
+        }
+        drop(pool);
+    }
+
+    #[test]
+    fn test_execute_queued_behind_busy_worker() {
+        let (sender, receiver) = mpsc::channel();
+
+        let pool = Pool::new(1);
+        for id in 0..3 {
+            let sender = sender.clone();
+            pool.execute(move || {
+                sender.send(id).unwrap();
+            });
+        }
+        drop(pool);
+
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec!(0, 1, 2));
+    }
+
+    #[test]
+    #[should_panic(expected = "worker pool cannot be empty")]
+    fn test_new_empty() {
+        Pool::new(0);
+    }
+}
@@ -0,0 +1,166 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+//! Utilities for cancelling actions that are being executed.
+//!
+//! Every received request registers a cancellation token under its session
+//! identifier (before it is queued for execution, so that requests waiting for
+//! an idle worker can be cancelled too). When the server decides to cancel or
+//! kill a flow, it sends a special message with the identifier of the session
+//! and the agent trips all tokens registered under it. Action handlers are
+//! expected to check the token of their session regularly and stop as soon as
+//! it is tripped.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use lazy_static::lazy_static;
+
+/// Names of the actions that the server uses to request a cancellation.
+///
+/// A `Kill` message is handled the same way as a `Cancel` one: the agent stops
+/// all the actions of the session rather than terminating itself.
+pub const ACTIONS: &[&str] = &["Cancel", "Kill"];
+
+lazy_static! {
+    /// Tokens of all pending sessions (with the number of pending requests).
+    static ref TOKENS: Mutex<HashMap<String, (Token, usize)>> = {
+        Mutex::new(HashMap::new())
+    };
+}
+
+/// A flag indicating whether an action should be cancelled.
+#[derive(Clone, Debug, Default)]
+pub struct Token {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Token {
+
+    /// Trips the token, signalling that the action should stop.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Checks whether the token has been tripped.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// A registration of a pending session in the global token registry.
+///
+/// The session stays registered (and hence can be cancelled) for as long as
+/// this object is alive.
+pub struct Registration {
+    session_id: String,
+    token: Token,
+}
+
+impl Registration {
+
+    /// Returns the cancellation token of the registered session.
+    pub fn token(&self) -> &Token {
+        &self.token
+    }
+}
+
+impl Drop for Registration {
+
+    fn drop(&mut self) {
+        let mut tokens = TOKENS.lock().unwrap();
+
+        let count = match tokens.get_mut(&self.session_id) {
+            Some((_, count)) => {
+                *count -= 1;
+                *count
+            }
+            None => return,
+        };
+
+        if count == 0 {
+            tokens.remove(&self.session_id);
+        }
+    }
+}
+
+/// Registers a pending (queued or running) request of the given session.
+///
+/// All the requests of the same session share a single token, so cancelling
+/// a session stops all the actions being executed on its behalf.
+pub fn register(session_id: &str) -> Registration {
+    let mut tokens = TOKENS.lock().unwrap();
+
+    let entry = tokens.entry(String::from(session_id))
+        .or_insert_with(|| (Token::default(), 0));
+    entry.1 += 1;
+
+    Registration {
+        session_id: String::from(session_id),
+        token: entry.0.clone(),
+    }
+}
+
+/// Cancels all the pending requests of the given session.
+///
+/// The function returns `false` if there are no pending requests of the
+/// session (e.g. because they have already finished).
+pub fn cancel(session_id: &str) -> bool {
+    match TOKENS.lock().unwrap().get(session_id) {
+        Some((token, _)) => {
+            token.cancel();
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_cancel_registered() {
+        let registration = register("test_cancel_registered");
+        assert!(!registration.token().is_cancelled());
+
+        assert!(cancel("test_cancel_registered"));
+        assert!(registration.token().is_cancelled());
+    }
+
+    #[test]
+    fn test_cancel_unregistered() {
+        assert!(!cancel("test_cancel_unregistered"));
+    }
+
+    #[test]
+    fn test_cancel_after_drop() {
+        let registration = register("test_cancel_after_drop");
+        drop(registration);
+
+        assert!(!cancel("test_cancel_after_drop"));
+    }
+
+    #[test]
+    fn test_cancel_multiple_requests() {
+        let registration_1 = register("test_cancel_multiple_requests");
+        let registration_2 = register("test_cancel_multiple_requests");
+
+        assert!(cancel("test_cancel_multiple_requests"));
+        assert!(registration_1.token().is_cancelled());
+        assert!(registration_2.token().is_cancelled());
+    }
+
+    #[test]
+    fn test_cancel_other_session() {
+        let registration = register("test_cancel_other_session_foo");
+        drop(register("test_cancel_other_session_bar"));
+
+        assert!(!cancel("test_cancel_other_session_bar"));
+        assert!(!registration.token().is_cancelled());
+    }
+}
@@ -0,0 +1,205 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+//! Utilities for isolating panics of action handlers.
+//!
+//! A bug in a single action handler (e.g. an `unwrap` on a malformed proto)
+//! should not take the whole agent down. Instead, the panic is caught, reported
+//! to the server as a failure of the particular action and the agent carries on
+//! serving other requests.
+//!
+//! Some panics are deliberate, though: they signal failures after which the
+//! agent cannot continue (e.g. a broken connection with the server). These
+//! should be raised through the [`fatal`] function and are never caught.
+//!
+//! [`fatal`]: fn.fatal.html
+
+use std::cell::{Cell, RefCell};
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Once;
+
+use log::error;
+
+thread_local! {
+    /// A location of the last panic that occurred on the current thread.
+    static LOCATION: RefCell<Option<String>> = RefCell::new(None);
+
+    /// Whether the panic unwinding the current thread is a fatal one.
+    static FATAL: Cell<bool> = Cell::new(false);
+}
+
+/// A number of action panics caught since the agent started.
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// A guard ensuring that the panic hook is installed only once.
+static HOOK: Once = Once::new();
+
+/// An error type for situations where an action handler panicked.
+#[derive(Debug)]
+pub struct PanicError {
+    /// A message that the handler panicked with.
+    pub message: String,
+    /// A location of the panic in the source code (if available).
+    pub location: Option<String>,
+}
+
+/// Runs the given function, converting its panics to errors.
+///
+/// Note that the panic is still reported by the panic hook (so it is logged
+/// to the standard error as usual). Panics raised through [`fatal`] are not
+/// converted and continue to unwind the stack.
+///
+/// [`fatal`]: fn.fatal.html
+pub fn catch<F, T>(func: F) -> Result<T, PanicError>
+where
+    F: FnOnce() -> T,
+{
+    HOOK.call_once(install_hook);
+
+    // The function is not used after the panic (and all the state it touches
+    // is owned by the session that is about to be finished), so it does not
+    // matter whether it is unwind-safe or not.
+    let func = std::panic::AssertUnwindSafe(func);
+    let payload = match std::panic::catch_unwind(func) {
+        Ok(value) => return Ok(value),
+        Err(payload) => payload,
+    };
+
+    if FATAL.with(|fatal| fatal.replace(false)) {
+        std::panic::resume_unwind(payload);
+    }
+
+    let count = COUNT.fetch_add(1, Ordering::SeqCst) + 1;
+    error!("caught an action panic ({} since the agent started)", count);
+
+    let message = if let Some(message) = payload.downcast_ref::<&str>() {
+        String::from(*message)
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("unknown panic payload")
+    };
+
+    Err(PanicError {
+        message: message,
+        location: LOCATION.with(|location| location.borrow_mut().take()),
+    })
+}
+
+/// Returns the number of action panics caught since the agent started.
+pub fn count() -> usize {
+    COUNT.load(Ordering::SeqCst)
+}
+
+/// Panics with the given message in a way that cannot be caught by [`catch`].
+///
+/// This should be used for failures after which the agent cannot continue, so
+/// that they are not mistaken for a failure of a particular action.
+///
+/// [`catch`]: fn.catch.html
+pub fn fatal<D: Display>(message: D) -> ! {
+    FATAL.with(|fatal| fatal.set(true));
+    panic!("{}", message)
+}
+
+/// Installs a panic hook that records panic locations.
+///
+/// Panic payloads carry only the message, so the location has to be obtained
+/// in the hook and passed to `catch` through a thread-local variable. The hook
+/// delegates to the previously installed one afterwards.
+fn install_hook() {
+    let hook = std::panic::take_hook();
+
+    std::panic::set_hook(Box::new(move |info| {
+        let location = info.location().map(|location| {
+            format!("{}:{}:{}", location.file(), location.line(),
+                    location.column())
+        });
+
+        // The thread-local storage might have been already destroyed if the
+        // panic occurs during the thread teardown, in which case we simply do
+        // not record the location.
+        let _ = LOCATION.try_with(|cell| *cell.borrow_mut() = location);
+
+        hook(info);
+    }));
+}
+
+impl Display for PanicError {
+
+    fn fmt(&self, fmt: &mut Formatter) -> std::fmt::Result {
+        match self.location {
+            Some(ref location) => {
+                write!(fmt, "'{}' at {}", self.message, location)
+            }
+            None => write!(fmt, "'{}'", self.message),
+        }
+    }
+}
+
+impl std::error::Error for PanicError {
+
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_catch_no_panic() {
+        assert_eq!(catch(|| 42).unwrap(), 42);
+    }
+
+    #[test]
+    fn test_catch_str_message() {
+        let error = catch(|| panic!("foo")).unwrap_err();
+
+        assert_eq!(error.message, "foo");
+    }
+
+    #[test]
+    fn test_catch_string_message() {
+        let error = catch(|| panic!("foo: {}", 42)).unwrap_err();
+
+        assert_eq!(error.message, "foo: 42");
+    }
+
+    #[test]
+    fn test_catch_location() {
+        let error = catch(|| None::<()>.unwrap()).unwrap_err();
+
+        let location = error.location.unwrap();
+        assert!(location.starts_with(file!()), "location: {}", location);
+    }
+
+    #[test]
+    fn test_catch_fatal() {
+        let result = std::panic::catch_unwind(|| {
+            catch(|| fatal("foo"))
+        });
+
+        let payload = result.unwrap_err();
+        assert_eq!(payload.downcast_ref::<String>().unwrap(), "foo");
+
+        // Ordinary panics should still be caught afterwards.
+        let error = catch(|| panic!("bar")).unwrap_err();
+        assert_eq!(error.message, "bar");
+    }
+
+    #[test]
+    fn test_count() {
+        let count = super::count();
+        let _ = catch(|| panic!());
+
+        // Tests are run concurrently, so other tests might have increased the
+        // count in the meantime as well.
+        assert!(super::count() > count);
+    }
+}
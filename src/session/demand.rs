@@ -34,6 +34,8 @@ pub struct Header {
     pub session_id: String,
     /// A server-issued request identifier.
     pub request_id: u64,
+    /// Resource limits imposed on the action execution.
+    pub limits: session::Limits,
 }
 
 /// Serialized request data for the action handler.
@@ -71,9 +73,12 @@ impl TryFrom<rrg_proto::GrrMessage> for Demand {
     fn try_from(message: rrg_proto::GrrMessage) -> Result<Demand, Self::Error> {
         let missing = session::MissingFieldError::new;
 
+        let limits = session::Limits::from_message(&message);
+
         let header = Header {
             session_id: message.session_id.ok_or(missing("session id"))?,
             request_id: message.request_id.ok_or(missing("request id"))?,
+            limits: limits,
         };
 
         Ok(Demand {
@@ -5,6 +5,11 @@
 use std::fmt::{Debug, Display, Formatter};
 use regex::Error as RegexError;
 
+use crate::action::policy::DeniedError;
+
+use super::crash::PanicError;
+use super::limits::LimitError;
+
 /// An error type for failures that can occur during a session.
 #[derive(Debug)]
 pub enum Error {
@@ -16,6 +21,14 @@ pub enum Error {
     Encode(prost::EncodeError),
     /// An error occurred when parsing a proto message.
     Parse(ParseError),
+    /// The action exceeded one of the resource limits.
+    Limit(LimitError),
+    /// The action has been cancelled by the server.
+    Cancelled,
+    /// The action handler panicked.
+    Panic(PanicError),
+    /// The request has been refused by the action policy.
+    Denied(DeniedError),
 }
 
 impl Error {
@@ -53,6 +66,18 @@ impl Display for Error {
             Parse(ref error) => {
                 write!(fmt, "malformed proto message: {}", error)
             }
+            Limit(ref error) => {
+                write!(fmt, "action aborted: {}", error)
+            }
+            Cancelled => {
+                write!(fmt, "action cancelled by the server")
+            }
+            Panic(ref error) => {
+                write!(fmt, "action panicked: {}", error)
+            }
+            Denied(ref error) => {
+                write!(fmt, "denied by policy: {}", error)
+            }
         }
     }
 }
@@ -67,6 +92,10 @@ impl std::error::Error for Error {
             Dispatch(_) => None,
             Encode(ref error) => Some(error),
             Parse(ref error) => Some(error),
+            Limit(ref error) => Some(error),
+            Cancelled => None,
+            Panic(ref error) => Some(error),
+            Denied(ref error) => Some(error),
         }
     }
 }
@@ -85,6 +114,27 @@ impl From<ParseError> for Error {
     }
 }
 
+impl From<LimitError> for Error {
+
+    fn from(error: LimitError) -> Error {
+        Error::Limit(error)
+    }
+}
+
+impl From<PanicError> for Error {
+
+    fn from(error: PanicError) -> Error {
+        Error::Panic(error)
+    }
+}
+
+impl From<DeniedError> for Error {
+
+    fn from(error: DeniedError) -> Error {
+        Error::Denied(error)
+    }
+}
+
 /// An error type for failures that can occur when parsing proto messages.
 #[derive(Debug)]
 pub enum ParseError {
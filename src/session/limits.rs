@@ -0,0 +1,504 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+//! Utilities for enforcing resource limits of action executions.
+//!
+//! The server can restrict the amount of resources (CPU time, network traffic
+//! or wall time) that a single action execution is allowed to consume. Sessions
+//! keep track of the used resources and abort the action once any of the limits
+//! is exceeded.
+//!
+//! Since many actions can be executed concurrently, CPU time is measured for
+//! the worker thread that executes the action rather than for the whole
+//! process. Helper threads spawned by the action itself have to charge their
+//! CPU time to the execution explicitly (see [`CpuAccount`]).
+//!
+//! [`CpuAccount`]: struct.CpuAccount.html
+
+use std::cell::RefCell;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread::ThreadId;
+use std::time::{Duration, Instant};
+
+thread_local! {
+    /// An account of the action execution running on the current thread.
+    static ACCOUNT: RefCell<Option<CpuAccount>> = RefCell::new(None);
+}
+
+/// Resource limits imposed by the server on a single action execution.
+///
+/// Absent limits mean that the corresponding resource is not limited.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// A maximum CPU time (user and system) that the action can use.
+    pub cpu_time: Option<Duration>,
+    /// A maximum number of bytes that the action can send to the server.
+    pub network_bytes: Option<u64>,
+    /// A maximum wall time that the action can run for.
+    pub runtime: Option<Duration>,
+}
+
+/// Resources consumed by an action execution so far.
+pub struct Usage {
+    /// A time at which the execution started.
+    start_time: Instant,
+    /// A thread executing the action (the one whose CPU time is measured).
+    thread: ThreadId,
+    /// A CPU time used by the executing thread when the execution started.
+    start_cpu_time: Option<Duration>,
+    /// A number of bytes sent to the server so far.
+    network_bytes: u64,
+    /// A CPU time used by helper threads spawned by the action.
+    helpers: CpuAccount,
+}
+
+/// A CPU time used by helper threads of an action execution.
+///
+/// Helper threads (e.g. directory walkers) should obtain the account of the
+/// execution with [`current`] before they are spawned and charge their CPU time
+/// to it with a [`CpuMeter`], so that it counts towards the CPU time limit.
+///
+/// [`current`]: #method.current
+/// [`CpuMeter`]: struct.CpuMeter.html
+#[derive(Clone, Debug, Default)]
+pub struct CpuAccount {
+    /// A total CPU time charged to the account (in microseconds).
+    micros: Arc<AtomicU64>,
+}
+
+/// A meter charging CPU time used by the current thread to an account.
+///
+/// The time used since the last update is charged when the meter is dropped,
+/// so the meter should be dropped on the thread that it was started on.
+pub struct CpuMeter {
+    /// An account to charge the CPU time to.
+    account: CpuAccount,
+    /// A CPU time used by the current thread when it was last charged.
+    charged: Option<Duration>,
+}
+
+/// An error type for situations where an action exceeds one of its limits.
+#[derive(Debug, PartialEq, Eq)]
+pub enum LimitError {
+    /// The action used more CPU time than allowed.
+    CpuTime(Duration),
+    /// The action attempted to send more bytes than allowed.
+    NetworkBytes(u64),
+    /// The action has been running for longer than allowed.
+    Runtime(Duration),
+}
+
+impl Limits {
+
+    /// Extracts limits specified in the given server message.
+    ///
+    /// Following the GRR convention, limits equal to zero are treated as if
+    /// they were not specified at all.
+    pub fn from_message(message: &rrg_proto::GrrMessage) -> Limits {
+        let cpu_time = message.cpu_limit
+            .map(|secs| secs as f64)
+            .filter(|secs| secs.is_finite() && *secs > 0.0)
+            .map(Duration::from_secs_f64);
+
+        let network_bytes = message.network_bytes_limit
+            .filter(|bytes| *bytes > 0);
+
+        let runtime = message.runtime_limit_us
+            .filter(|micros| *micros > 0)
+            .map(Duration::from_micros);
+
+        Limits {
+            cpu_time: cpu_time,
+            network_bytes: network_bytes,
+            runtime: runtime,
+        }
+    }
+
+    /// Combines the limits with other ones, picking the stricter of each.
+    pub fn restrict(self, other: &Limits) -> Limits {
+        fn min<T: Ord>(this: Option<T>, other: Option<T>) -> Option<T> {
+            match (this, other) {
+                (Some(this), Some(other)) => Some(std::cmp::min(this, other)),
+                (this, None) => this,
+                (None, other) => other,
+            }
+        }
+
+        Limits {
+            cpu_time: min(self.cpu_time, other.cpu_time),
+            network_bytes: min(self.network_bytes, other.network_bytes),
+            runtime: min(self.runtime, other.runtime),
+        }
+    }
+}
+
+impl Usage {
+
+    /// Starts tracking resources used from now on.
+    ///
+    /// This should be called on the thread that is going to execute the action,
+    /// as the CPU time used by this thread is taken as the baseline.
+    pub fn start() -> Usage {
+        let helpers = CpuAccount::default();
+        ACCOUNT.with(|account| {
+            *account.borrow_mut() = Some(helpers.clone());
+        });
+
+        Usage {
+            start_time: Instant::now(),
+            thread: std::thread::current().id(),
+            start_cpu_time: cpu_time(),
+            network_bytes: 0,
+            helpers: helpers,
+        }
+    }
+
+    /// Records that the given number of bytes is about to be sent.
+    pub fn add_network_bytes(&mut self, bytes: u64) {
+        self.network_bytes += bytes;
+    }
+
+    /// Returns the number of bytes sent to the server so far.
+    pub fn network_bytes(&self) -> u64 {
+        self.network_bytes
+    }
+
+    /// Verifies that the resources used so far do not exceed the limits.
+    pub fn check(&self, limits: &Limits) -> Result<(), LimitError> {
+        if let Some(limit) = limits.network_bytes {
+            if self.network_bytes > limit {
+                return Err(LimitError::NetworkBytes(limit));
+            }
+        }
+
+        if let Some(limit) = limits.runtime {
+            if self.start_time.elapsed() > limit {
+                return Err(LimitError::Runtime(limit));
+            }
+        }
+
+        // CPU time of another thread says nothing about the action, so it can
+        // only be verified on the thread on which the execution started.
+        let cpu_limit = limits.cpu_time
+            .filter(|_| std::thread::current().id() == self.thread);
+
+        if let Some(limit) = cpu_limit {
+            // If we are not able to measure the CPU time on this platform, we
+            // have no choice but to let the action run.
+            let start = self.start_cpu_time;
+            if let (Some(start), Some(now)) = (start, cpu_time()) {
+                if now - start + self.helpers.total() > limit {
+                    return Err(LimitError::CpuTime(limit));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Usage {
+
+    fn drop(&mut self) {
+        if std::thread::current().id() == self.thread {
+            ACCOUNT.with(|account| account.borrow_mut().take());
+        }
+    }
+}
+
+impl CpuAccount {
+
+    /// Returns the account of the action executed by the current thread.
+    ///
+    /// `None` is returned if the current thread does not execute any action.
+    pub fn current() -> Option<CpuAccount> {
+        ACCOUNT.with(|account| account.borrow().clone())
+    }
+
+    /// Returns the total CPU time charged to the account so far.
+    fn total(&self) -> Duration {
+        Duration::from_micros(self.micros.load(Ordering::Relaxed))
+    }
+}
+
+impl CpuMeter {
+
+    /// Starts charging CPU time used by the current thread from now on.
+    pub fn start(account: CpuAccount) -> CpuMeter {
+        CpuMeter {
+            account: account,
+            charged: cpu_time(),
+        }
+    }
+
+    /// Charges the CPU time used since the last update to the account.
+    pub fn update(&mut self) {
+        if let (Some(charged), Some(now)) = (self.charged, cpu_time()) {
+            let micros = (now - charged).as_micros() as u64;
+            self.account.micros.fetch_add(micros, Ordering::Relaxed);
+            self.charged = Some(now);
+        }
+    }
+}
+
+impl Drop for CpuMeter {
+
+    fn drop(&mut self) {
+        self.update();
+    }
+}
+
+/// Returns the total CPU time (user and system) used by the current thread.
+#[cfg(target_os = "linux")]
+fn cpu_time() -> Option<Duration> {
+    let mut usage = std::mem::MaybeUninit::<libc::rusage>::uninit();
+
+    // SAFETY: We pass a valid pointer to a buffer of the right size that the
+    // function fills in. We read the buffer only if the call succeeded.
+    let usage = unsafe {
+        if libc::getrusage(libc::RUSAGE_THREAD, usage.as_mut_ptr()) != 0 {
+            return None;
+        }
+        usage.assume_init()
+    };
+
+    let timeval = |time: libc::timeval| {
+        Duration::from_secs(time.tv_sec as u64) +
+        Duration::from_micros(time.tv_usec as u64)
+    };
+
+    Some(timeval(usage.ru_utime) + timeval(usage.ru_stime))
+}
+
+/// Returns the total CPU time (user and system) used by the current thread.
+#[cfg(not(target_os = "linux"))]
+fn cpu_time() -> Option<Duration> {
+    // TODO: Add support for measuring per-thread CPU time on other systems.
+    None
+}
+
+impl Display for LimitError {
+
+    fn fmt(&self, fmt: &mut Formatter) -> std::fmt::Result {
+        use LimitError::*;
+
+        match *self {
+            CpuTime(limit) => {
+                write!(fmt, "CPU time limit of {:?} exceeded", limit)
+            }
+            NetworkBytes(limit) => {
+                write!(fmt, "network limit of {} bytes exceeded", limit)
+            }
+            Runtime(limit) => {
+                write!(fmt, "runtime limit of {:?} exceeded", limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LimitError {
+
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_from_message_no_limits() {
+        let message = rrg_proto::GrrMessage::default();
+
+        assert_eq!(Limits::from_message(&message), Limits::default());
+    }
+
+    #[test]
+    fn test_from_message_zero_limits() {
+        let message = rrg_proto::GrrMessage {
+            cpu_limit: Some(Default::default()),
+            network_bytes_limit: Some(0),
+            runtime_limit_us: Some(0),
+            ..Default::default()
+        };
+
+        assert_eq!(Limits::from_message(&message), Limits::default());
+    }
+
+    #[test]
+    fn test_from_message_all_limits() {
+        let message = rrg_proto::GrrMessage {
+            network_bytes_limit: Some(1024),
+            runtime_limit_us: Some(1_500_000),
+            ..Default::default()
+        };
+
+        let limits = Limits::from_message(&message);
+        assert_eq!(limits.network_bytes, Some(1024));
+        assert_eq!(limits.runtime, Some(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn test_restrict() {
+        let limits = Limits {
+            cpu_time: Some(Duration::from_secs(10)),
+            network_bytes: Some(1024),
+            runtime: None,
+        };
+
+        let other = Limits {
+            cpu_time: Some(Duration::from_secs(5)),
+            network_bytes: None,
+            runtime: Some(Duration::from_secs(60)),
+        };
+
+        assert_eq!(limits.restrict(&other), Limits {
+            cpu_time: Some(Duration::from_secs(5)),
+            network_bytes: Some(1024),
+            runtime: Some(Duration::from_secs(60)),
+        });
+    }
+
+    #[test]
+    fn test_check_no_limits() {
+        let mut usage = Usage::start();
+        usage.add_network_bytes(u64::MAX);
+
+        assert_eq!(usage.check(&Limits::default()), Ok(()));
+    }
+
+    #[test]
+    fn test_check_network_bytes() {
+        let limits = Limits {
+            network_bytes: Some(1024),
+            ..Default::default()
+        };
+
+        let mut usage = Usage::start();
+
+        usage.add_network_bytes(1000);
+        assert_eq!(usage.check(&limits), Ok(()));
+
+        usage.add_network_bytes(24);
+        assert_eq!(usage.check(&limits), Ok(()));
+
+        usage.add_network_bytes(1);
+        assert_eq!(usage.check(&limits), Err(LimitError::NetworkBytes(1024)));
+    }
+
+    #[test]
+    fn test_check_runtime() {
+        let limits = Limits {
+            runtime: Some(Duration::from_millis(10)),
+            ..Default::default()
+        };
+
+        let usage = Usage::start();
+        assert_eq!(usage.check(&limits), Ok(()));
+
+        std::thread::sleep(Duration::from_millis(20));
+        let limit = Duration::from_millis(10);
+        assert_eq!(usage.check(&limits), Err(LimitError::Runtime(limit)));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_check_cpu_time() {
+        let limit = Duration::from_millis(1);
+        let limits = Limits {
+            cpu_time: Some(limit),
+            ..Default::default()
+        };
+
+        let usage = Usage::start();
+
+        // We burn the CPU until the limit is exceeded. The wall time bound is
+        // there just to avoid looping forever if something goes wrong.
+        let start = Instant::now();
+        let mut result = Ok(());
+        while result.is_ok() && start.elapsed() < Duration::from_secs(10) {
+            let mut hash = 0u64;
+            for i in 0..100_000u64 {
+                hash = hash.wrapping_mul(31).wrapping_add(i);
+            }
+            assert!(hash != 42);
+
+            result = usage.check(&limits);
+        }
+
+        assert_eq!(result, Err(LimitError::CpuTime(limit)));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_check_cpu_time_other_threads() {
+        let limit = Duration::from_millis(50);
+        let limits = Limits {
+            cpu_time: Some(limit),
+            ..Default::default()
+        };
+
+        let usage = Usage::start();
+
+        // Another thread (e.g. a worker executing a different action) burns
+        // the CPU for much longer than the limit of the current execution.
+        std::thread::spawn(|| {
+            let start = Instant::now();
+            let mut hash = 0u64;
+            while start.elapsed() < Duration::from_millis(200) {
+                for i in 0..100_000u64 {
+                    hash = hash.wrapping_mul(31).wrapping_add(i);
+                }
+            }
+            assert!(hash != 42);
+        }).join().unwrap();
+
+        assert_eq!(usage.check(&limits), Ok(()));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_check_cpu_time_helper_threads() {
+        let limit = Duration::from_millis(50);
+        let limits = Limits {
+            cpu_time: Some(limit),
+            ..Default::default()
+        };
+
+        let usage = Usage::start();
+        let account = CpuAccount::current().unwrap();
+
+        // A helper thread of the execution burns the CPU for much longer than
+        // the limit and charges it to the execution.
+        std::thread::spawn(move || {
+            assert!(CpuAccount::current().is_none());
+            let _meter = CpuMeter::start(account);
+
+            let start = Instant::now();
+            let mut hash = 0u64;
+            while start.elapsed() < Duration::from_millis(200) {
+                for i in 0..100_000u64 {
+                    hash = hash.wrapping_mul(31).wrapping_add(i);
+                }
+            }
+            assert!(hash != 42);
+        }).join().unwrap();
+
+        assert_eq!(usage.check(&limits), Err(LimitError::CpuTime(limit)));
+    }
+
+    #[test]
+    fn test_current_account_after_drop() {
+        let usage = Usage::start();
+        assert!(CpuAccount::current().is_some());
+
+        drop(usage);
+        assert!(CpuAccount::current().is_none());
+    }
+}
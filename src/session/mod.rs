@@ -14,22 +14,33 @@
 //! bytes, action runtime, etc.) and stop the execution if they exceed limits
 //! for a particular request.
 
+mod cancel;
+mod crash;
 mod demand;
 mod error;
+mod limits;
 mod response;
 mod sink;
 mod parse_enum;
 mod time;
 
 use std::convert::TryInto;
+use std::time::{Duration, Instant};
 
-use log::{error, info};
+use log::{error, info, warn};
 
 use crate::action;
+use crate::audit;
 use crate::message;
+use crate::config::Config;
 pub use self::demand::{Demand, Header, Payload};
 pub use self::error::{Error, ParseError, MissingFieldError, RegexParseError,
                       UnsupportedValueError, UnknownEnumValueError};
+pub use self::limits::{CpuAccount, CpuMeter, Limits, LimitError};
+pub use self::crash::{PanicError, catch as catch_panic, count as crash_count,
+                       fatal};
+pub use self::cancel::Registration;
+use self::limits::Usage;
 use self::response::{Response, Status};
 pub use self::sink::Sink;
 pub use self::time::time_from_micros;
@@ -61,7 +72,11 @@ impl<'s, S: Session> Task<'s, S> {
         R: action::Request,
         H: FnOnce(&mut S, R) -> Result<()>,
     {
-        let request = self.payload.parse()?;
+        let request: R = self.payload.parse()?;
+
+        let config = crate::config::get();
+        action::policy::check_paths(&config.actions, &request.paths())?;
+
         handler(self.session, request)
     }
 }
@@ -77,7 +92,7 @@ impl<'s, S: Session> Task<'s, S> {
 /// Note that if action execution fails, this function deals with all the errors
 /// by sending appropriate information to the server (if possible), logging them
 /// and failing hard if a critical error (e.g. communication failure) occurred.
-pub fn handle<M>(message: M)
+pub fn handle<M>(message: M, registration: Registration, config: &Config)
 where
     M: TryInto<Demand, Error=ParseError>,
 {
@@ -92,18 +107,59 @@ where
         }
     };
 
-    let mut session = Action::from_demand(&demand);
+    let mut session = Action::from_demand(&demand, config, registration);
+
+    let name = &demand.action;
+    let payload = demand.payload;
 
-    let result = action::dispatch(&demand.action, Task {
-        session: &mut session,
-        payload: demand.payload,
+    let start_time = chrono::Utc::now();
+    let args_digest = match payload.data {
+        Some(ref data) => crate::audit::digest(data),
+        None => crate::audit::digest(&[]),
+    };
+
+    // The request might have been cancelled while waiting for an idle worker,
+    // in which case the action is not started at all.
+    let result = session.check_cancelled().and_then(|()| {
+        // A panic in an action handler should not take down the whole agent,
+        // so we report it as an ordinary action failure instead.
+        crash::catch(|| {
+            action::dispatch(name, Task {
+                session: &mut session,
+                payload: payload,
+            })
+        }).unwrap_or_else(|error| Err(error.into()))
     });
 
-    if let Err(ref error) = result {
-        error!("failed to execute the '{}' action: {}", demand.action, error);
-    } else {
-        info!("finished executing the '{}' action", demand.action);
-    }
+    let outcome = match result {
+        Err(Error::Denied(ref error)) => {
+            // Refused requests might indicate that the server is compromised,
+            // so they are recorded in the audit log for the local operators.
+            warn!(target: "audit", "denied '{}' action of session '{}': {}",
+                  name, demand.header.session_id, error);
+            audit::Outcome::Denied
+        }
+        Err(ref error) => {
+            error!("failed to execute the '{}' action: {}", name, error);
+            audit::Outcome::Error
+        }
+        Ok(()) => {
+            info!("finished executing the '{}' action", name);
+            audit::Outcome::Ok
+        }
+    };
+
+    audit::record(audit::Record {
+        prev: String::new(),
+        session_id: demand.header.session_id.clone(),
+        action: name.clone(),
+        args_digest: args_digest,
+        start_time: start_time.to_rfc3339(),
+        end_time: chrono::Utc::now().to_rfc3339(),
+        bytes_sent: session.network_bytes(),
+        result: outcome,
+        error: result.as_ref().err().map(|error| error.to_string()),
+    });
 
     let message = match session.status(result).try_into() {
         Ok(message) => message,
@@ -119,6 +175,50 @@ where
     message::send(message);
 }
 
+/// Checks whether the given message is a request to cancel a session.
+pub fn is_cancellation(message: &rrg_proto::GrrMessage) -> bool {
+    match message.name {
+        Some(ref name) => cancel::ACTIONS.contains(&name.as_str()),
+        None => false,
+    }
+}
+
+/// Registers the request in the given message, so that it can be cancelled.
+///
+/// Requests should be registered as soon as they are received, before being
+/// queued for execution. Otherwise, a cancellation arriving while the request
+/// waits for an idle worker would find nothing to cancel and the action would
+/// be executed anyway. The registration is released once the request is done.
+pub fn register(message: &rrg_proto::GrrMessage) -> Registration {
+    // Messages without a session identifier are rejected by the handler, so
+    // it does not matter under which identifier they are registered.
+    let session_id = message.session_id.as_deref().unwrap_or_default();
+    cancel::register(session_id)
+}
+
+/// Cancels all running actions of the session specified in the message.
+///
+/// Cancellation requests are not actions on their own: they are not executed
+/// by a session and no status is sent back for them. Note that this function
+/// should be called as soon as the message is received (rather than being
+/// queued with other requests), as otherwise it could only be processed after
+/// the action it is supposed to cancel is finished.
+pub fn cancel(message: rrg_proto::GrrMessage) {
+    let session_id = match message.session_id {
+        Some(session_id) => session_id,
+        None => {
+            error!("cancellation request without session id");
+            return;
+        }
+    };
+
+    if cancel::cancel(&session_id) {
+        info!("cancelled actions of session '{}'", session_id);
+    } else {
+        info!("no pending actions of session '{}' to cancel", session_id);
+    }
+}
+
 /// Abstraction for various kinds of sessions.
 pub trait Session {
     /// Sends a reply to the flow that call the action.
@@ -130,8 +230,31 @@ pub trait Session {
     where R: action::Response + 'static;
 
     /// Sends a heartbeat signal to the Fleetspeak process.
+    ///
+    /// Long-running actions should call this method regularly (e.g. for every
+    /// visited file), as otherwise Fleetspeak will consider the agent to be
+    /// unresponsive and kill it. Implementations are responsible for limiting
+    /// the rate of the signals, so it is fine to call it very often.
     fn heartbeat(&mut self) {
-        // TODO: Create a real implementation.
+    }
+
+    /// Verifies that the action has not been cancelled by the server.
+    ///
+    /// Long-running actions should call this method regularly (e.g. for every
+    /// visited file) and propagate the error, so that they stop as soon as
+    /// possible once cancelled. Implementations can also use it to stop actions
+    /// that exceeded their resource limits without sending any responses.
+    fn check_cancelled(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Returns an identifier of the flow session that the action belongs to.
+    ///
+    /// Requests retried by the server have the same session identifier, so
+    /// actions can use it to pick up the state persisted by previous attempts.
+    /// Sessions not associated with any flow do not have an identifier.
+    fn id(&self) -> Option<&str> {
+        None
     }
 }
 
@@ -176,22 +299,52 @@ impl Session for Adhoc {
 pub struct Action {
     header: Header,
     next_response_id: u64,
+    /// A minimum interval between two consecutive heartbeat signals.
+    heartbeat_rate: Duration,
+    /// A time at which the last heartbeat signal was sent.
+    last_heartbeat: Instant,
+    /// Resources used by the action so far.
+    usage: Usage,
+    /// A registration of the session allowing it to be cancelled.
+    registration: cancel::Registration,
 }
 
 impl Action {
 
     /// Constructs a new session for the given `demand` object.
-    pub fn from_demand(demand: &Demand) -> Action {
+    ///
+    /// The session is cancelled once the token of the given registration is
+    /// tripped. Limits specified in the configuration are applied on top of
+    /// the ones requested by the server.
+    fn from_demand(
+        demand: &Demand,
+        config: &Config,
+        registration: cancel::Registration,
+    ) -> Action {
         // Response identifiers that GRR agents use start at 1. Unfortunately,
         // the server uses this assumption (to determine the number of expected
         // responses when status message is received), so we have to follow this
         // behaviour in RRG as well.
+        let mut header = demand.header.clone();
+        header.limits = header.limits.restrict(&config.limits);
+
         Action {
-            header: demand.header.clone(),
+            header: header,
             next_response_id: 1,
+            heartbeat_rate: config.heartbeat_rate,
+            // The agent has just collected the request, so Fleetspeak knows
+            // that it is alive and there is no need to signal it right away.
+            last_heartbeat: Instant::now(),
+            usage: Usage::start(),
+            registration: registration,
         }
     }
 
+    /// Returns the number of bytes that the session sent to the server so far.
+    fn network_bytes(&self) -> u64 {
+        self.usage.network_bytes()
+    }
+
     /// Wraps an action response to a session-specific response.
     fn wrap<R>(&self, response: R) -> Response<R>
     where
@@ -205,6 +358,27 @@ impl Action {
         }
     }
 
+    /// Sends a session response to the server unless it exceeds the limits.
+    ///
+    /// The response is accounted for before being sent, so that an action is
+    /// never able to send more bytes than its network limit allows.
+    fn send_limited<R>(&mut self, response: Response<R>) -> Result<()>
+    where
+        R: action::Response,
+    {
+        self.check_cancelled()?;
+
+        let message: rrg_proto::GrrMessage = response.try_into()?;
+
+        let len = prost::Message::encoded_len(&message) as u64;
+        self.usage.add_network_bytes(len);
+        self.usage.check(&self.header.limits)?;
+
+        message::send(message);
+
+        Ok(())
+    }
+
     /// Wraps an action result to a session-specific status response.
     ///
     /// Note that this method consumes the session. The reason for this is that
@@ -223,7 +397,7 @@ impl Action {
 impl Session for Action {
 
     fn reply<R: action::Response>(&mut self, response: R) -> Result<()> {
-        send(self.wrap(response))?;
+        self.send_limited(self.wrap(response))?;
         self.next_response_id += 1;
 
         Ok(())
@@ -233,10 +407,37 @@ impl Session for Action {
     where
         R: action::Response,
     {
-        send(sink.wrap(response))?;
+        self.send_limited(sink.wrap(response))?;
+
+        Ok(())
+    }
+
+    fn heartbeat(&mut self) {
+        let now = Instant::now();
+        if now.duration_since(self.last_heartbeat) < self.heartbeat_rate {
+            return;
+        }
+
+        message::heartbeat();
+        self.last_heartbeat = now;
+    }
+
+    fn check_cancelled(&self) -> Result<()> {
+        if self.registration.token().is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+
+        // Actions that run for a long time without replying (e.g. recursive
+        // walks) would never be stopped if limits were verified only when
+        // something is sent, so we also verify them here.
+        self.usage.check(&self.header.limits)?;
 
         Ok(())
     }
+
+    fn id(&self) -> Option<&str> {
+        Some(&self.header.session_id)
+    }
 }
 
 /// Sends a session response to the server.
@@ -273,6 +474,9 @@ pub mod test {
     pub struct Fake {
         replies: Vec<Box<dyn Any>>,
         responses: HashMap<Sink, Vec<Box<dyn Any>>>,
+        heartbeats: usize,
+        cancelled: bool,
+        id: Option<String>,
     }
 
     impl Fake {
@@ -282,9 +486,25 @@ pub mod test {
             Fake {
                 replies: Vec::new(),
                 responses: std::collections::HashMap::new(),
+                heartbeats: 0,
+                cancelled: false,
+                id: None,
             }
         }
 
+        /// Constructs a new fake session with the given identifier.
+        pub fn with_id(id: &str) -> Fake {
+            Fake {
+                id: Some(String::from(id)),
+                ..Fake::new()
+            }
+        }
+
+        /// Marks the session as cancelled by the server.
+        pub fn cancel(&mut self) {
+            self.cancelled = true;
+        }
+
         /// Yields the number of replies that this session sent so far.
         pub fn reply_count(&self) -> usize {
             self.replies.len()
@@ -320,6 +540,14 @@ pub mod test {
             })
         }
 
+        /// Yields the number of heartbeat signals the action has sent so far.
+        ///
+        /// Note that the fake session does not limit the rate of heartbeats, so
+        /// this is simply the number of times the action asked for one.
+        pub fn heartbeat_count(&self) -> usize {
+            self.heartbeats
+        }
+
         /// Yields the number of responses sent so far to the specified sink.
         pub fn response_count(&self, sink: Sink) -> usize {
             match self.responses.get(&sink) {
@@ -388,6 +616,22 @@ pub mod test {
 
             Ok(())
         }
+
+        fn heartbeat(&mut self) {
+            self.heartbeats += 1;
+        }
+
+        fn check_cancelled(&self) -> Result<()> {
+            if self.cancelled {
+                Err(Error::Cancelled)
+            } else {
+                Ok(())
+            }
+        }
+
+        fn id(&self) -> Option<&str> {
+            self.id.as_deref()
+        }
     }
 }
 
@@ -580,6 +824,48 @@ mod tests {
         assert_eq!(responses.next(), None);
     }
 
+    #[test]
+    fn test_is_cancellation() {
+        let message = rrg_proto::GrrMessage {
+            name: Some(String::from("Cancel")),
+            ..Default::default()
+        };
+        assert!(is_cancellation(&message));
+
+        let message = rrg_proto::GrrMessage {
+            name: Some(String::from("Kill")),
+            ..Default::default()
+        };
+        assert!(is_cancellation(&message));
+
+        let message = rrg_proto::GrrMessage {
+            name: Some(String::from("GetClientInfo")),
+            ..Default::default()
+        };
+        assert!(!is_cancellation(&message));
+    }
+
+    #[test]
+    fn test_cancel_registered_request() {
+        let session_id = "C.1234567890abcdef/flows/F:CANCEL";
+
+        let registration = register(&rrg_proto::GrrMessage {
+            session_id: Some(String::from(session_id)),
+            name: Some(String::from("GetClientInfo")),
+            ..Default::default()
+        });
+
+        // The request has not been picked up by any worker yet, but it should
+        // be possible to cancel it already.
+        cancel(rrg_proto::GrrMessage {
+            session_id: Some(String::from(session_id)),
+            name: Some(String::from("Cancel")),
+            ..Default::default()
+        });
+
+        assert!(registration.token().is_cancelled());
+    }
+
     #[derive(Debug, PartialEq, Eq)]
     struct StringResponse(String);
 
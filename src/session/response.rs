@@ -5,6 +5,8 @@
 
 use std::convert::TryInto;
 
+use rrg_proto::grr_status::ReturnedStatus;
+
 use crate::action;
 use crate::session;
 
@@ -64,15 +66,13 @@ impl TryInto<rrg_proto::GrrMessage> for Status {
     type Error = prost::EncodeError;
 
     fn try_into(self) -> Result<rrg_proto::GrrMessage, prost::EncodeError> {
-        use rrg_proto::grr_status::ReturnedStatus;
-
         let status = match self.result {
             Ok(()) => rrg_proto::GrrStatus {
                 status: Some(ReturnedStatus::Ok.into()),
                 ..Default::default()
             },
             Err(error) => rrg_proto::GrrStatus {
-                status: Some(ReturnedStatus::GenericError.into()),
+                status: Some(returned_status(&error).into()),
                 error_message: Some(error.to_string()),
                 ..Default::default()
             },
@@ -92,3 +92,100 @@ impl TryInto<rrg_proto::GrrMessage> for Status {
         })
     }
 }
+
+/// Determines the status code reported to the server for the given error.
+///
+/// Failures caused by exceeding the resource limits and cancellations have
+/// dedicated codes, so that the server can tell them apart from failures of
+/// the action itself.
+fn returned_status(error: &session::Error) -> ReturnedStatus {
+    use session::{Error, LimitError};
+
+    match *error {
+        // There is no code for cancellations specifically, but from the point
+        // of view of the server they are the same as the agent terminating the
+        // action before it could finish.
+        Error::Cancelled => ReturnedStatus::ClientKilled,
+        Error::Limit(LimitError::CpuTime(_)) => {
+            ReturnedStatus::CpuLimitExceeded
+        }
+        Error::Limit(LimitError::NetworkBytes(_)) => {
+            ReturnedStatus::NetworkLimitExceeded
+        }
+        Error::Limit(LimitError::Runtime(_)) => {
+            ReturnedStatus::RuntimeLimitExceeded
+        }
+        _ => ReturnedStatus::GenericError,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_status_ok() {
+        let status = grr_status(Ok(()));
+        assert_eq!(status.status(), ReturnedStatus::Ok);
+    }
+
+    #[test]
+    fn test_status_generic_error() {
+        let error = session::Error::Dispatch(String::from("Foo"));
+
+        let status = grr_status(Err(error));
+        assert_eq!(status.status(), ReturnedStatus::GenericError);
+        assert_eq!(status.error_message(), "unknown action: Foo");
+    }
+
+    #[test]
+    fn test_status_cpu_limit() {
+        let limit = Duration::from_secs(1);
+        let error = session::LimitError::CpuTime(limit).into();
+
+        let status = grr_status(Err(error));
+        assert_eq!(status.status(), ReturnedStatus::CpuLimitExceeded);
+    }
+
+    #[test]
+    fn test_status_network_limit() {
+        let error = session::LimitError::NetworkBytes(1024).into();
+
+        let status = grr_status(Err(error));
+        assert_eq!(status.status(), ReturnedStatus::NetworkLimitExceeded);
+    }
+
+    #[test]
+    fn test_status_runtime_limit() {
+        let limit = Duration::from_secs(60);
+        let error = session::LimitError::Runtime(limit).into();
+
+        let status = grr_status(Err(error));
+        assert_eq!(status.status(), ReturnedStatus::RuntimeLimitExceeded);
+    }
+
+    #[test]
+    fn test_status_cancelled() {
+        let status = grr_status(Err(session::Error::Cancelled));
+        assert_eq!(status.status(), ReturnedStatus::ClientKilled);
+        assert_eq!(status.error_message(), "action cancelled by the server");
+    }
+
+    /// Converts the given result to a status message and extracts its payload.
+    fn grr_status(result: session::Result<()>) -> rrg_proto::GrrStatus {
+        let status = Status {
+            session_id: String::from("F:ABCD"),
+            request_id: 42,
+            response_id: 1,
+            result: result,
+        };
+
+        let message: rrg_proto::GrrMessage = status.try_into().unwrap();
+        assert_eq!(message.args_rdf_name(), "GrrStatus");
+
+        prost::Message::decode(&message.args.unwrap()[..]).unwrap()
+    }
+}
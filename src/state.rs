@@ -0,0 +1,213 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+//! Persistent local state of the agent.
+//!
+//! Some actions can take hours to complete and the agent can be restarted in
+//! the meantime (e.g. because of an update or a crash). Such actions can save
+//! their progress in a [`Store`], so that a retried request can pick up where
+//! the previous attempt left off instead of starting from scratch.
+//!
+//! Stores live in subdirectories of the state directory specified in the agent
+//! configuration. If it is not specified, no state is persisted at all.
+//!
+//! [`Store`]: struct.Store.html
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use log::warn;
+
+/// A directory-backed key-value store of binary blobs.
+///
+/// Keys can be arbitrary strings (e.g. session identifiers): they are hashed
+/// before being used as file names. Values are written atomically, so that a
+/// crash in the middle of an update never leaves a corrupted value behind.
+pub struct Store {
+    /// A directory in which the values are stored.
+    path: PathBuf,
+}
+
+/// Opens the store with the given name in the configured state directory.
+///
+/// If the agent is not configured with a state directory, `None` is returned.
+pub fn open(name: &str) -> Option<Store> {
+    let config = crate::config::get();
+    let state_dir = config.state_dir.as_ref()?;
+
+    Some(Store::new(state_dir.join(name)))
+}
+
+impl Store {
+
+    /// Creates a store backed by the directory at the given path.
+    ///
+    /// The directory does not have to exist: it is created once the first value
+    /// is put into the store.
+    pub fn new<P: AsRef<Path>>(path: P) -> Store {
+        Store {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Retrieves the value associated with the given key (if there is any).
+    pub fn get(&self, key: &str) -> std::io::Result<Option<Vec<u8>>> {
+        match std::fs::read(self.value_path(key)) {
+            Ok(value) => Ok(Some(value)),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                Ok(None)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Associates the given value with the given key.
+    ///
+    /// The value is written to a temporary file first and then moved to its
+    /// final location, so the previous value is kept intact on failures.
+    pub fn put(&self, key: &str, value: &[u8]) -> std::io::Result<()> {
+        use std::io::Write as _;
+
+        std::fs::create_dir_all(&self.path)?;
+
+        let path = self.value_path(key);
+        let temp_path = path.with_extension("tmp");
+
+        let mut file = std::fs::File::create(&temp_path)?;
+        file.write_all(value)?;
+        file.sync_all()?;
+
+        std::fs::rename(&temp_path, &path)
+    }
+
+    /// Removes the value associated with the given key (if there is any).
+    pub fn remove(&self, key: &str) -> std::io::Result<()> {
+        match std::fs::remove_file(self.value_path(key)) {
+            Ok(()) => Ok(()),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                Ok(())
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Removes values that have not been updated for longer than `max_age`.
+    ///
+    /// Actions that never get retried would otherwise leave their state behind
+    /// forever. Values that cannot be removed are skipped with a warning.
+    pub fn prune(&self, max_age: Duration) -> std::io::Result<()> {
+        let iter = match crate::fs::list_dir(&self.path) {
+            Ok(iter) => iter,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(());
+            }
+            Err(error) => return Err(error),
+        };
+
+        let now = SystemTime::now();
+        for entry in iter {
+            let mtime = match entry.metadata.modified() {
+                Ok(mtime) => mtime,
+                Err(_) => continue,
+            };
+
+            match now.duration_since(mtime) {
+                Ok(age) if age > max_age => (),
+                _ => continue,
+            }
+
+            if let Err(error) = std::fs::remove_file(&entry.path) {
+                warn! {
+                    "failed to remove stale state '{}': {}",
+                    entry.path.display(), error
+                };
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns a path to the file with the value of the given key.
+    fn value_path(&self, key: &str) -> PathBuf {
+        self.path.join(crate::audit::digest(key.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_get_missing() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let store = Store::new(tempdir.path().join("foo"));
+
+        assert_eq!(store.get("bar").unwrap(), None);
+    }
+
+    #[test]
+    fn test_put_and_get() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let store = Store::new(tempdir.path().join("foo"));
+
+        store.put("aff4:/C.1234/flows/F:ABCD", b"bar").unwrap();
+        store.put("aff4:/C.1234/flows/F:EFGH", b"baz").unwrap();
+
+        let value = store.get("aff4:/C.1234/flows/F:ABCD").unwrap();
+        assert_eq!(value, Some(b"bar".to_vec()));
+
+        let value = store.get("aff4:/C.1234/flows/F:EFGH").unwrap();
+        assert_eq!(value, Some(b"baz".to_vec()));
+    }
+
+    #[test]
+    fn test_put_overwrites() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let store = Store::new(tempdir.path());
+
+        store.put("foo", b"bar").unwrap();
+        store.put("foo", b"quux").unwrap();
+
+        assert_eq!(store.get("foo").unwrap(), Some(b"quux".to_vec()));
+    }
+
+    #[test]
+    fn test_remove() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let store = Store::new(tempdir.path());
+
+        store.put("foo", b"bar").unwrap();
+        store.remove("foo").unwrap();
+        assert_eq!(store.get("foo").unwrap(), None);
+
+        // Removing a missing value is not an error.
+        store.remove("foo").unwrap();
+    }
+
+    #[test]
+    fn test_prune() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let store = Store::new(tempdir.path());
+
+        store.put("foo", b"bar").unwrap();
+
+        store.prune(Duration::from_secs(60 * 60)).unwrap();
+        assert_eq!(store.get("foo").unwrap(), Some(b"bar".to_vec()));
+
+        std::thread::sleep(Duration::from_millis(10));
+
+        store.prune(Duration::from_millis(1)).unwrap();
+        assert_eq!(store.get("foo").unwrap(), None);
+    }
+
+    #[test]
+    fn test_prune_missing_dir() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let store = Store::new(tempdir.path().join("foo"));
+
+        assert!(store.prune(Duration::from_secs(60)).is_ok());
+    }
+}
@@ -0,0 +1,269 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+//! Periodic sampling of the resource usage of the agent process.
+//!
+//! GRR servers expect agents to report how much CPU time and I/O they consume
+//! over time. A background thread (started with [`start`]) samples counters of
+//! the agent process from the `/proc/self` directory at a fixed rate and keeps
+//! samples from the recent past, so that they can be reported to the server
+//! with the client stats action.
+//!
+//! [`start`]: fn.start.html
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use lazy_static::lazy_static;
+use log::warn;
+
+use crate::action::processes::{self, System};
+
+/// A frequency at which the resource usage is sampled.
+pub const RATE: Duration = Duration::from_secs(10);
+
+/// A period of time for which the samples are kept.
+pub const HISTORY: Duration = Duration::from_secs(60 * 60);
+
+lazy_static! {
+    /// Samples collected so far (from the oldest one).
+    static ref SAMPLES: Mutex<VecDeque<Sample>> = Mutex::new(VecDeque::new());
+}
+
+/// A single sample of the resource usage of the agent process.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sample {
+    /// A time at which the sample was taken.
+    pub time: SystemTime,
+    /// A total CPU time that the agent spent in the user mode.
+    pub user_cpu_time: Duration,
+    /// A total CPU time that the agent spent in the kernel mode.
+    pub system_cpu_time: Duration,
+    /// A CPU utilization (in percent) since the preceding sample.
+    pub cpu_percent: f32,
+    /// A total number of bytes that the agent read from the storage.
+    pub read_bytes: u64,
+    /// A total number of bytes that the agent wrote to the storage.
+    pub write_bytes: u64,
+}
+
+/// Starts sampling the resource usage in a background thread.
+///
+/// This function should be called once at the agent startup.
+pub fn start() -> std::io::Result<()> {
+    std::thread::Builder::new()
+        .name(String::from("stats"))
+        .spawn(run)?;
+
+    Ok(())
+}
+
+/// Returns all the samples collected within the recent history.
+pub fn samples() -> Vec<Sample> {
+    SAMPLES.lock().unwrap().iter().copied().collect()
+}
+
+/// Samples the resource usage at a fixed rate (forever).
+fn run() {
+    let system = System::new();
+    let mut last = None;
+
+    loop {
+        match sample(&system, last.as_ref()) {
+            Ok(sample) => {
+                push(&mut SAMPLES.lock().unwrap(), sample);
+                last = Some(sample);
+            }
+            Err(error) => warn!("failed to sample resource usage: {}", error),
+        }
+
+        std::thread::sleep(RATE);
+    }
+}
+
+/// Takes a sample of the current resource usage.
+///
+/// The CPU utilization is computed relative to the `last` sample (if any).
+fn sample(system: &System, last: Option<&Sample>) -> std::io::Result<Sample> {
+    let stat = std::fs::read_to_string("/proc/self/stat")?;
+    let stat = processes::parse_stat(&stat).ok_or_else(|| {
+        use std::io::{Error, ErrorKind};
+        Error::new(ErrorKind::InvalidData, "malformed process stat")
+    })?;
+
+    // I/O accounting might not be enabled in the kernel, in which case we do
+    // not want to lose CPU samples and just report no I/O at all.
+    let io = std::fs::read_to_string("/proc/self/io")
+        .map(|io| parse_io(&io))
+        .unwrap_or_default();
+
+    let mut sample = Sample {
+        time: SystemTime::now(),
+        user_cpu_time: processes::ticks(stat.user_time, system.ticks_per_sec),
+        system_cpu_time: processes::ticks(stat.system_time,
+                                          system.ticks_per_sec),
+        cpu_percent: 0.0,
+        read_bytes: io.read_bytes,
+        write_bytes: io.write_bytes,
+    };
+
+    if let Some(last) = last {
+        sample.cpu_percent = cpu_percent(last, &sample);
+    }
+
+    Ok(sample)
+}
+
+/// Appends the sample, discarding the ones that are older than the history.
+fn push(samples: &mut VecDeque<Sample>, sample: Sample) {
+    samples.push_back(sample);
+
+    while let Some(oldest) = samples.front() {
+        match sample.time.duration_since(oldest.time) {
+            Ok(age) if age > HISTORY => samples.pop_front(),
+            _ => break,
+        };
+    }
+}
+
+/// Computes the CPU utilization (in percent) between the two samples.
+fn cpu_percent(prev: &Sample, next: &Sample) -> f32 {
+    let wall_time = match next.time.duration_since(prev.time) {
+        Ok(wall_time) if wall_time > Duration::from_secs(0) => wall_time,
+        _ => return 0.0,
+    };
+
+    let prev_cpu_time = prev.user_cpu_time + prev.system_cpu_time;
+    let next_cpu_time = next.user_cpu_time + next.system_cpu_time;
+    let cpu_time = next_cpu_time.checked_sub(prev_cpu_time)
+        .unwrap_or_default();
+
+    (cpu_time.as_secs_f64() / wall_time.as_secs_f64() * 100.0) as f32
+}
+
+/// I/O counters as reported in the `/proc/[pid]/io` file.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Io {
+    /// A number of bytes fetched from the storage layer.
+    read_bytes: u64,
+    /// A number of bytes sent to the storage layer.
+    write_bytes: u64,
+}
+
+/// Parses contents of the `/proc/[pid]/io` file.
+fn parse_io(io: &str) -> Io {
+    let field = |key: &str| {
+        io.lines()
+            .find(|line| line.starts_with(key))
+            .and_then(|line| line[key.len()..].trim().parse().ok())
+            .unwrap_or(0)
+    };
+
+    Io {
+        read_bytes: field("read_bytes:"),
+        write_bytes: field("write_bytes:"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_sample_current_process() {
+        let system = System::new();
+
+        let first = sample(&system, None).unwrap();
+        assert_eq!(first.cpu_percent, 0.0);
+
+        // Burn some CPU time, so that there is something to measure.
+        let mut sum = 0u64;
+        for i in 0..10_000_000u64 {
+            sum = sum.wrapping_add(i * i);
+        }
+        assert!(sum > 0);
+
+        let second = sample(&system, Some(&first)).unwrap();
+        assert!(second.time >= first.time);
+        assert!(second.user_cpu_time >= first.user_cpu_time);
+        assert!(second.cpu_percent >= 0.0);
+    }
+
+    #[test]
+    fn test_push_discards_old_samples() {
+        let mut samples = VecDeque::new();
+
+        let start = SystemTime::now();
+        push(&mut samples, sample_at(start));
+        push(&mut samples, sample_at(start + HISTORY / 2));
+        assert_eq!(samples.len(), 2);
+
+        push(&mut samples, sample_at(start + HISTORY + RATE));
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[0].time, start + HISTORY / 2);
+    }
+
+    #[test]
+    fn test_cpu_percent() {
+        let start = SystemTime::now();
+
+        let prev = Sample {
+            user_cpu_time: Duration::from_secs(1),
+            system_cpu_time: Duration::from_secs(1),
+            ..sample_at(start)
+        };
+        let next = Sample {
+            user_cpu_time: Duration::from_secs(4),
+            system_cpu_time: Duration::from_secs(2),
+            ..sample_at(start + Duration::from_secs(8))
+        };
+
+        assert_eq!(cpu_percent(&prev, &next), 50.0);
+    }
+
+    #[test]
+    fn test_cpu_percent_same_time() {
+        let start = SystemTime::now();
+
+        assert_eq!(cpu_percent(&sample_at(start), &sample_at(start)), 0.0);
+    }
+
+    #[test]
+    fn test_parse_io() {
+        let io = "\
+rchar: 4096
+wchar: 1024
+syscr: 8
+syscw: 2
+read_bytes: 12288
+write_bytes: 8192
+cancelled_write_bytes: 0
+";
+
+        assert_eq!(parse_io(io), Io {
+            read_bytes: 12288,
+            write_bytes: 8192,
+        });
+    }
+
+    #[test]
+    fn test_parse_io_empty() {
+        assert_eq!(parse_io(""), Io::default());
+    }
+
+    /// Constructs an empty sample taken at the given time.
+    fn sample_at(time: SystemTime) -> Sample {
+        Sample {
+            time: time,
+            user_cpu_time: Duration::from_secs(0),
+            system_cpu_time: Duration::from_secs(0),
+            cpu_percent: 0.0,
+            read_bytes: 0,
+            write_bytes: 0,
+        }
+    }
+}
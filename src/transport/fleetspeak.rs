@@ -0,0 +1,107 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+//! A transport that exchanges messages through the Fleetspeak client.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use fleetspeak::Packet;
+use log::warn;
+
+use super::ReadError;
+
+/// A transport that uses the Fleetspeak client as the communication channel.
+///
+/// The Fleetspeak connection is global, so there should be only one instance
+/// of this type in the agent.
+pub struct Fleetspeak {
+    /// A lock serializing all the writes to the Fleetspeak connection.
+    ///
+    /// Actions are executed concurrently by multiple workers, but messages
+    /// (and heartbeats) have to be written to the connection one at a time so
+    /// that they do not interleave.
+    output: Mutex<()>,
+}
+
+impl Fleetspeak {
+
+    /// Performs the startup procedure of the Fleetspeak connection.
+    ///
+    /// The `version` string is reported to the Fleetspeak client as the
+    /// version of the agent.
+    pub fn startup(version: &str) -> std::io::Result<Fleetspeak> {
+        fleetspeak::startup(version).map_err(|error| {
+            std::io::Error::new(std::io::ErrorKind::Other, error.to_string())
+        })?;
+
+        Ok(Fleetspeak {
+            output: Mutex::new(()),
+        })
+    }
+}
+
+impl super::Transport for Fleetspeak {
+
+    fn send(&self, message: rrg_proto::GrrMessage) -> std::io::Result<()> {
+        let packet = Packet {
+            service: String::from("GRR"),
+            kind: Some(String::from("GrrMessage")),
+            data: message,
+        };
+
+        let _guard = self.output.lock().unwrap();
+        fleetspeak::send(packet).map_err(|error| {
+            std::io::Error::new(std::io::ErrorKind::Other, error.to_string())
+        })
+    }
+
+    fn heartbeat(&self) -> std::io::Result<()> {
+        let _guard = self.output.lock().unwrap();
+        fleetspeak::heartbeat().map_err(|error| {
+            std::io::Error::new(std::io::ErrorKind::Other, error.to_string())
+        })
+    }
+
+    fn collect(&self, heartbeat_rate: Duration)
+        -> Result<rrg_proto::GrrMessage, ReadError>
+    {
+        use fleetspeak::ReadError::*;
+
+        let packet = match fleetspeak::collect(heartbeat_rate) {
+            Ok(packet) => packet,
+            Err(Malformed(error)) => {
+                return Err(ReadError::Malformed(error.to_string()));
+            }
+            Err(Decode(error)) => {
+                return Err(ReadError::Malformed(error.to_string()));
+            }
+            Err(error) => {
+                // Other errors (I/O failures or failed magic checks) mean that
+                // the connection is broken.
+                let error = std::io::Error::new(std::io::ErrorKind::Other,
+                                                error.to_string());
+                return Err(ReadError::Io(error));
+            }
+        };
+
+        if packet.service != "GRR" {
+            warn!("message send by '{}' service (instead of GRR)",
+                  packet.service);
+        }
+
+        match packet.kind {
+            Some(ref kind) if kind != "GrrMessage" => {
+                warn!("message with unrecognized type '{}'", kind);
+            }
+            Some(_) => (),
+            None => {
+                warn!("message with missing type specification");
+            }
+        }
+
+        Ok(packet.data)
+    }
+}
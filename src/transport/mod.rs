@@ -0,0 +1,105 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+//! Transports used to exchange messages with the GRR server.
+//!
+//! Normally, the agent talks to the server through the Fleetspeak client that
+//! spawns it. However, for testing purposes (or when deployed behind a custom
+//! relay) it is also possible to exchange messages directly over a stream of
+//! bytes (e.g. standard streams or a Unix socket).
+//!
+//! The transport is established once at the agent startup and then installed
+//! as the one used by the [`message`] module.
+//!
+//! [`message`]: crate::message
+
+pub mod fleetspeak;
+pub mod stream;
+
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+use crate::opts::{self, Opts};
+
+/// Abstraction for various kinds of transports.
+///
+/// Transports are shared by all the action workers, so implementations are
+/// responsible for making sure that concurrently sent messages do not
+/// interleave.
+pub trait Transport: Send + Sync {
+
+    /// Sends a message to the server.
+    fn send(&self, message: rrg_proto::GrrMessage) -> std::io::Result<()>;
+
+    /// Signals that the agent is still alive.
+    ///
+    /// Transports that do not need any liveness signals may do nothing here.
+    fn heartbeat(&self) -> std::io::Result<()>;
+
+    /// Waits for the next message from the server.
+    ///
+    /// While waiting, the transport should signal that the agent is alive
+    /// with the given frequency (if it needs such signals at all).
+    fn collect(&self, heartbeat_rate: Duration)
+        -> Result<rrg_proto::GrrMessage, ReadError>;
+}
+
+/// Establishes the transport specified in the command-line arguments.
+pub fn connect(opts: &Opts) -> std::io::Result<Box<dyn Transport>> {
+    let transport: Box<dyn Transport> = match opts.transport {
+        opts::Transport::Fleetspeak => {
+            let version = env!("CARGO_PKG_VERSION");
+            Box::new(self::fleetspeak::Fleetspeak::startup(version)?)
+        }
+        opts::Transport::Stdio => {
+            Box::new(self::stream::Stream::stdio())
+        }
+        opts::Transport::Socket(ref path) => {
+            Box::new(self::stream::Stream::socket(path)?)
+        }
+    };
+
+    Ok(transport)
+}
+
+/// An error type for failures that can occur when collecting messages.
+#[derive(Debug)]
+pub enum ReadError {
+    /// The connection is broken and no more messages can be collected.
+    Io(std::io::Error),
+    /// A message has been received but it is not a valid `GrrMessage`.
+    Malformed(String),
+}
+
+impl Display for ReadError {
+
+    fn fmt(&self, fmt: &mut Formatter) -> std::fmt::Result {
+        use ReadError::*;
+
+        match *self {
+            Io(ref error) => write!(fmt, "input error: {}", error),
+            Malformed(ref error) => write!(fmt, "malformed message: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {
+
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use ReadError::*;
+
+        match *self {
+            Io(ref error) => Some(error),
+            Malformed(_) => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ReadError {
+
+    fn from(error: std::io::Error) -> ReadError {
+        ReadError::Io(error)
+    }
+}
@@ -0,0 +1,221 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+//! A transport that exchanges messages over a plain stream of bytes.
+//!
+//! Messages are framed the same way as in the [chunked] format: every message
+//! is prepended with its length (as 64-bit unsigned big-endian integer). This
+//! makes it trivial to talk to the agent from a test harness or to put it
+//! behind a custom relay without the need for a Fleetspeak client.
+//!
+//! [chunked]: crate::chunked
+
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use byteorder::{BigEndian, ReadBytesExt as _};
+
+use super::ReadError;
+
+/// A maximum length of a message that the transport accepts (in bytes).
+///
+/// Messages sent by the server are small, so anything bigger than this means
+/// that the peer is broken (or malicious) and allocating a buffer for it could
+/// exhaust the memory of the host.
+const MAX_MESSAGE_LEN: u64 = 64 * 1024 * 1024;
+
+/// A transport that reads and writes length-prefixed messages.
+pub struct Stream {
+    /// A stream from which messages of the server are read.
+    reader: Mutex<BufReader<Box<dyn Read + Send>>>,
+    /// A stream to which messages to the server are written.
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Stream {
+
+    /// Creates a new transport over the given pair of streams.
+    pub fn new<R, W>(reader: R, writer: W) -> Stream
+    where
+        R: Read + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        Stream {
+            reader: Mutex::new(BufReader::new(Box::new(reader))),
+            writer: Mutex::new(Box::new(writer)),
+        }
+    }
+
+    /// Creates a new transport over the standard input and output.
+    ///
+    /// Note that in this mode nothing else (e.g. logs) should be written to
+    /// the standard output, as otherwise the peer will not be able to decode
+    /// the messages.
+    pub fn stdio() -> Stream {
+        Stream::new(std::io::stdin(), std::io::stdout())
+    }
+
+    /// Creates a new transport over a Unix socket at the given path.
+    ///
+    /// The peer is expected to listen on the socket before the agent starts.
+    #[cfg(target_family = "unix")]
+    pub fn socket<P: AsRef<Path>>(path: P) -> std::io::Result<Stream> {
+        let socket = std::os::unix::net::UnixStream::connect(path)?;
+        Ok(Stream::new(socket.try_clone()?, socket))
+    }
+
+    /// Creates a new transport over a Unix socket at the given path.
+    ///
+    /// Unix sockets are not available on this platform, so this function
+    /// always fails.
+    #[cfg(not(target_family = "unix"))]
+    pub fn socket<P: AsRef<Path>>(path: P) -> std::io::Result<Stream> {
+        let message = format!("sockets are not supported: {}",
+                              path.as_ref().display());
+        Err(std::io::Error::new(std::io::ErrorKind::Other, message))
+    }
+}
+
+impl super::Transport for Stream {
+
+    fn send(&self, message: rrg_proto::GrrMessage) -> std::io::Result<()> {
+        let mut buf = vec!();
+        crate::chunked::encode_into(&mut buf, &message)?;
+
+        // The message is written with a single call, so that messages sent
+        // concurrently never interleave.
+        let mut writer = self.writer.lock().unwrap();
+        writer.write_all(&buf)?;
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    fn heartbeat(&self) -> std::io::Result<()> {
+        // The peer does not monitor the agent, so there is no need to send
+        // anything.
+        Ok(())
+    }
+
+    fn collect(&self, _: Duration) -> Result<rrg_proto::GrrMessage, ReadError> {
+        let mut reader = self.reader.lock().unwrap();
+
+        let len = reader.read_u64::<BigEndian>()?;
+
+        // We cannot skip the oversized frame without reading all of it, so the
+        // stream is out of sync from now on and we treat it as broken.
+        if len > MAX_MESSAGE_LEN {
+            use std::io::{Error, ErrorKind::InvalidData};
+
+            let message = format!("message too long ({} bytes)", len);
+            return Err(ReadError::Io(Error::new(InvalidData, message)));
+        }
+
+        let mut buf = vec![0; len as usize];
+        reader.read_exact(&mut buf[..])?;
+
+        // Since the whole frame has been consumed, the stream is still in sync
+        // and we can continue to read further messages after a failure here.
+        prost::Message::decode(&buf[..])
+            .map_err(|error| ReadError::Malformed(error.to_string()))
+    }
+}
+
+#[cfg(test)]
+#[cfg(target_family = "unix")]
+mod tests {
+
+    use std::os::unix::net::UnixStream;
+
+    use crate::transport::Transport as _;
+
+    use super::*;
+
+    #[test]
+    fn test_send() {
+        let (socket, mut peer) = UnixStream::pair().unwrap();
+        let stream = Stream::new(socket.try_clone().unwrap(), socket);
+
+        let message = rrg_proto::GrrMessage {
+            name: Some(String::from("Foo")),
+            ..Default::default()
+        };
+        stream.send(message.clone()).unwrap();
+
+        let len = peer.read_u64::<BigEndian>().unwrap() as usize;
+        let mut buf = vec![0; len];
+        peer.read_exact(&mut buf[..]).unwrap();
+
+        let received: rrg_proto::GrrMessage = prost::Message::decode(&buf[..])
+            .unwrap();
+        assert_eq!(received, message);
+    }
+
+    #[test]
+    fn test_collect() {
+        let (socket, mut peer) = UnixStream::pair().unwrap();
+        let stream = Stream::new(socket.try_clone().unwrap(), socket);
+
+        let message = rrg_proto::GrrMessage {
+            name: Some(String::from("Foo")),
+            ..Default::default()
+        };
+
+        let mut buf = vec!();
+        crate::chunked::encode_into(&mut buf, &message).unwrap();
+        peer.write_all(&buf).unwrap();
+
+        let collected = stream.collect(Duration::from_secs(1)).unwrap();
+        assert_eq!(collected, message);
+    }
+
+    #[test]
+    fn test_collect_malformed() {
+        let (socket, mut peer) = UnixStream::pair().unwrap();
+        let stream = Stream::new(socket.try_clone().unwrap(), socket);
+
+        peer.write_all(&[0, 0, 0, 0, 0, 0, 0, 2, 0xff, 0xff]).unwrap();
+
+        let message = rrg_proto::GrrMessage {
+            name: Some(String::from("Foo")),
+            ..Default::default()
+        };
+
+        let mut buf = vec!();
+        crate::chunked::encode_into(&mut buf, &message).unwrap();
+        peer.write_all(&buf).unwrap();
+
+        let error = stream.collect(Duration::from_secs(1)).unwrap_err();
+        assert!(matches!(error, ReadError::Malformed(_)));
+
+        // The malformed message should not break the following ones.
+        let collected = stream.collect(Duration::from_secs(1)).unwrap();
+        assert_eq!(collected, message);
+    }
+
+    #[test]
+    fn test_collect_too_long() {
+        let (socket, mut peer) = UnixStream::pair().unwrap();
+        let stream = Stream::new(socket.try_clone().unwrap(), socket);
+
+        peer.write_all(&u64::MAX.to_be_bytes()).unwrap();
+
+        let error = stream.collect(Duration::from_secs(1)).unwrap_err();
+        assert!(matches!(error, ReadError::Io(_)));
+    }
+
+    #[test]
+    fn test_collect_closed() {
+        let (socket, peer) = UnixStream::pair().unwrap();
+        let stream = Stream::new(socket.try_clone().unwrap(), socket);
+
+        drop(peer);
+
+        let error = stream.collect(Duration::from_secs(1)).unwrap_err();
+        assert!(matches!(error, ReadError::Io(_)));
+    }
+}
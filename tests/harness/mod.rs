@@ -0,0 +1,183 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+//! A harness for end-to-end tests of the agent.
+//!
+//! The harness runs the agent main loop in a background thread and plays the
+//! role of Fleetspeak on the other end of the connection: it sends requests as
+//! raw `GrrMessage`s and collects everything that the agent sends back. This
+//! way, the tests exercise exactly the same code paths as a deployed agent
+//! (request parsing, response numbering, status encoding and so on).
+//!
+//! Because the transport of the agent is global, there is only one agent per
+//! test binary. Tests get exclusive access to it through the [`server`]
+//! function, so that messages of different tests never interleave.
+
+#![allow(dead_code)]
+
+use std::io::{Read as _, Write as _};
+use std::os::unix::net::UnixStream;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use byteorder::{BigEndian, ReadBytesExt as _};
+use lazy_static::lazy_static;
+
+/// A maximum time to wait for a message from the agent.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+lazy_static! {
+    /// The fake server connected to the agent running in the background.
+    static ref SERVER: Mutex<Server> = Mutex::new(Server::start());
+}
+
+/// Returns an exclusive handle to the fake server.
+///
+/// The agent is started the first time this function is called.
+pub fn server() -> MutexGuard<'static, Server> {
+    // A failed test poisons the lock but the connection itself stays valid,
+    // so other tests can carry on.
+    SERVER.lock().unwrap_or_else(|error| error.into_inner())
+}
+
+/// A fake server talking to the agent through the stream transport.
+pub struct Server {
+    /// The server end of the connection with the agent.
+    socket: UnixStream,
+    /// A number of sessions started so far.
+    session_count: u64,
+}
+
+impl Server {
+
+    /// Starts the agent and connects the fake server to it.
+    fn start() -> Server {
+        let (agent, server) = UnixStream::pair()
+            .expect("failed to create a socket pair");
+
+        let reader = agent.try_clone()
+            .expect("failed to clone the agent socket");
+        let transport = rrg::transport::stream::Stream::new(reader, agent);
+        rrg::message::init(Box::new(transport));
+
+        let config = rrg::config::Config::default();
+        std::thread::Builder::new()
+            .name(String::from("rrg-listen"))
+            .spawn(move || rrg::listen(&config))
+            .expect("failed to spawn the agent thread");
+
+        server.set_read_timeout(Some(TIMEOUT))
+            .expect("failed to set the read timeout");
+
+        Server {
+            socket: server,
+            session_count: 0,
+        }
+    }
+
+    /// Sends a raw message to the agent.
+    pub fn send(&mut self, message: rrg_proto::GrrMessage) {
+        let mut buf = vec!();
+        rrg::chunked::encode_into(&mut buf, &message)
+            .expect("failed to encode a message");
+
+        self.socket.write_all(&buf)
+            .expect("failed to send a message");
+    }
+
+    /// Waits for a raw message from the agent.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if no message arrives within the timeout.
+    pub fn recv(&mut self) -> rrg_proto::GrrMessage {
+        let len = self.socket.read_u64::<BigEndian>()
+            .expect("failed to receive a message");
+
+        let mut buf = vec![0; len as usize];
+        self.socket.read_exact(&mut buf[..])
+            .expect("failed to receive a message");
+
+        prost::Message::decode(&buf[..])
+            .expect("failed to decode a message")
+    }
+
+    /// Requests the agent to execute an action and waits for it to finish.
+    ///
+    /// The request is sent within a new session and all the messages that
+    /// the agent sends back (up to and including the final status) are
+    /// returned in the order in which they were received.
+    pub fn execute<A>(&mut self, action: &str, args: A)
+        -> Vec<rrg_proto::GrrMessage>
+    where
+        A: prost::Message,
+    {
+        let mut data = vec!();
+        args.encode(&mut data)
+            .expect("failed to encode the request arguments");
+
+        let session_id = self.next_session_id();
+        self.send(rrg_proto::GrrMessage {
+            session_id: Some(session_id),
+            request_id: Some(1),
+            name: Some(String::from(action)),
+            args: Some(data),
+            ..Default::default()
+        });
+
+        let mut messages = vec!();
+        loop {
+            let message = self.recv();
+            let is_status = is_status(&message);
+
+            messages.push(message);
+            if is_status {
+                return messages;
+            }
+        }
+    }
+
+    /// Generates an identifier of a new (unique) session.
+    pub fn next_session_id(&mut self) -> String {
+        self.session_count += 1;
+        format!("C.1234567890abcdef/flows/F:{:08X}", self.session_count)
+    }
+}
+
+/// Checks whether the given message is a final status of some action.
+pub fn is_status(message: &rrg_proto::GrrMessage) -> bool {
+    message.r#type == Some(rrg_proto::grr_message::Type::Status.into())
+}
+
+/// Decodes the status carried by the given message.
+///
+/// # Panics
+///
+/// This function will panic if the message is not a status message.
+pub fn status(message: &rrg_proto::GrrMessage) -> rrg_proto::GrrStatus {
+    assert!(is_status(message), "not a status message: {:?}", message);
+    assert_eq!(message.args_rdf_name.as_deref(), Some("GrrStatus"));
+
+    prost::Message::decode(message.args())
+        .expect("failed to decode the status")
+}
+
+/// Decodes the reply carried by the given message.
+///
+/// # Panics
+///
+/// This function will panic if the message is not an ordinary reply or if it
+/// does not carry a message of the expected RDF class.
+pub fn reply<M>(message: &rrg_proto::GrrMessage, rdf_name: &str) -> M
+where
+    M: prost::Message + Default,
+{
+    let kind: i32 = rrg_proto::grr_message::Type::Message.into();
+    assert_eq!(message.r#type, Some(kind), "not a reply: {:?}", message);
+    assert_eq!(message.args_rdf_name.as_deref(), Some(rdf_name));
+
+    prost::Message::decode(message.args())
+        .expect("failed to decode the reply")
+}
@@ -0,0 +1,209 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+//! End-to-end tests of the protocol spoken by the agent.
+
+#![cfg(target_family = "unix")]
+
+mod harness;
+
+use rrg_proto::grr_status::ReturnedStatus;
+
+#[test]
+fn test_reply_and_status() {
+    let mut server = harness::server();
+
+    let messages = server.execute("GetClientInfo", ());
+    assert_eq!(messages.len(), 2);
+
+    let info: rrg_proto::ClientInformation = {
+        harness::reply(&messages[0], "ClientInformation")
+    };
+    assert_eq!(info.client_name(), env!("CARGO_PKG_NAME"));
+
+    let status = harness::status(&messages[1]);
+    assert_eq!(status.status, Some(ReturnedStatus::Ok.into()));
+}
+
+#[test]
+fn test_response_ids() {
+    let tempdir = tempfile::tempdir().unwrap();
+    std::fs::write(tempdir.path().join("foo"), b"").unwrap();
+    std::fs::write(tempdir.path().join("bar"), b"").unwrap();
+    std::fs::write(tempdir.path().join("baz"), b"").unwrap();
+
+    let request = rrg_proto::ListDirRequest {
+        pathspec: Some(tempdir.path().to_path_buf().into()),
+        ..Default::default()
+    };
+
+    let mut server = harness::server();
+
+    let messages = server.execute("ListDirectory", request);
+    assert_eq!(messages.len(), 4);
+
+    for (id, message) in messages.iter().enumerate() {
+        assert_eq!(message.response_id, Some(id as u64 + 1));
+        assert_eq!(message.request_id, Some(1));
+    }
+
+    let session_id = messages[0].session_id.clone();
+    assert!(messages.iter().all(|message| message.session_id == session_id));
+
+    for message in &messages[..3] {
+        harness::reply::<rrg_proto::StatEntry>(message, "StatEntry");
+    }
+
+    let status = harness::status(&messages[3]);
+    assert_eq!(status.status, Some(ReturnedStatus::Ok.into()));
+}
+
+#[test]
+fn test_unknown_action() {
+    let mut server = harness::server();
+
+    let messages = server.execute("Foo", ());
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].response_id, Some(1));
+
+    let status = harness::status(&messages[0]);
+    assert_eq!(status.status, Some(ReturnedStatus::GenericError.into()));
+    assert!(status.error_message().contains("Foo"));
+}
+
+#[test]
+fn test_malformed_args() {
+    let mut server = harness::server();
+
+    let session_id = server.next_session_id();
+    server.send(rrg_proto::GrrMessage {
+        session_id: Some(session_id.clone()),
+        request_id: Some(42),
+        name: Some(String::from("ListDirectory")),
+        args: Some(vec!(0xff, 0xff)),
+        ..Default::default()
+    });
+
+    let message = server.recv();
+    assert_eq!(message.session_id, Some(session_id));
+    assert_eq!(message.request_id, Some(42));
+    assert_eq!(message.response_id, Some(1));
+
+    let status = harness::status(&message);
+    assert_eq!(status.status, Some(ReturnedStatus::GenericError.into()));
+}
+
+#[test]
+fn test_network_limit_exceeded() {
+    let mut server = harness::server();
+
+    let session_id = server.next_session_id();
+    server.send(rrg_proto::GrrMessage {
+        session_id: Some(session_id.clone()),
+        request_id: Some(1),
+        name: Some(String::from("GetClientInfo")),
+        network_bytes_limit: Some(1),
+        ..Default::default()
+    });
+
+    // The reply does not fit into the limit, so only the status is sent.
+    let message = server.recv();
+    assert_eq!(message.session_id, Some(session_id));
+    assert_eq!(message.response_id, Some(1));
+
+    let status = harness::status(&message);
+    let expected = ReturnedStatus::NetworkLimitExceeded;
+    assert_eq!(status.status, Some(expected.into()));
+}
+
+#[test]
+fn test_missing_session_id_ignored() {
+    let mut server = harness::server();
+
+    // Requests without the session identifier cannot be answered at all, so
+    // the agent should drop it and process the next request as usual.
+    server.send(rrg_proto::GrrMessage {
+        request_id: Some(1),
+        name: Some(String::from("GetClientInfo")),
+        ..Default::default()
+    });
+
+    let messages = server.execute("Foo", ());
+    assert_eq!(messages.len(), 1);
+    harness::status(&messages[0]);
+}
+
+#[test]
+fn test_killed_mid_timeline() {
+    let tempdir = tempfile::tempdir().unwrap();
+    for idx in 0..4096 {
+        std::fs::write(tempdir.path().join(idx.to_string()), b"").unwrap();
+    }
+
+    let request = rrg_proto::rrg::TimelineArgs {
+        root: Some(rrg_proto::path::to_bytes(tempdir.path().to_path_buf())),
+        ..Default::default()
+    };
+
+    let mut data = vec!();
+    prost::Message::encode(&request, &mut data).unwrap();
+
+    let mut server = harness::server();
+
+    // Both messages are sent at once, so the agent receives the second one
+    // long before it is done walking the directory.
+    let session_id = server.next_session_id();
+    server.send(rrg_proto::GrrMessage {
+        session_id: Some(session_id.clone()),
+        request_id: Some(1),
+        name: Some(String::from("Timeline")),
+        args: Some(data),
+        ..Default::default()
+    });
+    server.send(rrg_proto::GrrMessage {
+        session_id: Some(session_id.clone()),
+        request_id: Some(2),
+        name: Some(String::from("Kill")),
+        ..Default::default()
+    });
+
+    // Parts of the timeline might have been sent before the agent noticed,
+    // so we skip everything until the final status.
+    let message = loop {
+        let message = server.recv();
+        if harness::is_status(&message) {
+            break message;
+        }
+    };
+    assert_eq!(message.session_id, Some(session_id));
+    assert_eq!(message.request_id, Some(1));
+
+    let status = harness::status(&message);
+    assert_eq!(status.status, Some(ReturnedStatus::ClientKilled.into()));
+}
+
+#[test]
+fn test_denied_by_policy() {
+    let mut server = harness::server();
+
+    // The configuration is global, but the server lock guarantees that no
+    // other test is executing actions in the meantime.
+    rrg::config::init(rrg::config::Config {
+        actions: rrg::config::Actions {
+            deny: vec!(String::from("GetClientInfo")),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+
+    let messages = server.execute("GetClientInfo", ());
+    rrg::config::init(rrg::config::Config::default());
+
+    assert_eq!(messages.len(), 1);
+
+    let status = harness::status(&messages[0]);
+    assert_eq!(status.status, Some(ReturnedStatus::GenericError.into()));
+    assert!(status.error_message().starts_with("denied by policy"));
+}